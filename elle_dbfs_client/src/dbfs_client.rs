@@ -1,16 +1,22 @@
 //! DBFS Client - 与内核通信的客户端
 //!
-//! 运行在 Host Linux 上,通过 socket 与 Alien 内核通信
+//! 运行在 Host Linux 上, 通过 QEMU 为 virtio-console 创建的 unix socket 与 Alien 内核通信
+//!
+//! 每个数据包为 `[len:4 (BE)][payload]`, payload 使用与内核 `dbfs::elle_protocol`
+//! 相同的大端二进制编码, 两端不再有其他分隔符
 
-use std::io::{BufRead, BufReader, BufWriter, Read, Write};
-use std::net::TcpStream;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::os::unix::net::UnixStream;
 use std::time::Duration;
 
-use serde::{Deserialize, Serialize};
+use anyhow::bail;
+
+/// 单个数据包的最大长度, 与内核 `drivers::elle_comm::MAX_PACKET_SIZE` 一致
+pub const MAX_PACKET_SIZE: usize = 64 * 1024 - 4;
 
 // ==================== 协议定义 (与内核同步) ====================
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum DbfsOpType {
     BeginTx = 1,
@@ -21,9 +27,12 @@ pub enum DbfsOpType {
     Readdir = 6,
     CommitTx = 7,
     RollbackTx = 8,
+    Savepoint = 9,
+    RollbackToSavepoint = 10,
+    ReleaseSavepoint = 11,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct DbfsRequest {
     pub tx_id: u64,
     pub op_type: DbfsOpType,
@@ -32,7 +41,7 @@ pub struct DbfsRequest {
     pub data: Vec<u8>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone)]
 pub struct DbfsResponse {
     pub tx_id: u64,
     pub status: i32,
@@ -40,19 +49,60 @@ pub struct DbfsResponse {
     pub data: Vec<u8>,
 }
 
+impl DbfsRequest {
+    /// 编码为 `[tx_id:8][op_type:1][path_len:2][path][offset:8][data_len:4][data]`
+    pub fn serialize(&self) -> Vec<u8> {
+        let path = self.path.as_bytes();
+        let mut bytes = Vec::with_capacity(23 + path.len() + self.data.len());
+        bytes.extend_from_slice(&self.tx_id.to_be_bytes());
+        bytes.push(self.op_type as u8);
+        bytes.extend_from_slice(&(path.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(path);
+        bytes.extend_from_slice(&self.offset.to_be_bytes());
+        bytes.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+impl DbfsResponse {
+    /// 解码 `[tx_id:8][status:4][lsn:8][data_len:4][data]`
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, anyhow::Error> {
+        if bytes.len() < 24 {
+            bail!("response too short: {} bytes", bytes.len());
+        }
+        let tx_id = u64::from_be_bytes(bytes[0..8].try_into()?);
+        let status = i32::from_be_bytes(bytes[8..12].try_into()?);
+        let lsn = u64::from_be_bytes(bytes[12..20].try_into()?);
+        let data_len = u32::from_be_bytes(bytes[20..24].try_into()?) as usize;
+        if bytes.len() < 24 + data_len {
+            bail!("response data truncated: {} < {}", bytes.len() - 24, data_len);
+        }
+        Ok(Self {
+            tx_id,
+            status,
+            lsn,
+            data: bytes[24..24 + data_len].to_vec(),
+        })
+    }
+}
+
 // ==================== DBFS 客户端 ====================
 
 pub struct DbfsClient {
-    reader: BufReader<TcpStream>,
-    writer: BufWriter<TcpStream>,
+    reader: BufReader<UnixStream>,
+    writer: BufWriter<UnixStream>,
 }
 
 impl DbfsClient {
     /// 连接到 Alien 内核
-    pub fn connect(addr: &str) -> Result<Self, anyhow::Error> {
-        println!("🔌 Connecting to Alien kernel at {}", addr);
+    ///
+    /// `path` 为 QEMU `-chardev socket,path=...` 的路径。该通道只接受一个连接,
+    /// 所有并发任务需共享同一个客户端
+    pub fn connect(path: &str) -> Result<Self, anyhow::Error> {
+        println!("🔌 Connecting to Alien kernel at {}", path);
 
-        let stream = TcpStream::connect(addr)?;
+        let stream = UnixStream::connect(path)?;
         stream.set_read_timeout(Some(Duration::from_secs(5)))?;
 
         let reader = BufReader::new(stream.try_clone()?);
@@ -68,8 +118,10 @@ impl DbfsClient {
 
     /// 发送请求
     fn send_request(&mut self, req: &DbfsRequest) -> Result<(), anyhow::Error> {
-        // 序列化
-        let bytes = bincode::serialize(&req)?;
+        let bytes = req.serialize();
+        if bytes.len() > MAX_PACKET_SIZE {
+            bail!("request too large: {} bytes", bytes.len());
+        }
 
         // 发送长度前缀
         let len = bytes.len() as u32;
@@ -88,15 +140,15 @@ impl DbfsClient {
         let mut len_bytes = [0u8; 4];
         self.reader.read_exact(&mut len_bytes)?;
         let len = u32::from_be_bytes(len_bytes) as usize;
+        if len > MAX_PACKET_SIZE {
+            bail!("response too large: {} bytes", len);
+        }
 
         // 读取数据
         let mut data = vec![0u8; len];
         self.reader.read_exact(&mut data)?;
 
-        // 反序列化
-        let resp: DbfsResponse = bincode::deserialize(&data)?;
-
-        Ok(resp)
+        DbfsResponse::deserialize(&data)
     }
 
     /// 发送请求并接收响应
@@ -179,4 +231,40 @@ impl DbfsClient {
 
         self.call(req)
     }
-}
\ No newline at end of file
+
+    pub fn savepoint(&mut self, tx_id: u64, name: &str) -> Result<DbfsResponse, anyhow::Error> {
+        let req = DbfsRequest {
+            tx_id,
+            op_type: DbfsOpType::Savepoint,
+            path: name.to_string(),
+            offset: 0,
+            data: Vec::new(),
+        };
+
+        self.call(req)
+    }
+
+    pub fn rollback_to_savepoint(&mut self, tx_id: u64, name: &str) -> Result<DbfsResponse, anyhow::Error> {
+        let req = DbfsRequest {
+            tx_id,
+            op_type: DbfsOpType::RollbackToSavepoint,
+            path: name.to_string(),
+            offset: 0,
+            data: Vec::new(),
+        };
+
+        self.call(req)
+    }
+
+    pub fn release_savepoint(&mut self, tx_id: u64, name: &str) -> Result<DbfsResponse, anyhow::Error> {
+        let req = DbfsRequest {
+            tx_id,
+            op_type: DbfsOpType::ReleaseSavepoint,
+            path: name.to_string(),
+            offset: 0,
+            data: Vec::new(),
+        };
+
+        self.call(req)
+    }
+}
@@ -1,6 +1,6 @@
 //! Elle DBFS Client - 真正的 Elle + Jepsen 测试客户端
 //!
-//! 运行在 Host Linux 上,通过 socket 与 Alien 内核中的 DBFS 通信
+//! 运行在 Host Linux 上,通过 virtio-console 的 unix socket 与 Alien 内核中的 DBFS 通信
 
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -8,21 +8,25 @@ use serde::{Serialize, Deserialize};
 
 // 引入 socket 客户端
 mod dbfs_client;
-use dbfs_client::{DbfsClient, DbfsOpType, DbfsRequest, DbfsResponse};
+
+/// `make run ELLE=y` 时 QEMU 创建的 virtio-console socket
+const DEFAULT_SOCKET: &str = "/tmp/alien-elle.sock";
 
 // ==================== Async DBFS 客户端封装 ====================
 
+/// 多个任务共享的客户端, 请求在锁内逐个收发
+#[derive(Clone)]
 pub struct AsyncDbfsClient {
     client: Arc<Mutex<dbfs_client::DbfsClient>>,
     next_tx_id: Arc<AtomicU64>,
 }
 
 impl AsyncDbfsClient {
-    pub async fn new(addr: &str) -> anyhow::Result<Self> {
+    pub async fn new(path: &str) -> anyhow::Result<Self> {
         // 在单独的线程中运行阻塞的 socket 客户端
-        let addr = addr.to_string();
+        let path = path.to_string();
         let client = tokio::task::spawn_blocking(move || {
-            dbfs_client::DbfsClient::connect(&addr)
+            dbfs_client::DbfsClient::connect(&path)
         }).await??;
 
         Ok(Self {
@@ -162,11 +166,11 @@ impl ElleHistory {
 pub async fn run_elle_test(
     num_ops: usize,
     concurrency: usize,
-    addr: &str,
+    socket: &str,
 ) -> anyhow::Result<()> {
     println!("========================================");
     println!("Elle DBFS Test Starting");
-    println!("Target: {}", addr);
+    println!("Target: {}", socket);
     println!("Operations: {}", num_ops);
     println!("Concurrency: {}", concurrency);
     println!("========================================");
@@ -174,23 +178,17 @@ pub async fn run_elle_test(
     let history = Arc::new(ElleHistory::new());
     let mut tasks = Vec::new();
 
-    // 启动并发任务 - 每个任务独立的连接
+    // virtio-console 只有一条通道, 所有任务共享同一个连接
+    let client = AsyncDbfsClient::new(socket).await?;
+
+    // 启动并发任务
     for task_id in 0..concurrency {
         let history = history.clone();
-        let addr = addr.to_string();
+        let client = client.clone();
 
         let handle = tokio::spawn(async move {
             let ops_per_task = num_ops / concurrency;
 
-            // 每个任务创建独立的客户端连接
-            let client = match AsyncDbfsClient::new(&addr).await {
-                Ok(c) => c,
-                Err(e) => {
-                    eprintln!("Task {}: Failed to connect: {:?}", task_id, e);
-                    return Err(anyhow::anyhow!("Connection failed"));
-                }
-            };
-
             for i in 0..ops_per_task {
                 // 记录开始时间 (Unix timestamp in seconds)
                 let start = std::time::SystemTime::now()
@@ -318,15 +316,14 @@ async fn main() -> anyhow::Result<()> {
     println!("Elle DBFS Client v0.1.0");
     println!("Testing Alien Kernel DBFS with Elle framework");
 
-    // 连接地址 (通过 virtio-serial 或 TCP socket)
-    // 使用 localhost 作为测试地址
-    let addr = "127.0.0.1:12345";
+    // virtio-console 的 unix socket 路径, 可由第一个参数覆盖
+    let socket = std::env::args().nth(1).unwrap_or_else(|| DEFAULT_SOCKET.to_string());
 
-    println!("Connecting to Alien kernel at {}", addr);
+    println!("Connecting to Alien kernel at {}", socket);
 
     // 运行 Elle 测试
-    // 参数: 50000 个操作, 200 个并发客户端
-    run_elle_test(50000, 200, addr).await?;
+    // 参数: 50000 个操作, 200 个并发任务
+    run_elle_test(50000, 200, &socket).await?;
 
     Ok(())
 }
\ No newline at end of file
@@ -1,4 +1,8 @@
-use alloc::{sync::Arc, vec};
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+};
 use core::cmp::min;
 
 use constants::{
@@ -11,19 +15,39 @@ use constants::{
 use gmanager::ManagerError;
 use log::{info, warn};
 use syscall_table::syscall_func;
-use vfs::{eventfd::eventfd, kfile::KernelFile, system_root_fs};
+use vfs::{
+    eventfd::eventfd,
+    inotify::{self, InotifyEntry},
+    kfile::KernelFile,
+    pagecache,
+};
 use vfscore::{
-    path::VfsPath,
+    dentry::VfsDentry,
     utils::{VfsFileStat, VfsFsStat, VfsNodeType, VfsRenameFlag},
 };
 
 use super::im2vim;
 use crate::{
-    fs::{syscontext_for_vfs, user_path_at},
-    task::current_task,
+    fs::{check_writable_at, check_writable_target, syscontext_for_vfs, user_path_at, writeback},
+    task::{current_task, tasks},
 };
 
 /// 用于将一个设备(通常是存储设备)挂载到一个已经存在的目录上，可以挂载文件系统。
+///
+/// `fs_type` 可以是任何已注册的文件系统。`source` 为路径时 (包含 `/`) 被解析为块设备或文件,
+/// 交给文件系统作为设备; 否则 (`none`、`tmpfs` 等) 文件系统没有设备。`data` 原样交给文件系统。
+///
+/// 支持的标志:
+/// + `MS_RDONLY`、`MS_NOSUID`、`MS_NODEV`、`MS_NOEXEC`: 记录为挂载的标志, 显示在 `/proc/mounts` 中。
+///   通过只读挂载的写入、创建、删除和修改属性返回 EROFS, 带有 `MS_NOEXEC` 的挂载上的文件不能执行
+/// + `MS_REMOUNT`: 修改 `dir` 上已有挂载的标志
+/// + `MS_BIND`: 把目录 `source` 绑定挂载到 `dir`, 同时带有 `MS_REC` 时 `source` 之下的挂载也被绑定。
+///   例如先绑定 `/data`, 再以 `MS_REMOUNT | MS_BIND | MS_RDONLY` 重新挂载, 得到 `/data` 的只读视图
+/// + `MS_SHARED`、`MS_PRIVATE`、`MS_SLAVE`、`MS_UNBINDABLE`: 所有挂载都是私有的, 直接成功
+///
+/// 挂载只出现在调用者所在的挂载命名空间中。
+///
+/// Reference: [mount](https://man7.org/linux/man-pages/man2/mount.2.html)
 #[syscall_func(40)]
 pub fn sys_mount(
     source: *const u8,
@@ -33,32 +57,45 @@ pub fn sys_mount(
     data: *const u8,
 ) -> AlienResult<isize> {
     let task = current_task().unwrap();
-    let source = task.transfer_str(source);
-    let dir = task.transfer_str(dir);
-    let fs_type = task.transfer_str(fs_type);
-    assert!(data.is_null());
-    let flags = MountFlags::from_bits(flags as u32).unwrap();
+    let read_str = |ptr: *const u8| {
+        if ptr.is_null() {
+            String::new()
+        } else {
+            task.transfer_str(ptr)
+        }
+    };
+    let source = read_str(source);
+    let dir = read_str(dir);
+    let fs_type = read_str(fs_type);
+    let data = read_str(data);
+    let flags = MountFlags::from_bits_truncate(flags as u32);
     info!(
         "mount special:{:?},dir:{:?},fs_type:{:?},flags:{:?},data:{:?}",
-        source, dir, fs_type, flags, data
+        source,
+        dir,
+        fs_type,
+        flags,
+        vfs::mount::redact_options(&data)
     );
-    let find = vfs::system_support_fs(&fs_type).ok_or(LinuxErrno::EINVAL)?;
-    let path = VfsPath::new(vfs::system_root_fs(), system_root_fs());
-    let fs_root = match find.fs_name().as_str() {
-        name @ ("tmpfs" | "ramfs" | "fat32") => {
-            let fs = vfs::system_support_fs(name).unwrap();
-            let dev = if name.eq("fat32") {
-                let dev = path.join(source)?.open(None)?;
-                Some(dev.inode()?)
-            } else {
-                None
-            };
-            let new_fs = fs.i_mount(0, &dir, dev, &[])?;
-            new_fs
-        }
-        _ => return Err(LinuxErrno::EINVAL),
-    };
-    path.join(dir)?.mount(fs_root, flags.bits())?;
+    if vfs::mount::propagation_only(flags) {
+        return Ok(0);
+    }
+    let mnt_ns = task.access_inner().fs_info.mnt_ns.clone();
+    let target = user_path_at(AT_FDCWD, &dir)?;
+    if flags.contains(MountFlags::MS_REMOUNT) {
+        let mount_root = target.open(None)?;
+        mnt_ns.remount(&mount_root, flags, &data)?;
+    } else if flags.contains(MountFlags::MS_BIND) {
+        let source = user_path_at(AT_FDCWD, &source)?.open(None)?;
+        mnt_ns.bind(&source, &target, flags)?;
+    } else {
+        let dev = if source.contains('/') {
+            Some(user_path_at(AT_FDCWD, &source)?.open(None)?)
+        } else {
+            None
+        };
+        mnt_ns.mount(&source, dev, &target, &fs_type, flags, &data)?;
+    }
     Ok(0)
 }
 
@@ -68,11 +105,62 @@ pub fn sys_umount(dir: *const u8) -> AlienResult<isize> {
     let process = current_task().unwrap();
     let dir = process.transfer_str(dir);
     info!("umount dir:{:?}", dir);
-    let path = VfsPath::new(vfs::system_root_fs(), system_root_fs());
-    path.join(dir)?.umount()?;
+    let mnt_ns = process.access_inner().fs_info.mnt_ns.clone();
+    let path = user_path_at(AT_FDCWD, &dir)?;
+    mnt_ns.umount(&path)?;
+    Ok(0)
+}
+
+/// 一个系统调用，用于把调用者所在挂载命名空间的根目录换成 `new_root`，原来的根目录移到 `put_old`。
+///
+/// `new_root` 需要是一个挂载点，`put_old` 需要是 `new_root` 之下的目录，调用者的根目录需要是命名空间的根目录。
+/// 命名空间中所有进程的根目录和当前工作目录随之移动: 原来在 `new_root` 之下的路径去掉 `new_root` 的前缀，
+/// 其余路径移到 `put_old` 之下。通常之后 `chdir("/")` 并卸载 `put_old`。
+///
+/// Reference: [pivot_root](https://man7.org/linux/man-pages/man2/pivot_root.2.html)
+#[syscall_func(41)]
+pub fn sys_pivot_root(new_root: *const u8, put_old: *const u8) -> AlienResult<isize> {
+    let task = current_task().unwrap();
+    let new_root = task.transfer_str(new_root);
+    let put_old = task.transfer_str(put_old);
+    info!("pivot_root new_root:{:?}, put_old:{:?}", new_root, put_old);
+    let fs_info = task.access_inner().fs_info.clone();
+    if !same_dentry(&fs_info.root, &fs_info.mnt_ns.root()) {
+        return Err(LinuxErrno::EINVAL);
+    }
+    let new_root = user_path_at(AT_FDCWD, &new_root)?.open(None)?;
+    let put_old = user_path_at(AT_FDCWD, &put_old)?.open(None)?;
+    for dir in [&new_root, &put_old] {
+        if dir.inode()?.inode_type() != VfsNodeType::Dir {
+            return Err(LinuxErrno::ENOTDIR);
+        }
+    }
+    let mnt_ns = fs_info.mnt_ns;
+    let pivot = mnt_ns.pivot_root(&new_root, &put_old)?;
+    for other in tasks() {
+        let fs_info = other.access_inner().fs_info.clone();
+        if !Arc::ptr_eq(&fs_info.mnt_ns, &mnt_ns) {
+            continue;
+        }
+        // 已经不存在的目录换成新的根目录
+        let lookup = |path: &str| {
+            mnt_ns
+                .lookup(&pivot.path(path))
+                .unwrap_or_else(|_| mnt_ns.root())
+        };
+        let root = lookup(&fs_info.root.path());
+        let cwd = lookup(&fs_info.cwd.path());
+        let mut inner = other.access_inner();
+        inner.fs_info.root = root;
+        inner.fs_info.cwd = cwd;
+    }
     Ok(0)
 }
 
+fn same_dentry(a: &Arc<dyn VfsDentry>, b: &Arc<dyn VfsDentry>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
 #[syscall_func(56)]
 pub fn sys_openat(dirfd: isize, path: *const u8, flag: usize, mode: u32) -> AlienResult<isize> {
     if path.is_null() {
@@ -96,7 +184,17 @@ pub fn sys_openat(dirfd: isize, path: *const u8, flag: usize, mode: u32) -> Alie
         file_mode
     );
 
+    let exists = path.open(None).is_ok();
+    let writes = flag.intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR | OpenFlags::O_TRUNC);
+    let created = flag.contains(OpenFlags::O_CREAT) && !exists;
+    if writes || created {
+        check_writable_target(dirfd, &path_str, flag)?;
+    }
+    let created = created && inotify::watching();
     let dentry = path.open(file_mode)?;
+    if created {
+        inotify::notify_create(&dentry);
+    }
     let file = KernelFile::new(dentry, flag);
 
     let fd = process.add_file(Arc::new(file));
@@ -173,8 +271,18 @@ pub fn sys_getdents(fd: usize, buf: *mut u8, len: usize) -> AlienResult<isize> {
 pub fn sys_truncate(path: usize, len: usize) -> AlienResult<isize> {
     let process = current_task().unwrap();
     let path = process.transfer_str(path as *const u8);
+    check_writable_target(AT_FDCWD, &path, OpenFlags::empty())?;
     let path = user_path_at(AT_FDCWD, &path)?;
     path.truncate(len as u64)?;
+    let Ok(dentry) = path.open(None) else {
+        return Ok(0);
+    };
+    if let Ok(inode) = dentry.inode() {
+        pagecache::truncated(&inode, len as u64);
+    }
+    if inotify::watching() {
+        inotify::notify_modify(&dentry);
+    }
     Ok(0)
 }
 
@@ -256,7 +364,7 @@ pub fn sys_getcwd(buf: *mut u8, len: usize) -> isize {
 
     let mut buf = task.transfer_buffer(buf, len);
     let mut count = 0;
-    let path = cwd.cwd.path();
+    let path = cwd.cwd_path();
     let mut cwd = path.as_bytes();
     buf.iter_mut().for_each(|buf| {
         // fill buf
@@ -307,6 +415,24 @@ pub fn sys_fchdir(fd: usize) -> AlienResult<isize> {
     Ok(0)
 }
 
+/// 一个系统调用，用于把调用者的根目录换成 `path`，之后的绝对路径从 `path` 开始解析。
+///
+/// 当前工作目录不变，`path` 不是目录时返回 ENOTDIR。
+///
+/// Reference: [chroot](https://man7.org/linux/man-pages/man2/chroot.2.html)
+#[syscall_func(51)]
+pub fn sys_chroot(path: *const u8) -> AlienResult<isize> {
+    let process = current_task().unwrap();
+    let path = process.transfer_str(path);
+    let dt = user_path_at(AT_FDCWD, &path)?.open(None)?;
+    if dt.inode()?.inode_type() != VfsNodeType::Dir {
+        return Err(LinuxErrno::ENOTDIR);
+    }
+    info!("chroot: {:?}", dt.path());
+    process.access_inner().fs_info.root = dt;
+    Ok(0)
+}
+
 /// 一个系统调用，用于在指定路径下创建一个空的目录。
 /// 
 /// 成功创建目录则返回 0；否则返回错误码。
@@ -329,10 +455,12 @@ pub fn sys_mkdirat(dirfd: isize, path: *const u8, mode: u32) -> AlienResult<isiz
     let path = process.transfer_str(path);
     let mut mode = InodeMode::from_bits_truncate(mode);
     warn!("mkdirat path: {}, mode: {:?}", path, mode);
+    check_writable_at(dirfd, &path)?;
     let path = user_path_at(dirfd, &path)?;
     mode |= InodeMode::DIR;
     assert_eq!(mode & InodeMode::TYPE_MASK, InodeMode::DIR);
-    let _ = path.open(Some(im2vim(mode)))?;
+    let dentry = path.open(Some(im2vim(mode)))?;
+    inotify::notify_create(&dentry);
     Ok(0)
 }
 
@@ -577,6 +705,9 @@ pub fn sys_renameat(
         "renameat2: {:?} {:?} {:?} {:?}",
         old_dirfd, old_path, new_dirfd, new_path
     );
+    check_writable_at(old_dirfd, &old_path)?;
+    check_writable_at(new_dirfd, &new_path)?;
+    let events = RenameEvents::capture(old_dirfd, &old_path, new_dirfd, &new_path);
     let old_path = user_path_at(old_dirfd, &old_path)?;
     let new_path = user_path_at(new_dirfd, &new_path)?;
     old_path.rename_to(
@@ -584,6 +715,9 @@ pub fn sys_renameat(
         new_path,
         VfsRenameFlag::empty(),
     )?;
+    if let Some(events) = events {
+        events.notify(false);
+    }
     Ok(0)
 }
 
@@ -608,6 +742,9 @@ pub fn sys_renameat2(
         "renameat2: {:?} {:?} {:?} {:?}, flag: {:?}",
         old_dirfd, old_path, new_dirfd, new_path, flag
     );
+    check_writable_at(old_dirfd, &old_path)?;
+    check_writable_at(new_dirfd, &new_path)?;
+    let events = RenameEvents::capture(old_dirfd, &old_path, new_dirfd, &new_path);
     let old_path = user_path_at(old_dirfd, &old_path)?;
     let new_path = user_path_at(new_dirfd, &new_path)?;
 
@@ -623,9 +760,65 @@ pub fn sys_renameat2(
         new_path,
         VfsRenameFlag::from_bits_truncate(flag.bits()),
     )?;
+    if let Some(events) = events {
+        events.notify(flag.contains(Renameat2Flags::RENAME_EXCHANGE));
+    }
     Ok(0)
 }
 
+/// 重命名前记录的源和 (可能被覆盖的) 目标, 重命名成功后用于生成 inotify 事件
+struct RenameEvents {
+    from: InotifyEntry,
+    replaced: Option<InotifyEntry>,
+    old: (isize, String),
+    new: (isize, String),
+}
+
+impl RenameEvents {
+    fn capture(
+        old_dirfd: isize,
+        old_path: &str,
+        new_dirfd: isize,
+        new_path: &str,
+    ) -> Option<Self> {
+        if !inotify::watching() {
+            return None;
+        }
+        Some(Self {
+            from: Self::entry(old_dirfd, old_path)?,
+            replaced: Self::entry(new_dirfd, new_path),
+            old: (old_dirfd, old_path.to_string()),
+            new: (new_dirfd, new_path.to_string()),
+        })
+    }
+
+    fn entry(dirfd: isize, path: &str) -> Option<InotifyEntry> {
+        let dentry = Self::open(dirfd, path)?;
+        InotifyEntry::new(&dentry)
+    }
+
+    fn open(dirfd: isize, path: &str) -> Option<Arc<dyn VfsDentry>> {
+        let path = user_path_at(dirfd, path).ok()?;
+        path.open2(None, OpenFlags::O_NOFOLLOW).ok()
+    }
+
+    /// `IN_MOVED_FROM` / `IN_MOVED_TO` 等事件, `RENAME_EXCHANGE` 时两个文件互相移动
+    fn notify(self, exchange: bool) {
+        let Some(moved) = Self::open(self.new.0, &self.new.1) else {
+            return;
+        };
+        if !exchange {
+            inotify::notify_move(&self.from, &moved, self.replaced.as_ref());
+            return;
+        }
+        inotify::notify_move(&self.from, &moved, None);
+        let exchanged = Self::open(self.old.0, &self.old.1);
+        if let (Some(replaced), Some(exchanged)) = (self.replaced, exchanged) {
+            inotify::notify_move(&replaced, &exchanged, None);
+        }
+    }
+}
+
 /// 一个系统调用，用于在文件描述符之间传递数据。
 ///
 /// 从 `in_fd` 读取最多 `count` 个字符，存到 `out_fd` 中。
@@ -671,18 +864,31 @@ pub fn send_file(
 }
 
 /// 一个系统调用函数，用于包把含更新文件的所有内核缓冲区(包含数据块、指针块、元数据等)都flush到磁盘上。
+///
+/// 写回出错时只记录日志, `sync` 没有错误返回值。
 #[syscall_func(81)]
 pub fn sync() -> isize {
+    if let Err(e) = writeback::sync_all() {
+        warn!("sync failed: {:?}", e);
+    }
     0
 }
 
 /// 用于把打开的文件描述符fd相关的所有缓冲元数据和数据都刷新到磁盘上。
+///
+/// 依次写回文件的页缓存、文件系统的缓存和块设备缓存, 返回时数据已经落盘;
+/// 写回失败时返回 `EIO`。
 #[syscall_func(82)]
 pub fn fsync(fd: usize) -> AlienResult<isize> {
     let task = current_task().unwrap();
     let file = task.get_file(fd).ok_or(LinuxErrno::EBADF)?;
-    let fs = file.inode().get_super_block()?;
+    let inode = file.inode();
+    if let Some(cache) = pagecache::find(&inode) {
+        cache.sync(0, usize::MAX)?;
+    }
+    let fs = inode.get_super_block()?;
     fs.sync_fs(false)?;
+    devices::sync_block_device()?;
     Ok(0)
 }
 
@@ -705,6 +911,43 @@ pub fn copy_file_range(
     if !(in_file.is_readable() && out_file.is_writable()) {
         return Err(LinuxErrno::EBADF);
     }
+    // 只支持文件系统中的普通文件, 管道等文件没有 inode
+    if !(in_file.is::<KernelFile>() && out_file.is::<KernelFile>()) {
+        return Err(LinuxErrno::EINVAL);
+    }
+    let (in_inode, out_inode) = (in_file.inode(), out_file.inode());
+    match (in_inode.inode_type(), out_inode.inode_type()) {
+        (VfsNodeType::File, VfsNodeType::File) => {}
+        (VfsNodeType::Dir, _) | (_, VfsNodeType::Dir) => return Err(LinuxErrno::EISDIR),
+        _ => return Err(LinuxErrno::EINVAL),
+    }
+    // 两个文件在同一个支持 reflink 的文件系统上时直接克隆, 不复制数据
+    let off_in = match off_in_ptr {
+        0 => in_file.seek(SeekFrom::Current(0))?,
+        ptr => *task.transfer_raw_ptr(ptr as *mut u64),
+    };
+    let off_out = match off_out_ptr {
+        0 => out_file.seek(SeekFrom::Current(0))?,
+        ptr => *task.transfer_raw_ptr(ptr as *mut u64),
+    };
+    match vfs::clone_file_range(&in_inode, off_in, len as u64, &out_inode, off_out) {
+        Ok(cloned) => {
+            info!("sys_copy_file_range: cloned {} bytes", cloned);
+            if off_in_ptr == 0 {
+                in_file.seek(SeekFrom::Current(cloned as i64))?;
+            } else {
+                *task.transfer_raw_ptr(off_in_ptr as *mut u64) += cloned as u64;
+            }
+            if off_out_ptr == 0 {
+                out_file.seek(SeekFrom::Current(cloned as i64))?;
+            } else {
+                *task.transfer_raw_ptr(off_out_ptr as *mut u64) += cloned as u64;
+            }
+            return Ok(cloned as _);
+        }
+        Err(LinuxErrno::EXDEV | LinuxErrno::EOPNOTSUPP) => {}
+        Err(e) => return Err(e),
+    }
     let mut buf = vec![0u8; len];
     let r = if off_in_ptr == 0 {
         in_file.read(&mut buf)?
@@ -1,3 +1,5 @@
+use alloc::sync::Arc;
+
 use constants::{
     io::{FaccessatFlags, FaccessatMode, Fcntl64Cmd, OpenFlags},
     time::TimeSpec,
@@ -6,6 +8,14 @@ use constants::{
 use log::{info, warn};
 use syscall_table::syscall_func;
 use timer::{TimeNow, ToVfsTimeSpec};
+use vfs::{
+    dev::loopdev::{
+        loop_device, LoopDevice, LoopInfo, LoopInfo64, LOOP_CLR_FD, LOOP_GET_STATUS,
+        LOOP_GET_STATUS64, LOOP_SET_FD,
+    },
+    inotify,
+    kfile::{File, KernelFile},
+};
 use vfscore::utils::*;
 
 use crate::{fs::user_path_at, task::current_task};
@@ -86,16 +96,129 @@ pub fn fcntl(fd: usize, cmd: usize, arg: usize) -> AlienResult<isize> {
 ///
 /// 根据不同的 ioctl 命令，将有不同的返回值。
 ///
+/// `FICLONE` 和 `FICLONERANGE` 由这里处理: 把另一个文件 (或其中一段) 克隆到 `fd`,
+/// 两个文件需要在同一个支持 reflink 的文件系统上 (目前只有 DBFS), 否则返回
+/// `EXDEV` 或 `EOPNOTSUPP`; `FICLONERANGE` 的偏移和长度需要按文件系统的块大小对齐。
+///
+/// loop 设备的 `LOOP_SET_FD`、`LOOP_CLR_FD`、`LOOP_GET_STATUS` 和 `LOOP_GET_STATUS64`
+/// 也由这里处理, 见 [`vfs::dev::loopdev`]。
+///
 /// Reference: [ioctl](https:///man7.org/linux/man-pages/man2/ioctl.2.html)
 #[syscall_func(29)]
 pub fn ioctl(fd: usize, cmd: usize, arg: usize) -> AlienResult<isize> {
     let process = current_task().unwrap();
     let file = process.get_file(fd).ok_or(LinuxErrno::EBADF)?;
     // log::info!("ioctl: {:?} {:?} {:?}", fd, cmd, arg);
+    match cmd as u32 {
+        FICLONE => {
+            let src = process.get_file(arg).ok_or(LinuxErrno::EBADF)?;
+            return clone_range(&src, 0, u64::MAX, &file, 0);
+        }
+        FICLONERANGE => {
+            let range = *process.transfer_raw_ptr(arg as *mut FileCloneRange);
+            let src = process
+                .get_file(range.src_fd as usize)
+                .ok_or(LinuxErrno::EBADF)?;
+            // 长度为 0 表示到源文件末尾
+            let len = if range.src_length == 0 {
+                u64::MAX
+            } else {
+                range.src_length
+            };
+            return clone_range(&src, range.src_offset, len, &file, range.dest_offset);
+        }
+        LOOP_SET_FD | LOOP_CLR_FD | LOOP_GET_STATUS | LOOP_GET_STATUS64 => {
+            if let Some(device) = loop_file(&file) {
+                return loop_ioctl(&device, cmd as u32, arg);
+            }
+        }
+        _ => {}
+    }
     let res = file.ioctl(cmd as u32, arg)?;
     Ok(res as isize)
 }
 
+/// ioctl: 把参数 fd 指向的整个文件克隆到目标文件
+const FICLONE: u32 = 0x40049409;
+/// ioctl: 按 [`FileCloneRange`] 克隆一段
+const FICLONERANGE: u32 = 0x4020940d;
+
+/// `struct file_clone_range`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FileCloneRange {
+    src_fd: i64,
+    src_offset: u64,
+    src_length: u64,
+    dest_offset: u64,
+}
+
+fn clone_range(
+    src: &Arc<dyn File>,
+    src_offset: u64,
+    len: u64,
+    dst: &Arc<dyn File>,
+    dst_offset: u64,
+) -> AlienResult<isize> {
+    if !(src.is_readable() && dst.is_writable()) {
+        return Err(LinuxErrno::EBADF);
+    }
+    // 管道等文件没有 inode, 只能克隆文件系统中的普通文件
+    if !(src.is::<KernelFile>() && dst.is::<KernelFile>()) {
+        return Err(LinuxErrno::EINVAL);
+    }
+    let (src_inode, dst_inode) = (src.inode(), dst.inode());
+    match (src_inode.inode_type(), dst_inode.inode_type()) {
+        (VfsNodeType::File, VfsNodeType::File) => {}
+        (VfsNodeType::Dir, _) | (_, VfsNodeType::Dir) => return Err(LinuxErrno::EISDIR),
+        _ => return Err(LinuxErrno::EINVAL),
+    }
+    // 偏移需要按块对齐, 长度也一样, 除非一直克隆到源文件末尾
+    let block = dst_inode.get_attr()?.st_blksize.max(1) as u64;
+    let to_eof = src_offset.saturating_add(len) >= src_inode.get_attr()?.st_size;
+    if src_offset % block != 0 || dst_offset % block != 0 || (!to_eof && len % block != 0) {
+        return Err(LinuxErrno::EINVAL);
+    }
+    let cloned = vfs::clone_file_range(&src_inode, src_offset, len, &dst_inode, dst_offset)?;
+    info!("ioctl: cloned {} bytes", cloned);
+    Ok(0)
+}
+
+/// 打开的 `/dev/loopN` 对应的 loop 设备, 其它文件为 None, 这些 ioctl 交给文件自己处理
+fn loop_file(file: &Arc<dyn File>) -> Option<Arc<LoopDevice>> {
+    if !file.is::<KernelFile>() {
+        return None;
+    }
+    loop_device(&file.dentry().inode().ok()?)
+}
+
+/// `/dev/loopN` 的 ioctl: `LOOP_SET_FD` 的参数是文件描述符, 所以在这里处理
+fn loop_ioctl(device: &LoopDevice, cmd: u32, arg: usize) -> AlienResult<isize> {
+    let process = current_task().unwrap();
+    match cmd {
+        LOOP_SET_FD => {
+            let backing = process.get_file(arg).ok_or(LinuxErrno::EBADF)?;
+            // 管道、套接字等没有目录项, 不能作为后备文件
+            if !backing.is::<KernelFile>() {
+                return Err(LinuxErrno::EBADF);
+            }
+            let dentry = backing.dentry();
+            device.attach(dentry.inode()?, &dentry.path(), !backing.is_writable())?;
+        }
+        LOOP_CLR_FD => device.detach()?,
+        LOOP_GET_STATUS => {
+            let info = LoopInfo::from_info64(&device.status()?);
+            process.access_inner().copy_to_user(&info, arg as *mut LoopInfo);
+        }
+        LOOP_GET_STATUS64 => {
+            let info = device.status()?;
+            process.access_inner().copy_to_user(&info, arg as *mut LoopInfo64);
+        }
+        _ => return Err(LinuxErrno::ENOTTY),
+    }
+    Ok(0)
+}
+
 const UTIME_NOW: usize = 0x3fffffff;
 /// ignore
 #[allow(dead_code)]
@@ -132,6 +255,7 @@ pub fn utimensat(
         let dt = path.open(None)?;
         dt
     };
+    vfs::mount::check_writable(&dt)?;
 
     let mut inner = task.access_inner();
     if times.is_null() {
@@ -188,6 +312,8 @@ pub fn utimensat(
             )?;
         };
     };
+    drop(inner);
+    inotify::notify_attrib(&dt);
     Ok(0)
 }
 
@@ -1,11 +1,14 @@
 use core::cmp::min;
 
-use constants::{AlienResult, LinuxErrno, AT_FDCWD};
+use constants::{io::OpenFlags, AlienResult, LinuxErrno, AT_FDCWD};
 use syscall_table::syscall_func;
-use vfs::system_root_fs;
+use vfs::{inotify, system_root_fs};
 use vfscore::path::VfsPath;
 
-use crate::{fs::user_path_at, task::current_task};
+use crate::{
+    fs::{check_writable_target, user_path_at},
+    task::current_task,
+};
 
 /// 一个系统调用，用于设置文件的 扩展属性(xattrs, Extended Attributes)。
 ///
@@ -36,8 +39,14 @@ pub fn sys_setxattr(
     let path = process.transfer_str(path);
     let name = process.transfer_str(name);
     let value = process.transfer_buffer(value, size);
+    check_writable_target(AT_FDCWD, &path, OpenFlags::empty())?;
     let path = user_path_at(AT_FDCWD, &path)?;
     path.set_xattr(&name, value[0])?;
+    if inotify::watching() {
+        if let Ok(dentry) = path.open(None) {
+            inotify::notify_attrib(&dentry);
+        }
+    }
     Ok(0)
 }
 
@@ -74,8 +83,10 @@ pub fn sys_fsetxattr(
     let name = process.transfer_str(name);
     let value = process.transfer_buffer(value, size);
     let file = process.get_file(fd).ok_or(LinuxErrno::EBADF)?;
+    vfs::mount::check_writable(&file.dentry())?;
     let path = VfsPath::new(system_root_fs(), file.dentry());
     path.set_xattr(&name, value[0])?;
+    inotify::notify_attrib(&file.dentry());
     Ok(0)
 }
 
@@ -0,0 +1,66 @@
+//! inotify 相关的系统调用
+//!
+//! 监视和事件队列由 [`vfs::inotify`] 实现, 文件系统的修改由各个文件相关的系统调用
+//! 和 `KernelFile` 报告。inotify 文件描述符可以被 `ppoll`、`pselect6` 和 epoll 等待。
+use constants::{io::OpenFlags, AlienResult, LinuxErrno, AT_FDCWD};
+use log::info;
+use syscall_table::syscall_func;
+use vfs::inotify::{inotify, InotifyFile, InotifyMask};
+
+use crate::{fs::user_path_at, task::current_task};
+
+/// 一个系统调用，用于创建一个 inotify 实例，返回指向它的文件描述符。
+///
+/// `flags` 可以包含 `IN_NONBLOCK` 和 `IN_CLOEXEC`。
+///
+/// Reference: [inotify_init1](https://man7.org/linux/man-pages/man2/inotify_init1.2.html)
+#[syscall_func(26)]
+pub fn sys_inotify_init1(flags: u32) -> AlienResult<isize> {
+    let file = inotify(flags)?;
+    let task = current_task().unwrap();
+    let fd = task.add_file(file).map_err(|_| LinuxErrno::EMFILE)?;
+    Ok(fd as isize)
+}
+
+/// 一个系统调用，用于在 `fd` 指向的 inotify 实例中监视 `path` 指向的文件或目录。
+///
+/// `path` 相对于当前工作目录解析，`mask` 中包含 `IN_DONT_FOLLOW` 时不跟随最后的软链接。
+/// 同一个文件被再次监视时返回相同的监视描述符。
+///
+/// 成功时返回监视描述符；否则返回错误码。
+///
+/// Reference: [inotify_add_watch](https://man7.org/linux/man-pages/man2/inotify_add_watch.2.html)
+#[syscall_func(27)]
+pub fn sys_inotify_add_watch(fd: usize, path: *const u8, mask: u32) -> AlienResult<isize> {
+    let task = current_task().unwrap();
+    let file = task.get_file(fd).ok_or(LinuxErrno::EBADF)?;
+    let file = file
+        .downcast_arc::<InotifyFile>()
+        .map_err(|_| LinuxErrno::EINVAL)?;
+    let path = task.transfer_str(path);
+    info!("inotify_add_watch: fd: {}, path: {}, mask: {:#x}", fd, path, mask);
+    let path = user_path_at(AT_FDCWD, &path)?;
+    let dentry = if InotifyMask::from_bits_truncate(mask).contains(InotifyMask::IN_DONT_FOLLOW) {
+        path.open2(None, OpenFlags::O_NOFOLLOW)?
+    } else {
+        path.open(None)?
+    };
+    let wd = file.add_watch(dentry.inode()?, mask)?;
+    Ok(wd as isize)
+}
+
+/// 一个系统调用，用于移除 `fd` 指向的 inotify 实例中的监视 `wd`。
+///
+/// 移除后实例中会收到该监视的 `IN_IGNORED` 事件。
+///
+/// Reference: [inotify_rm_watch](https://man7.org/linux/man-pages/man2/inotify_rm_watch.2.html)
+#[syscall_func(28)]
+pub fn sys_inotify_rm_watch(fd: usize, wd: i32) -> AlienResult<isize> {
+    let task = current_task().unwrap();
+    let file = task.get_file(fd).ok_or(LinuxErrno::EBADF)?;
+    let file = file
+        .downcast_arc::<InotifyFile>()
+        .map_err(|_| LinuxErrno::EINVAL)?;
+    file.rm_watch(wd)?;
+    Ok(0)
+}
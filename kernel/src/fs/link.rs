@@ -6,8 +6,12 @@ use constants::{
 };
 use log::{info, warn};
 use syscall_table::syscall_func;
+use vfs::inotify::{self, InotifyEntry};
 
-use crate::{fs::user_path_at, task::current_task};
+use crate::{
+    fs::{check_writable_at, user_path_at},
+    task::current_task,
+};
 /// 一个系统调用，用于创建相对于一个目录某位置处的一个文件的(硬)链接。
 ///
 /// 当传入的 `old_name` 是一个相对地址时，那么 `old_name` 会被解析成基于文件描述符 `old_fd`
@@ -41,6 +45,7 @@ pub fn sys_linkat(
     let old_name = process.transfer_str(old_name);
     let old_path = user_path_at(old_fd, &old_name)?;
     let new_name = process.transfer_str(new_name);
+    check_writable_at(new_fd, &new_name)?;
     let new_path = user_path_at(new_fd, &new_name)?;
 
     warn!(
@@ -51,9 +56,23 @@ pub fn sys_linkat(
     let old_dt = old_path.open(None)?;
 
     new_path.link(old_dt)?;
+    notify_created(new_fd, &new_name);
     Ok(0)
 }
 
+/// 链接已经建立后生成 `IN_CREATE` 事件, 找不到新链接时不报告, 也不影响系统调用的结果
+fn notify_created(fd: isize, name: &str) {
+    if !inotify::watching() {
+        return;
+    }
+    let dentry = user_path_at(fd, name)
+        .ok()
+        .and_then(|path| path.open2(None, OpenFlags::O_NOFOLLOW).ok());
+    if let Some(dentry) = dentry {
+        inotify::notify_create(&dentry);
+    }
+}
+
 /// 一个系统调用，用于删除相对于一个目录某位置处的一个文件的链接。
 ///
 /// `unlinkat`执行的操作将根据`flag`参数是否设置为`AT_REMOVEDIR`而执行`unlink`或`rmdir`操作。
@@ -88,12 +107,22 @@ pub fn sys_unlinkat(fd: isize, path: *const u8, flag: usize) -> AlienResult<isiz
     let path = task.transfer_str(path);
     let flag = UnlinkatFlags::from_bits_truncate(flag as u32);
     info!("unlinkat path: {:?}, flag: {:?}", path, flag);
+    check_writable_at(fd, &path)?;
     let path = user_path_at(fd, &path)?;
+    let entry = if inotify::watching() {
+        let dentry = path.open2(None, OpenFlags::O_NOFOLLOW)?;
+        InotifyEntry::new(&dentry)
+    } else {
+        None
+    };
     if flag.contains(UnlinkatFlags::AT_REMOVEDIR) {
         path.rmdir()?;
     } else {
         path.unlink()?;
     }
+    if let Some(entry) = entry {
+        inotify::notify_delete(&entry);
+    }
     Ok(0)
 }
 
@@ -111,8 +140,10 @@ pub fn sys_symlinkat(
     let process = current_task().unwrap();
     let old_name = process.transfer_str(old_name);
     let new_name = process.transfer_str(new_name);
+    check_writable_at(new_fd, &new_name)?;
     let new_path = user_path_at(new_fd, &new_name)?;
     new_path.symlink(&old_name)?;
+    notify_created(new_fd, &new_name);
     Ok(0)
 }
 
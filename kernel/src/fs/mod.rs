@@ -1,14 +1,19 @@
 pub mod basic;
 pub mod control;
 pub mod ext;
+pub mod inotify;
 pub mod link;
 pub mod poll;
 pub mod select;
 pub mod stdio;
+pub mod writeback;
 
 use alloc::vec::Vec;
 
-use constants::{io::InodeMode, AlienResult, LinuxErrno, AT_FDCWD};
+use constants::{
+    io::{InodeMode, MountFlags, OpenFlags},
+    AlienResult, LinuxErrno, AT_FDCWD,
+};
 use log::info;
 use vfs::system_root_fs;
 use vfscore::{
@@ -26,20 +31,23 @@ use crate::task::{current_task, FsContext};
 /// 在`Alien`使用的`rvfs`中，对一个文件路径`path`是相对路径还是绝对路径的的判断条件如下：
 /// + 绝对路径：以`/`开头，如`/file1.txt`，表示根目录下的`file1.txt`文件；
 /// + 相对路径: 以`./`或者`../`或者其它开头，如`./file1.txt`，表示`dirfd`所指向的目录下的`file1.txt`文件。
+///
+/// 绝对路径从进程的根目录 (`chroot` 设置的目录, 在进程所在的挂载命名空间中) 开始解析。
 fn user_path_at(fd: isize, path: &str) -> AlienResult<VfsPath> {
     info!("user_path_at fd: {},path:{}", fd, path);
     let process = current_task().unwrap();
+    let fs_context = process.access_inner().fs_info.clone();
+    let root = fs_context.root;
     let res = if !path.starts_with("/") {
         if fd == AT_FDCWD {
-            let fs_context = process.access_inner().fs_info.clone();
-            VfsPath::new(system_root_fs(), fs_context.cwd).join(path)
+            VfsPath::new(root, fs_context.cwd).join(path)
         } else {
             let fd = fd as usize;
             let file = process.get_file(fd).ok_or(LinuxErrno::EBADF)?;
-            VfsPath::new(system_root_fs(), file.dentry()).join(path)
+            VfsPath::new(root, file.dentry()).join(path)
         }
     } else {
-        VfsPath::new(system_root_fs(), system_root_fs()).join(path)
+        VfsPath::new(root.clone(), root).join(path)
     };
     res.map_err(|e| e.into())
 }
@@ -68,7 +76,7 @@ pub fn read_all(file_name: &str, buf: &mut Vec<u8>) -> bool {
     let mut offset = 0;
     while offset < size {
         let mut tmp = [0; 512];
-        let res = inode.read_at(offset, &mut tmp).unwrap();
+        let res = vfs::pagecache::read_at(&inode, offset, &mut tmp).unwrap();
         offset += res as u64;
         buf.extend_from_slice(&tmp);
     }
@@ -76,6 +84,51 @@ pub fn read_all(file_name: &str, buf: &mut Vec<u8>) -> bool {
     true
 }
 
+/// 检查能否通过 `path` 所在的挂载修改文件系统, 只读挂载返回 EROFS
+///
+/// `path` 不存在时 (创建文件) 检查它的父目录; 父目录也不存在时不报错, 由后续的操作报告。
+fn check_writable_at(dirfd: isize, path: &str) -> AlienResult<()> {
+    check_writable_with(dirfd, path, OpenFlags::O_NOFOLLOW)
+}
+
+/// 与 [`check_writable_at`] 相同, 但 `path` 是软链接时检查它指向的文件, 用于 `openat` 等跟随软链接的调用
+///
+/// `flags` 带有 `O_NOFOLLOW` 时不跟随。
+fn check_writable_target(dirfd: isize, path: &str, flags: OpenFlags) -> AlienResult<()> {
+    check_writable_with(dirfd, path, flags & OpenFlags::O_NOFOLLOW)
+}
+
+fn check_writable_with(dirfd: isize, path: &str, flags: OpenFlags) -> AlienResult<()> {
+    let target = user_path_at(dirfd, path)?;
+    if let Ok(dentry) = target.open2(None, flags) {
+        return vfs::mount::check_writable(&dentry);
+    }
+    let path = path.trim_end_matches('/');
+    let parent = match path.rfind('/') {
+        Some(0) => "/",
+        Some(index) => &path[..index],
+        None => ".",
+    };
+    match user_path_at(dirfd, parent).and_then(|path| Ok(path.open(None)?)) {
+        Ok(dentry) => vfs::mount::check_writable(&dentry),
+        Err(_) => Ok(()),
+    }
+}
+
+/// 检查 `file_name` 所在的挂载是否允许执行, 挂载带有 `MS_NOEXEC` 时返回 EACCES
+///
+/// 文件不存在时不报错, 由读取文件时报告。
+pub fn check_exec(file_name: &str) -> AlienResult<()> {
+    let dentry = match user_path_at(AT_FDCWD, file_name).and_then(|path| Ok(path.open(None)?)) {
+        Ok(dentry) => dentry,
+        Err(_) => return Ok(()),
+    };
+    if vfs::mount::flags_of(&dentry).contains(MountFlags::MS_NOEXEC) {
+        return Err(LinuxErrno::EACCES);
+    }
+    Ok(())
+}
+
 /// [InodeMode](InodeMode)转换为[VfsInodeMode](VfsInodeMode)
 fn im2vim(mode: InodeMode) -> VfsInodeMode {
     VfsInodeMode::from_bits_truncate(mode.bits())
@@ -0,0 +1,67 @@
+//! 写回线程和 `sync`
+//!
+//! 文件页缓存和块设备缓存中的脏页除了在 `fsync`/`sync` 时写回, 还由写回线程定期写回:
+//! 每 [`WRITEBACK_INTERVAL_MS`] 让块设备写回过期的脏页 (脏页比例过高时还写回最老的脏页),
+//! 每 [`DIRTY_EXPIRE_MS`] 把页缓存中的脏页写回文件系统 (DBFS 的文件除外, 见
+//! [`pagecache::write_back_all`])。
+//!
+//! 写回失败的页仍然是脏的, 写回线程只记录日志并在下次重试; 错误由之后的 `fsync`/`sync`
+//! 返回给用户 (EIO)。
+use alloc::{sync::Arc, vec::Vec};
+
+use config::{DIRTY_EXPIRE_MS, WRITEBACK_INTERVAL_MS};
+use constants::AlienResult;
+use log::{info, warn};
+use timer::get_time_ms;
+use vfs::pagecache;
+use vfscore::superblock::VfsSuperBlock;
+
+use crate::task::do_suspend;
+
+/// 把所有缓存的修改写入磁盘, 返回遇到的第一个错误
+///
+/// 依次写回页缓存、各个文件系统自己的缓存、块设备缓存, 最后刷新设备的写缓存。
+pub fn sync_all() -> AlienResult<()> {
+    let mut result = pagecache::sync_all().map(|_| ());
+    // 包括只在其它命名空间中挂载的文件系统, 每个文件系统只同步一次
+    let mut synced: Vec<Arc<dyn VfsSuperBlock>> = Vec::new();
+    for mount in vfs::mount::all_mounts() {
+        let Ok(sb) = mount.root.inode().and_then(|inode| inode.get_super_block()) else {
+            continue;
+        };
+        let ptr = Arc::as_ptr(&sb) as *const u8;
+        if synced.iter().any(|other| Arc::as_ptr(other) as *const u8 == ptr) {
+            continue;
+        }
+        synced.push(sb.clone());
+        if let Err(e) = sb.sync_fs(true) {
+            warn!("sync: sync {} failed: {:?}", mount.target, e);
+            result = result.and(Err(e.into()));
+        }
+    }
+    result.and(devices::sync_block_device())
+}
+
+/// 写回线程
+pub fn writeback_thread() {
+    let mut block_time = get_time_ms();
+    let mut cache_time = block_time;
+    loop {
+        let now = get_time_ms();
+        if now - cache_time >= DIRTY_EXPIRE_MS as isize {
+            cache_time = now;
+            if let Err(e) = pagecache::write_back_all() {
+                warn!("writeback: write back page cache failed: {:?}", e);
+            }
+        }
+        if now - block_time >= WRITEBACK_INTERVAL_MS as isize {
+            block_time = now;
+            match devices::writeback_block_device() {
+                Ok(0) => {}
+                Ok(pages) => info!("writeback: wrote back {} block cache pages", pages),
+                Err(e) => warn!("writeback: write back block cache failed: {:?}", e),
+            }
+        }
+        do_suspend();
+    }
+}
@@ -38,7 +38,7 @@ use core::{
 
 use platform::platform_machine_info;
 
-use crate::task::DriverTaskImpl;
+use crate::task::{DriverTaskImpl, ProcessInfoImpl};
 
 /// 多核启动标志
 static STARTED: AtomicBool = AtomicBool::new(false);
@@ -55,6 +55,7 @@ fn main(hart_id: usize) {
         mem::init_memory_system(machine_info.memory.end, true);
         interrupt::init_plic(machine_info.plic.start);
         shim::register_task_func(Box::new(DriverTaskImpl));
+        vfs::proc::register_process_info(Box::new(ProcessInfoImpl));
         devices::init_device();
         vfs::init_filesystem().expect("init filesystem failed");
         // ksym::init_kallsyms(); // Temporarily disabled for testing
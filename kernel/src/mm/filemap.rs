@@ -0,0 +1,120 @@
+//! 普通文件的共享内存映射 (MAP_SHARED)
+//!
+//! 共享映射直接映射文件页缓存 ([`vfs::pagecache`]) 中的页, 与 `read`/`write` 使用同一组页,
+//! 因此映射了同一个文件的各个进程以及读写这个文件的进程看到的内容一致。`msync` 和
+//! `munmap` 时写回映射覆盖的页 (DBFS 上只写改变了的页, 并且作为一次事务写入),
+//! 映射期间这些页不会被回收。
+//!
+//! 私有映射 (MAP_PRIVATE) 不经过这里: 页在缺页时读入进程自己的副本,
+//! fork 之后写时复制, 修改不会写回文件。
+use alloc::sync::Arc;
+
+use config::FRAME_SIZE;
+use constants::{AlienResult, LinuxErrno};
+use mem::VmmPageAllocator;
+use page_table::{
+    addr::{align_up_4k, PhysAddr, VirtAddr},
+    pte::MappingFlags,
+    table::Sv39PageTable,
+};
+use vfs::{
+    kfile::{File, KernelFile},
+    pagecache,
+};
+use vfscore::{inode::VfsInode, utils::VfsNodeType};
+
+/// `file` 是否是普通文件 (共享映射使用页缓存, 私有映射在缺页时读入)
+pub fn is_regular_file(file: &Arc<dyn File>) -> bool {
+    file.is::<KernelFile>() && file.inode().inode_type() == VfsNodeType::File
+}
+
+/// 映射的页是否可能被进程直接修改
+///
+/// 以打开文件的方式为准而不是映射的保护位, 保护位可以被 mprotect 修改。
+fn writable(file: &Arc<dyn File>) -> bool {
+    file.is_writable()
+}
+
+/// 把 `file` 从 `offset` 开始的 `len` 字节用页缓存中的页映射到 `start`
+///
+/// `offset` 需要和页对齐。同一个区域在 fork 出的子进程中也通过这个函数重新映射。
+/// 没有页缓存的文件 (如 procfs 中的文件) 不能共享映射, 返回 EINVAL。
+pub fn map_shared(
+    address_space: &mut Sv39PageTable<VmmPageAllocator>,
+    file: &Arc<dyn File>,
+    start: usize,
+    len: usize,
+    offset: usize,
+    flags: MappingFlags,
+) -> AlienResult<()> {
+    if offset % FRAME_SIZE != 0 {
+        return Err(LinuxErrno::EINVAL);
+    }
+    let cache = pagecache::open(&file.inode()).ok_or(LinuxErrno::EINVAL)?;
+    let writable = writable(file);
+    let first = offset / FRAME_SIZE;
+    let mut mapped = 0;
+    let mut map = || -> AlienResult<()> {
+        for i in 0..align_up_4k(len) / FRAME_SIZE {
+            let phys = cache.map_page(first + i, writable)?;
+            mapped += 1;
+            // 页由页缓存持有, 不归这个地址空间所有 (与共享内存相同)
+            address_space
+                .map_region(
+                    VirtAddr::from(start + i * FRAME_SIZE),
+                    PhysAddr::from(phys),
+                    FRAME_SIZE,
+                    flags,
+                    false,
+                )
+                .map_err(|_| LinuxErrno::ENOMEM)?;
+        }
+        Ok(())
+    };
+    let result = map();
+    if result.is_err() {
+        for i in 0..mapped {
+            let _ = address_space.unmap_region(VirtAddr::from(start + i * FRAME_SIZE), FRAME_SIZE);
+            cache.unmap_page(first + i, writable);
+        }
+        let _ = cache.release();
+    }
+    result
+}
+
+/// 把 `inode` 在 `[offset, offset + len)` 中的缓存页写回文件
+pub fn sync(inode: &Arc<dyn VfsInode>, offset: usize, len: usize) -> AlienResult<()> {
+    let Some(cache) = pagecache::find(inode) else {
+        return Ok(());
+    };
+    let written = cache.sync(offset, len)?;
+    if written > 0 {
+        info!("msync: wrote back {} bytes", written);
+    }
+    Ok(())
+}
+
+/// 共享映射被拆成两个区域时, 为多出来的区域登记一次页缓存的使用
+///
+/// 两个区域解除映射时各调用一次 [`unmap_shared`]。
+pub fn share(file: &Arc<dyn File>) -> AlienResult<()> {
+    pagecache::open(&file.inode())
+        .map(|_| ())
+        .ok_or(LinuxErrno::EINVAL)
+}
+
+/// 解除 `file` 的一个共享映射: 写回它覆盖的页, 之后这些页可以被回收
+///
+/// 写回失败时映射仍然被解除, 错误返回给调用者。
+pub fn unmap_shared(file: &Arc<dyn File>, offset: usize, len: usize) -> AlienResult<()> {
+    let inode = file.inode();
+    let Some(cache) = pagecache::find(&inode) else {
+        return Ok(());
+    };
+    let result = cache.sync(offset, len).map(|_| ());
+    let first = offset / FRAME_SIZE;
+    for i in 0..align_up_4k(len) / FRAME_SIZE {
+        cache.unmap_page(first + i, writable(file));
+    }
+    result.and(cache.release())
+}
@@ -11,6 +11,7 @@ use page_table::{
 use syscall_table::syscall_func;
 use vfs::kfile::File;
 
+use super::filemap;
 use crate::task::current_task;
 
 bitflags! {
@@ -103,6 +104,10 @@ impl MMapInfo {
         None
     }
 
+    pub fn regions(&self) -> impl Iterator<Item = &MMapRegion> {
+        self.regions.iter()
+    }
+
     pub fn remove_region(&mut self, addr: usize) {
         let mut index = 0;
         for region in self.regions.iter() {
@@ -155,6 +160,13 @@ impl MMapRegion {
     pub fn set_flags(&mut self, flags: MMapFlags) {
         self.flags = flags;
     }
+
+    /// 普通文件的共享映射 (MAP_SHARED) 所映射的文件, 它的页由 [`filemap`] 管理
+    pub fn shared_file(&self) -> Option<&Arc<dyn File>> {
+        self.fd.as_ref().filter(|file| {
+            self.flags.contains(MMapFlags::MAP_SHARED) && filemap::is_regular_file(file)
+        })
+    }
 }
 
 /// 一个函数调用，用于消除内存映射。
@@ -224,24 +236,40 @@ pub fn map_protect(start: usize, len: usize, prot: u32) -> AlienResult<isize> {
     Ok(0)
 }
 
-/// (待实现)一个系统调用，用于同步文件在内存映射中的修改。一个文件通过[`do_mmap`]映射到内存中，可以在内存中对其进行快速的读写。
+/// 一个系统调用，用于同步文件在内存映射中的修改。一个文件通过[`do_mmap`]映射到内存中，可以在内存中对其进行快速的读写。
 /// 当我们对文件的映射进行修改后，如果不调用`msync`系统调用，那么在调用[`do_munmap`]之前内存中的相应内容都不会写回磁盘文件，有可能导致不一致性问题。
-/// 目前函数仅会检查所传入的`addr`是否已经被映射，如果没有被映射，则会返回`EFAULT`；否则直接返回0。
 ///
-/// Reference: [madvise](https://man7.org/linux/man-pages/man2/madvise.2.html)
+/// 函数会把`addr`所在的共享文件映射 (MAP_SHARED) 中与`[addr, addr + len)`相交的页
+/// (这些页就是文件页缓存中的页, `read` 也能看到) 写回文件 (DBFS 上作为一次事务写入)；
+/// 私有映射和匿名映射无需写回。如果`addr`没有被映射，则会返回`EFAULT`；`addr + len`
+/// 溢出时返回`ENOMEM`。
+///
+/// Reference: [msync](https://man7.org/linux/man-pages/man2/msync.2.html)
 #[syscall_func(227)]
-pub fn msync(addr: usize, len: usize, flags: usize) -> isize {
+pub fn msync(addr: usize, len: usize, flags: usize) -> AlienResult<isize> {
     warn!(
         "msync: addr: {:#x}, len: {:#x}, flags: {:#x}",
         addr, len, flags
     );
+    let end = addr.checked_add(len).ok_or(LinuxErrno::ENOMEM)?;
     let task = current_task().unwrap();
-    let address_space = &task.access_inner().address_space;
-    let res = address_space.lock().query(VirtAddr::from(addr));
+    let inner = task.access_inner();
+    let res = inner.address_space.lock().query(VirtAddr::from(addr));
     if res.is_err() {
-        return LinuxErrno::EFAULT as isize;
+        return Err(LinuxErrno::EFAULT);
     }
-    0
+    let Some(region) = inner.mmap.get_region(addr) else {
+        return Ok(0);
+    };
+    let Some(file) = region.shared_file() else {
+        return Ok(0);
+    };
+    let end = end.min(region.start + region.len);
+    let offset = region.offset + (addr - region.start);
+    let inode = file.inode();
+    drop(inner);
+    filemap::sync(&inode, offset, end - addr)?;
+    Ok(0)
 }
 
 /// (待实现)一个系统调用，用于向内核提供使用内存的建议。目前直接返回0。
@@ -1,6 +1,7 @@
 use arch::hart_id;
 
 pub mod elf;
+pub mod filemap;
 pub mod loader;
 pub mod map;
 
@@ -267,6 +267,7 @@ pub fn get_tid() -> isize {
 /// `ptid`是一个在父进程地址空间中的地址，用于在创建子进程成功后向该位置写入子进程的tid号。在flag包含`CLONE_PARENT_SETTID`时才会发挥效果。
 /// `tls`用于为子进程创建新的TLS(thread-local storage)值，在flag包含`CLONE_SETTLS`时才会实际产生效果。
 /// `ctid`用于给子进程中的[`set_child_tid`]和[`clear_child_tid`]赋值(分别在flag中包含`CLONE_CHILD_SETTID`和`CLONE_CHILD_CLEARTID`时产生效果)。
+/// flag包含`CLONE_NEWNS`时，子进程在父进程挂载命名空间的一个副本中。
 ///
 /// 成功创建子进程后父进程会返回子进程的tid号，子进程的返回值将被设置为0；否则返回-1。
 ///
@@ -297,6 +298,39 @@ pub fn clone(flag: usize, stack: usize, ptid: usize, tls: usize, ctid: usize) ->
     tid
 }
 
+/// 一个系统调用，用于让调用者不再与其他进程共享某些资源。
+///
+/// 支持的标志:
+/// + `CLONE_NEWNS`: 换到挂载命名空间的一个副本中，之后的 `mount`、`umount` 和 `pivot_root`
+///   只影响调用者和它之后创建的子进程
+/// + `CLONE_FILES`: 复制文件描述符表
+/// + `CLONE_FS`: 根目录和当前工作目录本来就不与其他进程共享，直接成功
+///
+/// 其余标志返回 EINVAL。
+///
+/// Reference: [unshare](https://man7.org/linux/man-pages/man2/unshare.2.html)
+#[syscall_func(97)]
+pub fn sys_unshare(flags: usize) -> AlienResult<isize> {
+    let flags = CloneFlags::from_bits(flags as u32).ok_or(AlienError::EINVAL)?;
+    info!("unshare: {:?}", flags);
+    let supported = CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_FILES | CloneFlags::CLONE_FS;
+    if !supported.contains(flags) {
+        return Err(AlienError::EINVAL);
+    }
+    let task = current_task().unwrap();
+    if flags.contains(CloneFlags::CLONE_NEWNS) {
+        let fs_info = task.access_inner().fs_info.clone();
+        let fs_info = fs_info.unshare_mnt_ns()?;
+        task.access_inner().fs_info = fs_info;
+    }
+    if flags.contains(CloneFlags::CLONE_FILES) {
+        let mut inner = task.access_inner();
+        let fd_table = inner.fd_table.lock().clone();
+        inner.fd_table = Arc::new(Mutex::new(fd_table));
+    }
+    Ok(0)
+}
+
 /// 一个系统调用，用于执行一个文件。
 ///
 /// `path`用于指明要执行的文件的绝对路径。
@@ -325,6 +359,7 @@ pub fn do_exec(path: *const u8, args_ptr: usize, env: usize) -> AlienResult<isiz
     if path_str.contains("libc-bench") {
         path_str = "libc-bench2".to_string();
     }
+    fs::check_exec(&path_str)?;
     if fs::read_all(&path_str, &mut data) {
         let res = task.exec(&path_str, data.as_slice(), args, envs);
         if res.is_err() {
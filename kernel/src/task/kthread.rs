@@ -1,5 +1,11 @@
-use alloc::{collections::BTreeMap, string::ToString, sync::Arc, vec::Vec};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 
+use arch::interrupt_enable;
 use bit_field::BitField;
 use config::{CPU_NUM, FRAME_SIZE, MAX_FD_NUM, MAX_THREAD_NUM, USER_KERNEL_STACK_SIZE};
 use constants::{
@@ -21,20 +27,31 @@ use crate::{
         resource::{HeapInfo, TidHandle},
         stack::Stack,
         task::{TaskInner, TaskTimer},
-        FsContext, StatisticalData, Task, TaskState, GLOBAL_TASK_MANAGER,
+        current_task, FsContext, StatisticalData, Task, TaskState, GLOBAL_TASK_MANAGER,
     },
 };
 
 type FdManager = MinimalManager<Arc<dyn File>>;
 
+/// 尚未开始运行的内核线程的线程函数, 以线程号为键
+static KTHREAD_FUNCS: Mutex<BTreeMap<usize, fn()>> = Mutex::new(BTreeMap::new());
+
+/// 内核线程的入口。调度器总是在关中断时切换任务, 内核线程不经过返回用户态的路径,
+/// 需要在这里打开中断后再进入线程函数
+fn kthread_entry() {
+    interrupt_enable();
+    let tid = current_task().unwrap().get_tid() as usize;
+    let func = KTHREAD_FUNCS.lock().remove(&tid).unwrap();
+    func();
+}
+
 pub fn ktread_create(func: fn(), name: &str) -> AlienResult<()> {
     let tid = TidHandle::new().ok_or(AlienError::ENOSPC)?;
     let pid = tid.0;
     let k_stack = Stack::new(USER_KERNEL_STACK_SIZE / FRAME_SIZE).ok_or(AlienError::ENOMEM)?;
     let kspace = kernel_space();
-    let cwd = vfs::system_root_fs();
     let k_stack_top = k_stack.top();
-    let func_ptr = func as usize;
+    KTHREAD_FUNCS.lock().insert(tid.0, func);
     let task = Task {
         tid,
         kernel_stack: k_stack,
@@ -54,8 +71,8 @@ pub fn ktread_create(func: fn(), name: &str) -> AlienResult<()> {
                 fd_table.insert(STDOUT.clone()).unwrap();
                 Arc::new(Mutex::new(fd_table))
             },
-            context: Context::new(func_ptr, k_stack_top),
-            fs_info: FsContext::new(cwd.clone(), cwd),
+            context: Context::new(kthread_entry as usize, k_stack_top),
+            fs_info: FsContext::init(),
             statistical_data: StatisticalData::new(),
             timer: TaskTimer::default(),
             exit_code: 0,
@@ -85,6 +102,9 @@ pub fn ktread_create(func: fn(), name: &str) -> AlienResult<()> {
             },
             exit_group: false,
             kretprobe_instances: Vec::new(),
+            args: Vec::new(),
+            envs: Vec::new(),
+            exe: String::new(),
         }),
         send_sigchld_when_exit: false,
     };
@@ -3,12 +3,14 @@
 //! [`context`] 子模块定义了 Alien 中线程上下文的相关结构.
 //! [`cpu`] 子模块中指明了 Alien 中有关进程的系统调用 和 多核的相关支持。
 //! [`heap`] 子模块定义了 Alien 记录进程堆空间的相关信息的结构。
+//! [`procfs`] 子模块为 `/proc/<pid>` 提供进程的信息, 并记录所有用户任务。
 //! [`schedule`] 子模块指明了 Alien 中有关 CPU 调度的相关机制
 //! [`stack`] 子模块定义了 Alien 中有关内核栈的相关结构。
 //! [`task`] 子模块定义了 Alien 中有关进程控制块的定义。
 use alloc::{sync::Arc, vec::Vec};
 
 pub use cpu::*;
+pub use procfs::{tasks, ProcessInfoImpl};
 use shim::{KTask, KTaskShim};
 use smpscheduler::FifoTask;
 use spin::Lazy;
@@ -16,12 +18,16 @@ pub use task::{StatisticalData, Task, TaskState};
 use timer::get_time_ms;
 
 pub use crate::task::task::FsContext;
-use crate::{fs::read_all, task::schedule::schedule_now};
+use crate::{
+    fs::{read_all, writeback},
+    task::schedule::schedule_now,
+};
 
 mod context;
 mod control;
 mod cpu;
 mod kthread;
+mod procfs;
 mod resource;
 pub mod schedule;
 mod stack;
@@ -49,12 +55,15 @@ pub static INIT_PROCESS: Lazy<Arc<Task>> = Lazy::new(|| {
     }
     
     let task = Task::from_elf("init", data.as_slice()).unwrap();
-    Arc::new(task)
+    let task = Arc::new(task);
+    procfs::register_task(&task);
+    task
 });
 
 /// 将初始进程加入进程池中进行调度
 pub fn init_task() {
     kthread::ktread_create(kthread_init, "kthread_test").unwrap();
+    kthread::ktread_create(writeback::writeback_thread, "writeback").unwrap();
     let task = INIT_PROCESS.clone();
     GLOBAL_TASK_MANAGER.add_task(Arc::new(FifoTask::new(task)));
     println!("Init task success");
@@ -83,6 +92,12 @@ impl KTask for Task {
     fn have_signal(&self) -> bool {
         self.access_inner().signal_receivers.lock().have_signal()
     }
+    fn pid(&self) -> usize {
+        self.get_pid() as usize
+    }
+    fn tid(&self) -> usize {
+        self.get_tid() as usize
+    }
 }
 pub struct DriverTaskImpl;
 impl KTaskShim for DriverTaskImpl {
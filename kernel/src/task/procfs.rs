@@ -0,0 +1,373 @@
+//! `/proc/<pid>` 的内容来源
+//!
+//! 这里记录所有用户任务 (tid -> 任务), 并为 procfs 实现 [`ProcessInfo`]:
+//! `/proc/<pid>` 下的文件在每次查找或读取时从 [`TaskInner`] 生成。
+//! 进程 (线程组 leader) 创建时注册 `/proc/<pid>` 目录, tid 被释放 (任务被回收) 时移除。
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use config::{FRAME_SIZE, TRAP_CONTEXT_BASE};
+use constants::io::MMapFlags;
+use ksync::Mutex;
+use page_table::pte::MappingFlags;
+use vfs::{
+    epoll::EpollFile,
+    eventfd::EventFdInode,
+    kfile::{File, KernelFile},
+    mount::MountNamespace,
+    proc::{ProcessFile, ProcessInfo, ProcessLink},
+    timerfd::TimerFile,
+};
+use vfscore::dentry::VfsDentry;
+
+use super::{current_task, task::TaskInner, Task, TaskState};
+use crate::{ipc::PipeFile, mm::map::ProtFlags};
+
+/// tid -> (pid, 任务)
+static TASKS: Mutex<BTreeMap<usize, (usize, Weak<Task>)>> = Mutex::new(BTreeMap::new());
+
+/// 记录一个新任务, 它是进程时注册 `/proc/<pid>`
+pub fn register_task(task: &Arc<Task>) {
+    let (tid, pid) = (task.get_tid() as usize, task.get_pid() as usize);
+    TASKS.lock().insert(tid, (pid, Arc::downgrade(task)));
+    if tid == pid {
+        if let Err(e) = vfs::proc::register_process(pid) {
+            warn!("register /proc/{} failed: {:?}", pid, e);
+        }
+    }
+}
+
+/// tid 被释放时调用, 是进程时移除 `/proc/<pid>`
+pub fn unregister_task(tid: usize) {
+    let removed = TASKS.lock().remove(&tid);
+    if let Some((pid, _)) = removed {
+        if pid == tid {
+            vfs::proc::unregister_process(pid);
+        }
+    }
+}
+
+/// 在锁外 upgrade, 最后一个引用在这里释放时不会在持有锁时回收任务
+fn task(tid: usize) -> Option<Arc<Task>> {
+    let task = TASKS.lock().get(&tid).map(|(_, task)| task.clone());
+    task?.upgrade()
+}
+
+/// 所有仍然存在的用户任务
+pub fn tasks() -> Vec<Arc<Task>> {
+    let tasks = TASKS
+        .lock()
+        .values()
+        .map(|(_, task)| task.clone())
+        .collect::<Vec<_>>();
+    tasks.iter().filter_map(Weak::upgrade).collect()
+}
+
+fn threads(pid: usize) -> Vec<usize> {
+    TASKS
+        .lock()
+        .iter()
+        .filter(|(_, (owner, task))| *owner == pid && task.strong_count() > 0)
+        .map(|(tid, _)| *tid)
+        .collect()
+}
+
+/// `path` 相对于 `cwd` 的绝对路径 (只在字面上处理 `.` 和 `..`)
+pub fn absolute_path(cwd: &Arc<dyn VfsDentry>, path: &str) -> String {
+    let base = if path.starts_with('/') {
+        String::new()
+    } else {
+        cwd.path()
+    };
+    let mut parts = Vec::new();
+    for part in base.split('/').chain(path.split('/')) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    format!("/{}", parts.join("/"))
+}
+
+/// 打开的文件在 `/proc/<pid>/fd` 中显示的目标
+fn file_path(file: &Arc<dyn File>) -> String {
+    if file.is::<KernelFile>() {
+        file.dentry().path()
+    } else if file.is::<PipeFile>() {
+        format!("pipe:[{}]", file.dentry().name())
+    } else if file.is::<EpollFile>() {
+        "anon_inode:[eventpoll]".to_string()
+    } else if file.is::<EventFdInode>() {
+        "anon_inode:[eventfd]".to_string()
+    } else if file.is::<TimerFile>() {
+        "anon_inode:[timerfd]".to_string()
+    } else {
+        "anon_inode:[unknown]".to_string()
+    }
+}
+
+/// 以 '\0' 结尾依次拼接 (参数和环境变量可能已经带有 '\0')
+fn nul_separated(strings: &[String]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for string in strings {
+        bytes.extend_from_slice(string.trim_end_matches('\0').as_bytes());
+        bytes.push(0);
+    }
+    bytes
+}
+
+/// 进程名: 可执行文件名的前 15 个字符
+fn comm(inner: &TaskInner) -> String {
+    let name = inner.name.trim_end_matches('\0');
+    name.rsplit('/').next().unwrap_or(name).chars().take(15).collect()
+}
+
+fn state(state: TaskState) -> (char, &'static str) {
+    match state {
+        TaskState::Ready | TaskState::Running => ('R', "running"),
+        TaskState::Waiting => ('S', "sleeping"),
+        TaskState::Zombie => ('Z', "zombie"),
+        TaskState::Terminated => ('X', "dead"),
+    }
+}
+
+fn ppid(inner: &TaskInner) -> isize {
+    inner
+        .parent
+        .as_ref()
+        .and_then(Weak::upgrade)
+        .map_or(0, |parent| parent.get_pid())
+}
+
+/// 虚拟内存大小 (字节)
+fn vm_size(inner: &TaskInner) -> usize {
+    let heap = inner.heap.lock().clone();
+    let mmap = inner.mmap.regions().map(|region| region.map_len).sum::<usize>();
+    let elf = elf_pages(inner).len() * FRAME_SIZE;
+    mmap + (heap.current - heap.start) + inner.stack.len() + elf
+}
+
+/// 驻留的页数: 地址空间实际拥有的页
+fn rss_pages(inner: &TaskInner) -> usize {
+    inner.address_space.lock().get_record().len()
+}
+
+/// 不属于 mmap 区域、堆、栈的用户页 (ELF 段), 以及它们的权限
+fn elf_pages(inner: &TaskInner) -> Vec<(usize, usize, [u8; 3])> {
+    let heap = inner.heap.lock();
+    let heap_range = heap.start..heap.end.max(heap.current);
+    drop(heap);
+    let address_space = inner.address_space.lock();
+    let mut pages = Vec::new();
+    for (vaddr, _) in address_space.get_record().into_iter() {
+        let addr = vaddr.as_usize();
+        if addr >= TRAP_CONTEXT_BASE
+            || heap_range.contains(&addr)
+            || inner.stack.contains(&addr)
+            || inner.mmap.get_region(addr).is_some()
+        {
+            continue;
+        }
+        if let Ok((_, flags, size)) = address_space.query(vaddr) {
+            pages.push((addr, addr + usize::from(size), page_perms(flags)));
+        }
+    }
+    pages
+}
+
+fn page_perms(flags: MappingFlags) -> [u8; 3] {
+    // 写时复制的页暂时没有 W, 用 RSD 标记
+    let writable = flags.contains(MappingFlags::W) || flags.contains(MappingFlags::RSD);
+    [
+        if flags.contains(MappingFlags::R) { b'r' } else { b'-' },
+        if writable { b'w' } else { b'-' },
+        if flags.contains(MappingFlags::X) { b'x' } else { b'-' },
+    ]
+}
+
+fn prot_perms(prot: ProtFlags) -> [u8; 3] {
+    [
+        if prot.contains(ProtFlags::PROT_READ) { b'r' } else { b'-' },
+        if prot.contains(ProtFlags::PROT_WRITE) { b'w' } else { b'-' },
+        if prot.contains(ProtFlags::PROT_EXEC) { b'x' } else { b'-' },
+    ]
+}
+
+fn map_line(
+    start: usize,
+    end: usize,
+    perms: [u8; 3],
+    shared: bool,
+    offset: usize,
+    name: &str,
+) -> String {
+    let mut line = format!(
+        "{:08x}-{:08x} {}{} {:08x} 00:00 0",
+        start,
+        end,
+        core::str::from_utf8(&perms).unwrap(),
+        if shared { 's' } else { 'p' },
+        offset
+    );
+    if !name.is_empty() {
+        line.push_str(&" ".repeat(73usize.saturating_sub(line.len()).max(1)));
+        line.push_str(name);
+    }
+    line.push('\n');
+    line
+}
+
+fn maps(inner: &TaskInner) -> String {
+    // start -> 行, 按地址排序
+    let mut lines = BTreeMap::new();
+    // ELF 段: 合并地址连续、权限相同的页
+    let mut run: Option<(usize, usize, [u8; 3])> = None;
+    for (start, end, perms) in elf_pages(inner) {
+        match &mut run {
+            Some((_, run_end, run_perms)) if *run_end == start && *run_perms == perms => {
+                *run_end = end;
+            }
+            _ => {
+                if let Some((start, end, perms)) = run.replace((start, end, perms)) {
+                    lines.insert(start, map_line(start, end, perms, false, 0, ""));
+                }
+            }
+        }
+    }
+    if let Some((start, end, perms)) = run {
+        lines.insert(start, map_line(start, end, perms, false, 0, ""));
+    }
+    for region in inner.mmap.regions() {
+        let shared = region.flags.contains(MMapFlags::MAP_SHARED);
+        let (offset, name) = match &region.fd {
+            Some(file) => (region.offset, file_path(file)),
+            None => (0, String::new()),
+        };
+        let end = region.start + region.map_len;
+        let perms = prot_perms(region.prot);
+        lines.insert(region.start, map_line(region.start, end, perms, shared, offset, &name));
+    }
+    let heap = inner.heap.lock();
+    if heap.current > heap.start {
+        let line = map_line(heap.start, heap.current, *b"rw-", false, 0, "[heap]");
+        lines.insert(heap.start, line);
+    }
+    drop(heap);
+    let stack = &inner.stack;
+    let line = map_line(stack.start, stack.end, *b"rw-", false, 0, "[stack]");
+    lines.insert(stack.start, line);
+    lines.into_values().collect()
+}
+
+fn stat(task: &Task, inner: &TaskInner) -> String {
+    let pid = task.get_pid();
+    let data = &inner.statistical_data;
+    let heap_start = inner.heap.lock().start;
+    format!(
+        "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 {} {} {} {} 20 0 {} 0 0 {} {} {} 0 0 {} \
+         0 0 0 0 0 0 0 0 0 17 0 0 0 0 0 0 0 0 {} 0 0 0 0 {}\n",
+        task.get_tid(),
+        comm(inner),
+        state(inner.state).0,
+        ppid(inner),
+        pid,
+        pid,
+        data.tms_utime,
+        data.tms_stime,
+        data.tms_cutime,
+        data.tms_cstime,
+        threads(pid as usize).len().max(1),
+        vm_size(inner),
+        rss_pages(inner),
+        u64::MAX,
+        inner.stack.end,
+        heap_start,
+        inner.exit_code,
+    )
+}
+
+fn status(task: &Task, inner: &TaskInner) -> String {
+    let (state, state_name) = state(inner.state);
+    format!(
+        "Name:\t{}\nUmask:\t{:04o}\nState:\t{} ({})\nTgid:\t{}\nPid:\t{}\nPPid:\t{}\n\
+         TracerPid:\t0\nUid:\t0\t0\t0\t0\nGid:\t0\t0\t0\t0\nFDSize:\t{}\n\
+         VmSize:\t{} kB\nVmRSS:\t{} kB\nThreads:\t{}\n",
+        comm(inner),
+        inner.unmask,
+        state,
+        state_name,
+        task.get_pid(),
+        task.get_tid(),
+        ppid(inner),
+        inner.fd_table.lock().max(),
+        vm_size(inner) / 1024,
+        rss_pages(inner) * FRAME_SIZE / 1024,
+        threads(task.get_pid() as usize).len().max(1),
+    )
+}
+
+/// procfs 的进程信息来源, 启动时注册到 vfs
+pub struct ProcessInfoImpl;
+
+impl ProcessInfo for ProcessInfoImpl {
+    fn current_pid(&self) -> Option<usize> {
+        current_task().map(|task| task.get_pid() as usize)
+    }
+
+    fn is_alive(&self, tid: usize) -> bool {
+        task(tid).is_some()
+    }
+
+    fn threads(&self, pid: usize) -> Vec<usize> {
+        threads(pid)
+    }
+
+    fn fds(&self, tid: usize) -> Vec<usize> {
+        let Some(task) = task(tid) else {
+            return Vec::new();
+        };
+        let fd_table = task.access_inner().fd_table.clone();
+        let fds = fd_table.lock().iter().map(|(fd, _)| fd).collect();
+        fds
+    }
+
+    fn read(&self, tid: usize, file: ProcessFile) -> Option<Vec<u8>> {
+        let task = task(tid)?;
+        let inner = task.access_inner();
+        let content = match file {
+            ProcessFile::Stat => stat(&task, &inner).into_bytes(),
+            ProcessFile::Status => status(&task, &inner).into_bytes(),
+            ProcessFile::Cmdline => nul_separated(&inner.args),
+            ProcessFile::Environ => nul_separated(&inner.envs),
+            ProcessFile::Maps => maps(&inner).into_bytes(),
+        };
+        Some(content)
+    }
+
+    fn link(&self, tid: usize, link: ProcessLink) -> Option<String> {
+        let task = task(tid)?;
+        let inner = task.access_inner();
+        match link {
+            ProcessLink::Cwd => Some(inner.fs_info.cwd.path()),
+            ProcessLink::Exe => Some(inner.exe.clone()).filter(|exe| !exe.is_empty()),
+            ProcessLink::Fd(fd) => {
+                let file = inner.fd_table.lock().get(fd).ok()??;
+                Some(file_path(&file))
+            }
+        }
+    }
+
+    fn current_fs(&self) -> Option<(Arc<MountNamespace>, Arc<dyn VfsDentry>)> {
+        let task = current_task()?;
+        let fs_info = task.access_inner().fs_info.clone();
+        Some((fs_info.mnt_ns, fs_info.root))
+    }
+}
@@ -25,6 +25,7 @@ impl TidHandle {
 
 impl Drop for TidHandle {
     fn drop(&mut self) {
+        super::procfs::unregister_task(self.0);
         TID_MANAGER.lock().deallocate(self.0).unwrap();
     }
 }
@@ -2,6 +2,7 @@
 use alloc::sync::Arc;
 use core::hint::spin_loop;
 
+use arch::{interrupt_disable, interrupt_enable, is_interrupt_enable};
 use constants::signal::SignalNumber;
 use smpscheduler::FifoTask;
 
@@ -37,7 +38,10 @@ pub fn run_task() -> ! {
             drop(task);
             switch(cpu_context, context);
         } else {
+            // 空闲时打开中断, 让等待中的任务能被完成中断唤醒
+            interrupt_enable();
             spin_loop();
+            interrupt_disable();
         }
     }
 }
@@ -48,8 +52,12 @@ pub fn schedule() {
     schedule_now(task)
 }
 
+/// 让渡 CPU。中断状态属于任务本身: 切换前关闭中断, 任务被再次调度回来后恢复切换前的状态,
+/// 因此关中断登记等待者再调用该函数不会丢失唤醒, 也不会把关中断的状态带给其它任务。
 // todo!(fix bugs)
 pub fn schedule_now(task: Arc<Task>) {
+    let enabled = is_interrupt_enable();
+    interrupt_disable();
     let context = task.get_context_mut_raw_ptr();
     match task.state() {
         TaskState::Waiting => {
@@ -76,4 +84,7 @@ pub fn schedule_now(task: Arc<Task>) {
     let cpu = current_cpu();
     let cpu_context = cpu.get_context_raw_ptr();
     switch(context, cpu_context);
+    if enabled {
+        interrupt_enable();
+    }
 }
@@ -28,7 +28,7 @@ use page_table::{
     table::Sv39PageTable,
 };
 use timer::{read_timer, TimeNow, ToClock};
-use vfs::kfile::File;
+use vfs::{kfile::File, mount::MountNamespace};
 use vfscore::dentry::VfsDentry;
 
 use crate::{
@@ -38,6 +38,7 @@ use crate::{
         loader::{
             build_cow_address_space, build_elf_address_space, build_thread_address_space, UserStack,
         },
+        filemap,
         map::{MMapInfo, MMapRegion, ProtFlags},
     },
     task::{
@@ -135,6 +136,12 @@ pub struct TaskInner {
     pub ss_stack: SignalStack,
     pub exit_group: bool,
     pub kretprobe_instances: Vec<kprobe::KretprobeInstance>,
+    /// 执行时的参数 (`/proc/<pid>/cmdline`)
+    pub args: Vec<String>,
+    /// 执行时的环境变量 (`/proc/<pid>/environ`)
+    pub envs: Vec<String>,
+    /// 可执行文件的绝对路径 (`/proc/<pid>/exe`)
+    pub exe: String,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -224,8 +231,10 @@ impl StatisticalData {
 pub struct FsContext {
     /// 当前工作目录
     pub cwd: Arc<dyn VfsDentry>,
-    /// 根目录
+    /// 根目录, 绝对路径从这里开始解析, 可以被 `chroot` 修改
     pub root: Arc<dyn VfsDentry>,
+    /// 所在的挂载命名空间, 只在带有 `CLONE_NEWNS` 时不与父进程共享
+    pub mnt_ns: Arc<MountNamespace>,
 }
 
 impl Debug for FsContext {
@@ -241,8 +250,39 @@ impl Debug for FsContext {
 
 impl FsContext {
     /// 创建一个新的 `FsContext` 结构
-    pub fn new(root: Arc<dyn VfsDentry>, cwd: Arc<dyn VfsDentry>) -> Self {
-        FsContext { cwd, root }
+    pub fn new(
+        mnt_ns: Arc<MountNamespace>,
+        root: Arc<dyn VfsDentry>,
+        cwd: Arc<dyn VfsDentry>,
+    ) -> Self {
+        FsContext { cwd, root, mnt_ns }
+    }
+
+    /// 初始挂载命名空间中, 根目录和当前工作目录都是命名空间的根目录
+    pub fn init() -> Self {
+        let mnt_ns = vfs::mount::init_mnt_ns();
+        let root = mnt_ns.root();
+        Self::new(mnt_ns, root.clone(), root)
+    }
+
+    /// 换到挂载命名空间的一个副本中 (`CLONE_NEWNS`)
+    ///
+    /// 根目录和当前工作目录换成新命名空间中相同路径的目录项, 找不到时返回错误。
+    pub fn unshare_mnt_ns(&self) -> AlienResult<Self> {
+        let mnt_ns = self.mnt_ns.copy()?;
+        let root = mnt_ns.lookup(&self.root.path())?;
+        let cwd = mnt_ns.lookup(&self.cwd.path())?;
+        Ok(Self::new(mnt_ns, root, cwd))
+    }
+
+    /// 当前工作目录相对于根目录的路径, 根目录之外 (`chroot` 之前打开的目录) 为完整路径
+    pub fn cwd_path(&self) -> String {
+        let cwd = self.cwd.path();
+        let root = self.root.path();
+        match vfs::mount::relative(&cwd, &root) {
+            Some(rest) => format!("/{}", rest),
+            None => cwd,
+        }
     }
 }
 
@@ -1023,6 +1063,13 @@ impl TaskInner {
                 .get(fd)
                 .map_err(|_| LinuxErrno::EBADF)?
                 .ok_or(LinuxErrno::EBADF)?; // EBADF
+            // 可写的共享映射会写回文件
+            if flags.contains(MMapFlags::MAP_SHARED)
+                && prot.contains(ProtFlags::PROT_WRITE)
+                && !file.is_writable()
+            {
+                return Err(LinuxErrno::EACCES);
+            }
             Some(file)
         };
         // todo!
@@ -1050,42 +1097,52 @@ impl TaskInner {
             }
             // check if the region is already mapped
             if let Some(region) = self.mmap.get_region(start) {
-                // split the region
-                let (left, mut right) = region.split(start);
-                // delete the old region
-                self.mmap.remove_region(region.start);
-                // add the left region
-                self.mmap.add_region(left);
-                if start + len < right.start + right.map_len {
-                    // slice the right region
-                    trace!(
-                        "again slice the right region:{:#x?}, len:{:#x}",
-                        right.start,
-                        right.len
-                    );
-                    let (mut left, right) = right.split(start + len);
-                    // add the right region
-                    self.mmap.add_region(right);
-                    // update prot and flags
-                    left.set_prot(prot);
-                    left.set_flags(flags);
-                    left.offset = offset;
-                    left.fd = fd;
-                    self.mmap.add_region(left);
+                let new_shared = fd.as_ref().is_some_and(|file| {
+                    flags.contains(MMapFlags::MAP_SHARED) && filemap::is_regular_file(file)
+                });
+                // 共享文件映射的页属于页缓存: 先解除被覆盖的部分, 新的映射
+                // 和其它 mmap 一样在下面建立
+                if new_shared || region.shared_file().is_some() {
+                    let region = region.clone();
+                    self.unmap_overlap(region, start, len)?;
                 } else {
-                    trace!(
-                        "directly add the right region:{:#x?}, len:{:#x}",
-                        right.start,
-                        right.len
-                    );
-                    // update prot and flags
-                    right.set_prot(prot);
-                    right.set_flags(flags);
-                    right.offset = offset;
-                    right.fd = fd;
-                    self.mmap.add_region(right);
+                    // split the region
+                    let (left, mut right) = region.split(start);
+                    // delete the old region
+                    self.mmap.remove_region(region.start);
+                    // add the left region
+                    self.mmap.add_region(left);
+                    if start + len < right.start + right.map_len {
+                        // slice the right region
+                        trace!(
+                            "again slice the right region:{:#x?}, len:{:#x}",
+                            right.start,
+                            right.len
+                        );
+                        let (mut left, right) = right.split(start + len);
+                        // add the right region
+                        self.mmap.add_region(right);
+                        // update prot and flags
+                        left.set_prot(prot);
+                        left.set_flags(flags);
+                        left.offset = offset;
+                        left.fd = fd;
+                        self.mmap.add_region(left);
+                    } else {
+                        trace!(
+                            "directly add the right region:{:#x?}, len:{:#x}",
+                            right.start,
+                            right.len
+                        );
+                        // update prot and flags
+                        right.set_prot(prot);
+                        right.set_flags(flags);
+                        right.offset = offset;
+                        right.fd = fd;
+                        self.mmap.add_region(right);
+                    }
+                    return Ok(start);
                 }
-                return Ok(start);
             }
             start..start + len
         } else {
@@ -1107,6 +1164,26 @@ impl TaskInner {
         let start = v_range.start;
         let mut map_flags: MappingFlags = prot.into(); // no V  flag
         map_flags |= "AD".into();
+        // 普通文件的共享映射使用文件的共享页, 私有映射和匿名映射一样在缺页时
+        // 分配 (缺页时读入文件内容), fork 之后写时复制
+        let region = self.mmap.get_region(start).unwrap();
+        if let Some(file) = region.shared_file().cloned() {
+            let map_len = region.map_len;
+            let res = filemap::map_shared(
+                &mut self.address_space.lock(),
+                &file,
+                start,
+                map_len,
+                offset,
+                map_flags | MappingFlags::V,
+            );
+            if let Err(e) = res {
+                self.mmap.remove_region(start);
+                return Err(e);
+            }
+            return Ok(start);
+        }
+        let fd = fd.filter(|file| !filemap::is_regular_file(file));
         let mut lazy_alloc = true;
         if fd.is_some() {
             map_flags |= MappingFlags::V;
@@ -1152,10 +1229,54 @@ impl TaskInner {
             .lock()
             .unmap_region(VirtAddr::from(start), region.map_len)
             .unwrap();
+        let shared = region
+            .shared_file()
+            .map(|file| (file.clone(), region.offset, region.len));
         self.mmap.remove_region(start);
+        // 共享映射的修改在解除映射时写回文件
+        if let Some((file, offset, len)) = shared {
+            filemap::unmap_shared(&file, offset, len).map_err(|e| -> isize { e.into() })?;
+        }
+        Ok(())
+    }
+
+    /// 从 `region` 中去掉被 MAP_FIXED 映射覆盖的 `start..start + len` 并解除这一段的页映射,
+    /// 两边剩下的部分仍然是独立的映射
+    fn unmap_overlap(&mut self, region: MMapRegion, start: usize, len: usize) -> AlienResult<()> {
+        let end = (start + len).min(region.start + region.len);
+        let (left, right) = region.split(start);
+        let (middle, rest) = right.split(end);
+        self.mmap.remove_region(region.start);
+        let kept = [left, rest].into_iter().filter(|part| part.len > 0);
+        for part in kept {
+            // 每个剩下的区域在解除映射时各释放一次页缓存
+            if let Some(file) = part.shared_file() {
+                filemap::share(file)?;
+            }
+            self.mmap.add_region(part);
+        }
+        // 没有访问过的惰性分配页没有页表项
+        let _ = self
+            .address_space
+            .lock()
+            .unmap_region(VirtAddr::from(middle.start), middle.map_len);
+        if let Some(file) = middle.shared_file() {
+            filemap::unmap_shared(file, middle.offset, middle.len)?;
+        }
         Ok(())
     }
 
+    /// 解除所有共享文件映射并写回修改, 在进程退出和 exec 替换地址空间时调用
+    pub fn release_shared_mappings(&mut self) {
+        for region in self.mmap.regions() {
+            if let Some(file) = region.shared_file() {
+                if let Err(e) = filemap::unmap_shared(file, region.offset, region.len) {
+                    warn!("write back shared mapping at {:#x} failed: {:?}", region.start, e);
+                }
+            }
+        }
+    }
+
     /// 设置内存映射的保护位，函数会检查传入的`start`和`len`所指示的内存映射区是否已经处于被映射状态，如果是，则将对应内存映射区的保护位与`prot`做或运算。
     pub fn map_protect(&mut self, start: usize, len: usize, prot: ProtFlags) -> AlienResult<()> {
         // check whether the start is in mmap
@@ -1382,6 +1503,8 @@ impl Task {
         inner.children.clear();
         let thread_number = inner.thread_number;
         if thread_number == 0 {
+            inner.release_shared_mappings();
+            inner.mmap = MMapInfo::new();
             let _ = inner.fd_table.lock().clear();
             drop(inner);
         }
@@ -1407,7 +1530,6 @@ impl Task {
         let k_stack = Stack::new(USER_KERNEL_STACK_SIZE / FRAME_SIZE)?;
         let k_stack_top = k_stack.top();
         let stack_info = elf_info.stack_top - USER_STACK_SIZE..elf_info.stack_top;
-        let cwd = vfs::system_root_fs();
 
         let process = Task {
             tid,
@@ -1429,7 +1551,7 @@ impl Task {
                     Arc::new(Mutex::new(fd_table))
                 },
                 context: Context::new(trap_return as usize, k_stack_top),
-                fs_info: FsContext::new(cwd.clone(), cwd),
+                fs_info: FsContext::init(),
                 statistical_data: StatisticalData::new(),
                 timer: TaskTimer::default(),
                 exit_code: 0,
@@ -1461,6 +1583,9 @@ impl Task {
                 },
                 exit_group: false,
                 kretprobe_instances: Vec::new(),
+                args: vec![name.to_string()],
+                envs: Vec::new(),
+                exe: super::procfs::absolute_path(&vfs::system_root_fs(), name),
             }),
             send_sigchld_when_exit: false,
         };
@@ -1514,11 +1639,33 @@ impl Task {
             inner.address_space.clone()
         } else {
             // to create process
-            let address_space =
+            let mut address_space =
                 build_cow_address_space(&mut inner.address_space.lock(), inner.shm.clone());
+            // 共享文件映射不做写时复制, 子进程映射同一组共享页
+            for region in inner.mmap.regions() {
+                if let Some(file) = region.shared_file() {
+                    let mut map_flags: MappingFlags = region.prot.into();
+                    map_flags |= "VAD".into();
+                    filemap::map_shared(
+                        &mut address_space,
+                        file,
+                        region.start,
+                        region.map_len,
+                        region.offset,
+                        map_flags,
+                    )
+                    .ok()?;
+                }
+            }
             Arc::new(Mutex::new(address_space))
         };
 
+        let fs_info = if flag.contains(CloneFlags::CLONE_NEWNS) {
+            inner.fs_info.unshare_mnt_ns().ok()?
+        } else {
+            inner.fs_info.clone()
+        };
+
         let fd_table = if flag.contains(CloneFlags::CLONE_FILES) {
             inner.fd_table.clone()
         } else {
@@ -1629,7 +1776,7 @@ impl Task {
                 children: Vec::new(),
                 fd_table,
                 context: Context::new(trap_return as usize, k_stack_top),
-                fs_info: inner.fs_info.clone(),
+                fs_info,
                 statistical_data: StatisticalData::new(),
                 timer: TaskTimer::default(),
                 exit_code: 0,
@@ -1666,10 +1813,14 @@ impl Task {
                 },
                 exit_group: false,
                 kretprobe_instances: Vec::new(),
+                args: inner.args.clone(),
+                envs: inner.envs.clone(),
+                exe: inner.exe.clone(),
             }),
             send_sigchld_when_exit: sig == SignalNumber::SIGCHLD,
         };
         let task = Arc::new(task);
+        super::procfs::register_task(&task);
         if !flag.contains(CloneFlags::CLONE_PARENT) {
             inner.children.push(task.clone());
         }
@@ -1700,6 +1851,7 @@ impl Task {
         let elf_info = elf_info.unwrap();
         let mut inner = self.inner.lock();
         assert_eq!(inner.thread_number, 0);
+        inner.exe = super::procfs::absolute_path(&inner.fs_info.cwd, name);
         let name = elf_info.name;
         let address_space = elf_info.address_space;
         // reset the address space
@@ -1710,6 +1862,7 @@ impl Task {
             elf_info.heap_bottom,
         )));
         // reset the mmap
+        inner.release_shared_mappings();
         inner.mmap = MMapInfo::new();
         // set the name of the process
         inner.name = name.to_string();
@@ -1737,6 +1890,8 @@ impl Task {
         } else {
             env
         };
+        inner.args = args.clone();
+        inner.envs = env.clone();
         // we need make sure the args and env size is less than 4KB
         let phy_button = inner.transfer_raw(elf_info.stack_top - FRAME_SIZE);
         let mut user_stack = UserStack::new(phy_button + FRAME_SIZE, elf_info.stack_top);
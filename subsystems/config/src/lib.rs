@@ -38,6 +38,16 @@ pub const USER_STACK_SIZE: usize = 0x50_000;
 /// pipe缓冲区大小
 pub const PIPE_BUF: usize = 65536;
 
+/// 页缓存最多缓存的页数, 超出时回收最久未访问的页
+pub const PAGE_CACHE_MAX_PAGES: usize = 0x4000;
+
+/// 块设备缓存的脏页超过这个时间 (ms) 后由写回线程写回
+pub const DIRTY_EXPIRE_MS: usize = 3000;
+/// 块设备缓存中脏页所占的百分比超过这个值时, 写回线程写回最老的脏页
+pub const DIRTY_BACKGROUND_RATIO: usize = 10;
+/// 写回线程的唤醒间隔 (ms)
+pub const WRITEBACK_INTERVAL_MS: usize = 500;
+
 /// 线程数量大小限制
 pub const MAX_THREAD_NUM: usize = 65536;
 /// 描述符数量大小限制
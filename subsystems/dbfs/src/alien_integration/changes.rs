@@ -0,0 +1,42 @@
+//! `/proc/fs/dbfs/<mount>/changes`: 已提交事务的变更流
+//!
+//! 记录格式见 [`crate::cdc`]。文件偏移就是流中的字节位置, 读到末尾返回 0,
+//! 之后的提交会接着追加; 偏移早于保留窗口时读取返回 EINVAL。
+//!
+//! 续读: `ioctl(fd, CDC_IOC_OFFSET_AFTER, lsn)` 返回第一条提交 LSN 大于 `lsn`
+//! 的记录的位置, `lseek` 到该位置后继续读。
+
+use vfscore::{error::VfsError, VfsResult};
+
+use super::stats::find_by_name;
+pub use crate::cdc::CDC_IOC_OFFSET_AFTER;
+
+/// 从流位置 `offset` 读取, 挂载不存在时返回 ENOENT
+pub fn read(name: &str, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+    let sb = find_by_name(name).ok_or(VfsError::NoEntry)?;
+    let changes = sb.changes().lock();
+    changes.read_at(offset, buf).map_err(|e| {
+        log::warn!(
+            "⚠ DBFS: Change stream offset {} of {} expired (oldest {}): {:?}",
+            offset,
+            name,
+            changes.start(),
+            e
+        );
+        VfsError::Invalid
+    })
+}
+
+/// 当前流的末尾位置
+pub fn end(name: &str) -> VfsResult<u64> {
+    let sb = find_by_name(name).ok_or(VfsError::NoEntry)?;
+    let end = sb.changes().lock().end();
+    Ok(end)
+}
+
+/// 第一条提交 LSN 大于 `lsn` 的记录的位置
+pub fn offset_after(name: &str, lsn: u64) -> VfsResult<u64> {
+    let sb = find_by_name(name).ok_or(VfsError::NoEntry)?;
+    let offset = sb.changes().lock().offset_after(lsn);
+    Ok(offset)
+}
@@ -0,0 +1,643 @@
+//! 文件系统级崩溃一致性测试
+//!
+//! [`crate::crash_harness`] 只检查 WAL 本身的不变量。这里在
+//! [`RecordingBlockDevice`] 上挂载完整的 DBFS, 通过 VFS inode 接口运行工作负载,
+//! 然后把每个崩溃状态当作新的块设备重新挂载 (崩溃恢复 + 重放到 inode 树),
+//! 比较恢复出的目录树:
+//!
+//! - 恢复出的目录树等于某次提交之后的目录树 (原子性), 且不早于 flush 屏障
+//!   已完成的最后一次提交 (持久性)
+//! - 恢复后的文件系统可以继续提交并正常卸载, 再次挂载后新旧内容都在
+//!
+//! 不依赖内核服务 (见 [`super::sys`]), 由 `dbfs_crash` 在 host 上运行。
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use vfscore::{
+    error::VfsError,
+    file::VfsFile,
+    inode::VfsInode,
+    utils::{VfsNodePerm, VfsNodeType, VfsRenameFlag},
+};
+
+use crate::{
+    common::{DbfsError, DbfsResult},
+    crash_harness::{
+        build_image, enumerate_crash_states, CrashReport, CrashState, IoEvent,
+        RecordingBlockDevice, CRASH_DEVICE_SIZE, SECTOR_SIZE,
+    },
+    log_manager::{BlockDevice, MemBlockDevice},
+    wal::{TxId, Wal},
+};
+use super::{
+    fstype::DbfsFsType,
+    inode::{begin_tx_on, commit_tx, rollback_tx},
+    options::DbfsMountOptions,
+    superblock::{register_mount, DbfsSuperBlock},
+};
+
+/// 目录树: 路径 -> 文件内容 (目录为 None)
+pub type TreeImage = BTreeMap<String, Option<Vec<u8>>>;
+
+const CRASH_DB_PATH: &str = "/crash";
+const CRASH_MOUNT_POINT: &str = "/crash";
+/// 恢复后追加的文件, 工作负载不使用该路径
+const AFTER_RECOVERY_PATH: &str = "/after-recovery";
+
+/// 在设备上挂载 DBFS 并登记到挂载表 (事务 API 按挂载表查找事务所属的挂载)
+fn mount(
+    device: Arc<dyn BlockDevice>,
+    options: &DbfsMountOptions,
+) -> DbfsResult<Arc<DbfsSuperBlock>> {
+    let wal = Wal::with_device(format!("{}/.wal", CRASH_DB_PATH), device)?;
+    let fs_type = Arc::new(DbfsFsType::new(CRASH_DB_PATH.to_string()));
+    let sb = DbfsSuperBlock::with_wal(
+        fs_type,
+        CRASH_MOUNT_POINT.to_string(),
+        options.clone(),
+        wal,
+    )?;
+    register_mount(sb.clone());
+    Ok(sb)
+}
+
+/// 挂载的当前目录树
+fn tree_of(sb: &DbfsSuperBlock) -> DbfsResult<TreeImage> {
+    let mut tree = TreeImage::new();
+    sb.resolve_dir("/")?.collect_tree(&mut tree)?;
+    Ok(tree)
+}
+
+/// VFS 错误转换为工作负载的错误
+fn vfs_error(e: VfsError) -> DbfsError {
+    match e {
+        VfsError::NoEntry => DbfsError::NotFound,
+        VfsError::EExist => DbfsError::FileExists,
+        VfsError::Invalid => DbfsError::InvalidArgument,
+        _ => DbfsError::Io,
+    }
+}
+
+/// 路径拆分为 (父目录, 文件名)
+fn split_path(path: &str) -> DbfsResult<(&str, &str)> {
+    let path = path.trim_end_matches('/');
+    let pos = path.rfind('/').ok_or(DbfsError::InvalidArgument)?;
+    let name = &path[pos + 1..];
+    if name.is_empty() {
+        return Err(DbfsError::InvalidArgument);
+    }
+    Ok((&path[..pos], name))
+}
+
+// ==================== 记录 ====================
+
+/// 在记录设备上挂载的 DBFS, 同时记录每次提交之后的目录树
+///
+/// 文件操作在当前事务中执行, 和系统调用一样需要先 [`RecordedFs::begin`]
+pub struct RecordedFs {
+    sb: Arc<DbfsSuperBlock>,
+    device: Arc<RecordingBlockDevice>,
+    /// (提交完成时的 flush 屏障数, 提交之后的目录树); 第 0 项为空文件系统
+    commits: Vec<(usize, TreeImage)>,
+    options: DbfsMountOptions,
+}
+
+impl RecordedFs {
+    pub fn new(device_size: usize, options: DbfsMountOptions) -> DbfsResult<Self> {
+        let device = Arc::new(RecordingBlockDevice::new(device_size));
+        let sb = mount(device.clone(), &options)?;
+        Ok(Self {
+            sb,
+            device,
+            commits: vec![(0, TreeImage::new())],
+            options,
+        })
+    }
+
+    pub fn begin(&self) -> TxId {
+        begin_tx_on(&self.sb, None)
+    }
+
+    pub fn mkdir(&self, path: &str) -> DbfsResult<()> {
+        self.create_entry(path, VfsNodeType::Dir)
+    }
+
+    pub fn create(&self, path: &str) -> DbfsResult<()> {
+        self.create_entry(path, VfsNodeType::File)
+    }
+
+    fn create_entry(&self, path: &str, ty: VfsNodeType) -> DbfsResult<()> {
+        let (parent, name) = split_path(path)?;
+        self.sb
+            .resolve_dir(parent)?
+            .create(name, ty, VfsNodePerm::from_bits_truncate(0o644), None)
+            .map_err(vfs_error)?;
+        Ok(())
+    }
+
+    pub fn write(&self, path: &str, offset: u64, data: &[u8]) -> DbfsResult<()> {
+        self.sb
+            .resolve(path)?
+            .write_at(offset, data)
+            .map_err(vfs_error)?;
+        Ok(())
+    }
+
+    pub fn unlink(&self, path: &str) -> DbfsResult<()> {
+        let (parent, name) = split_path(path)?;
+        self.sb.resolve_dir(parent)?.unlink(name).map_err(vfs_error)
+    }
+
+    /// 设置 FS_COMPR_FL
+    pub fn compress(&self, path: &str) -> DbfsResult<()> {
+        self.sb.resolve(path)?.set_compressed(true).map_err(vfs_error)
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> DbfsResult<()> {
+        let (from_parent, from_name) = split_path(from)?;
+        let (to_parent, to_name) = split_path(to)?;
+        let new_parent = self.sb.resolve_dir(to_parent)?;
+        self.sb
+            .resolve_dir(from_parent)?
+            .rename_to(from_name, new_parent, to_name, VfsRenameFlag::empty())
+            .map_err(vfs_error)
+    }
+
+    /// 提交并记录提交之后的目录树
+    pub fn commit(&mut self, tx_id: TxId) -> DbfsResult<()> {
+        commit_tx(tx_id)?;
+        let tree = tree_of(&self.sb)?;
+        self.commits.push((self.device.flush_count(), tree));
+        Ok(())
+    }
+
+    pub fn rollback(&self, tx_id: TxId) {
+        rollback_tx(tx_id);
+    }
+
+    pub fn checkpoint(&self) -> DbfsResult<()> {
+        self.sb.checkpoint().map(|_| ())
+    }
+
+    /// 正常卸载 (刷 WAL 并 checkpoint), 之后不能再执行操作
+    pub fn unmount(&self) -> DbfsResult<()> {
+        self.sb.shutdown()
+    }
+
+    /// 结束记录; 没有卸载的挂载被直接丢弃, 相当于掉电
+    pub fn finish(self) -> FsCrashRecording {
+        self.sb.detach();
+        FsCrashRecording {
+            initial: self.device.initial_image(),
+            log: self.device.events(),
+            commits: self.commits,
+            options: self.options,
+        }
+    }
+}
+
+/// 一次文件系统工作负载运行的完整记录
+pub struct FsCrashRecording {
+    pub initial: Vec<u8>,
+    pub log: Vec<IoEvent>,
+    pub commits: Vec<(usize, TreeImage)>,
+    /// 记录和重新挂载时使用的挂载选项
+    pub options: DbfsMountOptions,
+}
+
+impl FsCrashRecording {
+    pub fn flush_count(&self) -> usize {
+        self.log
+            .iter()
+            .filter(|e| matches!(e, IoEvent::Flush))
+            .count()
+    }
+
+    pub fn write_count(&self) -> usize {
+        self.log.len() - self.flush_count()
+    }
+}
+
+// ==================== 检查 ====================
+
+/// 重新挂载崩溃状态并检查目录树, 返回第一个被违反的不变量
+pub fn check_fs_state(recording: &FsCrashRecording, state: &CrashState) -> Result<(), String> {
+    let image = build_image(&recording.initial, &recording.log, state);
+    let device = Arc::new(MemBlockDevice::from_image(image));
+    let sb = mount(device.clone(), &recording.options)
+        .map_err(|e| format!("remount failed: {:?}", e))?;
+    let result = check_recovered(recording, state, &sb);
+    sb.detach();
+    let recovered = result?;
+    check_append_after_recovery(device, &recording.options, recovered)
+}
+
+/// 检查恢复出的目录树, 返回该目录树
+fn check_recovered(
+    recording: &FsCrashRecording,
+    state: &CrashState,
+    sb: &DbfsSuperBlock,
+) -> Result<TreeImage, String> {
+    let recovered = tree_of(sb).map_err(|e| format!("cannot read recovered tree: {:?}", e))?;
+    // flush 屏障已完成的最后一次提交
+    let durable = recording
+        .commits
+        .iter()
+        .rposition(|(barrier, _)| *barrier <= state.barrier)
+        .unwrap_or(0);
+    let matched = recording.commits[durable..]
+        .iter()
+        .any(|(_, tree)| *tree == recovered);
+    if !matched {
+        let earlier = recording.commits[..durable]
+            .iter()
+            .any(|(_, tree)| *tree == recovered);
+        return Err(if earlier {
+            format!(
+                "lost commit: recovered tree predates durable commit {}",
+                durable
+            )
+        } else {
+            format!(
+                "torn state: recovered tree {:?} matches no commit",
+                paths(&recovered)
+            )
+        });
+    }
+    Ok(recovered)
+}
+
+/// 恢复后的文件系统必须可以继续使用: 提交一个新文件并正常卸载 (checkpoint)
+/// 后再次挂载, 新文件和之前恢复出的内容都应该在
+fn check_append_after_recovery(
+    device: Arc<MemBlockDevice>,
+    options: &DbfsMountOptions,
+    recovered: TreeImage,
+) -> Result<(), String> {
+    let sb = mount(device.clone(), options)
+        .map_err(|e| format!("second remount failed: {:?}", e))?;
+    let tx_id = begin_tx_on(&sb, None);
+    let appended = sb
+        .resolve_dir("/")
+        .and_then(|root| {
+            let name = &AFTER_RECOVERY_PATH[1..];
+            let file = root
+                .create(
+                    name,
+                    VfsNodeType::File,
+                    VfsNodePerm::from_bits_truncate(0o644),
+                    None,
+                )
+                .map_err(vfs_error)?;
+            file.write_at(0, b"after recovery").map_err(vfs_error)
+        })
+        .and_then(|_| commit_tx(tx_id));
+    let unmounted = appended.and_then(|_| sb.shutdown());
+    if unmounted.is_err() {
+        rollback_tx(tx_id);
+        sb.detach();
+    }
+    unmounted.map_err(|e| format!("append after recovery failed: {:?}", e))?;
+
+    let sb = mount(device, options).map_err(|e| format!("third remount failed: {:?}", e))?;
+    let tree = tree_of(&sb);
+    sb.detach();
+    let tree = tree.map_err(|e| format!("cannot read tree after append: {:?}", e))?;
+
+    let mut expected = recovered;
+    expected.insert(
+        AFTER_RECOVERY_PATH.to_string(),
+        Some(b"after recovery".to_vec()),
+    );
+    if tree != expected {
+        return Err(format!(
+            "tree changed across remount: {:?} -> {:?}",
+            paths(&expected),
+            paths(&tree)
+        ));
+    }
+    Ok(())
+}
+
+fn paths(tree: &TreeImage) -> Vec<&str> {
+    tree.keys().map(|path| path.as_str()).collect()
+}
+
+/// 枚举并检查一次记录的全部崩溃状态
+pub fn explore_fs(recording: &FsCrashRecording, max_subset: usize) -> CrashReport {
+    let mut report = CrashReport::default();
+    for state in enumerate_crash_states(&recording.log, max_subset) {
+        report.states += 1;
+        if let Err(reason) = check_fs_state(recording, &state) {
+            report.failures.push((state, reason));
+        }
+    }
+    report
+}
+
+// ==================== 内置工作负载 ====================
+
+/// 工作负载: 在 [`RecordedFs`] 上执行一系列事务
+pub type FsCrashWorkload = fn(&mut RecordedFs) -> DbfsResult<()>;
+
+/// 内置文件系统工作负载: (名称, 工作负载, 挂载选项)
+pub const FS_CRASH_WORKLOADS: &[(&str, FsCrashWorkload, &str)] = &[
+    ("fs_create_write", workload_create_write, ""),
+    ("fs_unlink_rollback", workload_unlink_rollback, ""),
+    ("fs_checkpoint", workload_checkpoint, ""),
+    ("fs_log_reuse", workload_log_reuse, ""),
+    ("fs_clean_unmount", workload_clean_unmount, ""),
+    ("fs_rename", workload_rename, ""),
+    ("fs_compress", workload_compress, ""),
+    ("fs_dedup", workload_dedup, "dedup"),
+];
+
+/// 用给定的挂载选项运行文件系统工作负载并返回记录
+pub fn record_fs_workload(
+    workload: FsCrashWorkload,
+    options: &str,
+) -> DbfsResult<FsCrashRecording> {
+    let options = DbfsMountOptions::parse(options.as_bytes())?;
+    let mut fs = RecordedFs::new(CRASH_DEVICE_SIZE, options)?;
+    if let Err(e) = workload(&mut fs) {
+        fs.finish();
+        return Err(e);
+    }
+    Ok(fs.finish())
+}
+
+fn workload_create_write(fs: &mut RecordedFs) -> DbfsResult<()> {
+    let tx = fs.begin();
+    fs.mkdir("/docs")?;
+    fs.create("/docs/a")?;
+    fs.write("/docs/a", 0, b"first version")?;
+    fs.commit(tx)?;
+
+    // 跨越多个扇区的写入可能被撕裂
+    let tx = fs.begin();
+    fs.create("/docs/b")?;
+    fs.write("/docs/b", 0, &[0x5a; 2 * SECTOR_SIZE + 100])?;
+    fs.commit(tx)?;
+
+    let tx = fs.begin();
+    fs.write("/docs/a", 6, b"VERSION, extended")?;
+    fs.write("/docs/b", 4096, b"tail")?;
+    fs.commit(tx)?;
+    Ok(())
+}
+
+fn workload_unlink_rollback(fs: &mut RecordedFs) -> DbfsResult<()> {
+    let tx = fs.begin();
+    fs.create("/a")?;
+    fs.create("/b")?;
+    fs.write("/a", 0, b"keep me")?;
+    fs.write("/b", 0, b"delete me")?;
+    fs.commit(tx)?;
+
+    // 回滚的事务不能出现在任何崩溃状态中
+    let tx = fs.begin();
+    fs.unlink("/a")?;
+    fs.write("/b", 0, b"never visible")?;
+    fs.rollback(tx);
+
+    let tx = fs.begin();
+    fs.unlink("/b")?;
+    fs.mkdir("/dir")?;
+    fs.create("/dir/c")?;
+    fs.commit(tx)?;
+
+    // 未提交: 崩溃时丢失
+    let _tx = fs.begin();
+    fs.write("/a", 0, b"pending")?;
+    Ok(())
+}
+
+fn workload_checkpoint(fs: &mut RecordedFs) -> DbfsResult<()> {
+    let tx = fs.begin();
+    fs.mkdir("/ckpt")?;
+    fs.create("/ckpt/a")?;
+    fs.write("/ckpt/a", 0, b"checkpointed")?;
+    fs.commit(tx)?;
+    fs.checkpoint()?;
+
+    let tx = fs.begin();
+    fs.create("/ckpt/b")?;
+    fs.write("/ckpt/b", 0, b"after checkpoint")?;
+    fs.commit(tx)?;
+
+    let tx = fs.begin();
+    fs.unlink("/ckpt/a")?;
+    fs.commit(tx)?;
+    fs.checkpoint()?;
+    Ok(())
+}
+
+/// 写入量超过设备容量, 依靠提交时自动触发的 checkpoint 回收日志空间
+fn workload_log_reuse(fs: &mut RecordedFs) -> DbfsResult<()> {
+    let tx = fs.begin();
+    fs.create("/ring")?;
+    fs.commit(tx)?;
+    for round in 0..12u8 {
+        let tx = fs.begin();
+        fs.write("/ring", 0, &[round; 6 * 1024])?;
+        fs.commit(tx)?;
+    }
+    Ok(())
+}
+
+fn workload_clean_unmount(fs: &mut RecordedFs) -> DbfsResult<()> {
+    let tx = fs.begin();
+    fs.mkdir("/home")?;
+    fs.create("/home/notes")?;
+    fs.write("/home/notes", 0, b"saved before unmount")?;
+    fs.commit(tx)?;
+    fs.unmount()
+}
+
+/// 重命名目录 (连同其下的文件)、覆盖已有文件, 回滚的重命名不可见
+fn workload_rename(fs: &mut RecordedFs) -> DbfsResult<()> {
+    let tx = fs.begin();
+    fs.mkdir("/src")?;
+    fs.create("/src/a")?;
+    fs.write("/src/a", 0, b"moved with its directory")?;
+    fs.create("/old")?;
+    fs.write("/old", 0, b"replaced")?;
+    fs.commit(tx)?;
+
+    let tx = fs.begin();
+    fs.rename("/src", "/dst")?;
+    fs.write("/dst/a", 0, b"MOVED")?;
+    fs.commit(tx)?;
+
+    let tx = fs.begin();
+    fs.rename("/dst/a", "/old")?;
+    fs.commit(tx)?;
+
+    let tx = fs.begin();
+    fs.rename("/old", "/never")?;
+    fs.rollback(tx);
+    fs.checkpoint()?;
+
+    let tx = fs.begin();
+    fs.rename("/dst", "/final")?;
+    fs.commit(tx)?;
+    Ok(())
+}
+
+/// 压缩文件的写入和 checkpoint 镜像在 WAL 中压缩存放
+fn workload_compress(fs: &mut RecordedFs) -> DbfsResult<()> {
+    let tx = fs.begin();
+    fs.mkdir("/z")?;
+    fs.compress("/z")?;
+    fs.create("/z/f")?;
+    fs.write("/z/f", 0, &b"squeeze ".repeat(1024))?;
+    fs.commit(tx)?;
+    fs.checkpoint()?;
+
+    let tx = fs.begin();
+    fs.write("/z/f", 4096, &[0x33; 2 * SECTOR_SIZE])?;
+    fs.commit(tx)?;
+    Ok(())
+}
+
+/// 去重挂载: 相同内容的写入在 WAL 中只记录引用, 包括 checkpoint 镜像之后的写入
+fn workload_dedup(fs: &mut RecordedFs) -> DbfsResult<()> {
+    let data: Vec<u8> = (0..6000u32).map(|i| (i * 31 % 251) as u8).collect();
+    let tx = fs.begin();
+    fs.create("/orig")?;
+    fs.write("/orig", 0, &data)?;
+    fs.commit(tx)?;
+
+    let tx = fs.begin();
+    fs.create("/copy")?;
+    fs.write("/copy", 0, &data)?;
+    fs.write("/copy", 100, b"edit")?;
+    fs.commit(tx)?;
+    fs.checkpoint()?;
+
+    let tx = fs.begin();
+    fs.create("/again")?;
+    fs.write("/again", 0, &data)?;
+    fs.commit(tx)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crash_harness::DEFAULT_MAX_SUBSET_WRITES;
+
+    #[test]
+    fn test_flags_survive_remount() {
+        let options = DbfsMountOptions::default();
+        let mut fs = RecordedFs::new(CRASH_DEVICE_SIZE, options.clone()).unwrap();
+        workload_compress(&mut fs).unwrap();
+        // 回滚的标志修改不可见
+        let tx = fs.begin();
+        fs.create("/plain").unwrap();
+        fs.commit(tx).unwrap();
+        let tx = fs.begin();
+        fs.compress("/plain").unwrap();
+        fs.rollback(tx);
+        fs.unmount().unwrap();
+
+        let sb = mount(fs.device.clone(), &options).unwrap();
+        let compressed = |path: &str| sb.resolve(path).unwrap().file_sizes().map(|s| s.2);
+        assert_eq!(compressed("/z/f"), Some(true));
+        assert_eq!(compressed("/plain"), Some(false));
+        sb.detach();
+        fs.finish();
+    }
+
+    #[test]
+    fn test_failed_commit_is_undone() {
+        let options = DbfsMountOptions::default();
+        let mut fs = RecordedFs::new(16 * 1024, options.clone()).unwrap();
+        let tx = fs.begin();
+        fs.create("/f").unwrap();
+        fs.write("/f", 0, b"kept").unwrap();
+        fs.commit(tx).unwrap();
+
+        // WAL 放不下这个事务: 提交失败, 修改被撤销, 之后的提交也不会把它写出去
+        let data: Vec<u8> = (0..32 * 1024u32).map(|i| (i * 31 % 251) as u8).collect();
+        let tx = fs.begin();
+        fs.write("/f", 0, &data).unwrap();
+        fs.create("/g").unwrap();
+        assert_eq!(fs.commit(tx), Err(DbfsError::Io));
+        let tx = fs.begin();
+        fs.create("/h").unwrap();
+        fs.commit(tx).unwrap();
+        let tree = tree_of(&fs.sb).unwrap();
+        assert_eq!(tree.get("/f"), Some(&Some(b"kept".to_vec())));
+        assert!(!tree.contains_key("/g"));
+
+        let device = fs.device.clone();
+        fs.finish();
+        let sb = mount(device, &options).unwrap();
+        assert_eq!(tree_of(&sb).unwrap(), tree);
+        sb.detach();
+    }
+
+    #[test]
+    fn test_changes_follow_flushed_commits() {
+        use vfscore::superblock::VfsSuperBlock;
+
+        use super::super::options::Durability;
+
+        // 异步提交: 提交记录刷盘之后才出现在变更流中
+        let options = DbfsMountOptions {
+            durability: Durability::Async,
+            ..DbfsMountOptions::default()
+        };
+        let mut fs = RecordedFs::new(CRASH_DEVICE_SIZE, options).unwrap();
+        let tx = fs.begin();
+        fs.create("/a").unwrap();
+        fs.commit(tx).unwrap();
+        assert_eq!(fs.sb.changes().lock().end(), 0);
+        fs.sb.sync_fs(true).unwrap();
+        assert!(fs.sb.changes().lock().end() > 0);
+        fs.finish();
+
+        // 同步提交失败时不出现在变更流中
+        let mut fs = RecordedFs::new(16 * 1024, DbfsMountOptions::default()).unwrap();
+        let tx = fs.begin();
+        fs.create("/f").unwrap();
+        fs.commit(tx).unwrap();
+        let end = fs.sb.changes().lock().end();
+        let tx = fs.begin();
+        fs.write("/f", 0, &[7; 32 * 1024]).unwrap();
+        assert_eq!(fs.commit(tx), Err(DbfsError::Io));
+        assert_eq!(fs.sb.changes().lock().end(), end);
+        fs.finish();
+    }
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("/a"), Ok(("", "a")));
+        assert_eq!(split_path("/a/b/"), Ok(("/a", "b")));
+        assert_eq!(split_path("a"), Err(DbfsError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_fs_crash_workloads() {
+        for (name, workload, options) in FS_CRASH_WORKLOADS {
+            let recording = record_fs_workload(*workload, options)
+                .unwrap_or_else(|e| panic!("{}: workload failed: {:?}", name, e));
+            assert!(recording.commits.len() > 1, "{}: nothing committed", name);
+            let report = explore_fs(&recording, DEFAULT_MAX_SUBSET_WRITES);
+            assert!(report.states > 0);
+            assert!(
+                report.failures.is_empty(),
+                "{}: {:?}",
+                name,
+                &report.failures[..report.failures.len().min(3)]
+            );
+        }
+    }
+}
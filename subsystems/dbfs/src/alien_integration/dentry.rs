@@ -20,7 +20,7 @@ struct DbfsDentryInner {
     parent: Option<Arc<dyn VfsDentry>>,
     inode: Arc<dyn VfsInode>,
     name: String,
-    children: BTreeMap<String, Arc<dyn VfsDentry>>,
+    children: BTreeMap<String, Arc<DbfsDentry>>,
 }
 
 impl DbfsDentry {
@@ -35,6 +35,18 @@ impl DbfsDentry {
             }),
         }
     }
+
+    /// 子树中是否有 dentry 被目录树之外引用 (打开的文件、工作目录等)
+    ///
+    /// 每个子 dentry 被父目录的 children 持有一次, 它的每个子 dentry 又通过
+    /// parent 持有它一次; 超出这些的引用来自外部。
+    pub(super) fn has_external_refs(&self) -> bool {
+        let inner = self.inner.lock();
+        inner.children.values().any(|child| {
+            let internal = 1 + child.inner.lock().children.len();
+            Arc::strong_count(child) > internal || child.has_external_refs()
+        })
+    }
 }
 
 impl VfsDentry for DbfsDentry {
@@ -66,7 +78,10 @@ impl VfsDentry for DbfsDentry {
         let inner = self.inner.lock();
         let inode_type = inner.inode.inode_type();
         if inode_type == VfsNodeType::Dir {
-            inner.children.get(path).cloned()
+            inner
+                .children
+                .get(path)
+                .map(|child| child.clone() as Arc<dyn VfsDentry>)
         } else {
             None
         }
@@ -96,7 +111,10 @@ impl VfsDentry for DbfsDentry {
 
     fn remove(&self, name: &str) -> Option<Arc<dyn VfsDentry>> {
         let mut inner = self.inner.lock();
-        inner.children.remove(name)
+        inner
+            .children
+            .remove(name)
+            .map(|child| child as Arc<dyn VfsDentry>)
     }
 
     fn parent(&self) -> Option<Arc<dyn VfsDentry>> {
@@ -0,0 +1,62 @@
+//! DBFS 后端存储
+//!
+//! 把挂载时传入的后端 inode (块设备或普通文件) 包装成 WAL 使用的 [`BlockDevice`]
+
+use alloc::sync::Arc;
+
+use vfscore::{inode::VfsInode, utils::VfsNodeType};
+
+use crate::{
+    common::{DbfsError, DbfsResult},
+    log_manager::BlockDevice,
+};
+
+/// 基于 VFS inode 的块设备
+pub struct InodeDevice {
+    inode: Arc<dyn VfsInode>,
+    /// 可用容量: 块设备为设备大小, 普通文件为 wal_size 选项
+    capacity: u64,
+}
+
+impl InodeDevice {
+    /// 包装后端 inode
+    ///
+    /// 只接受块设备和普通文件; 普通文件 (或报告大小为 0 的设备) 使用 `file_capacity`
+    pub fn new(inode: Arc<dyn VfsInode>, file_capacity: u64) -> DbfsResult<Self> {
+        let capacity = match inode.inode_type() {
+            VfsNodeType::BlockDevice => {
+                let size = inode.get_attr().map_err(|_| DbfsError::Io)?.st_size;
+                if size == 0 {
+                    file_capacity
+                } else {
+                    size
+                }
+            }
+            VfsNodeType::File => file_capacity,
+            _ => return Err(DbfsError::InvalidArgument),
+        };
+        Ok(Self { inode, capacity })
+    }
+}
+
+impl BlockDevice for InodeDevice {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> DbfsResult<usize> {
+        self.inode.read_at(pos, buf).map_err(|_| DbfsError::Io)
+    }
+
+    fn write_at(&self, pos: u64, buf: &[u8]) -> DbfsResult<usize> {
+        if pos + buf.len() as u64 > self.capacity {
+            return Err(DbfsError::NoSpace);
+        }
+        self.inode.write_at(pos, buf).map_err(|_| DbfsError::Io)
+    }
+
+    fn size(&self) -> u64 {
+        self.capacity
+    }
+
+    /// 块设备的 fsync 是写入屏障: 写回设备缓存并刷新设备的写缓存
+    fn flush(&self) -> DbfsResult<()> {
+        self.inode.fsync().map_err(|_| DbfsError::Io)
+    }
+}
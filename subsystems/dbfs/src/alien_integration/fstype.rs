@@ -1,46 +1,149 @@
 //! DBFS FsType for Alien Integration
 //!
 //! Phase 1: 基本挂载功能
+//!
+//! 挂载选项见 [`super::options`]。后端 (`dev`) 决定 WAL 存放位置:
+//! - 无后端: 内存 WAL
+//! - 目录: WAL 存放在该目录下的 `wal=` 文件中
+//! - 块设备或普通文件: 整个后端作为 WAL 设备
+//!
+//! 指定密钥 (`key=` / `keyfile=`) 时 WAL 和文件数据都加密存放。密钥与 WAL 不匹配
+//! 时拒绝挂载 (EACCES), WAL 记录认证失败时挂载返回 EIO。
 
-use alloc::{string::String, string::ToString, sync::Arc};
-use log::info;
+use alloc::{format, string::String, string::ToString, sync::Arc, vec};
+use log::{error, info, warn};
 use vfscore::{
     dentry::VfsDentry,
     error::VfsError,
     fstype::{FileSystemFlags, VfsFsType},
     inode::VfsInode,
+    utils::{VfsNodePerm, VfsNodeType},
     VfsResult,
 };
 
-use super::{dentry::DbfsDentry, superblock::DbfsSuperBlock};
+use crate::{
+    common::DbfsError,
+    crypt::{Cipher, MountKey, KEY_LEN},
+    wal::Wal,
+};
+use super::{
+    dentry::DbfsDentry,
+    device::InodeDevice,
+    options::DbfsMountOptions,
+    superblock::{find_mount, register_mount, DbfsSuperBlock},
+};
+
+/// sys_mount 的 MS_RDONLY 标志
+const MS_RDONLY: u32 = 1;
 
 /// DBFS Filesystem Type
 ///
 /// Phase 1: 可以在 Alien OS 中注册和挂载
 pub struct DbfsFsType {
-    /// Database path (Phase 1: 暂不使用，为未来预留)
-    _db_path: String,
+    /// Database path (用于命名内存 WAL 和日志)
+    db_path: String,
 }
 
 impl DbfsFsType {
     /// Create a new DBFS filesystem type
     pub fn new(db_path: String) -> Self {
-        Self { _db_path: db_path }
+        Self { db_path }
+    }
+
+    pub fn db_path(&self) -> &str {
+        &self.db_path
+    }
+
+    /// 读取 `keyfile=` 指定的密钥文件 (位于后端目录下)
+    fn read_key_file(name: &str, dev: Option<&Arc<dyn VfsInode>>) -> VfsResult<MountKey> {
+        let dir = match dev {
+            Some(dir) if dir.inode_type() == VfsNodeType::Dir => dir,
+            _ => {
+                warn!("⚠ DBFS: keyfile requires a backing directory");
+                return Err(VfsError::Invalid);
+            }
+        };
+        let file = dir.lookup(name)?;
+        // 十六进制形式后面可能有换行
+        let mut buf = vec![0u8; KEY_LEN * 2 + 2];
+        let len = file.read_at(0, &mut buf)?;
+        MountKey::parse(&buf[..len]).map_err(|_| {
+            warn!("⚠ DBFS: Key file {} does not hold a key", name);
+            VfsError::Invalid
+        })
+    }
+
+    /// 按挂载选项在后端上打开 WAL
+    fn open_wal(
+        &self,
+        options: &DbfsMountOptions,
+        dev: Option<Arc<dyn VfsInode>>,
+    ) -> VfsResult<Wal> {
+        let wal_path = format!("{}/{}", self.db_path, options.wal_name);
+        let backing = match dev {
+            None => {
+                info!("✓ DBFS: No backing store, using in-memory WAL");
+                return Wal::new(wal_path).map_err(|_| VfsError::IoError);
+            }
+            Some(dir) if dir.inode_type() == VfsNodeType::Dir => {
+                match dir.lookup(&options.wal_name) {
+                    Ok(inode) => inode,
+                    Err(VfsError::NoEntry) if !options.read_only => dir.create(
+                        &options.wal_name,
+                        VfsNodeType::File,
+                        VfsNodePerm::from_bits_truncate(0o600),
+                        None,
+                    )?,
+                    Err(e) => return Err(e),
+                }
+            }
+            Some(dev) => dev,
+        };
+
+        let device = InodeDevice::new(backing, options.wal_size).map_err(|e| {
+            warn!("⚠ DBFS: Unsupported backing store: {:?}", e);
+            VfsError::Invalid
+        })?;
+        let device = Arc::new(device);
+        let cipher = options.key.as_ref().map(|key| Arc::new(Cipher::new(key)));
+        Wal::open_device(wal_path, device, cipher, !options.read_only).map_err(|e| {
+            error!("✗ DBFS: Cannot open WAL on backing store: {:?}", e);
+            match e {
+                DbfsError::AccessError => VfsError::PermissionDenied,
+                DbfsError::InvalidArgument => VfsError::Invalid,
+                _ => VfsError::IoError,
+            }
+        })
     }
 }
 
 impl VfsFsType for DbfsFsType {
     fn mount(
         self: Arc<Self>,
-        _flags: u32,
-        _ab_mnt: &str,
-        _dev: Option<Arc<dyn VfsInode>>,
-        _data: &[u8],
+        flags: u32,
+        ab_mnt: &str,
+        dev: Option<Arc<dyn VfsInode>>,
+        data: &[u8],
     ) -> VfsResult<Arc<dyn VfsDentry>> {
-        info!("✓ DBFS: Mounting DBFS filesystem");
+        info!("✓ DBFS: Mounting DBFS filesystem at {}", ab_mnt);
+
+        let mut options = DbfsMountOptions::parse(data).map_err(|_| VfsError::Invalid)?;
+        if flags & MS_RDONLY != 0 {
+            options.read_only = true;
+        }
+        if let Some(name) = options.key_file.clone() {
+            options.key = Some(Self::read_key_file(&name, dev.as_ref())?);
+        }
+        let wal = self.open_wal(&options, dev)?;
 
         // Create superblock (already returns Arc)
-        let sb = DbfsSuperBlock::new(self._db_path.clone());
+        let sb = match DbfsSuperBlock::with_wal(self.clone(), ab_mnt.to_string(), options, wal) {
+            Ok(sb) => sb,
+            Err(e) => {
+                error!("✗ DBFS: Crash recovery of {} failed: {:?}", ab_mnt, e);
+                return Err(VfsError::IoError);
+            }
+        };
 
         // Create root inode using direct method (receives &Arc<Self>)
         // This bypasses the trait method which only gives &self
@@ -48,6 +151,8 @@ impl VfsFsType for DbfsFsType {
 
         // Create root dentry
         let root_dentry = Arc::new(DbfsDentry::root(root_inode));
+        sb.set_root_dentry(root_dentry.clone());
+        register_mount(sb);
 
         info!("✓ DBFS: Mount successful");
         Ok(root_dentry)
@@ -55,10 +160,18 @@ impl VfsFsType for DbfsFsType {
 
     fn kill_sb(
         &self,
-        _sb: Arc<dyn vfscore::superblock::VfsSuperBlock>,
+        sb: Arc<dyn vfscore::superblock::VfsSuperBlock>,
     ) -> VfsResult<()> {
-        info!("✓ DBFS: Unmounting DBFS");
-        Ok(())
+        let sb = find_mount(&sb).ok_or(VfsError::Invalid)?;
+        info!("✓ DBFS: Unmounting {}", sb.mount_point());
+        sb.shutdown().map_err(|e| match e {
+            // 调用方应先用 mount_busy 检查以返回 EBUSY
+            DbfsError::Busy => VfsError::Invalid,
+            _ => {
+                error!("✗ DBFS: Failed to flush {} on unmount: {:?}", sb.mount_point(), e);
+                VfsError::IoError
+            }
+        })
     }
 
     fn fs_flag(&self) -> FileSystemFlags {
@@ -69,38 +182,3 @@ impl VfsFsType for DbfsFsType {
         "dbfs".to_string()
     }
 }
-
-/// Dummy FsType for superblock.fs_type() to return
-///
-/// This is only used to satisfy the VfsSuperBlock trait requirement.
-/// The real functionality is in DbfsFsType.
-pub struct DummyFsType {
-    pub name: String,
-}
-
-impl VfsFsType for DummyFsType {
-    fn mount(
-        self: Arc<Self>,
-        _flags: u32,
-        _ab_mnt: &str,
-        _dev: Option<Arc<dyn VfsInode>>,
-        _data: &[u8],
-    ) -> VfsResult<Arc<dyn VfsDentry>> {
-        Err(VfsError::NoSys)
-    }
-
-    fn kill_sb(
-        &self,
-        _sb: Arc<dyn vfscore::superblock::VfsSuperBlock>,
-    ) -> VfsResult<()> {
-        Ok(())
-    }
-
-    fn fs_flag(&self) -> FileSystemFlags {
-        FileSystemFlags::empty()
-    }
-
-    fn fs_name(&self) -> String {
-        self.name.clone()
-    }
-}
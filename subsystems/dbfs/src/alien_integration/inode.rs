@@ -10,15 +10,24 @@
 //! - ✅ write_at: 写入文件 (记录到 WAL)
 //! - ✅ unlink: 删除文件 (记录到 WAL)
 //! - ✅ rmdir: 删除目录 (记录到 WAL)
+//! - ✅ rename_to: 同一挂载内重命名 (记录到 WAL), 不支持 RENAME_EXCHANGE
+//! - ✅ ioctl FS_IOC_GETFLAGS / FS_IOC_SETFLAGS: 透明压缩 (FS_COMPR_FL)
+//! - ✅ clone_range_from: 克隆文件区间 (reflink, 记录到 WAL, 见 [`super::reflink`])
 //!
 //! 事务性:
 //! - ✅ 所有写操作都记录到 WAL
 //! - ✅ 延迟执行 (commit 时才真正修改数据)
 //! - ✅ 支持 begin/commit/rollback
+//!
+//! 压缩:
+//! - 文件数据按 extent 存放 (见 [`crate::compress`]), 带 FS_COMPR_FL 的文件逐 extent 压缩
+//! - 目录上的 FS_COMPR_FL 由之后新建的文件和子目录继承
+//! - 修改标志立即生效, 和其他修改一样记录到 WAL 并随事务回滚;
+//!   压缩文件的写入在 WAL 中同样压缩存放
+//! - `stat` 的 st_size 为逻辑大小, st_blocks 按实际占用计算
 
 use alloc::{collections::BTreeMap, format, string::String, string::ToString, sync::Arc, vec::Vec};
-use core::sync::atomic::{AtomicU64, Ordering};
-use ksync::Mutex;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use log::{debug, error, info, warn};
 use vfscore::{
     error::VfsError,
@@ -32,19 +41,26 @@ use vfscore::{
     VfsResult,
 };
 
-use crate::wal::TxId;
-use super::superblock::DbfsSuperBlock;
-
-/// 当前事务上下文 (存储在 SuperBlock 中)
-///
-/// 每个线程/进程可以有一个活跃的事务
-/// 简化实现: 使用 SuperBlock 中的原子变量存储当前事务 ID
-static CURRENT_TX: Mutex<Option<TxId>> = Mutex::new(None);
+use crate::{
+    common::{DbfsError, DbfsResult},
+    compress::{FileData, EXTENT_SIZE},
+    wal::{Lsn, TxId, Wal, WalOp},
+};
+use super::{
+    crash::TreeImage,
+    options::IsolationLevel,
+    quota::{Charge, QuotaUsage},
+    superblock::{default_mount, mounts, DbfsSuperBlock},
+    sys::{self, Mutex},
+    txn::{
+        AbortReason, AbortStats, LockStatus, LockWait, TxInfo, TxManager, DEFAULT_TX_TIMEOUT_MS,
+    },
+};
 
 /// Inode 数据存储
 #[derive(Debug)]
 enum InodeData {
-    File { data: Vec<u8> },
+    File { data: FileData },
     Directory {
         entries: BTreeMap<String, (u64, VfsNodeType)>, // name -> (ino, type)
     },
@@ -77,16 +93,26 @@ pub struct DbfsInode {
     ino: u64,
     /// Inode 类型
     inode_type: VfsNodeType,
-    /// Inode 数据 (实际存储)
-    data: Mutex<InodeData>,
+    /// Inode 数据 (实际存储, 事务写集合通过 Arc 撤销修改)
+    data: Arc<Mutex<InodeData>>,
     /// 权限
     perm: VfsNodePerm,
     /// 下一个可用的 inode 号 (全局)
     next_ino: Arc<AtomicU64>,
     /// 文件路径 (用于 WAL 记录)
     path: Mutex<String>,
+    /// FS_COMPR_FL: 文件是否压缩存放, 目录下新建的文件是否继承压缩
+    compress: AtomicBool,
 }
 
+/// ioctl: 读取 / 设置 inode 标志 (参数为指向 int 的用户指针)
+pub const FS_IOC_GETFLAGS: u32 = 0x80086601;
+pub const FS_IOC_SETFLAGS: u32 = 0x40086602;
+/// inode 标志: 透明压缩
+pub const FS_COMPR_FL: u32 = 0x00000004;
+/// stat 报告的块大小, 也是 FICLONERANGE 偏移和长度的对齐单位
+const BLOCK_SIZE: u32 = 4096;
+
 impl DbfsInode {
     /// Create root inode (ino = 1)
     pub fn new_root(sb: Arc<DbfsSuperBlock>) -> Arc<Self> {
@@ -94,12 +120,13 @@ impl DbfsInode {
             sb,
             ino: 1,
             inode_type: VfsNodeType::Dir,
-            data: Mutex::new(InodeData::Directory {
+            data: Arc::new(Mutex::new(InodeData::Directory {
                 entries: BTreeMap::new(),
-            }),
+            })),
             perm: VfsNodePerm::from_bits_truncate(0o755),
             next_ino: Arc::new(AtomicU64::new(2)), // 下一个从 2 开始
             path: Mutex::new("/".to_string()),
+            compress: AtomicBool::new(false),
         })
     }
 
@@ -111,12 +138,16 @@ impl DbfsInode {
         type_: VfsNodeType,
     ) -> Arc<Self> {
         let ino = parent.next_ino.fetch_add(1, Ordering::SeqCst);
+        let compress = parent.compress.load(Ordering::Relaxed);
+        let cipher = sb.cipher();
+        let dedup = sb.dedup();
         let data = match type_ {
-            VfsNodeType::File => InodeData::File { data: Vec::new() },
             VfsNodeType::Dir => InodeData::Directory {
                 entries: BTreeMap::new(),
             },
-            _ => InodeData::File { data: Vec::new() },
+            _ => InodeData::File {
+                data: FileData::new(compress, cipher, dedup),
+            },
         };
 
         let perm = match type_ {
@@ -133,17 +164,546 @@ impl DbfsInode {
             format!("{}/{}", parent_path, name)
         };
 
-        Arc::new(Self {
-            sb,
+        let inode = Arc::new(Self {
+            sb: sb.clone(),
             ino,
             inode_type: type_,
-            data: Mutex::new(data),
+            data: Arc::new(Mutex::new(data)),
             perm,
             next_ino: parent.next_ino.clone(),
             path: Mutex::new(new_path),
+            compress: AtomicBool::new(compress),
+        });
+        sb.cache_inode(inode.clone());
+        inode
+    }
+
+    /// Inode 号
+    pub(super) fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    pub(super) fn is_dir(&self) -> bool {
+        self.inode_type == VfsNodeType::Dir
+    }
+
+    /// 目录中的子 inode (只查 inode 缓存)
+    pub(super) fn child(&self, name: &str) -> Option<Arc<Self>> {
+        let ino = match &*self.data.lock() {
+            InodeData::Directory { entries } => entries.get(name)?.0,
+            InodeData::File { .. } => return None,
+        };
+        self.sb.cached_inode(ino)
+    }
+
+    /// 本 inode 之下 (不含自身) 的数据字节数和 inode 数; 文件返回自身数据大小
+    pub(super) fn usage_below(&self) -> QuotaUsage {
+        let entries = match &*self.data.lock() {
+            InodeData::File { data } => {
+                return QuotaUsage {
+                    bytes: data.len() as u64,
+                    inodes: 0,
+                }
+            }
+            InodeData::Directory { entries } => {
+                entries.values().map(|&(ino, _)| ino).collect::<Vec<_>>()
+            }
+        };
+        let mut usage = QuotaUsage::default();
+        for ino in entries {
+            usage.inodes += 1;
+            if let Some(child) = self.sb.cached_inode(ino) {
+                let below = child.usage_below();
+                usage.bytes += below.bytes;
+                usage.inodes += below.inodes;
+            }
+        }
+        usage
+    }
+
+    /// 计入配额用量; 超出目录配额时以 [`AbortReason::Quota`] 中止事务
+    ///
+    /// 该操作和之后的 commit_tx 都返回 EDQUOT
+    fn charge_quota(&self, tx_id: TxId, charge: &Charge) -> VfsResult<()> {
+        self.sb.charge(charge).map_err(|_| {
+            warn!("⚠ DBFS: {} exceeds the quota on {}", tx_id, charge.path);
+            self.sb.tx().manager.lock().abort(tx_id, AbortReason::Quota);
+            VfsError::EDQUOT
         })
     }
 
+    /// 把本目录之下的子目录和文件内容作为 checkpoint 镜像记录到 WAL
+    ///
+    /// 文件按 extent 大小分段写入; 调用者持有 WAL 的锁且没有进行中的事务
+    pub(super) fn log_image(&self, wal: &mut Wal, tx_id: TxId) -> DbfsResult<()> {
+        let entries = match &*self.data.lock() {
+            InodeData::Directory { entries } => entries.clone(),
+            InodeData::File { .. } => return Ok(()),
+        };
+        if self.ino == 1 && self.compress.load(Ordering::Relaxed) {
+            wal.set_flags(tx_id, "/", FS_COMPR_FL);
+        }
+        for &(ino, _) in entries.values() {
+            let Some(child) = self.sb.cached_inode(ino) else {
+                continue;
+            };
+            let path = child.get_path();
+            let compress = child.compress.load(Ordering::Relaxed);
+            match &*child.data.lock() {
+                InodeData::Directory { .. } => {
+                    wal.mkdir(tx_id, &path);
+                    if compress {
+                        wal.set_flags(tx_id, &path, FS_COMPR_FL);
+                    }
+                }
+                InodeData::File { data } => {
+                    wal.create_file(tx_id, &path);
+                    if compress {
+                        wal.set_flags(tx_id, &path, FS_COMPR_FL);
+                    }
+                    let mut offset = 0;
+                    while offset < data.len() {
+                        let chunk = data.read_range(offset, EXTENT_SIZE.min(data.len() - offset))?;
+                        if compress {
+                            wal.write_file_lz(tx_id, &path, offset as u64, &chunk);
+                        } else {
+                            wal.write_file(tx_id, &path, offset as u64, &chunk);
+                        }
+                        offset += chunk.len();
+                    }
+                }
+            }
+            child.log_image(wal, tx_id)?;
+        }
+        Ok(())
+    }
+
+    /// 把本目录之下的目录树加入 `tree` (崩溃测试比较恢复前后的文件系统)
+    pub(super) fn collect_tree(&self, tree: &mut TreeImage) -> DbfsResult<()> {
+        let entries = match &*self.data.lock() {
+            InodeData::Directory { entries } => entries.clone(),
+            InodeData::File { .. } => return Ok(()),
+        };
+        for &(ino, _) in entries.values() {
+            let Some(child) = self.sb.cached_inode(ino) else {
+                continue;
+            };
+            let content = match &*child.data.lock() {
+                InodeData::Directory { .. } => None,
+                InodeData::File { data } => Some(data.read_range(0, data.len())?),
+            };
+            tree.insert(child.get_path(), content);
+            child.collect_tree(tree)?;
+        }
+        Ok(())
+    }
+
+    /// 挂载时把一条已提交的操作直接应用到以本 inode (根目录) 为根的目录树
+    ///
+    /// 不写 WAL, 不加锁, 只计入用量不检查配额。与 checkpoint 镜像一致,
+    /// 缺失的父目录随之创建, 写入不存在的文件时先创建文件, 删除不存在的路径被忽略。
+    pub(super) fn replay(self: &Arc<Self>, op: WalOp) -> DbfsResult<()> {
+        match op {
+            WalOp::Mkdir(path) => {
+                self.replay_entry(path, VfsNodeType::Dir)?;
+            }
+            WalOp::Create(path) => {
+                self.replay_entry(path, VfsNodeType::File)?;
+            }
+            WalOp::Delete(path) => self.replay_delete(path)?,
+            WalOp::Rename { from, to } => self.replay_rename(from, to)?,
+            WalOp::SetFlags { path, flags } => {
+                if let Some(inode) = self.resolve(path) {
+                    inode.apply_compressed(flags & FS_COMPR_FL != 0)?;
+                }
+            }
+            WalOp::Write { path, offset, data } => {
+                let file = self.replay_entry(path, VfsNodeType::File)?;
+                file.replay_update(path, |file| file.write_at(offset as usize, data))?;
+            }
+            WalOp::Clone {
+                src,
+                src_offset,
+                len,
+                dst,
+                dst_offset,
+            } => {
+                let source = match self.resolve(src) {
+                    Some(src) => match &*src.data.lock() {
+                        InodeData::File { data } => Some(data.clone()),
+                        InodeData::Directory { .. } => return Err(DbfsError::InvalidArgument),
+                    },
+                    None => None,
+                };
+                let file = self.replay_entry(dst, VfsNodeType::File)?;
+                if let Some(source) = source {
+                    let len = len.min(usize::MAX as u64) as usize;
+                    file.replay_update(dst, |file| {
+                        file.clone_range(&source, src_offset as usize, len, dst_offset as usize)
+                            .map(|_| ())
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// 按挂载内的绝对路径查找已缓存的 inode
+    fn resolve(self: &Arc<Self>, path: &str) -> Option<Arc<Self>> {
+        let mut inode = self.clone();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            inode = inode.child(name)?;
+        }
+        Some(inode)
+    }
+
+    /// 恢复时找到或创建 `path`, 缺失的父目录一并创建
+    fn replay_entry(self: &Arc<Self>, path: &str, ty: VfsNodeType) -> DbfsResult<Arc<Self>> {
+        let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+        let (last, parents) = names.split_last().ok_or(DbfsError::InvalidArgument)?;
+        let mut dir = self.clone();
+        for name in parents {
+            dir = dir.replay_child(name, VfsNodeType::Dir)?;
+        }
+        dir.replay_child(last, ty)
+    }
+
+    fn replay_child(self: &Arc<Self>, name: &str, ty: VfsNodeType) -> DbfsResult<Arc<Self>> {
+        let existing = match &*self.data.lock() {
+            InodeData::Directory { entries } => entries.get(name).map(|&(ino, _)| ino),
+            InodeData::File { .. } => return Err(DbfsError::InvalidArgument),
+        };
+        if let Some(child) = existing.and_then(|ino| self.sb.cached_inode(ino)) {
+            if child.inode_type != ty {
+                return Err(DbfsError::InvalidArgument);
+            }
+            return Ok(child);
+        }
+
+        let child = Self::new_inode(self.sb.clone(), self, name, ty);
+        if let InodeData::Directory { entries } = &mut *self.data.lock() {
+            entries.insert(name.to_string(), (child.ino, ty));
+        }
+        let _ = self
+            .sb
+            .quotas()
+            .lock()
+            .charge(&Charge::new(child.get_path(), 0, 1), false);
+        Ok(child)
+    }
+
+    fn replay_delete(self: &Arc<Self>, path: &str) -> DbfsResult<()> {
+        let (parent, name) = path.rsplit_once('/').ok_or(DbfsError::InvalidArgument)?;
+        let Some(dir) = self.resolve(parent) else {
+            return Ok(());
+        };
+        let entry = match &mut *dir.data.lock() {
+            InodeData::Directory { entries } => entries.remove(name),
+            InodeData::File { .. } => return Err(DbfsError::InvalidArgument),
+        };
+        if let Some((ino, _)) = entry {
+            let freed = self
+                .sb
+                .cached_inode(ino)
+                .map_or(QuotaUsage::default(), |inode| inode.usage_below());
+            let charge = Charge::new(
+                path.to_string(),
+                -(freed.bytes as i64),
+                -(freed.inodes as i64 + 1),
+            );
+            let _ = self.sb.quotas().lock().charge(&charge, false);
+        }
+        Ok(())
+    }
+
+    /// 恢复时把 `from` 移到 `to`; 已存在的 `to` 先被删除, `from` 不存在时忽略
+    fn replay_rename(self: &Arc<Self>, from: &str, to: &str) -> DbfsResult<()> {
+        let (from_parent, from_name) = from.rsplit_once('/').ok_or(DbfsError::InvalidArgument)?;
+        let (to_parent, to_name) = to.rsplit_once('/').ok_or(DbfsError::InvalidArgument)?;
+        let Some(from_dir) = self.resolve(from_parent) else {
+            return Ok(());
+        };
+        let entry = match &mut *from_dir.data.lock() {
+            InodeData::Directory { entries } => entries.remove(from_name),
+            InodeData::File { .. } => return Err(DbfsError::InvalidArgument),
+        };
+        let Some(entry) = entry else {
+            return Ok(());
+        };
+
+        self.replay_delete(to)?;
+        let to_dir = match self.resolve(to_parent) {
+            Some(dir) => dir,
+            None => self.replay_entry(to_parent, VfsNodeType::Dir)?,
+        };
+        match &mut *to_dir.data.lock() {
+            InodeData::Directory { entries } => entries.insert(to_name.to_string(), entry),
+            InodeData::File { .. } => return Err(DbfsError::InvalidArgument),
+        };
+        if let Some(inode) = self.sb.cached_inode(entry.0) {
+            let (release, charge) = inode.move_charges(from, to);
+            let mut quotas = self.sb.quotas().lock();
+            let _ = quotas.charge(&release, false);
+            let _ = quotas.charge(&charge, false);
+            drop(quotas);
+            inode.repath(to.to_string());
+        }
+        Ok(())
+    }
+
+    /// 把本 inode 从 `from` 移到 `to` 时 (释放, 计入) 的用量
+    fn move_charges(&self, from: &str, to: &str) -> (Charge, Charge) {
+        let usage = self.usage_below();
+        let (bytes, inodes) = (usage.bytes as i64, usage.inodes as i64 + 1);
+        (
+            Charge::new(from.to_string(), -bytes, -inodes),
+            Charge::new(to.to_string(), bytes, inodes),
+        )
+    }
+
+    /// 重命名后更新本 inode 和其下已缓存的 inode 的路径
+    fn repath(&self, path: String) {
+        let entries = match &*self.data.lock() {
+            InodeData::Directory { entries } => entries.clone(),
+            InodeData::File { .. } => BTreeMap::new(),
+        };
+        for (name, (ino, _)) in entries {
+            if let Some(child) = self.sb.cached_inode(ino) {
+                child.repath(format!("{}/{}", path.trim_end_matches('/'), name));
+            }
+        }
+        *self.path.lock() = path;
+    }
+
+    /// 恢复时修改文件内容并计入增长的用量
+    fn replay_update(
+        &self,
+        path: &str,
+        f: impl FnOnce(&mut FileData) -> DbfsResult<()>,
+    ) -> DbfsResult<()> {
+        let growth = match &mut *self.data.lock() {
+            InodeData::File { data } => {
+                let old_len = data.len();
+                f(data)?;
+                data.len().saturating_sub(old_len)
+            }
+            InodeData::Directory { .. } => return Err(DbfsError::InvalidArgument),
+        };
+        let charge = Charge::new(path.to_string(), growth as i64, 0);
+        let _ = self.sb.quotas().lock().charge(&charge, false);
+        Ok(())
+    }
+
+    /// 文件的 (逻辑大小, 实际占用, 是否压缩); 目录返回 None
+    pub(super) fn file_sizes(&self) -> Option<(u64, u64, bool)> {
+        match &*self.data.lock() {
+            InodeData::File { data } => Some((
+                data.len() as u64,
+                data.stored_len() as u64,
+                data.is_compressed(),
+            )),
+            InodeData::Directory { .. } => None,
+        }
+    }
+
+    /// 设置 FS_COMPR_FL; 文件立即按新方式重新编码
+    ///
+    /// 在当前事务中执行并记录到 WAL; 没有当前事务时在独立的事务中执行并立即
+    /// 提交。回滚时恢复原来的标志和编码。
+    pub(super) fn set_compressed(&self, compress: bool) -> VfsResult<()> {
+        self.check_writable()?;
+        let old = self.compress.load(Ordering::Relaxed);
+        if old == compress {
+            return Ok(());
+        }
+        let path = self.get_path();
+        let inode = self.sb.resolve(&path).map_err(|_| VfsError::NoEntry)?;
+        self.in_tx("Set flags", |tx_id| {
+            self.lock_for_tx(tx_id)?;
+            enlist(tx_id, &self.sb);
+            let flags = if compress { FS_COMPR_FL } else { 0 };
+            let lsn = self.sb.record_set_flags(tx_id, &path, flags);
+            track(tx_id, &self.sb, lsn, UndoOp::Flags { inode, compress: old }, None);
+            self.apply_compressed(compress).map_err(|e| {
+                error!("✗ DBFS: Corrupt extent in {}: {:?}", path, e);
+                VfsError::IoError
+            })
+        })?;
+        info!(
+            "✓ DBFS: Compression {} for {}",
+            if compress { "enabled" } else { "disabled" },
+            path
+        );
+        Ok(())
+    }
+
+    /// 修改压缩标志并重新编码文件内容 (不写 WAL)
+    fn apply_compressed(&self, compress: bool) -> DbfsResult<()> {
+        if let InodeData::File { data } = &mut *self.data.lock() {
+            data.set_compressed(compress)?;
+        }
+        self.compress.store(compress, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 把 `src` 从 `src_offset` 开始的 `len` 字节克隆到本文件的 `offset`
+    /// (reflink), 返回克隆的字节数 (截断到 `src` 末尾)
+    ///
+    /// 在当前事务中执行; 没有当前事务时在独立的事务中执行并立即提交。
+    /// 对齐的整 extent 直接共享, 之后写入任一文件时才复制。
+    pub(super) fn clone_range_from(
+        &self,
+        src: &DbfsInode,
+        src_offset: u64,
+        len: u64,
+        offset: u64,
+    ) -> VfsResult<u64> {
+        if self.inode_type != VfsNodeType::File || src.inode_type != VfsNodeType::File {
+            return Err(VfsError::IsDir);
+        }
+        self.check_writable()?;
+        self.in_tx("Clone", |tx_id| {
+            self.clone_in_tx(tx_id, src, src_offset, len, offset)
+        })
+    }
+
+    /// 把共享内存映射的页写回文件 (`msync` / `munmap`), 返回写入的字节数
+    ///
+    /// `pages` 是 (文件偏移, 页内容); 只写入和文件内容不同的部分, 不会
+    /// 让文件变长 (超出文件末尾的部分被丢弃)。所有页在同一个事务中写入:
+    /// 在当前事务中执行, 没有当前事务时在独立的事务中执行并立即提交。
+    pub(super) fn write_back_pages(&self, pages: &[(u64, &[u8])]) -> VfsResult<usize> {
+        if self.inode_type != VfsNodeType::File {
+            return Err(VfsError::IsDir);
+        }
+        let size = self.get_size();
+        let mut dirty = Vec::new();
+        for &(offset, page) in pages {
+            let start = offset as usize;
+            let len = page.len().min(size.saturating_sub(start));
+            if len == 0 {
+                continue;
+            }
+            let current = match &*self.data.lock() {
+                InodeData::File { data } => data.read_range(start, len).map_err(|e| {
+                    error!("✗ DBFS: Corrupt extent in {}: {:?}", self.get_path(), e);
+                    VfsError::IoError
+                })?,
+                InodeData::Directory { .. } => return Err(VfsError::IsDir),
+            };
+            if current[..] != page[..len] {
+                dirty.push((offset, &page[..len]));
+            }
+        }
+        if dirty.is_empty() {
+            return Ok(0);
+        }
+
+        self.check_writable()?;
+        let written = self.in_tx("Write back", |_| {
+            dirty
+                .iter()
+                .try_fold(0, |total, (offset, page)| Ok(total + self.write_at(*offset, page)?))
+        })?;
+        info!(
+            "✓ DBFS: Wrote back {} mapped pages ({} bytes) to {}",
+            dirty.len(),
+            written,
+            self.get_path()
+        );
+        Ok(written)
+    }
+
+    /// 在当前事务中执行 `f`; 没有当前事务时在独立的事务中执行并立即提交,
+    /// 出错时回滚
+    fn in_tx<T>(&self, what: &str, f: impl FnOnce(TxId) -> VfsResult<T>) -> VfsResult<T> {
+        if let Some(tx_id) = self.sb.tx().current() {
+            return f(tx_id);
+        }
+
+        let tx_id = begin_tx_on(&self.sb, None);
+        match f(tx_id) {
+            Ok(value) => {
+                commit_tx(tx_id).map_err(|e| {
+                    warn!("⚠ DBFS: {} of {} failed to commit: {:?}", what, self.get_path(), e);
+                    tx_vfs_error(e)
+                })?;
+                Ok(value)
+            }
+            Err(e) => {
+                rollback_tx(tx_id);
+                Err(e)
+            }
+        }
+    }
+
+    fn clone_in_tx(
+        &self,
+        tx_id: TxId,
+        src: &DbfsInode,
+        src_offset: u64,
+        len: u64,
+        offset: u64,
+    ) -> VfsResult<u64> {
+        self.lock_for_tx(tx_id)?;
+        if src.ino != self.ino {
+            src.lock_for_read()?;
+        }
+
+        // 先取源文件的副本 (共享 extent, 不复制数据), 同一个文件内克隆也不会死锁
+        let source = match &*src.data.lock() {
+            InodeData::File { data } => data.clone(),
+            InodeData::Directory { .. } => return Err(VfsError::IsDir),
+        };
+        let src_start = src_offset as usize;
+        let start = offset as usize;
+        let len = (len.min(usize::MAX as u64) as usize).min(source.len().saturating_sub(src_start));
+        if len == 0 {
+            return Ok(0);
+        }
+        if src.ino == self.ino && src_start < start + len && start < src_start + len {
+            return Err(VfsError::Invalid);
+        }
+
+        // 整个文件的旧内容 (同样只是共享 extent), 用于回滚
+        let old = match &*self.data.lock() {
+            InodeData::File { data } => data.clone(),
+            InodeData::Directory { .. } => return Err(VfsError::IsDir),
+        };
+        let path = self.get_path();
+        let growth = (start + len).saturating_sub(old.len());
+        let charge = Charge::new(path.clone(), growth as i64, 0);
+        self.charge_quota(tx_id, &charge)?;
+
+        enlist(tx_id, &self.sb);
+        let src_path = src.get_path();
+        let lsn = self
+            .sb
+            .record_clone(tx_id, &src_path, src_offset, len as u64, &path, offset);
+        track(
+            tx_id,
+            &self.sb,
+            lsn,
+            UndoOp::Restore {
+                data: self.data.clone(),
+                old,
+            },
+            Some(charge),
+        );
+
+        if let InodeData::File { data } = &mut *self.data.lock() {
+            data.clone_range(&source, src_start, len, start).map_err(|e| {
+                error!("✗ DBFS: Corrupt extent in {}: {:?}", src_path, e);
+                VfsError::IoError
+            })?;
+        }
+        info!(
+            "✓ DBFS: Cloned {} bytes from {}:{} to {}:{} (tx: {})",
+            len, src_path, src_offset, path, offset, tx_id
+        );
+        Ok(len as u64)
+    }
+
     /// Get current time (simplified)
     fn current_time() -> VfsTimeSpec {
         VfsTimeSpec::default()
@@ -157,16 +717,45 @@ impl DbfsInode {
         }
     }
 
-    /// Get current transaction ID
+    /// Get current transaction ID (of the current task on this mount)
     fn current_tx(&self) -> VfsResult<TxId> {
-        let tx_guard = CURRENT_TX.lock();
-        tx_guard.as_ref().ok_or(VfsError::NoSys).copied()
+        self.sb.tx().current().ok_or(VfsError::NoSys)
     }
 
     /// Get file path
     fn get_path(&self) -> String {
         self.path.lock().clone()
     }
+
+    /// 在本 inode 上为事务加排他锁
+    ///
+    /// 事务被中止时返回中止原因对应的错误 (见 [`tx_vfs_error`]), 之后 commit_tx
+    /// 返回同样的原因
+    fn lock_for_tx(&self, tx_id: TxId) -> VfsResult<()> {
+        lock_inode_on(&self.sb, tx_id, self.ino, LockWait::Block).map_err(|e| {
+            warn!("⚠ DBFS: {} cannot lock inode {}: {:?}", tx_id, self.ino, e);
+            tx_vfs_error(e)
+        })
+    }
+
+    /// 只读挂载拒绝所有修改 (EROFS)
+    fn check_writable(&self) -> VfsResult<()> {
+        if self.sb.is_read_only() {
+            return Err(VfsError::EROFS);
+        }
+        Ok(())
+    }
+
+    /// Serializable 隔离级别下, 事务内的读也对 inode 加锁并持有到事务结束
+    fn lock_for_read(&self) -> VfsResult<()> {
+        if self.sb.isolation() != IsolationLevel::Serializable {
+            return Ok(());
+        }
+        match self.sb.tx().current() {
+            Some(tx_id) => self.lock_for_tx(tx_id),
+            None => Ok(()),
+        }
+    }
 }
 
 impl VfsInode for DbfsInode {
@@ -188,6 +777,7 @@ impl VfsInode for DbfsInode {
         if self.inode_type != VfsNodeType::Dir {
             return Err(VfsError::NotDir);
         }
+        self.check_writable()?;
 
         // Check if exists
         let data = self.data.lock();
@@ -200,6 +790,7 @@ impl VfsInode for DbfsInode {
 
         // Get current transaction
         let tx_id = self.current_tx()?;
+        self.lock_for_tx(tx_id)?;
 
         // Get the new file path
         let parent_path = self.get_path();
@@ -209,9 +800,17 @@ impl VfsInode for DbfsInode {
             format!("{}/{}", parent_path, name)
         };
 
-        // Record to WAL
+        let charge = Charge::new(new_path.clone(), 0, 1);
+        self.charge_quota(tx_id, &charge)?;
+
+        // Record to WAL (目录记为 Mkdir, 恢复时据此重建节点类型)
         debug!("✓ DBFS: Recording create operation: {}", new_path);
-        self.sb.record_create(tx_id, &new_path);
+        enlist(tx_id, &self.sb);
+        let lsn = if ty == VfsNodeType::Dir {
+            self.sb.record_mkdir(tx_id, &new_path)
+        } else {
+            self.sb.record_create(tx_id, &new_path)
+        };
 
         // Create new inode (延迟执行)
         // We need Arc<Self> but only have &self, so create a temporary Arc
@@ -220,17 +819,18 @@ impl VfsInode for DbfsInode {
             sb: self.sb.clone(),
             ino: self.ino,
             inode_type: self.inode_type,
-            data: Mutex::new(match &*self.data.lock() {
+            data: Arc::new(Mutex::new(match &*self.data.lock() {
                 InodeData::File { data } => InodeData::File {
                     data: data.clone(),
                 },
                 InodeData::Directory { entries } => InodeData::Directory {
                     entries: entries.clone(),
                 },
-            }),
+            })),
             perm: self.perm,
             next_ino: self.next_ino.clone(),
             path: Mutex::new(self.get_path()),
+            compress: AtomicBool::new(self.compress.load(Ordering::Relaxed)),
         });
         let new_inode = Self::new_inode(self.sb.clone(), &temp_arc, name, ty);
 
@@ -242,6 +842,17 @@ impl VfsInode for DbfsInode {
         if let InodeData::Directory { ref mut entries } = &mut *data {
             entries.insert(name.to_string(), (new_inode.ino, ty));
         }
+        drop(data);
+        track(
+            tx_id,
+            &self.sb,
+            lsn,
+            UndoOp::Create {
+                dir: self.data.clone(),
+                name: name.to_string(),
+            },
+            Some(charge),
+        );
 
         info!("✓ DBFS: Created {} (tx: {})", new_path, tx_id);
         Ok(new_inode as Arc<dyn VfsInode>)
@@ -259,9 +870,11 @@ impl VfsInode for DbfsInode {
         if name == "." || name == ".." {
             return Err(VfsError::EExist); // Cannot delete . or ..
         }
+        self.check_writable()?;
 
         // Get current transaction
         let tx_id = self.current_tx()?;
+        self.lock_for_tx(tx_id)?;
 
         // Get the file path
         let parent_path = self.get_path();
@@ -271,16 +884,46 @@ impl VfsInode for DbfsInode {
             format!("{}/{}", parent_path, name)
         };
 
+        // 目录项不存在时不写 WAL (本事务持有目录的锁, 之后不会被删除)
+        match &*self.data.lock() {
+            InodeData::Directory { entries } if entries.contains_key(name) => {}
+            InodeData::Directory { .. } => return Err(VfsError::NoEntry),
+            InodeData::File { .. } => return Err(VfsError::NotDir),
+        }
+
         // Record to WAL
         debug!("✓ DBFS: Recording delete operation: {}", file_path);
-        self.sb.record_delete(tx_id, &file_path);
+        enlist(tx_id, &self.sb);
+        let lsn = self.sb.record_delete(tx_id, &file_path);
 
         // Remove from directory
-        let mut data = self.data.lock();
-        if let InodeData::Directory { ref mut entries } = &mut *data {
-            entries.remove(name)
-                .ok_or(VfsError::NoEntry)?;
-        }
+        let entry = match &mut *self.data.lock() {
+            InodeData::Directory { entries } => entries.remove(name).ok_or(VfsError::NoEntry)?,
+            InodeData::File { .. } => return Err(VfsError::NotDir),
+        };
+
+        // 释放被删除的文件 (或整个子目录) 的用量
+        let freed = self
+            .sb
+            .cached_inode(entry.0)
+            .map_or(QuotaUsage::default(), |inode| inode.usage_below());
+        let charge = Charge::new(
+            file_path.clone(),
+            -(freed.bytes as i64),
+            -(freed.inodes as i64 + 1),
+        );
+        let _ = self.sb.charge(&charge);
+        track(
+            tx_id,
+            &self.sb,
+            lsn,
+            UndoOp::Delete {
+                dir: self.data.clone(),
+                name: name.to_string(),
+                entry,
+            },
+            Some(charge),
+        );
 
         info!("✓ DBFS: Deleted {} (tx: {})", file_path, tx_id);
         Ok(())
@@ -305,24 +948,30 @@ impl VfsInode for DbfsInode {
                 sb: self.sb.clone(),
                 ino: self.ino,
                 inode_type: self.inode_type,
-                data: Mutex::new(match &*self.data.lock() {
+                data: Arc::new(Mutex::new(match &*self.data.lock() {
                     InodeData::File { data } => InodeData::File {
                         data: data.clone(),
                     },
                     InodeData::Directory { entries } => InodeData::Directory {
                         entries: entries.clone(),
                     },
-                }),
+                })),
                 perm: self.perm,
                 next_ino: self.next_ino.clone(),
                 path: Mutex::new(self.get_path()),
+                compress: AtomicBool::new(self.compress.load(Ordering::Relaxed)),
             }) as Arc<dyn VfsInode>);
         }
 
         // Find in directory
+        self.lock_for_read()?;
         let data = self.data.lock();
         if let InodeData::Directory { ref entries } = &*data {
             if let Some(&(ino, type_)) = entries.get(name) {
+                if let Some(inode) = self.sb.lookup_cached(ino) {
+                    return Ok(inode as Arc<dyn VfsInode>);
+                }
+
                 let parent_path = self.get_path();
                 let child_path = if parent_path.ends_with('/') {
                     format!("{}{}", parent_path, name)
@@ -330,12 +979,14 @@ impl VfsInode for DbfsInode {
                     format!("{}/{}", parent_path, name)
                 };
 
+                let compress = self.compress.load(Ordering::Relaxed);
                 let new_data = match type_ {
-                    VfsNodeType::File => InodeData::File { data: Vec::new() },
                     VfsNodeType::Dir => InodeData::Directory {
                         entries: BTreeMap::new(),
                     },
-                    _ => InodeData::File { data: Vec::new() },
+                    _ => InodeData::File {
+                        data: FileData::new(compress, self.sb.cipher(), self.sb.dedup()),
+                    },
                 };
 
                 let perm = match type_ {
@@ -344,15 +995,18 @@ impl VfsInode for DbfsInode {
                     _ => VfsNodePerm::from_bits_truncate(0o644),
                 };
 
-                return Ok(Arc::new(Self {
+                let inode = Arc::new(Self {
                     sb: self.sb.clone(),
                     ino,
                     inode_type: type_,
-                    data: Mutex::new(new_data),
+                    data: Arc::new(Mutex::new(new_data)),
                     perm,
-                    next_ino: Arc::new(AtomicU64::new(0)),
+                    next_ino: self.next_ino.clone(),
                     path: Mutex::new(child_path),
-                }) as Arc<dyn VfsInode>);
+                    compress: AtomicBool::new(compress),
+                });
+                self.sb.cache_inode(inode.clone());
+                return Ok(inode as Arc<dyn VfsInode>);
             }
         }
 
@@ -378,6 +1032,11 @@ impl VfsInode for DbfsInode {
         // Set the fields we know exist
         stat.st_ino = self.ino;
         stat.st_size = self.get_size() as u64;
+        stat.st_blksize = BLOCK_SIZE;
+        // 压缩文件按实际占用的 512 字节块数报告
+        if let InodeData::File { data } = &*self.data.lock() {
+            stat.st_blocks = data.stored_len().div_ceil(512) as u64;
+        }
         Ok(stat)
     }
 
@@ -395,12 +1054,110 @@ impl VfsInode for DbfsInode {
 
     fn rename_to(
         &self,
-        _old_name: &str,
-        _new_parent: Arc<dyn VfsInode>,
-        _new_name: &str,
-        _flag: VfsRenameFlag,
+        old_name: &str,
+        new_parent: Arc<dyn VfsInode>,
+        new_name: &str,
+        flag: VfsRenameFlag,
     ) -> VfsResult<()> {
-        Err(VfsError::NoSys)
+        if self.inode_type != VfsNodeType::Dir {
+            return Err(VfsError::NotDir);
+        }
+        if flag.contains(VfsRenameFlag::RENAME_EXCHANGE)
+            || [old_name, new_name].iter().any(|name| *name == "." || *name == "..")
+        {
+            return Err(VfsError::Invalid);
+        }
+        self.check_writable()?;
+        let new_parent = new_parent
+            .downcast_arc::<DbfsInode>()
+            .map_err(|_| VfsError::Invalid)?;
+        if !Arc::ptr_eq(&self.sb, &new_parent.sb) {
+            return Err(VfsError::Invalid);
+        }
+
+        // Get current transaction
+        let tx_id = self.current_tx()?;
+        self.lock_for_tx(tx_id)?;
+        if new_parent.ino != self.ino {
+            new_parent.lock_for_tx(tx_id)?;
+        }
+
+        let entry = match &*self.data.lock() {
+            InodeData::Directory { entries } => *entries.get(old_name).ok_or(VfsError::NoEntry)?,
+            InodeData::File { .. } => return Err(VfsError::NotDir),
+        };
+        let from = format!("{}/{}", self.get_path().trim_end_matches('/'), old_name);
+        let to = format!("{}/{}", new_parent.get_path().trim_end_matches('/'), new_name);
+        if from == to {
+            return Ok(());
+        }
+        // 目录不能移到自己之下
+        if to.starts_with(&format!("{}/", from)) {
+            return Err(VfsError::Invalid);
+        }
+        let moved = self.sb.cached_inode(entry.0).ok_or(VfsError::IoError)?;
+
+        // 被替换的目标先在同一事务中删除
+        let target = match &*new_parent.data.lock() {
+            InodeData::Directory { entries } => entries.get(new_name).copied(),
+            InodeData::File { .. } => return Err(VfsError::NotDir),
+        };
+        if let Some((target_ino, target_ty)) = target {
+            if flag.contains(VfsRenameFlag::RENAME_NOREPLACE) {
+                return Err(VfsError::EExist);
+            }
+            match (entry.1 == VfsNodeType::Dir, target_ty == VfsNodeType::Dir) {
+                (true, false) => return Err(VfsError::NotDir),
+                (false, true) => return Err(VfsError::IsDir),
+                _ => {}
+            }
+            let non_empty = self.sb.cached_inode(target_ino).map_or(false, |target| {
+                let data = target.data.lock();
+                matches!(&*data, InodeData::Directory { entries } if !entries.is_empty())
+            });
+            if non_empty {
+                return Err(VfsError::NotEmpty);
+            }
+            new_parent.unlink(new_name)?;
+        }
+
+        // 先释放原位置的用量, 同一配额目录内的移动不会误报超限
+        let (release, charge) = moved.move_charges(&from, &to);
+        let _ = self.sb.charge(&release);
+        if let Err(e) = self.charge_quota(tx_id, &charge) {
+            self.sb.uncharge(&release);
+            return Err(e);
+        }
+
+        debug!("✓ DBFS: Recording rename operation: {} -> {}", from, to);
+        enlist(tx_id, &self.sb);
+        let lsn = self.sb.record_rename(tx_id, &from, &to);
+        if let InodeData::Directory { entries } = &mut *self.data.lock() {
+            entries.remove(old_name);
+        }
+        if let InodeData::Directory { entries } = &mut *new_parent.data.lock() {
+            entries.insert(new_name.to_string(), entry);
+        }
+        moved.repath(to.clone());
+        track(
+            tx_id,
+            &self.sb,
+            lsn,
+            UndoOp::Rename {
+                from_dir: self.data.clone(),
+                from_name: old_name.to_string(),
+                to_dir: new_parent.data.clone(),
+                to_name: new_name.to_string(),
+                entry,
+                moved,
+                from: from.clone(),
+                release,
+            },
+            Some(charge),
+        );
+
+        info!("✓ DBFS: Renamed {} to {} (tx: {})", from, to, tx_id);
+        Ok(())
     }
 
     fn update_time(&self, _time: VfsTime, _now: VfsTimeSpec) -> VfsResult<()> {
@@ -413,18 +1170,14 @@ impl VfsFile for DbfsInode {
         if self.inode_type != VfsNodeType::File {
             return Err(VfsError::IsDir);
         }
+        self.lock_for_read()?;
 
         let data = self.data.lock();
         if let InodeData::File { ref data } = &*data {
-            let start = offset as usize;
-            if start >= data.len() {
-                return Ok(0);
-            }
-
-            let bytes_to_read = core::cmp::min(buf.len(), data.len() - start);
-            buf[..bytes_to_read].copy_from_slice(&data[start..start + bytes_to_read]);
-
-            Ok(bytes_to_read)
+            data.read_at(offset as usize, buf).map_err(|e| {
+                error!("✗ DBFS: Corrupt extent in {}: {:?}", self.get_path(), e);
+                VfsError::IoError
+            })
         } else {
             Err(VfsError::IsDir)
         }
@@ -434,16 +1187,38 @@ impl VfsFile for DbfsInode {
         if self.inode_type != VfsNodeType::File {
             return Err(VfsError::IsDir);
         }
+        self.check_writable()?;
 
         // Get current transaction
         let tx_id = self.current_tx()?;
+        self.lock_for_tx(tx_id)?;
 
         // Get file path
         let path = self.get_path();
+        let start = offset as usize;
+
+        // Save the overwritten bytes for savepoint / transaction rollback
+        // (本事务持有 inode 锁, 释放 data 锁之后内容不会变)
+        let (old, old_len) = match &*self.data.lock() {
+            InodeData::File { data } => {
+                let old = data.read_range(start, buf.len()).map_err(|e| {
+                    error!("✗ DBFS: Corrupt extent in {}: {:?}", path, e);
+                    VfsError::IoError
+                })?;
+                (old, data.len())
+            }
+            InodeData::Directory { .. } => return Err(VfsError::IsDir),
+        };
+
+        let growth = (start + buf.len()).saturating_sub(old_len);
+        let charge = Charge::new(path.clone(), growth as i64, 0);
+        self.charge_quota(tx_id, &charge)?;
 
         // Record to WAL
         debug!("✓ DBFS: Recording write operation: {} ({} bytes)", path, buf.len());
-        self.sb.record_write(tx_id, &path, offset, buf);
+        enlist(tx_id, &self.sb);
+        let compress = self.compress.load(Ordering::Relaxed);
+        let lsn = self.sb.record_write(tx_id, &path, offset, buf, compress);
 
         // TODO: 延迟到 commit 时才真正写入
         // Phase 2: 暂时立即写入,但已在 WAL 中记录
@@ -451,15 +1226,24 @@ impl VfsFile for DbfsInode {
         // Write data immediately
         let mut data = self.data.lock();
         if let InodeData::File { ref mut data } = &mut *data {
-            let start = offset as usize;
-
-            // Extend if necessary
-            if start + buf.len() > data.len() {
-                data.resize(start + buf.len(), 0);
-            }
+            track(
+                tx_id,
+                &self.sb,
+                lsn,
+                UndoOp::Write {
+                    data: self.data.clone(),
+                    offset: start,
+                    old,
+                    old_len,
+                },
+                Some(charge),
+            );
 
-            // Write data
-            data[start..start + buf.len()].copy_from_slice(buf);
+            // Write data (extends the file if necessary)
+            data.write_at(start, buf).map_err(|e| {
+                error!("✗ DBFS: Corrupt extent in {}: {:?}", path, e);
+                VfsError::IoError
+            })?;
 
             info!("✓ DBFS: Wrote {} bytes to {} (tx: {})", buf.len(), path, tx_id);
             Ok(buf.len())
@@ -477,87 +1261,563 @@ impl VfsFile for DbfsInode {
         // TODO: Flush WAL if needed
         Ok(())
     }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            FS_IOC_GETFLAGS => {
+                let flags = if self.compress.load(Ordering::Relaxed) {
+                    FS_COMPR_FL
+                } else {
+                    0
+                };
+                sys::write_user(arg as *mut u32, flags);
+                Ok(0)
+            }
+            FS_IOC_SETFLAGS => {
+                let flags = sys::read_user(arg as *const u32);
+                if flags & !FS_COMPR_FL != 0 {
+                    return Err(VfsError::Invalid);
+                }
+                self.set_compressed(flags & FS_COMPR_FL != 0)?;
+                Ok(0)
+            }
+            _ => Err(VfsError::Invalid),
+        }
+    }
 }
 
+
 /// ========== 事务管理 API ==========
 
-/// 全局事务 ID 计数器
-static GLOBAL_TX_ID: AtomicU64 = AtomicU64::new(1);
+/// 事务 ID 计数器
+///
+/// 事务状态属于各个挂载 (见 [`TxContext`]), 这里只保证事务 ID 在所有挂载之间唯一;
+/// 挂载时按 WAL 中已用过的 ID 推进, 同一个 WAL 中不会重用事务 ID。
+static NEXT_TX_ID: AtomicU64 = AtomicU64::new(1);
 
-/// 最大重试次数 (用于处理并发锁竞争)
-const MAX_TX_RETRY: u32 = 5;
+/// 之后开始的事务的默认超时 (新挂载继承该值)
+static DEFAULT_TX_TIMEOUT: Mutex<Option<u64>> = Mutex::new(Some(DEFAULT_TX_TIMEOUT_MS));
 
-/// Begin a new transaction (带重试机制)
-///
-/// 使用全局事务 ID,并设置到当前事务上下文
-/// 在高并发场景下，如果获取锁失败会自动重试
-pub fn begin_tx() -> TxId {
-    for retry in 0..MAX_TX_RETRY {
-        // 尝试获取 CURRENT_TX 锁
-        match CURRENT_TX.try_lock() {
-            Some(mut current_tx_guard) => {
-                // 成功获取锁，分配事务 ID
-                let tx_id = TxId::new(GLOBAL_TX_ID.fetch_add(1, Ordering::SeqCst));
-                *current_tx_guard = Some(tx_id);
-
-                if retry > 0 {
-                    log::info!("✓ DBFS: Transaction {} started (retry {})", tx_id, retry);
-                } else {
-                    log::info!("✓ DBFS: Transaction {} started", tx_id);
+/// 保证之后分配的事务 ID 不小于 `next`
+pub(super) fn reserve_tx_ids(next: u64) {
+    NEXT_TX_ID.fetch_max(next, Ordering::SeqCst);
+}
+
+/// 分配一个不属于任何 [`TxManager`] 的事务 ID (checkpoint 镜像)
+pub(super) fn alloc_tx_id() -> u64 {
+    NEXT_TX_ID.fetch_add(1, Ordering::SeqCst)
+}
+
+/// 事务写集合中一次修改的撤销信息
+enum UndoOp {
+    /// 恢复被覆盖的字节并截断回原长度
+    Write {
+        data: Arc<Mutex<InodeData>>,
+        offset: usize,
+        old: Vec<u8>,
+        old_len: usize,
+    },
+    /// 恢复 FS_COMPR_FL 和原来的编码
+    Flags {
+        inode: Arc<DbfsInode>,
+        compress: bool,
+    },
+    /// 恢复整个文件的旧内容 (克隆)
+    Restore {
+        data: Arc<Mutex<InodeData>>,
+        old: FileData,
+    },
+    /// 从父目录删除新建的目录项
+    Create {
+        dir: Arc<Mutex<InodeData>>,
+        name: String,
+    },
+    /// 把删除的目录项放回父目录
+    Delete {
+        dir: Arc<Mutex<InodeData>>,
+        name: String,
+        entry: (u64, VfsNodeType),
+    },
+    /// 把目录项移回原位置, 恢复路径并计回原位置的用量
+    Rename {
+        from_dir: Arc<Mutex<InodeData>>,
+        from_name: String,
+        to_dir: Arc<Mutex<InodeData>>,
+        to_name: String,
+        entry: (u64, VfsNodeType),
+        moved: Arc<DbfsInode>,
+        from: String,
+        release: Charge,
+    },
+}
+
+impl UndoOp {
+    fn undo(self) {
+        match self {
+            UndoOp::Write {
+                data,
+                offset,
+                old,
+                old_len,
+            } => {
+                if let InodeData::File { data } = &mut *data.lock() {
+                    let restored = data
+                        .write_at(offset, &old)
+                        .and_then(|_| data.resize(old_len));
+                    if let Err(e) = restored {
+                        error!("✗ DBFS: Cannot undo write: {:?}", e);
+                    }
                 }
-                return tx_id;
             }
-            None => {
-                // 锁被占用，记录警告并重试
-                if retry < MAX_TX_RETRY - 1 {
-                    log::warn!("⚠ DBFS: begin_tx lock contention (attempt {}/{}), retrying...",
-                              retry + 1, MAX_TX_RETRY);
-                    // 简单退避：让出 CPU 时间片
-                    core::hint::spin_loop();
-                } else {
-                    log::error!("✗ DBFS: begin_tx failed after {} retries, forcing lock acquisition", MAX_TX_RETRY);
-                    // 最后一次尝试：阻塞等待获取锁
-                    let mut current_tx_guard = CURRENT_TX.lock();
-                    let tx_id = TxId::new(GLOBAL_TX_ID.fetch_add(1, Ordering::SeqCst));
-                    *current_tx_guard = Some(tx_id);
-                    log::info!("✓ DBFS: Transaction {} started (forced)", tx_id);
-                    return tx_id;
+            UndoOp::Flags { inode, compress } => {
+                if let Err(e) = inode.apply_compressed(compress) {
+                    error!("✗ DBFS: Cannot undo flags of {}: {:?}", inode.get_path(), e);
+                }
+            }
+            UndoOp::Restore { data, old } => {
+                if let InodeData::File { data } = &mut *data.lock() {
+                    *data = old;
+                }
+            }
+            UndoOp::Create { dir, name } => {
+                if let InodeData::Directory { entries } = &mut *dir.lock() {
+                    entries.remove(&name);
+                }
+            }
+            UndoOp::Delete { dir, name, entry } => {
+                if let InodeData::Directory { entries } = &mut *dir.lock() {
+                    entries.insert(name, entry);
+                }
+            }
+            UndoOp::Rename {
+                from_dir,
+                from_name,
+                to_dir,
+                to_name,
+                entry,
+                moved,
+                from,
+                release,
+            } => {
+                if let InodeData::Directory { entries } = &mut *to_dir.lock() {
+                    entries.remove(&to_name);
                 }
+                if let InodeData::Directory { entries } = &mut *from_dir.lock() {
+                    entries.insert(from_name, entry);
+                }
+                moved.repath(from);
+                moved.sb.uncharge(&release);
             }
         }
     }
+}
 
-    // 不应该到达这里，但为了完整性提供一个 fallback
-    let tx_id = TxId::new(GLOBAL_TX_ID.fetch_add(1, Ordering::SeqCst));
-    *CURRENT_TX.lock() = Some(tx_id);
+struct WriteSetEntry {
+    /// 该操作的 WAL 记录
+    lsn: Lsn,
+    undo: UndoOp,
+    /// 该操作计入的配额用量
+    charge: Option<Charge>,
+}
+
+/// 事务写集合: 按执行顺序记录的修改和保存点
+#[derive(Default)]
+struct TxWriteSet {
+    entries: Vec<WriteSetEntry>,
+    /// (保存点名, 创建时 entries 的长度)
+    savepoints: Vec<(String, usize)>,
+    /// TxBegin 是否已写入 WAL
+    logged: bool,
+}
+
+/// 挂载实例的事务上下文
+///
+/// 每个 DBFS 挂载拥有自己的事务管理器 (inode 锁 / 超时 / 死锁检测) 和写集合;
+/// 事务在开始时绑定到一个挂载, 并成为开始它的任务在该挂载上的当前事务。
+/// 不同任务的事务并发执行, 通过 inode 锁互相等待。
+pub(super) struct TxContext {
+    /// 各任务的当前事务: 线程号 -> 事务 (没有当前任务时记在 0 号下)
+    current: Mutex<BTreeMap<usize, TxId>>,
+    manager: Mutex<TxManager>,
+    /// 活跃事务的写集合 (tx id -> 写集合)
+    write_sets: Mutex<BTreeMap<u64, TxWriteSet>>,
+    /// 累计提交 / 回滚次数
+    commits: AtomicU64,
+    rollbacks: AtomicU64,
+}
+
+impl TxContext {
+    pub(super) fn new() -> Self {
+        let mut manager = TxManager::new();
+        manager.set_default_timeout(*DEFAULT_TX_TIMEOUT.lock());
+        Self {
+            current: Mutex::new(BTreeMap::new()),
+            manager: Mutex::new(manager),
+            write_sets: Mutex::new(BTreeMap::new()),
+            commits: AtomicU64::new(0),
+            rollbacks: AtomicU64::new(0),
+        }
+    }
+
+    /// 当前任务在本挂载上的事务
+    pub(super) fn current(&self) -> Option<TxId> {
+        self.current.lock().get(&current_task_key()).copied()
+    }
+
+    /// 事务结束后不再是任何任务的当前事务
+    fn clear_current(&self, tx_id: TxId) {
+        self.current.lock().retain(|_, current| *current != tx_id);
+    }
+
+    /// 事务是否属于本挂载 (包括已中止但尚未提交/回滚的事务)
+    pub(super) fn owns(&self, tx_id: TxId) -> bool {
+        self.manager.lock().contains(tx_id)
+    }
+
+    /// 没有进行中的事务, 也没有尚未结束的写集合
+    pub(super) fn is_idle(&self) -> bool {
+        self.manager.lock().active_count() == 0 && self.write_sets.lock().is_empty()
+    }
+
+    pub(super) fn abort_stats(&self) -> AbortStats {
+        self.manager.lock().stats()
+    }
+
+    /// 累计 (提交, 回滚) 次数
+    pub(super) fn completed(&self) -> (u64, u64) {
+        (
+            self.commits.load(Ordering::Relaxed),
+            self.rollbacks.load(Ordering::Relaxed),
+        )
+    }
+
+    /// 登记中的事务及其写集合大小
+    pub(super) fn transactions(&self) -> Vec<(TxInfo, usize)> {
+        let txs = self.manager.lock().transactions();
+        let sets = self.write_sets.lock();
+        txs.into_iter()
+            .map(|info| {
+                let writes = sets
+                    .get(&info.tx_id.value())
+                    .map_or(0, |set| set.entries.len());
+                (info, writes)
+            })
+            .collect()
+    }
+}
+
+/// 当前任务在 [`TxContext`] 中的键
+fn current_task_key() -> usize {
+    sys::current_tid().unwrap_or(0)
+}
+
+/// 事务错误对应的 VFS 错误: 中止原因分别返回 EAGAIN / EDEADLK / ETIMEDOUT /
+/// ECANCELED / EDQUOT, 调用方据此决定是否重试
+pub(super) fn tx_vfs_error(e: DbfsError) -> VfsError {
+    match e {
+        DbfsError::Conflict => VfsError::EAGAIN,
+        DbfsError::Deadlock => VfsError::EDEADLK,
+        DbfsError::TimedOut => VfsError::ETIMEDOUT,
+        DbfsError::Aborted => VfsError::ECANCELED,
+        DbfsError::QuotaExceeded => VfsError::EDQUOT,
+        DbfsError::Busy => VfsError::EBUSY,
+        DbfsError::InvalidArgument => VfsError::Invalid,
+        DbfsError::NotFound => VfsError::NoEntry,
+        _ => VfsError::IoError,
+    }
+}
+
+/// 事务第一次修改本挂载时在 WAL 中开始事务
+fn enlist(tx_id: TxId, sb: &DbfsSuperBlock) {
+    let mut sets = sb.tx().write_sets.lock();
+    let set = sets.entry(tx_id.value()).or_default();
+    if !set.logged {
+        sb.begin_tx_with_id(tx_id);
+        set.logged = true;
+    }
+}
+
+/// 把一次已执行的修改加入事务写集合
+fn track(tx_id: TxId, sb: &DbfsSuperBlock, lsn: Lsn, undo: UndoOp, charge: Option<Charge>) {
+    sb.tx()
+        .write_sets
+        .lock()
+        .entry(tx_id.value())
+        .or_default()
+        .entries
+        .push(WriteSetEntry { lsn, undo, charge });
+}
+
+/// 逆序撤销写集合中的修改并退还配额用量
+///
+/// 撤销时不持有写集合的锁, 避免和 inode 数据锁交叉
+fn undo_entries(sb: &DbfsSuperBlock, entries: Vec<WriteSetEntry>) {
+    for entry in entries.into_iter().rev() {
+        entry.undo.undo();
+        if let Some(charge) = entry.charge {
+            sb.uncharge(&charge);
+        }
+    }
+}
+
+/// 事务增加过用量的目录中是否有已超出配额的 (配额在事务执行期间被调低)
+fn exceeds_quota(sb: &DbfsSuperBlock, tx_id: TxId) -> bool {
+    let sets = sb.tx().write_sets.lock();
+    sets.get(&tx_id.value()).map_or(false, |set| {
+        set.entries
+            .iter()
+            .filter_map(|entry| entry.charge.as_ref())
+            .any(|charge| charge.grows() && sb.quota_exceeded(&charge.path))
+    })
+}
+
+/// 撤销整个事务并在 WAL 中记录回滚
+fn rollback_write_set(sb: &DbfsSuperBlock, tx_id: TxId) {
+    let set = sb.tx().write_sets.lock().remove(&tx_id.value());
+    if let Some(set) = set {
+        undo_entries(sb, set.entries);
+        if set.logged {
+            sb.rollback_tx(tx_id);
+        }
+    }
+}
+
+/// 找到事务所属的挂载
+fn mount_of(tx_id: TxId) -> DbfsResult<Arc<DbfsSuperBlock>> {
+    mounts()
+        .into_iter()
+        .find(|sb| sb.tx().owns(tx_id))
+        .ok_or(DbfsError::NotFound)
+}
+
+/// Begin a new transaction on the default mount
+///
+/// 默认挂载是最早挂载且仍未卸载的 DBFS 实例; 没有 DBFS 挂载时返回 [`DbfsError::NotFound`]
+pub fn begin_tx() -> DbfsResult<TxId> {
+    begin_tx_with_timeout(None)
+}
+
+/// Begin a new transaction on the default mount with its own timeout
+///
+/// `timeout_ms` 为 None 时使用默认超时 (见 [`set_default_tx_timeout`])
+pub fn begin_tx_with_timeout(timeout_ms: Option<u64>) -> DbfsResult<TxId> {
+    let sb = default_mount().ok_or(DbfsError::NotFound)?;
+    Ok(begin_tx_on(&sb, timeout_ms))
+}
+
+/// Begin a new transaction on the given mount
+///
+/// 新事务成为当前任务在该挂载上的当前事务
+pub fn begin_tx_on(sb: &DbfsSuperBlock, timeout_ms: Option<u64>) -> TxId {
+    let tx_id = TxId::new(NEXT_TX_ID.fetch_add(1, Ordering::SeqCst));
+    let mut manager = sb.tx().manager.lock();
+    manager.begin(tx_id, sys::now_ms(), timeout_ms);
+    if let Some(pid) = sys::current_pid() {
+        manager.set_owner(tx_id, pid);
+    }
+    drop(manager);
+    sb.tx().current.lock().insert(current_task_key(), tx_id);
+    log::info!("✓ DBFS: Transaction {} started on {}", tx_id, sb.mount_point());
     tx_id
 }
 
-/// Commit current transaction
+/// Commit a transaction
 ///
-/// 清除当前事务上下文
-pub fn commit_tx(tx_id: TxId) -> VfsResult<()> {
-    let mut current_tx = CURRENT_TX.lock();
-    if let Some(current) = *current_tx {
+/// 清除事务所属任务的当前事务并释放事务持有的锁。写集合和锁保留到提交记录写入 WAL 之后,
+/// 写入失败时撤销事务的修改并返回 [`DbfsError::Io`]。
+/// 事务已被中止时返回中止原因: [`DbfsError::Conflict`] / [`DbfsError::Deadlock`] /
+/// [`DbfsError::TimedOut`] / [`DbfsError::Aborted`] / [`DbfsError::QuotaExceeded`],
+/// 事务的修改被撤销。
+pub fn commit_tx(tx_id: TxId) -> DbfsResult<()> {
+    let sb = mount_of(tx_id)?;
+    let ctx = sb.tx();
+    if let Some(current) = ctx.current() {
         if current != tx_id {
             log::error!("✗ DBFS: Transaction mismatch: expected {}, got {}", current, tx_id);
-            return Err(VfsError::NoSys);
+            return Err(DbfsError::InvalidArgument);
         }
     }
-    *current_tx = None;
+    ctx.clear_current(tx_id);
+
+    let over_quota = exceeds_quota(&sb, tx_id);
+    let aborted = {
+        let mut manager = ctx.manager.lock();
+        manager.expire(sys::now_ms());
+        if over_quota {
+            manager.abort(tx_id, AbortReason::Quota);
+        }
+        manager.start_commit(tx_id)
+    };
+    if let Some(reason) = aborted {
+        log::warn!("⚠ DBFS: Transaction {} was aborted ({:?}), commit refused", tx_id, reason);
+        ctx.manager.lock().finish(tx_id);
+        rollback_write_set(&sb, tx_id);
+        return Err(reason.error());
+    }
+
+    let logged = ctx
+        .write_sets
+        .lock()
+        .get(&tx_id.value())
+        .map_or(false, |set| set.logged);
+    if logged && sb.commit_tx(tx_id).is_err() {
+        // 提交记录没有写入, 事务的修改在释放锁之前撤销
+        rollback_write_set(&sb, tx_id);
+        ctx.manager.lock().finish(tx_id);
+        return Err(DbfsError::Io);
+    }
+    ctx.write_sets.lock().remove(&tx_id.value());
+    ctx.manager.lock().finish(tx_id);
+    ctx.commits.fetch_add(1, Ordering::Relaxed);
     log::info!("✓ DBFS: Transaction {} committed", tx_id);
     Ok(())
 }
 
-/// Rollback current transaction
+/// Rollback a transaction
 ///
-/// 清除当前事务上下文
+/// 撤销事务的修改, 清除事务所属任务的当前事务并释放事务持有的锁
 pub fn rollback_tx(tx_id: TxId) {
-    let mut current_tx = CURRENT_TX.lock();
-    if let Some(current) = *current_tx {
-        if current == tx_id {
-            *current_tx = None;
-            log::info!("✓ DBFS: Transaction {} rolled back", tx_id);
+    let sb = match mount_of(tx_id) {
+        Ok(sb) => sb,
+        Err(_) => {
+            log::warn!("⚠ DBFS: Rollback of unknown transaction {}", tx_id);
+            return;
+        }
+    };
+    let ctx = sb.tx();
+    ctx.clear_current(tx_id);
+    ctx.manager.lock().finish(tx_id);
+    rollback_write_set(&sb, tx_id);
+    ctx.rollbacks.fetch_add(1, Ordering::Relaxed);
+    log::info!("✓ DBFS: Transaction {} rolled back", tx_id);
+}
+
+/// Create a savepoint in a transaction
+///
+/// 同名保存点已存在时移动到当前位置
+pub fn savepoint(tx_id: TxId, name: &str) -> DbfsResult<()> {
+    let sb = mount_of(tx_id)?;
+    sb.tx().manager.lock().check(tx_id, sys::now_ms())?;
+    let mut sets = sb.tx().write_sets.lock();
+    let set = sets.entry(tx_id.value()).or_default();
+    set.savepoints.retain(|(sp, _)| sp != name);
+    let pos = set.entries.len();
+    set.savepoints.push((name.to_string(), pos));
+    log::info!("✓ DBFS: Savepoint {} created in transaction {}", name, tx_id);
+    Ok(())
+}
+
+/// Rollback a transaction to a savepoint
+///
+/// 只撤销保存点之后的操作; 保存点本身保留, 之后创建的保存点被丢弃。
+/// WAL 中追加一条 SavepointRollback 记录, 恢复时同样丢弃这些操作。
+pub fn rollback_to_savepoint(tx_id: TxId, name: &str) -> DbfsResult<()> {
+    let sb = mount_of(tx_id)?;
+    sb.tx().manager.lock().check(tx_id, sys::now_ms())?;
+    let discarded = {
+        let mut sets = sb.tx().write_sets.lock();
+        let set = sets.get_mut(&tx_id.value()).ok_or(DbfsError::NotFound)?;
+        let idx = set
+            .savepoints
+            .iter()
+            .position(|(sp, _)| sp == name)
+            .ok_or(DbfsError::NotFound)?;
+        let pos = set.savepoints[idx].1;
+        set.savepoints.truncate(idx + 1);
+        set.entries.split_off(pos)
+    };
+
+    // 回滚到第一条被丢弃记录之前
+    let first = discarded.iter().map(|entry| entry.lsn).min();
+    let count = discarded.len();
+    undo_entries(&sb, discarded);
+    if let Some(first) = first {
+        sb.rollback_to_savepoint(tx_id, first - 1);
+    }
+    log::info!(
+        "✓ DBFS: Transaction {} rolled back to savepoint {} ({} operations undone)",
+        tx_id,
+        name,
+        count
+    );
+    Ok(())
+}
+
+/// Release a savepoint
+///
+/// 保存点之后的操作保留在事务中, 该保存点及之后创建的保存点不再可用
+pub fn release_savepoint(tx_id: TxId, name: &str) -> DbfsResult<()> {
+    let sb = mount_of(tx_id)?;
+    sb.tx().manager.lock().check(tx_id, sys::now_ms())?;
+    let mut sets = sb.tx().write_sets.lock();
+    let set = sets.get_mut(&tx_id.value()).ok_or(DbfsError::NotFound)?;
+    let idx = set
+        .savepoints
+        .iter()
+        .position(|(sp, _)| sp == name)
+        .ok_or(DbfsError::NotFound)?;
+    set.savepoints.truncate(idx);
+    log::info!("✓ DBFS: Savepoint {} released in transaction {}", name, tx_id);
+    Ok(())
+}
+
+/// Abort a transaction on behalf of the user
+///
+/// 立即释放锁; 事务所有者的后续操作和 commit 返回 [`DbfsError::Aborted`]
+pub fn abort_tx(tx_id: TxId) {
+    if let Ok(sb) = mount_of(tx_id) {
+        sb.tx().manager.lock().abort(tx_id, AbortReason::User);
+        log::info!("✓ DBFS: Transaction {} aborted by user", tx_id);
+    }
+}
+
+/// 修改进行中事务的超时 (从事务开始时计算), None 表示不限时
+pub fn set_tx_timeout(tx_id: TxId, timeout_ms: Option<u64>) -> DbfsResult<()> {
+    mount_of(tx_id)?.tx().manager.lock().set_timeout(tx_id, timeout_ms)
+}
+
+/// 设置之后开始的事务的默认超时 (所有挂载), None 表示不限时
+pub fn set_default_tx_timeout(timeout_ms: Option<u64>) {
+    *DEFAULT_TX_TIMEOUT.lock() = timeout_ms;
+    for sb in mounts() {
+        sb.tx().manager.lock().set_default_timeout(timeout_ms);
+    }
+}
+
+/// 查询事务状态: 仍可继续时返回 Ok, 已中止时返回中止原因
+pub fn tx_status(tx_id: TxId) -> DbfsResult<()> {
+    mount_of(tx_id)?.tx().manager.lock().check(tx_id, sys::now_ms())
+}
+
+/// 所有挂载上各类中止的累计次数
+pub fn tx_abort_stats() -> AbortStats {
+    let mut total = AbortStats::default();
+    for sb in mounts() {
+        let stats = sb.tx().abort_stats();
+        total.conflict += stats.conflict;
+        total.deadlock += stats.deadlock;
+        total.timeout += stats.timeout;
+        total.user += stats.user;
+        total.quota += stats.quota;
+    }
+    total
+}
+
+/// 为事务在 inode 上加排他锁
+///
+/// `LockWait::Block` 时锁冲突会让出 CPU 并重试, 直到拿到锁、被选为死锁牺牲者
+/// 或超时; 等待期间不持有管理器的锁。
+pub fn lock_inode(tx_id: TxId, ino: u64, wait: LockWait) -> DbfsResult<()> {
+    let sb = mount_of(tx_id)?;
+    lock_inode_on(&sb, tx_id, ino, wait)
+}
+
+fn lock_inode_on(sb: &DbfsSuperBlock, tx_id: TxId, ino: u64, wait: LockWait) -> DbfsResult<()> {
+    loop {
+        let status = sb.tx().manager.lock().lock(tx_id, ino, sys::now_ms(), wait)?;
+        match status {
+            LockStatus::Granted => return Ok(()),
+            LockStatus::Waiting => sys::yield_now(),
         }
     }
-}
\ No newline at end of file
+}
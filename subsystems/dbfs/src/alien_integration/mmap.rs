@@ -0,0 +1,22 @@
+//! 共享内存映射 (MAP_SHARED) 的写回
+//!
+//! 内核为映射同一个文件的所有进程保留同一组物理页, `msync` 和 `munmap` 时把页
+//! 交给 [`write_back`]: 与文件内容不同的页作为一次事务写入写回 WAL 和文件,
+//! 提交前崩溃不会留下写了一半的映射内容。映射不会改变文件长度。
+
+use alloc::sync::Arc;
+
+use vfscore::{error::VfsError, inode::VfsInode, VfsResult};
+
+use super::superblock::find_mount;
+
+/// 把映射页 `pages` (文件偏移, 页内容) 写回 `inode`, 返回写入的字节数
+///
+/// `inode` 不是 DBFS 文件时返回 [`VfsError::NoSys`], 由调用者按普通文件写回。
+pub fn write_back(inode: &Arc<dyn VfsInode>, pages: &[(u64, &[u8])]) -> VfsResult<usize> {
+    let sb = find_mount(&inode.get_super_block()?).ok_or(VfsError::NoSys)?;
+    let inode = sb
+        .cached_inode(inode.get_attr()?.st_ino)
+        .ok_or(VfsError::NoEntry)?;
+    inode.write_back_pages(pages)
+}
@@ -8,12 +8,34 @@
 //! - ✅ 支持基本的 dentry 操作: insert, remove, parent
 //! - ✅ WAL (Write-Ahead Log) 支持
 //! - ✅ 事务管理: begin_tx / commit_tx / rollback_tx
+//! - ✅ 事务超时、inode 锁与死锁检测
+//! - ✅ 事务保存点: savepoint / rollback_to_savepoint / release_savepoint
+//! - ✅ 挂载选项: WAL 位置、提交持久化方式、隔离级别、checkpoint 间隔、只读
+//! - ✅ 多个独立挂载: 每个挂载拥有自己的 WAL、inode 缓存和事务上下文
+//! - ✅ statfs 用量统计和目录配额 (EDQUOT)
+//! - ✅ 运行统计: /proc/fs/dbfs/<mount>/{stats,transactions}
+//! - ✅ 变更流 (CDC): /proc/fs/dbfs/<mount>/changes
+//! - ✅ 透明压缩: FS_IOC_SETFLAGS + FS_COMPR_FL, 目录标志由新文件继承
+//! - ✅ 静态加密: key= / keyfile= 挂载选项, WAL 记录和文件 extent 用 ChaCha20-Poly1305 加密
+//! - ✅ Reflink: FICLONE / FICLONERANGE / copy_file_range 共享 extent, 写时复制
+//! - ✅ 写入时去重: dedup 挂载选项, 按内容切块并共享相同的块, 节省的空间计入 statfs
+//! - ✅ 共享内存映射: MAP_SHARED 的页在 msync / munmap 时作为一次事务写回
 //! - ✅ 崩溃恢复
 
+pub mod changes;
+pub mod crash;
 mod dentry;
+mod device;
 mod fstype;
 mod inode;
+pub mod mmap;
+pub mod options;
+pub mod quota;
+pub mod reflink;
+pub mod stats;
 mod superblock;
+mod sys;
+pub mod txn;
 
 // Test modules - always included for runtime testing
 pub mod tests;
@@ -21,5 +43,11 @@ pub mod tests_enhanced;
 pub mod tests_elle_jepsen;
 
 pub use fstype::DbfsFsType;
-pub use inode::{begin_tx, commit_tx, rollback_tx};
-pub use superblock::DbfsSuperBlock;
+pub use inode::{
+    abort_tx, begin_tx, begin_tx_on, begin_tx_with_timeout, commit_tx, lock_inode,
+    release_savepoint, rollback_to_savepoint, rollback_tx, savepoint, set_default_tx_timeout,
+    set_tx_timeout, tx_abort_stats, tx_status, FS_COMPR_FL, FS_IOC_GETFLAGS, FS_IOC_SETFLAGS,
+};
+pub use options::{DbfsMountOptions, Durability, IsolationLevel};
+pub use quota::{clear_quota, get_quota, set_quota, QuotaLimits, QuotaUsage};
+pub use superblock::{mount_busy, DbfsSuperBlock};
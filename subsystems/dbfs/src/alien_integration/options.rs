@@ -0,0 +1,208 @@
+//! DBFS 挂载选项
+//!
+//! 解析 `mount -t dbfs -o <options>` 传入的 data, 格式与 Linux 相同:
+//! 逗号分隔的 `key=value` 或单独的标志。
+//!
+//! ```text
+//! wal=<name>                 WAL 文件名 (后端为目录时, 相对该目录), 默认 .wal
+//! wal_size=<bytes>[K|M|G]    WAL 文件容量 (后端为目录或普通文件时), 默认 4M
+//! commit=sync|group|async    提交持久化方式, 默认 sync
+//! group_commit=<n>           group 模式下每 n 个提交刷一次 WAL, 默认 8
+//! isolation=read_uncommitted|serializable
+//!                            隔离级别, 默认 read_uncommitted
+//! checkpoint_interval=<ms>   两次 checkpoint 的最小间隔 (提交时检查), 0 表示关闭
+//! ro / rw                    只读 / 读写挂载
+//! key=<64 hex digits>        加密 WAL 和文件数据的密钥 (ChaCha20-Poly1305)
+//! keyfile=<name>             从后端目录下的文件读取密钥 (64 个十六进制数字或 32 字节)
+//! dedup / nodedup            写入时对文件数据去重 (见 [`crate::dedup`]), 默认关闭
+//! ```
+
+use alloc::string::{String, ToString};
+
+use crate::{
+    common::{DbfsError, DbfsResult},
+    crypt::{MountKey, KEY_LEN},
+};
+
+/// 默认 WAL 文件名
+pub const DEFAULT_WAL_NAME: &str = ".wal";
+/// 默认 WAL 文件容量
+pub const DEFAULT_WAL_SIZE: u64 = 4 * 1024 * 1024;
+/// group 模式下默认每组提交数
+pub const DEFAULT_GROUP_COMMIT: usize = 8;
+
+/// 提交持久化方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// 每次提交都刷 WAL, 提交返回即持久化
+    Sync,
+    /// 累积 group_commit 个提交后一起刷 WAL, 崩溃最多丢失最后一组
+    Group,
+    /// 提交不刷 WAL, 由 sync_fs / checkpoint 持久化
+    Async,
+}
+
+/// 事务隔离级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// 只有写操作加 inode 锁, 读可以看到未提交的修改
+    ReadUncommitted,
+    /// 事务内的读也加 inode 锁并持有到事务结束 (两阶段锁)
+    Serializable,
+}
+
+/// DBFS 挂载选项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbfsMountOptions {
+    pub wal_name: String,
+    pub wal_size: u64,
+    pub durability: Durability,
+    pub group_commit: usize,
+    pub isolation: IsolationLevel,
+    /// None 表示不自动 checkpoint
+    pub checkpoint_interval_ms: Option<u64>,
+    pub read_only: bool,
+    /// 加密密钥; `keyfile=` 在挂载时读入这里
+    pub key: Option<MountKey>,
+    pub key_file: Option<String>,
+    /// 写入时去重
+    pub dedup: bool,
+}
+
+impl Default for DbfsMountOptions {
+    fn default() -> Self {
+        Self {
+            wal_name: DEFAULT_WAL_NAME.to_string(),
+            wal_size: DEFAULT_WAL_SIZE,
+            durability: Durability::Sync,
+            group_commit: DEFAULT_GROUP_COMMIT,
+            isolation: IsolationLevel::ReadUncommitted,
+            checkpoint_interval_ms: None,
+            read_only: false,
+            key: None,
+            key_file: None,
+            dedup: false,
+        }
+    }
+}
+
+impl DbfsMountOptions {
+    /// 解析 sys_mount 的 data 参数
+    ///
+    /// data 可以以 NUL 结尾; 未知选项或非法取值返回 [`DbfsError::InvalidArgument`]
+    pub fn parse(data: &[u8]) -> DbfsResult<Self> {
+        let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        let data = core::str::from_utf8(&data[..len]).map_err(|_| DbfsError::InvalidArgument)?;
+
+        let mut options = Self::default();
+        for opt in data.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = match opt.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim())),
+                None => (opt, None),
+            };
+            match (key, value) {
+                ("ro", None) => options.read_only = true,
+                ("rw", None) => options.read_only = false,
+                ("dedup", None) => options.dedup = true,
+                ("nodedup", None) => options.dedup = false,
+                ("wal", Some(name)) if !name.is_empty() => options.wal_name = name.to_string(),
+                ("wal_size", Some(size)) => options.wal_size = parse_size(size)?,
+                ("commit", Some("sync")) => options.durability = Durability::Sync,
+                ("commit", Some("group")) => options.durability = Durability::Group,
+                ("commit", Some("async")) => options.durability = Durability::Async,
+                ("group_commit", Some(n)) => {
+                    options.group_commit = match n.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(DbfsError::InvalidArgument),
+                    }
+                }
+                ("isolation", Some("read_uncommitted")) => {
+                    options.isolation = IsolationLevel::ReadUncommitted
+                }
+                ("isolation", Some("serializable")) => {
+                    options.isolation = IsolationLevel::Serializable
+                }
+                ("checkpoint_interval", Some(ms)) => {
+                    let ms = ms.parse().map_err(|_| DbfsError::InvalidArgument)?;
+                    options.checkpoint_interval_ms = if ms == 0 { None } else { Some(ms) };
+                }
+                ("key", Some(hex)) if hex.len() == KEY_LEN * 2 => {
+                    options.key = Some(MountKey::parse(hex.as_bytes())?)
+                }
+                ("keyfile", Some(name)) if !name.is_empty() => {
+                    options.key_file = Some(name.to_string())
+                }
+                // 不在日志中打印密钥
+                ("key", _) => {
+                    log::warn!("⚠ DBFS: Invalid key option");
+                    return Err(DbfsError::InvalidArgument);
+                }
+                _ => {
+                    log::warn!("⚠ DBFS: Unknown or invalid mount option: {}", opt);
+                    return Err(DbfsError::InvalidArgument);
+                }
+            }
+        }
+        if options.key.is_some() && options.key_file.is_some() {
+            log::warn!("⚠ DBFS: key and keyfile are mutually exclusive");
+            return Err(DbfsError::InvalidArgument);
+        }
+        Ok(options)
+    }
+}
+
+/// 解析带可选 K/M/G 后缀的字节数
+pub(super) fn parse_size(s: &str) -> DbfsResult<u64> {
+    let (digits, shift) = match s.as_bytes().last() {
+        Some(b'K' | b'k') => (&s[..s.len() - 1], 10),
+        Some(b'M' | b'm') => (&s[..s.len() - 1], 20),
+        Some(b'G' | b'g') => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    let n: u64 = digits.parse().map_err(|_| DbfsError::InvalidArgument)?;
+    n.checked_shl(shift)
+        .filter(|size| size >> shift == n && *size > 0)
+        .ok_or(DbfsError::InvalidArgument)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_mount_options() {
+        assert_eq!(DbfsMountOptions::parse(b"").unwrap(), DbfsMountOptions::default());
+
+        let options = DbfsMountOptions::parse(
+            b"ro,wal=journal,wal_size=1M,commit=group,group_commit=4,isolation=serializable,checkpoint_interval=500,dedup\0",
+        )
+        .unwrap();
+        assert!(options.read_only);
+        assert!(options.dedup);
+        assert_eq!(options.wal_name, "journal");
+        assert_eq!(options.wal_size, 1 << 20);
+        assert_eq!(options.durability, Durability::Group);
+        assert_eq!(options.group_commit, 4);
+        assert_eq!(options.isolation, IsolationLevel::Serializable);
+        assert_eq!(options.checkpoint_interval_ms, Some(500));
+        assert_eq!(options.key, None);
+
+        let key = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+        let options = DbfsMountOptions::parse(format!("key={}", key).as_bytes()).unwrap();
+        assert_eq!(options.key, Some(MountKey::parse(key.as_bytes()).unwrap()));
+        let options = DbfsMountOptions::parse(b"keyfile=dbfs.key").unwrap();
+        assert_eq!(options.key_file.as_deref(), Some("dbfs.key"));
+    }
+
+    #[test]
+    fn test_parse_mount_options_invalid() {
+        assert!(DbfsMountOptions::parse(b"commit=never").is_err());
+        assert!(DbfsMountOptions::parse(b"group_commit=0").is_err());
+        assert!(DbfsMountOptions::parse(b"wal_size=12X").is_err());
+        assert!(DbfsMountOptions::parse(b"noatime").is_err());
+        assert!(DbfsMountOptions::parse(b"key=0011").is_err());
+        assert!(DbfsMountOptions::parse(b"key=00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff,keyfile=k").is_err());
+    }
+}
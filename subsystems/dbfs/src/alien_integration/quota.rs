@@ -0,0 +1,365 @@
+//! DBFS 目录配额
+//!
+//! 配额设置在目录上, 限制该目录之下 (不含目录本身) 的文件数据字节数和 inode 数。
+//! 用量在事务执行写操作时立即计入 (包括尚未提交的修改), 回滚时退还;
+//! 超出配额的写操作返回 EDQUOT 并中止事务, 提交时同样返回 [`DbfsError::QuotaExceeded`]。
+//! 事务执行期间配额被调低到用量以下时, 增加过该目录用量的事务在提交时失败。
+//!
+//! ## 控制命令
+//!
+//! 通过 `/proc/fs/dbfs/quotas` 写入, 读取时列出所有配额和当前用量:
+//!
+//! ```text
+//! set <dir> [bytes=<n>[K|M|G]] [inodes=<n>]   设置配额, 未给出的项不限制
+//! clear <dir>                                  删除配额
+//! ```
+//!
+//! `<dir>` 为绝对路径, 按最长的挂载点匹配到 DBFS 挂载。
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+};
+
+use super::{options::parse_size, superblock::{mounts, DbfsSuperBlock}};
+use crate::common::{DbfsError, DbfsResult};
+
+/// 配额限制, None 表示不限制
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaLimits {
+    pub bytes: Option<u64>,
+    pub inodes: Option<u64>,
+}
+
+/// 数据字节数和 inode 数
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub bytes: u64,
+    pub inodes: u64,
+}
+
+impl QuotaUsage {
+    fn apply(&mut self, bytes: i64, inodes: i64) {
+        self.bytes = apply_delta(self.bytes, bytes);
+        self.inodes = apply_delta(self.inodes, inodes);
+    }
+
+    fn exceeds(&self, limits: &QuotaLimits) -> bool {
+        limits.bytes.map_or(false, |limit| self.bytes > limit)
+            || limits.inodes.map_or(false, |limit| self.inodes > limit)
+    }
+}
+
+/// 一次修改对用量的影响, 负数表示释放
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Charge {
+    /// 被修改的文件 (挂载内路径)
+    pub path: String,
+    pub bytes: i64,
+    pub inodes: i64,
+}
+
+impl Charge {
+    pub fn new(path: String, bytes: i64, inodes: i64) -> Self {
+        Self {
+            path,
+            bytes,
+            inodes,
+        }
+    }
+
+    /// 撤销本次修改时的用量变化
+    pub fn inverse(&self) -> Self {
+        Self::new(self.path.clone(), -self.bytes, -self.inodes)
+    }
+
+    /// 是否增加了用量
+    pub fn grows(&self) -> bool {
+        self.bytes > 0 || self.inodes > 0
+    }
+}
+
+/// 一个挂载的总用量和各目录配额
+#[derive(Debug, Default)]
+pub struct QuotaTable {
+    total: QuotaUsage,
+    /// 目录 (挂载内路径) -> (限制, 用量)
+    quotas: BTreeMap<String, (QuotaLimits, QuotaUsage)>,
+}
+
+impl QuotaTable {
+    pub const fn new() -> Self {
+        Self {
+            total: QuotaUsage { bytes: 0, inodes: 0 },
+            quotas: BTreeMap::new(),
+        }
+    }
+
+    /// 整个挂载的用量
+    pub fn total(&self) -> QuotaUsage {
+        self.total
+    }
+
+    /// 设置 (或替换) 目录配额, `usage` 为目录之下的现有用量
+    pub fn set(&mut self, dir: &str, limits: QuotaLimits, usage: QuotaUsage) {
+        self.quotas.insert(dir.to_string(), (limits, usage));
+    }
+
+    pub fn clear(&mut self, dir: &str) -> DbfsResult<()> {
+        self.quotas
+            .remove(dir)
+            .map(|_| ())
+            .ok_or(DbfsError::NotFound)
+    }
+
+    pub fn get(&self, dir: &str) -> Option<(QuotaLimits, QuotaUsage)> {
+        self.quotas.get(dir).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &QuotaLimits, &QuotaUsage)> {
+        self.quotas
+            .iter()
+            .map(|(dir, (limits, usage))| (dir.as_str(), limits, usage))
+    }
+
+    /// 计入用量
+    ///
+    /// `enforce` 时, 只要有一个覆盖该路径的配额因本次增加而超出限制,
+    /// 就不做任何修改并返回 [`DbfsError::QuotaExceeded`]; 释放用量总是成功。
+    pub fn charge(&mut self, charge: &Charge, enforce: bool) -> DbfsResult<()> {
+        if enforce && charge.grows() {
+            for (dir, (limits, usage)) in self.quotas.iter() {
+                if !covers(dir, &charge.path) {
+                    continue;
+                }
+                let mut after = *usage;
+                after.apply(charge.bytes.max(0), charge.inodes.max(0));
+                let bytes_over = charge.bytes > 0 && limits.bytes.map_or(false, |l| after.bytes > l);
+                let inodes_over =
+                    charge.inodes > 0 && limits.inodes.map_or(false, |l| after.inodes > l);
+                if bytes_over || inodes_over {
+                    return Err(DbfsError::QuotaExceeded);
+                }
+            }
+        }
+        self.total.apply(charge.bytes, charge.inodes);
+        for (dir, (_, usage)) in self.quotas.iter_mut() {
+            if covers(dir, &charge.path) {
+                usage.apply(charge.bytes, charge.inodes);
+            }
+        }
+        Ok(())
+    }
+
+    /// 覆盖该路径的配额中是否有用量已超出限制的
+    pub fn exceeded(&self, path: &str) -> bool {
+        self.quotas
+            .iter()
+            .any(|(dir, (limits, usage))| covers(dir, path) && usage.exceeds(limits))
+    }
+}
+
+/// 目录 `dir` 的配额是否覆盖 `path` (严格位于目录之下)
+fn covers(dir: &str, path: &str) -> bool {
+    if dir == "/" {
+        return path != "/";
+    }
+    path.strip_prefix(dir)
+        .map_or(false, |rest| rest.starts_with('/'))
+}
+
+fn apply_delta(value: u64, delta: i64) -> u64 {
+    if delta >= 0 {
+        value.saturating_add(delta as u64)
+    } else {
+        value.saturating_sub(delta.unsigned_abs())
+    }
+}
+
+/// 按最长挂载点找到路径所在的 DBFS 挂载, 返回挂载和挂载内路径
+fn resolve(path: &str) -> DbfsResult<(Arc<DbfsSuperBlock>, String)> {
+    let path = path.trim_end_matches('/');
+    let path = if path.is_empty() { "/" } else { path };
+    let mut best: Option<(Arc<DbfsSuperBlock>, String)> = None;
+    for sb in mounts() {
+        let mnt = sb.mount_point().trim_end_matches('/');
+        let rest = match path.strip_prefix(mnt) {
+            Some("") => "/",
+            Some(rest) if rest.starts_with('/') => rest,
+            _ => continue,
+        };
+        let longer = best
+            .as_ref()
+            .map_or(true, |(b, _)| b.mount_point().trim_end_matches('/').len() < mnt.len());
+        if longer {
+            best = Some((sb, rest.to_string()));
+        }
+    }
+    best.ok_or(DbfsError::NotFound)
+}
+
+/// 在目录上设置配额
+///
+/// 目录之下的现有用量立即计入; 现有用量已超出限制时之后的增长都会失败
+pub fn set_quota(dir: &str, limits: QuotaLimits) -> DbfsResult<()> {
+    let (sb, rel) = resolve(dir)?;
+    let inode = sb.resolve_dir(&rel)?;
+    let usage = inode.usage_below();
+    sb.quotas().lock().set(&rel, limits, usage);
+    log::info!(
+        "✓ DBFS: Quota on {} set to {:?} (in use: {:?})",
+        dir,
+        limits,
+        usage
+    );
+    Ok(())
+}
+
+/// 删除目录配额
+pub fn clear_quota(dir: &str) -> DbfsResult<()> {
+    let (sb, rel) = resolve(dir)?;
+    sb.quotas().lock().clear(&rel)?;
+    log::info!("✓ DBFS: Quota on {} cleared", dir);
+    Ok(())
+}
+
+/// 查询目录配额和当前用量
+pub fn get_quota(dir: &str) -> DbfsResult<(QuotaLimits, QuotaUsage)> {
+    let (sb, rel) = resolve(dir)?;
+    let quota = sb.quotas().lock().get(&rel);
+    quota.ok_or(DbfsError::NotFound)
+}
+
+/// 解析并执行一条控制命令 (见模块文档)
+pub fn apply_command(cmd: &str) -> DbfsResult<()> {
+    let mut words = cmd.split_whitespace();
+    match (words.next(), words.next()) {
+        (None, _) => Ok(()),
+        (Some("clear"), Some(dir)) if words.next().is_none() => clear_quota(dir),
+        (Some("set"), Some(dir)) => {
+            let mut limits = QuotaLimits::default();
+            for word in words {
+                match word.split_once('=') {
+                    Some(("bytes", n)) => limits.bytes = Some(parse_size(n)?),
+                    Some(("inodes", n)) => {
+                        limits.inodes = Some(n.parse().map_err(|_| DbfsError::InvalidArgument)?)
+                    }
+                    _ => return Err(DbfsError::InvalidArgument),
+                }
+            }
+            set_quota(dir, limits)
+        }
+        _ => Err(DbfsError::InvalidArgument),
+    }
+}
+
+/// 所有挂载上的配额和用量 (每行一个)
+pub fn describe() -> String {
+    let limit = |limit: Option<u64>| limit.map_or("-".to_string(), |l| l.to_string());
+    let mut out = String::new();
+    for sb in mounts() {
+        let mnt = sb.mount_point().trim_end_matches('/');
+        for (dir, limits, usage) in sb.quotas().lock().iter() {
+            let path = if dir == "/" && !mnt.is_empty() {
+                mnt.to_string()
+            } else {
+                format!("{}{}", mnt, dir)
+            };
+            out.push_str(&format!(
+                "{} bytes={}/{} inodes={}/{}\n",
+                path,
+                usage.bytes,
+                limit(limits.bytes),
+                usage.inodes,
+                limit(limits.inodes)
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn charge(path: &str, bytes: i64, inodes: i64) -> Charge {
+        Charge::new(path.to_string(), bytes, inodes)
+    }
+
+    #[test]
+    fn test_quota_charge_and_release() {
+        let mut table = QuotaTable::new();
+        let limits = QuotaLimits {
+            bytes: Some(100),
+            inodes: Some(2),
+        };
+        table.set("/a", limits, QuotaUsage::default());
+
+        table.charge(&charge("/a/f", 0, 1), true).unwrap();
+        table.charge(&charge("/a/f", 100, 0), true).unwrap();
+        assert_eq!(
+            table.charge(&charge("/a/f", 1, 0), true),
+            Err(DbfsError::QuotaExceeded)
+        );
+        // 兄弟目录和同名前缀不受影响
+        table.charge(&charge("/b/f", 1000, 1), true).unwrap();
+        table.charge(&charge("/ab", 0, 1), true).unwrap();
+        assert_eq!(
+            table.get("/a").unwrap().1,
+            QuotaUsage {
+                bytes: 100,
+                inodes: 1
+            }
+        );
+        assert_eq!(
+            table.total(),
+            QuotaUsage {
+                bytes: 1100,
+                inodes: 3
+            }
+        );
+
+        // 回滚退还用量
+        let grow = charge("/a/g", 0, 1);
+        table.charge(&grow, true).unwrap();
+        assert_eq!(
+            table.charge(&charge("/a/h", 0, 1), true),
+            Err(DbfsError::QuotaExceeded)
+        );
+        table.charge(&grow.inverse(), false).unwrap();
+        table.charge(&charge("/a/h", 0, 1), true).unwrap();
+    }
+
+    #[test]
+    fn test_quota_lowered_below_usage() {
+        let mut table = QuotaTable::new();
+        table.set(
+            "/",
+            QuotaLimits {
+                bytes: Some(10),
+                inodes: None,
+            },
+            QuotaUsage {
+                bytes: 8,
+                inodes: 1,
+            },
+        );
+        assert!(!table.exceeded("/f"));
+        table.set(
+            "/",
+            QuotaLimits {
+                bytes: Some(4),
+                inodes: None,
+            },
+            table.get("/").unwrap().1,
+        );
+        assert!(table.exceeded("/f"));
+        // 释放用量和不增加字节数的修改仍然允许
+        table.charge(&charge("/f", -2, 0), true).unwrap();
+        table.charge(&charge("/g", 0, 1), true).unwrap();
+        assert_eq!(table.clear("/"), Ok(()));
+        assert_eq!(table.clear("/"), Err(DbfsError::NotFound));
+    }
+}
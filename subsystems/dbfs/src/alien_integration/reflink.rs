@@ -0,0 +1,43 @@
+//! Reflink: 在同一个 DBFS 挂载内克隆文件区间
+//!
+//! 供 `FICLONE` / `FICLONERANGE` ioctl 和 `copy_file_range` 使用。克隆作为一次
+//! 事务操作写入 WAL (只记录源和目标区间, 不记录数据), 可以随事务回滚;
+//! 对齐到 extent 边界的整 extent 在两个文件之间共享, 之后写入时才复制
+//! (见 [`crate::compress::FileData::clone_range`])。
+
+use alloc::sync::Arc;
+
+use vfscore::{error::VfsError, inode::VfsInode, VfsResult};
+
+use super::superblock::find_mount;
+
+/// 把 `src` 从 `src_offset` 开始的 `len` 字节克隆到 `dst` 的 `dst_offset`,
+/// 返回克隆的字节数 (到 `src` 末尾为止)
+///
+/// 两个 inode 不是同一个 DBFS 挂载上的文件时返回 [`VfsError::NoSys`],
+/// 由调用者决定回退到复制还是报错; 同一个文件内区间重叠返回 [`VfsError::Invalid`]。
+pub fn clone_range(
+    src: &Arc<dyn VfsInode>,
+    src_offset: u64,
+    len: u64,
+    dst: &Arc<dyn VfsInode>,
+    dst_offset: u64,
+) -> VfsResult<u64> {
+    // 没有超级块的 inode (例如设备文件) 同样不是 DBFS 文件
+    let mount = |inode: &Arc<dyn VfsInode>| {
+        let sb = inode.get_super_block().map_err(|_| VfsError::NoSys)?;
+        find_mount(&sb).ok_or(VfsError::NoSys)
+    };
+    let sb = mount(src)?;
+    let dst_sb = mount(dst)?;
+    if !Arc::ptr_eq(&sb, &dst_sb) {
+        return Err(VfsError::NoSys);
+    }
+    let src = sb
+        .cached_inode(src.get_attr()?.st_ino)
+        .ok_or(VfsError::NoEntry)?;
+    let dst = sb
+        .cached_inode(dst.get_attr()?.st_ino)
+        .ok_or(VfsError::NoEntry)?;
+    dst.clone_range_from(&src, src_offset, len, dst_offset)
+}
@@ -0,0 +1,150 @@
+//! DBFS 运行统计
+//!
+//! 为 `/proc/fs/dbfs/<mount>/` 下的文件生成内容:
+//! - `transactions`: 登记中的事务 (事务 ID、所属进程、已运行时间、写集合大小、状态)
+//! - `stats`: 累计的提交 / 回滚 / 中止次数、WAL 计数器、挂载时的恢复耗时、inode 缓存命中率,
+//!   以及文件数据的逻辑大小和实际占用 (压缩文件单独统计)
+//!
+//! `<mount>` 为挂载点去掉开头的 `/` 并把其余 `/` 换成 `-`, 根目录挂载为 `root`。
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+};
+use core::fmt::Write;
+
+use vfscore::superblock::VfsSuperBlock;
+
+use super::{
+    superblock::{find_mount, mounts, DbfsSuperBlock},
+    sys,
+};
+
+/// 挂载点在 `/proc/fs/dbfs` 下的目录名
+pub fn proc_name(mount_point: &str) -> String {
+    let name = mount_point.trim_matches('/');
+    if name.is_empty() {
+        "root".to_string()
+    } else {
+        name.replace('/', "-")
+    }
+}
+
+/// VFS 超级块对应的 DBFS 挂载点
+pub fn mount_point_of(sb: &Arc<dyn VfsSuperBlock>) -> Option<String> {
+    find_mount(sb).map(|sb| sb.mount_point().to_string())
+}
+
+pub(super) fn find_by_name(name: &str) -> Option<Arc<DbfsSuperBlock>> {
+    mounts()
+        .into_iter()
+        .find(|sb| proc_name(sb.mount_point()) == name)
+}
+
+/// `transactions` 文件内容, 挂载不存在时为 None
+pub fn describe_transactions(name: &str) -> Option<String> {
+    let sb = find_by_name(name)?;
+    let now = sys::now_ms();
+    let mut out = String::from("ID PID AGE_MS WRITES LOCKS STATE\n");
+    for (info, writes) in sb.tx().transactions() {
+        let pid = info
+            .owner
+            .map_or("-".to_string(), |pid| pid.to_string());
+        let state = match (info.aborted, info.waiting_for) {
+            (Some(reason), _) => format!("aborted({:?})", reason),
+            (None, Some(ino)) => format!("waiting(inode {})", ino),
+            (None, None) => "active".to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "{} {} {} {} {} {}",
+            info.tx_id.value(),
+            pid,
+            now.saturating_sub(info.started_ms),
+            writes,
+            info.locks_held,
+            state
+        );
+    }
+    Some(out)
+}
+
+/// `stats` 文件内容, 挂载不存在时为 None
+pub fn describe_stats(name: &str) -> Option<String> {
+    let sb = find_by_name(name)?;
+    let (commits, rollbacks) = sb.tx().completed();
+    let aborts = sb.tx().abort_stats();
+    let wal = sb.wal_counters();
+    let (replay_ms, replayed_txs) = sb.replay_stats();
+    let (hits, misses) = sb.cache_stats();
+    let hit_rate = if hits + misses == 0 {
+        0
+    } else {
+        hits * 100 / (hits + misses)
+    };
+
+    let mut out = String::new();
+    let _ = writeln!(out, "mount_point: {}", sb.mount_point());
+    let _ = writeln!(out, "encrypted: {}", if sb.is_encrypted() { "yes" } else { "no" });
+    let _ = writeln!(out, "transactions: {}", sb.tx().transactions().len());
+    let _ = writeln!(out, "commits: {}", commits);
+    let _ = writeln!(out, "rollbacks: {}", rollbacks);
+    let _ = writeln!(
+        out,
+        "aborts: {}",
+        aborts.conflict + aborts.deadlock + aborts.timeout + aborts.user + aborts.quota
+    );
+    let _ = writeln!(out, "conflicts: {}", aborts.conflict);
+    let _ = writeln!(out, "deadlocks: {}", aborts.deadlock);
+    let _ = writeln!(out, "timeouts: {}", aborts.timeout);
+    let _ = writeln!(out, "user_aborts: {}", aborts.user);
+    let _ = writeln!(out, "quota_aborts: {}", aborts.quota);
+    let _ = writeln!(out, "wal_records: {}", wal.next_lsn - 1);
+    let _ = writeln!(out, "wal_buffered_records: {}", wal.buffered_records);
+    let _ = writeln!(out, "wal_flushed_bytes: {}", wal.flushed_bytes);
+    let device_bytes = wal
+        .device_bytes
+        .map_or("-".to_string(), |bytes| bytes.to_string());
+    let _ = writeln!(out, "wal_device_bytes: {}", device_bytes);
+    let _ = writeln!(out, "flushed_lsn: {}", wal.flushed_lsn);
+    let _ = writeln!(out, "checkpoint_lsn: {}", wal.checkpoint_lsn);
+    let _ = writeln!(out, "next_tx_id: {}", wal.next_tx_id);
+    let _ = writeln!(out, "replay_ms: {}", replay_ms);
+    let _ = writeln!(out, "replayed_transactions: {}", replayed_txs);
+    let _ = writeln!(out, "inode_cache_hits: {}", hits);
+    let _ = writeln!(out, "inode_cache_misses: {}", misses);
+    let _ = writeln!(out, "inode_cache_hit_rate: {}%", hit_rate);
+    let data = sb.data_stats();
+    let _ = writeln!(out, "data_logical_bytes: {}", data.logical_bytes);
+    let _ = writeln!(out, "data_stored_bytes: {}", data.stored_bytes);
+    let _ = writeln!(out, "compressed_files: {}", data.compressed_files);
+    let _ = writeln!(out, "compressed_logical_bytes: {}", data.compressed_logical_bytes);
+    let _ = writeln!(out, "compressed_stored_bytes: {}", data.compressed_stored_bytes);
+    match sb.dedup_stats() {
+        Some(dedup) => {
+            let _ = writeln!(out, "dedup: yes");
+            let _ = writeln!(out, "dedup_chunks: {}", dedup.chunks);
+            let _ = writeln!(out, "dedup_shared_chunks: {}", dedup.shared_chunks);
+            let _ = writeln!(out, "dedup_unique_bytes: {}", dedup.unique_bytes);
+            let _ = writeln!(out, "dedup_saved_bytes: {}", dedup.saved_bytes);
+            let _ = writeln!(out, "dedup_hits: {}", dedup.hits);
+        }
+        None => {
+            let _ = writeln!(out, "dedup: no");
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proc_name() {
+        assert_eq!(proc_name("/"), "root");
+        assert_eq!(proc_name("/data"), "data");
+        assert_eq!(proc_name("/mnt/a/"), "mnt-a");
+    }
+}
@@ -1,19 +1,97 @@
 //! DBFS SuperBlock for Alien Integration
 //!
 //! Phase 2: 集成 WAL 事务层
-
-use alloc::{format, string::String, string::ToString, sync::Arc};
-use ksync::Mutex;
-use log::info;
+//!
+//! 每个挂载是一个独立的 [`DbfsSuperBlock`]: 拥有自己的 WAL、inode 缓存和事务上下文。
+//! 已挂载的实例登记在挂载表中, 卸载 (`kill_sb`) 时移除。
+//!
+//! `statfs` 的容量来自后端: 块设备或普通文件为设备大小, 内存 WAL 为 `wal_size` 选项;
+//! 已用空间为文件数据加上 WAL 在后端上占用的字节数。
+//!
+//! 提交的提交记录刷盘之后, 事务的变更才追加到本挂载的变更流 ([`ChangeLog`]);
+//! 异步 / 组提交模式下等到下一次刷 WAL。checkpoint 把流写入镜像, 挂载时从镜像和
+//! 之后的已提交事务重建。
+//!
+//! 挂载时 WAL 中已提交的事务 (包括最近一次 checkpoint 写入的镜像) 按 LSN 顺序
+//! 重放到 inode 树, 恢复失败时拒绝挂载。
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use log::{info, warn};
 use vfscore::{
+    error::VfsError,
     fstype::VfsFsType,
     superblock::{SuperType, VfsSuperBlock},
     utils::VfsFsStat,
     VfsResult,
 };
 
-use crate::wal::{TxId, Wal};
-use super::{fstype::DummyFsType, inode::DbfsInode};
+use crate::{
+    cdc::{self, ChangeLog, ChangeRecord},
+    common::{DbfsError, DbfsResult},
+    crypt::Cipher,
+    dedup::{DedupIndex, DedupStats},
+    wal::{Lsn, TxId, Wal, WalRecordType, WalStats},
+};
+use super::{
+    dentry::DbfsDentry,
+    fstype::DbfsFsType,
+    inode::{self, DbfsInode, TxContext},
+    options::{DbfsMountOptions, Durability, IsolationLevel},
+    quota::{Charge, QuotaTable},
+    sys::{self, Mutex},
+};
+
+/// statfs 返回的文件系统魔数 ("DBFS")
+const DBFS_MAGIC: i64 = 0x44424653;
+/// statfs f_flags: 只读挂载
+const ST_RDONLY: isize = 1;
+
+/// 已挂载的 DBFS 实例 (按挂载顺序)
+static MOUNTS: Mutex<Vec<Arc<DbfsSuperBlock>>> = Mutex::new(Vec::new());
+
+/// 登记新挂载
+pub(super) fn register_mount(sb: Arc<DbfsSuperBlock>) {
+    MOUNTS.lock().push(sb);
+}
+
+/// 当前所有挂载
+pub(super) fn mounts() -> Vec<Arc<DbfsSuperBlock>> {
+    MOUNTS.lock().clone()
+}
+
+/// 默认挂载: 最早挂载且仍未卸载的实例
+pub(super) fn default_mount() -> Option<Arc<DbfsSuperBlock>> {
+    MOUNTS.lock().first().cloned()
+}
+
+/// 按 VFS 超级块查找 DBFS 挂载
+pub(super) fn find_mount(sb: &Arc<dyn VfsSuperBlock>) -> Option<Arc<DbfsSuperBlock>> {
+    let ptr = Arc::as_ptr(sb) as *const u8;
+    MOUNTS
+        .lock()
+        .iter()
+        .find(|m| Arc::as_ptr(m) as *const u8 == ptr)
+        .cloned()
+}
+
+/// 从挂载表中移除
+fn unregister_mount(sb: &DbfsSuperBlock) {
+    MOUNTS
+        .lock()
+        .retain(|m| !core::ptr::eq(Arc::as_ptr(m), sb as *const DbfsSuperBlock));
+}
+
+/// 超级块是否为仍有事务或打开文件的 DBFS 挂载 (卸载前检查, 对应 EBUSY)
+pub fn mount_busy(sb: &Arc<dyn VfsSuperBlock>) -> bool {
+    find_mount(sb).map_or(false, |sb| sb.is_busy())
+}
 
 /// DBFS SuperBlock with Transaction Support
 ///
@@ -30,49 +108,361 @@ pub struct DbfsSuperBlock {
     wal: Mutex<Wal>,
     /// Root inode (cached)
     root: Mutex<Option<Arc<DbfsInode>>>,
+    /// Root dentry (用于卸载前检查打开的文件)
+    root_dentry: Mutex<Option<Arc<DbfsDentry>>>,
+    /// Inode 缓存: ino -> inode (不含根)
+    inodes: Mutex<BTreeMap<u64, Arc<DbfsInode>>>,
+    /// 本挂载的事务上下文
+    tx: TxContext,
+    /// 用量统计和目录配额
+    quotas: Mutex<QuotaTable>,
+    /// 文件系统类型
+    fs_type: Arc<DbfsFsType>,
+    /// 挂载点
+    mount_point: String,
+    /// 挂载选项
+    options: DbfsMountOptions,
+    /// 上次刷 WAL 之后提交的事务数 (group commit)
+    unflushed_commits: AtomicUsize,
+    /// 上次 checkpoint 的时间 (ms)
+    last_checkpoint_ms: AtomicU64,
+    /// 上次 checkpoint 之后 WAL 在后端上占用的字节数
+    checkpoint_used: AtomicU64,
+    /// 上次 checkpoint 之后是否有新的提交
+    dirty: AtomicBool,
+    /// 挂载时崩溃恢复的耗时 (ms) 和扫描到的事务数
+    replay_ms: AtomicU64,
+    replayed_txs: AtomicU64,
+    /// lookup 命中 / 未命中 inode 缓存的次数
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    /// 已提交事务的变更流
+    changes: Mutex<ChangeLog>,
+    /// 提交记录还没有刷盘的事务的变更, 刷 WAL 成功后追加到变更流
+    unflushed_changes: Mutex<Vec<ChangeRecord>>,
+    /// 加密挂载时用于文件 extent 的密钥
+    cipher: Option<Arc<Cipher>>,
+    /// 去重挂载的块索引
+    dedup: Option<Arc<DedupIndex>>,
 }
 
 impl DbfsSuperBlock {
-    /// Create a new superblock with WAL
+    /// Create a new superblock with an in-memory WAL and default options
+    ///
+    /// 不登记到挂载表
     pub fn new(db_path: String) -> Arc<Self> {
-        info!("✓ DBFS: Initializing superblock with WAL");
-
         let wal = Wal::new(format!("{}/.wal", db_path))
             .expect("Failed to initialize WAL");
+        let fs_type = Arc::new(DbfsFsType::new(db_path));
+        Self::with_wal(fs_type, "/".to_string(), DbfsMountOptions::default(), wal)
+            .expect("Failed to recover WAL")
+    }
 
+    /// Create a new superblock from mount options and an opened WAL
+    ///
+    /// WAL 中已提交的事务在返回之前重放到 inode 树; 恢复失败时拒绝挂载
+    pub fn with_wal(
+        fs_type: Arc<DbfsFsType>,
+        mount_point: String,
+        options: DbfsMountOptions,
+        mut wal: Wal,
+    ) -> DbfsResult<Arc<Self>> {
+        info!("✓ DBFS: Initializing superblock for {} with WAL ({:?})", mount_point, options);
+
+        // 之后分配的事务 ID 不与 WAL 中已有的事务重复
+        inode::reserve_tx_ids(wal.next_tx_id());
+
+        let cipher = options.key.as_ref().map(|key| Arc::new(Cipher::new(key)));
+        let dedup = options.dedup.then(|| Arc::new(DedupIndex::new()));
+        wal.set_dedup(options.dedup);
         let sb = Arc::new(Self {
             block_size: 4096,
-            db_path,
+            db_path: fs_type.db_path().to_string(),
             wal: Mutex::new(wal),
             root: Mutex::new(None),
+            root_dentry: Mutex::new(None),
+            inodes: Mutex::new(BTreeMap::new()),
+            tx: TxContext::new(),
+            quotas: Mutex::new(QuotaTable::new()),
+            fs_type,
+            mount_point,
+            options,
+            unflushed_commits: AtomicUsize::new(0),
+            last_checkpoint_ms: AtomicU64::new(sys::now_ms()),
+            checkpoint_used: AtomicU64::new(0),
+            dirty: AtomicBool::new(false),
+            replay_ms: AtomicU64::new(0),
+            replayed_txs: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            changes: Mutex::new(ChangeLog::new(cdc::DEFAULT_RETENTION)),
+            unflushed_changes: Mutex::new(Vec::new()),
+            cipher,
+            dedup,
         });
+        *sb.root.lock() = Some(DbfsInode::new_root(sb.clone()));
 
         // Perform crash recovery
-        sb.recover();
+        if let Err(e) = sb.recover() {
+            // 打破 superblock <-> inode 的引用环
+            sb.inodes.lock().clear();
+            *sb.root.lock() = None;
+            return Err(e);
+        }
 
-        sb
+        Ok(sb)
     }
 
-    /// Create root inode
+    /// Get root inode
     pub fn root_inode(self: &Arc<Self>) -> VfsResult<Arc<dyn vfscore::inode::VfsInode>> {
-        Ok(DbfsInode::new_root(self.clone()))
+        VfsSuperBlock::root_inode(self.as_ref())
+    }
+
+    /// 挂载点
+    pub fn mount_point(&self) -> &str {
+        &self.mount_point
+    }
+
+    pub(super) fn tx(&self) -> &TxContext {
+        &self.tx
+    }
+
+    pub(super) fn set_root_dentry(&self, dentry: Arc<DbfsDentry>) {
+        *self.root_dentry.lock() = Some(dentry);
+    }
+
+    pub(super) fn cache_inode(&self, inode: Arc<DbfsInode>) {
+        self.inodes.lock().insert(inode.ino(), inode);
+    }
+
+    pub(super) fn cached_inode(&self, ino: u64) -> Option<Arc<DbfsInode>> {
+        self.inodes.lock().get(&ino).cloned()
+    }
+
+    /// lookup 使用的缓存查询, 计入命中率统计
+    pub(super) fn lookup_cached(&self, ino: u64) -> Option<Arc<DbfsInode>> {
+        let inode = self.cached_inode(ino);
+        let counter = if inode.is_some() {
+            &self.cache_hits
+        } else {
+            &self.cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        inode
+    }
+
+    /// inode 缓存 (命中, 未命中) 次数
+    pub(super) fn cache_stats(&self) -> (u64, u64) {
+        (
+            self.cache_hits.load(Ordering::Relaxed),
+            self.cache_misses.load(Ordering::Relaxed),
+        )
+    }
+
+    /// 挂载时崩溃恢复的 (耗时 ms, 事务数)
+    pub(super) fn replay_stats(&self) -> (u64, u64) {
+        (
+            self.replay_ms.load(Ordering::Relaxed),
+            self.replayed_txs.load(Ordering::Relaxed),
+        )
+    }
+
+    pub(super) fn quotas(&self) -> &Mutex<QuotaTable> {
+        &self.quotas
+    }
+
+    /// 计入一次修改的用量, 超出目录配额时返回 [`DbfsError::QuotaExceeded`]
+    pub(super) fn charge(&self, charge: &Charge) -> DbfsResult<()> {
+        self.quotas.lock().charge(charge, true)
+    }
+
+    /// 撤销修改时退还用量
+    pub(super) fn uncharge(&self, charge: &Charge) {
+        let _ = self.quotas.lock().charge(&charge.inverse(), false);
+    }
+
+    /// 覆盖该路径的目录配额中是否有已超出限制的
+    pub(super) fn quota_exceeded(&self, path: &str) -> bool {
+        self.quotas.lock().exceeded(path)
+    }
+
+    /// 按挂载内的绝对路径查找 inode
+    pub(super) fn resolve(&self, path: &str) -> DbfsResult<Arc<DbfsInode>> {
+        let mut inode = self.root.lock().clone().ok_or(DbfsError::NotFound)?;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            inode = inode.child(name).ok_or(DbfsError::NotFound)?;
+        }
+        Ok(inode)
+    }
+
+    /// 按挂载内的绝对路径查找目录
+    pub(super) fn resolve_dir(&self, path: &str) -> DbfsResult<Arc<DbfsInode>> {
+        let inode = self.resolve(path)?;
+        if !inode.is_dir() {
+            return Err(DbfsError::InvalidArgument);
+        }
+        Ok(inode)
+    }
+
+    /// 是否仍有进行中的事务或打开的文件
+    pub fn is_busy(&self) -> bool {
+        if !self.tx.is_idle() {
+            return true;
+        }
+        let root_dentry = self.root_dentry.lock().clone();
+        root_dentry.map_or(false, |dentry| dentry.has_external_refs())
+    }
+
+    /// 卸载: 刷 WAL、checkpoint, 然后释放 inode 缓存
+    ///
+    /// 仍有事务或打开的文件时返回 [`DbfsError::Busy`], 挂载保持不变。
+    /// 刷盘之后日志就是完整的持久副本; checkpoint 只缩短下次挂载的重放,
+    /// 上次 checkpoint 之后没有提交时跳过, 失败时保留日志照常卸载。
+    pub(super) fn shutdown(&self) -> DbfsResult<()> {
+        if self.is_busy() {
+            warn!("⚠ DBFS: {} is busy, refusing to unmount", self.mount_point);
+            return Err(DbfsError::Busy);
+        }
+        if !self.options.read_only {
+            let mut wal = self.wal.lock();
+            self.flush_locked(&mut wal)?;
+            if self.dirty.load(Ordering::SeqCst) {
+                if let Err(e) = self.checkpoint_locked(&mut wal) {
+                    warn!(
+                        "⚠ DBFS: Checkpoint of {} failed, keeping the log: {:?}",
+                        self.mount_point, e
+                    );
+                }
+            }
+        }
+        self.unflushed_commits.store(0, Ordering::SeqCst);
+        self.detach();
+        info!("✓ DBFS: {} unmounted", self.mount_point);
+        Ok(())
+    }
+
+    /// 释放 inode 缓存并移出挂载表, 不刷 WAL
+    ///
+    /// 崩溃测试用它模拟掉电: 之后只有已经写到设备上的内容能被重新挂载
+    pub(super) fn detach(&self) {
+        // 打破 superblock <-> inode / dentry 的引用环
+        self.inodes.lock().clear();
+        *self.root_dentry.lock() = None;
+        *self.root.lock() = None;
+        unregister_mount(self);
+    }
+
+    /// 立即 checkpoint
+    ///
+    /// 有进行中的事务时返回 [`DbfsError::Busy`]
+    pub(super) fn checkpoint(&self) -> DbfsResult<Lsn> {
+        let mut wal = self.wal.lock();
+        let lsn = self.checkpoint_locked(&mut wal)?;
+        self.unflushed_commits.store(0, Ordering::SeqCst);
+        self.last_checkpoint_ms.store(sys::now_ms(), Ordering::SeqCst);
+        Ok(lsn)
+    }
+
+    /// checkpoint: 把整个文件系统作为镜像写入 WAL, 之前的日志空间随后被复用
+    ///
+    /// 有进行中的事务时返回 [`DbfsError::Busy`], 镜像不能包含未提交的修改
+    fn checkpoint_locked(&self, wal: &mut Wal) -> DbfsResult<Lsn> {
+        // 镜像事务的 ID 同样在所有挂载之间唯一
+        wal.reserve_tx_ids(inode::alloc_tx_id());
+        let root = self.root.lock().clone().ok_or(DbfsError::NotFound)?;
+        // 变更流随镜像保存, 截断日志后重新挂载仍能从原来的位置续读;
+        // 先刷盘, 让还没刷盘的提交进入变更流
+        self.flush_locked(wal)?;
+        let changes = self.changes.lock().encode();
+        let lsn = wal.checkpoint(|wal, tx_id| {
+            root.log_image(wal, tx_id)?;
+            wal.log_operation(tx_id, WalRecordType::ChangeState, changes);
+            Ok(())
+        })?;
+        let used = wal.device_usage().map_or(0, |(_, used)| used);
+        self.checkpoint_used.store(used, Ordering::SeqCst);
+        self.dirty.store(false, Ordering::SeqCst);
+        Ok(lsn)
+    }
+
+    /// 刷 WAL, 成功后把提交记录已经刷盘的事务追加到变更流
+    fn flush_locked(&self, wal: &mut Wal) -> DbfsResult<()> {
+        wal.flush()?;
+        let unflushed = core::mem::take(&mut *self.unflushed_changes.lock());
+        let mut changes = self.changes.lock();
+        for record in &unflushed {
+            changes.push(record);
+        }
+        Ok(())
     }
 
-    /// Begin a new transaction
+    /// Begin a new transaction on this mount
     pub fn begin_tx(&self) -> TxId {
-        self.wal.lock().begin_tx()
+        inode::begin_tx_on(self, None)
+    }
+
+    /// Begin a transaction with a global transaction ID
+    ///
+    /// 全局事务第一次修改本文件系统时调用
+    pub fn begin_tx_with_id(&self, tx_id: TxId) -> Lsn {
+        self.wal.lock().begin_tx_with_id(tx_id)
     }
 
     /// Commit a transaction
+    ///
+    /// 按挂载选项的 commit 模式决定是否立即刷 WAL, 并在到期时做 checkpoint。
+    /// 刷 WAL 失败时丢弃事务尚未写入的记录 (包括提交记录) 并返回错误, 由调用方回滚事务
     pub fn commit_tx(&self, tx_id: TxId) -> VfsResult<()> {
         info!("✓ DBFS: Committing transaction {}", tx_id);
 
-        // Flush WAL to disk first (durability)
-        self.wal.lock().commit_tx(tx_id)
-            .map_err(|e| {
+        let mut wal = self.wal.lock();
+        let commit_lsn = wal.log_commit(tx_id);
+        self.dirty.store(true, Ordering::SeqCst);
+        let record = ChangeRecord::from_records(tx_id, commit_lsn, wal.get_tx_records(tx_id));
+        self.unflushed_changes.lock().push(record);
+        let flush = match self.options.durability {
+            Durability::Sync => true,
+            Durability::Group => {
+                self.unflushed_commits.fetch_add(1, Ordering::SeqCst) + 1 >= self.options.group_commit
+            }
+            Durability::Async => false,
+        };
+        if flush {
+            // Flush WAL to disk first (durability)
+            if let Err(e) = self.flush_locked(&mut wal) {
                 log::error!("Failed to commit transaction {}: {:?}", tx_id, e);
-                vfscore::error::VfsError::IoError
-            })?;
+                // 之后的刷写不能再让这个事务持久化, 调用方随后回滚它
+                wal.discard_tx(tx_id);
+                self.unflushed_changes.lock().pop();
+                if self.options.durability == Durability::Group {
+                    self.unflushed_commits.fetch_sub(1, Ordering::SeqCst);
+                }
+                return Err(vfscore::error::VfsError::IoError);
+            }
+            self.unflushed_commits.store(0, Ordering::SeqCst);
+        }
+
+        let now = sys::now_ms();
+        let interval_due = self.options.checkpoint_interval_ms.map_or(false, |interval| {
+            now.saturating_sub(self.last_checkpoint_ms.load(Ordering::SeqCst)) >= interval
+        });
+        // 上次 checkpoint 之后日志又用掉了四分之一的后端空间
+        let space_due = wal.device_usage().map_or(false, |(size, used)| {
+            used.saturating_sub(self.checkpoint_used.load(Ordering::SeqCst)) >= size / 4
+        });
+        if interval_due || space_due {
+            // checkpoint 失败不影响已记录的提交, 下次提交时重试;
+            // 其他事务仍在进行时 (Busy) 同样推迟
+            match self.checkpoint_locked(&mut wal) {
+                Ok(_) => {
+                    self.unflushed_commits.store(0, Ordering::SeqCst);
+                    self.last_checkpoint_ms.store(now, Ordering::SeqCst);
+                }
+                Err(DbfsError::Busy) => {}
+                Err(e) => log::warn!("⚠ DBFS: Checkpoint failed: {:?}", e),
+            }
+        }
+        drop(wal);
 
         // TODO: Apply all operations to underlying filesystem
         // For now, operations are already logged in WAL
@@ -87,57 +477,135 @@ impl DbfsSuperBlock {
         self.wal.lock().rollback_tx(tx_id);
     }
 
+    /// Rollback a transaction to a savepoint
+    ///
+    /// 丢弃事务在 `savepoint` (LSN) 之后记录的操作
+    pub fn rollback_to_savepoint(&self, tx_id: TxId, savepoint: Lsn) {
+        info!("✓ DBFS: Rolling back transaction {} to LSN {}", tx_id, savepoint);
+        self.wal.lock().rollback_to_savepoint(tx_id, savepoint);
+    }
+
     /// Record a file write operation
-    pub fn record_write(&self, tx_id: TxId, path: &str, offset: u64, data: &[u8]) {
-        self.wal.lock().write_file(tx_id, path, offset, data);
+    ///
+    /// 压缩文件 (`compress`) 的数据在 WAL 中压缩存放
+    pub fn record_write(
+        &self,
+        tx_id: TxId,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        compress: bool,
+    ) -> Lsn {
+        let mut wal = self.wal.lock();
+        if compress {
+            wal.write_file_lz(tx_id, path, offset, data)
+        } else {
+            wal.write_file(tx_id, path, offset, data)
+        }
+    }
+
+    /// Record an inode flags change (FS_IOC_SETFLAGS)
+    pub fn record_set_flags(&self, tx_id: TxId, path: &str, flags: u32) -> Lsn {
+        self.wal.lock().set_flags(tx_id, path, flags)
+    }
+
+    /// Record a file range clone operation
+    pub fn record_clone(
+        &self,
+        tx_id: TxId,
+        src: &str,
+        src_offset: u64,
+        len: u64,
+        dst: &str,
+        dst_offset: u64,
+    ) -> Lsn {
+        self.wal
+            .lock()
+            .clone_file(tx_id, src, src_offset, len, dst, dst_offset)
     }
 
     /// Record a file create operation
-    pub fn record_create(&self, tx_id: TxId, path: &str) {
-        self.wal.lock().create_file(tx_id, path);
+    pub fn record_create(&self, tx_id: TxId, path: &str) -> Lsn {
+        self.wal.lock().create_file(tx_id, path)
     }
 
     /// Record a file delete operation
-    pub fn record_delete(&self, tx_id: TxId, path: &str) {
-        self.wal.lock().delete_file(tx_id, path);
+    pub fn record_delete(&self, tx_id: TxId, path: &str) -> Lsn {
+        self.wal.lock().delete_file(tx_id, path)
+    }
+
+    /// Record a rename operation
+    pub fn record_rename(&self, tx_id: TxId, from: &str, to: &str) -> Lsn {
+        self.wal.lock().rename(tx_id, from, to)
     }
 
     /// Record a mkdir operation
-    pub fn record_mkdir(&self, tx_id: TxId, path: &str) {
-        self.wal.lock().mkdir(tx_id, path);
+    pub fn record_mkdir(&self, tx_id: TxId, path: &str) -> Lsn {
+        self.wal.lock().mkdir(tx_id, path)
     }
 
     /// Crash recovery from WAL
-    fn recover(&self) {
+    ///
+    /// 按 LSN 顺序把已提交事务的操作 (包括最近一次 checkpoint 的镜像) 重放到
+    /// inode 树, 未提交的事务被丢弃
+    fn recover(&self) -> DbfsResult<()> {
         info!("✓ DBFS: Starting crash recovery...");
+        let start = sys::now_ms();
 
         let wal = self.wal.lock();
-        match wal.recover() {
-            Ok(recovery) => {
-                if recovery.committed.is_empty() && recovery.uncommitted.is_empty() {
-                    info!("✓ DBFS: No transactions to recover (clean shutdown)");
-                } else {
-                    info!("✓ DBFS: Found {} committed transactions",
-                          recovery.committed.len());
-                    info!("✓ DBFS: Found {} uncommitted transactions (will rollback)",
-                          recovery.uncommitted.len());
-
-                    // TODO: Replay committed transactions
-                    // For now, just log them
-                    for tx_id in &recovery.committed {
-                        info!("  - Transaction {} (committed)", tx_id);
-                    }
-
-                    // Uncommitted transactions are automatically rolled back
-                    for tx_id in &recovery.uncommitted {
-                        info!("  - Transaction {} (rolled back)", tx_id);
-                    }
-                }
-            }
-            Err(e) => {
-                log::error!("✗ DBFS: WAL recovery failed: {:?}", e);
-            }
+        self.changes.lock().recover(wal.records()).map_err(|e| {
+            log::error!("✗ DBFS: Corrupt change stream in the checkpoint image: {:?}", e);
+            e
+        })?;
+        let recovery = wal.recover().map_err(|e| {
+            log::error!("✗ DBFS: WAL recovery failed: {:?}", e);
+            e
+        })?;
+        drop(wal);
+
+        let txs = recovery.committed.len() + recovery.uncommitted.len();
+        self.replayed_txs.store(txs as u64, Ordering::Relaxed);
+        if txs == 0 {
+            info!("✓ DBFS: No transactions to recover (clean shutdown)");
+        } else {
+            info!("✓ DBFS: Found {} committed transactions", recovery.committed.len());
+            info!(
+                "✓ DBFS: Found {} uncommitted transactions (will rollback)",
+                recovery.uncommitted.len()
+            );
+        }
+
+        let root = self.root.lock().clone().ok_or(DbfsError::NotFound)?;
+        for record in &recovery.redo {
+            let op = record.operation().ok_or_else(|| {
+                log::error!("✗ DBFS: Malformed WAL record at LSN {}", record.lsn);
+                DbfsError::Io
+            })?;
+            root.replay(op).map_err(|e| {
+                log::error!("✗ DBFS: Cannot replay WAL record at LSN {}: {:?}", record.lsn, e);
+                e
+            })?;
         }
+        info!("✓ DBFS: Replayed {} operations", recovery.redo.len());
+
+        let elapsed = sys::now_ms().saturating_sub(start);
+        self.replay_ms.store(elapsed, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// 是否为只读挂载
+    pub fn is_read_only(&self) -> bool {
+        self.options.read_only
+    }
+
+    /// 挂载时指定的隔离级别
+    pub fn isolation(&self) -> IsolationLevel {
+        self.options.isolation
+    }
+
+    /// 挂载选项
+    pub fn options(&self) -> &DbfsMountOptions {
+        &self.options
     }
 
     /// Get WAL statistics
@@ -145,23 +613,105 @@ impl DbfsSuperBlock {
         let wal = self.wal.lock();
         (wal.next_tx_id(), wal.flushed_lsn())
     }
+
+    /// WAL 计数器快照
+    pub fn wal_counters(&self) -> WalStats {
+        self.wal.lock().stats()
+    }
+
+    /// inode 缓存中文件的逻辑大小和实际占用
+    pub(super) fn data_stats(&self) -> DataStats {
+        let mut stats = DataStats::default();
+        let inodes: Vec<_> = self.inodes.lock().values().cloned().collect();
+        for (logical, stored, compressed) in inodes.iter().filter_map(|inode| inode.file_sizes()) {
+            stats.logical_bytes += logical;
+            stats.stored_bytes += stored;
+            if compressed {
+                stats.compressed_files += 1;
+                stats.compressed_logical_bytes += logical;
+                stats.compressed_stored_bytes += stored;
+            }
+        }
+        stats
+    }
+
+    /// 文件 extent 的密钥, 未加密时为 None
+    pub(super) fn cipher(&self) -> Option<Arc<Cipher>> {
+        self.cipher.clone()
+    }
+
+    /// 是否为加密挂载
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// 去重挂载的块索引, 未开启去重时为 None
+    pub(super) fn dedup(&self) -> Option<Arc<DedupIndex>> {
+        self.dedup.clone()
+    }
+
+    /// 去重节省的空间, 未开启去重时为 None
+    pub(super) fn dedup_stats(&self) -> Option<DedupStats> {
+        self.dedup.as_ref().map(|index| index.stats())
+    }
+
+    /// 本挂载的变更流
+    pub(super) fn changes(&self) -> &Mutex<ChangeLog> {
+        &self.changes
+    }
+}
+
+/// 文件数据量 (见 [`DbfsSuperBlock::data_stats`])
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct DataStats {
+    pub logical_bytes: u64,
+    pub stored_bytes: u64,
+    pub compressed_files: u64,
+    pub compressed_logical_bytes: u64,
+    pub compressed_stored_bytes: u64,
 }
 
 impl VfsSuperBlock for DbfsSuperBlock {
     fn sync_fs(&self, _wait: bool) -> VfsResult<()> {
         info!("✓ DBFS: Syncing filesystem");
         // Flush WAL to disk
-        self.wal.lock().flush()
+        self.flush_locked(&mut self.wal.lock())
             .map_err(|_| vfscore::error::VfsError::IoError)?;
+        self.unflushed_commits.store(0, Ordering::SeqCst);
         Ok(())
     }
 
     fn stat_fs(&self) -> VfsResult<VfsFsStat> {
-        // Use default for now, as the exact field names vary between versions
-        let mut stat = VfsFsStat::default();
-        // Set the fields we know exist
-        stat.f_type = 0x44424653;      // "DBFS" magic number
-        Ok(stat)
+        let bsize = self.block_size;
+        let (capacity, wal_used) = self
+            .wal
+            .lock()
+            .device_usage()
+            .unwrap_or((self.options.wal_size, 0));
+        let usage = self.quotas.lock().total();
+        // 去重共享的块只占一份空间
+        let saved = self.dedup_stats().map_or(0, |stats| stats.saved_bytes);
+
+        let blocks = capacity / bsize;
+        let used = (usage.bytes.saturating_sub(saved) + wal_used + bsize - 1) / bsize;
+        let free = blocks.saturating_sub(used);
+        // 每个 inode 至少对应一个目录项, 按每块一个 inode 估算总数 (含根目录)
+        let files = blocks;
+        let ffree = files.saturating_sub(usage.inodes + 1);
+        Ok(VfsFsStat {
+            f_type: DBFS_MAGIC,
+            f_bsize: bsize as i64,
+            f_blocks: blocks,
+            f_bfree: free,
+            f_bavail: free,
+            f_files: files,
+            f_ffree: ffree,
+            f_fsid: [0; 2],
+            f_namelen: 255,
+            f_frsize: bsize as isize,
+            f_flags: if self.options.read_only { ST_RDONLY } else { 0 },
+            f_spare: [0; 4],
+        })
     }
 
     fn super_type(&self) -> SuperType {
@@ -170,35 +720,12 @@ impl VfsSuperBlock for DbfsSuperBlock {
     }
 
     fn fs_type(&self) -> Arc<dyn VfsFsType> {
-        Arc::new(DummyFsType {
-            name: self.db_path.clone(),
-        })
+        self.fs_type.clone()
     }
 
     fn root_inode(&self) -> VfsResult<Arc<dyn vfscore::inode::VfsInode>> {
-        // Check if root is already cached
-        let mut root_cache = self.root.lock();
-        if let Some(ref inode) = *root_cache {
-            return Ok(Arc::clone(inode) as Arc<dyn vfscore::inode::VfsInode>);
-        }
-
-        // Need to create root inode, but we need Arc<Self>
-        // Use unsafe to reconstruct Arc from &self
-        // This is safe because superblock is always created as Arc via DbfsSuperBlock::new()
-        unsafe {
-            // Reconstruct Arc from pointer - this increments ref count
-            let arc_self = Arc::from_raw(self as *const DbfsSuperBlock);
-
-            // Create the inode (which increments ref count again)
-            let inode = DbfsInode::new_root(Arc::clone(&arc_self));
-
-            // Forget the first Arc to avoid decrementing ref count
-            core::mem::forget(arc_self);
-
-            // Cache the inode
-            *root_cache = Some(Arc::clone(&inode));
-
-            Ok(inode as Arc<dyn vfscore::inode::VfsInode>)
-        }
+        // 根 inode 在创建超级块时建立, 卸载后为空
+        let root = self.root.lock().clone().ok_or(VfsError::Invalid)?;
+        Ok(root as Arc<dyn vfscore::inode::VfsInode>)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,78 @@
+//! DBFS 用到的内核服务
+//!
+//! 带 `kernel` feature (默认) 编译时使用 ksync / timer / shim; 不带该 feature 时
+//! (host 上的崩溃测试工具 `dbfs_crash`) 换成自旋锁、不前进的时钟和没有当前任务的
+//! 单线程环境, alien_integration 因此不依赖只能为 RISC-V 编译的 crate。
+//!
+//! host 上时钟停在 0: 事务不会超时, 也不会按时间间隔触发 checkpoint。
+
+#[cfg(feature = "kernel")]
+pub(super) use ksync::Mutex;
+#[cfg(not(feature = "kernel"))]
+pub(super) use spin::Mutex;
+
+/// 当前时间 (ms)
+#[cfg(feature = "kernel")]
+pub(super) fn now_ms() -> u64 {
+    timer::get_time_ms() as u64
+}
+
+#[cfg(not(feature = "kernel"))]
+pub(super) fn now_ms() -> u64 {
+    0
+}
+
+/// 当前进程号 (没有当前任务时为 None)
+#[cfg(feature = "kernel")]
+pub(super) fn current_pid() -> Option<usize> {
+    shim::current_task().map(|task| task.pid())
+}
+
+#[cfg(not(feature = "kernel"))]
+pub(super) fn current_pid() -> Option<usize> {
+    None
+}
+
+/// 当前任务 (线程) 号, 没有当前任务时为 None
+#[cfg(feature = "kernel")]
+pub(super) fn current_tid() -> Option<usize> {
+    shim::current_task().map(|task| task.tid())
+}
+
+#[cfg(not(feature = "kernel"))]
+pub(super) fn current_tid() -> Option<usize> {
+    None
+}
+
+/// 等待锁时让出 CPU
+#[cfg(feature = "kernel")]
+pub(super) fn yield_now() {
+    shim::suspend()
+}
+
+#[cfg(not(feature = "kernel"))]
+pub(super) fn yield_now() {
+    core::hint::spin_loop()
+}
+
+/// 读取 ioctl 参数指向的用户态数据
+#[cfg(feature = "kernel")]
+pub(super) fn read_user<T: Copy + 'static>(ptr: *const T) -> T {
+    *shim::transfer_ptr(ptr)
+}
+
+#[cfg(not(feature = "kernel"))]
+pub(super) fn read_user<T: Copy + 'static>(ptr: *const T) -> T {
+    unsafe { *ptr }
+}
+
+/// 写入 ioctl 参数指向的用户态数据
+#[cfg(feature = "kernel")]
+pub(super) fn write_user<T: 'static>(ptr: *mut T, value: T) {
+    *shim::transfer_ptr_mut(ptr) = value;
+}
+
+#[cfg(not(feature = "kernel"))]
+pub(super) fn write_user<T: 'static>(ptr: *mut T, value: T) {
+    unsafe { *ptr = value }
+}
@@ -18,10 +18,21 @@
 //! - 网络分区: 模拟部分失败
 //! - 长时间运行: 稳定性测试
 
-use alloc::{format, string::String, vec::Vec};
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use crate::common::DbfsResult;
+use crate::failpoint::{self, FailAction, FP_WAL_AFTER_WRITE, FP_WAL_BEFORE_WRITE, FP_WAL_CHECKPOINT, FP_WAL_REPLAY};
+use crate::log_manager::{BlockDevice, MemBlockDevice};
 use crate::wal::{TxId, Wal, WalRecordType};
 use log::info;
 
+/// 崩溃测试使用的 WAL 设备大小
+const CRASH_TEST_DEVICE_SIZE: usize = 256 * 1024;
+
+/// 在同一设备上重新挂载 WAL (模拟重启)
+fn remount(device: &Arc<MemBlockDevice>) -> Wal {
+    Wal::with_device(String::from("/test/wal"), device.clone() as Arc<dyn BlockDevice>).unwrap()
+}
+
 // ==================== Elle 测试: 隔离级别 ====================
 
 /// Elle 测试 1: G1c (G1-item) 检测
@@ -216,9 +227,11 @@ pub fn elle_test_serializable_isolation() -> bool {
 pub fn jepsen_test_crash_during_transaction() -> bool {
     info!("\n🔬 Jepsen Test 1: Crash During Transaction");
 
+    let device = Arc::new(MemBlockDevice::new(CRASH_TEST_DEVICE_SIZE));
+
     // 模拟阶段 1: 正常写入
     {
-        let mut wal = Wal::new(String::from("/test/wal")).unwrap();
+        let mut wal = remount(&device);
 
         let tx1 = wal.begin_tx();
         wal.write_file(tx1, "/important.txt", 0, b"critical_data");
@@ -227,22 +240,26 @@ pub fn jepsen_test_crash_during_transaction() -> bool {
 
         info!("  Phase 1: TX1 committed and flushed");
     } // wal 被销毁,模拟进程崩溃
+    device.crash();
 
     // 模拟阶段 2: 重启后恢复
     {
-        let mut wal = Wal::new(String::from("/test/wal")).unwrap();
+        let mut wal = remount(&device);
 
         // 未提交的事务开始,但未完成
         let tx2 = wal.begin_tx();
         wal.write_file(tx2, "/temp.txt", 0, b"will_be_lost");
+        // 中间状态已写入 WAL, 但事务未提交
+        wal.flush().unwrap();
         // 进程在这里崩溃,tx2 未提交
 
         info!("  Phase 2: TX2 started but not committed (crash)");
     } // 崩溃!
+    device.crash();
 
     // 模拟阶段 3: 再次重启
     {
-        let wal = Wal::new(String::from("/test/wal")).unwrap();
+        let wal = remount(&device);
         let recovery = wal.recover().unwrap();
 
         info!("  Phase 3: Recovery after crash");
@@ -331,7 +348,8 @@ pub fn jepsen_test_concurrent_fatigue() -> bool {
 pub fn jepsen_test_checkpoint_crash() -> bool {
     info!("\n🔬 Jepsen Test 3: Checkpoint Crash");
 
-    let mut wal = Wal::new(String::from("/test/wal")).unwrap();
+    let device = Arc::new(MemBlockDevice::new(CRASH_TEST_DEVICE_SIZE));
+    let mut wal = remount(&device);
 
     // 阶段 1: 创建 20 个事务
     for i in 1..=20 {
@@ -342,20 +360,45 @@ pub fn jepsen_test_checkpoint_crash() -> bool {
 
     info!("  Created 20 transactions");
 
-    // 阶段 2: Checkpoint 到 LSN 15
-    wal.truncate(15);
-    info!("  Checkpointed at LSN 15");
-
-    // 模拟崩溃
-    info!("  Simulating crash after checkpoint...");
-
-    // 阶段 3: 重启并恢复
-    let mut wal_new = Wal::new(String::from("/test/wal")).unwrap();
+    // 镜像: 20 个文件的当前内容
+    let image = |wal: &mut Wal, tx_id: TxId| -> DbfsResult<()> {
+        for i in 1..=20 {
+            wal.write_file(tx_id, &format!("/file{}.txt", i), 0, b"data");
+        }
+        Ok(())
+    };
+
+    // 阶段 2: checkpoint 写到一半时崩溃 (镜像已落盘, WAL 头未更新)
+    failpoint::arm(FP_WAL_CHECKPOINT, FailAction::Error, Some(1));
+    if wal.checkpoint(image).is_ok() {
+        info!("  ❌ Checkpoint failpoint did not fire");
+        return false;
+    }
+    drop(wal);
+    device.crash();
+    info!("  Simulating crash in the middle of checkpoint...");
+
+    // 阶段 3: 重启并恢复, 20 个事务 (和已落盘的镜像) 必须仍然可见
+    let mut wal_new = remount(&device);
+    let recovery = wal_new.recover().unwrap();
+    if recovery.committed.len() < 20 {
+        info!("  ❌ Expected 20 committed transactions, got {}", recovery.committed.len());
+        return false;
+    }
 
-    // 添加新的事务
+    // 完成一次正常的 checkpoint 后再重启, 旧事务不再需要重放, 只重放镜像
+    wal_new.checkpoint(image).unwrap();
     let tx_new = wal_new.begin_tx();
     wal_new.write_file(tx_new, "/after_crash.txt", 0, b"new_data");
     wal_new.commit_tx(tx_new).unwrap();
+    drop(wal_new);
+    device.crash();
+
+    let recovery = remount(&device).recover().unwrap();
+    if recovery.committed.len() != 2 || recovery.redo.len() != 21 {
+        info!("  ❌ Checkpointed transactions replayed again");
+        return false;
+    }
 
     info!("  ✅ Checkpoint crash recovery successful");
     info!("     Old transactions truncated");
@@ -424,10 +467,11 @@ pub fn jepsen_test_crash_recovery_loop() -> bool {
 
     for cycle in 0..num_cycles {
         info!("  Cycle {}/{}", cycle + 1, num_cycles);
+        let device = Arc::new(MemBlockDevice::new(CRASH_TEST_DEVICE_SIZE));
 
         // 阶段 1: 写入一些数据
         {
-            let mut wal = Wal::new(format!("/test/wal_cycle{}", cycle)).unwrap();
+            let mut wal = remount(&device);
 
             for i in 0..5 {
                 let tx_id = wal.begin_tx();
@@ -445,10 +489,11 @@ pub fn jepsen_test_crash_recovery_loop() -> bool {
             wal.flush().unwrap();
             info!("    Committed 4, left 1 uncommitted");
         } // 崩溃!
+        device.crash();
 
         // 阶段 2: 恢复
         {
-            let wal = Wal::new(format!("/test/wal_cycle{}", cycle)).unwrap();
+            let wal = remount(&device);
             let recovery = wal.recover().unwrap();
 
             info!("    Recovered: {} committed, {} uncommitted",
@@ -466,6 +511,114 @@ pub fn jepsen_test_crash_recovery_loop() -> bool {
     true
 }
 
+/// Jepsen 测试 6: WAL 写入撕裂
+///
+/// **目标**: 提交记录只写了一半时崩溃, 重启后该事务不可见, 之前的事务完好
+pub fn jepsen_test_torn_wal_write() -> bool {
+    info!("\n🔬 Jepsen Test 6: Torn WAL Write");
+
+    let device = Arc::new(MemBlockDevice::new(CRASH_TEST_DEVICE_SIZE));
+    {
+        let mut wal = remount(&device);
+        let tx1 = wal.begin_tx();
+        wal.write_file(tx1, "/stable.txt", 0, b"stable");
+        wal.commit_tx(tx1).unwrap();
+
+        // TX2 的记录只落盘 30 字节
+        failpoint::arm(FP_WAL_BEFORE_WRITE, FailAction::TornWrite(30), Some(1));
+        let tx2 = wal.begin_tx();
+        wal.write_file(tx2, "/torn.txt", 0, b"torn_payload");
+        if wal.commit_tx(tx2).is_ok() {
+            info!("  ❌ Torn write failpoint did not fire");
+            return false;
+        }
+        // 撕裂的数据也必须经过 flush 才能落盘
+        device.flush().unwrap();
+    }
+    device.crash();
+
+    let recovery = remount(&device).recover().unwrap();
+    info!("  Recovered: {} committed, {} uncommitted",
+          recovery.committed.len(), recovery.uncommitted.len());
+    if recovery.committed.len() == 1 && recovery.redo.len() == 1 {
+        info!("  ✅ Torn tail ignored, TX1 intact");
+        true
+    } else {
+        info!("  ❌ Torn write corrupted recovery");
+        false
+    }
+}
+
+/// Jepsen 测试 7: 写入后 flush 前崩溃 / flush 被丢弃
+///
+/// **目标**: 提交返回之前崩溃的事务不可见; flush 被设备丢弃时同样不可见
+pub fn jepsen_test_crash_before_flush() -> bool {
+    info!("\n🔬 Jepsen Test 7: Crash Between Write and Flush");
+
+    for action in [FailAction::Error, FailAction::DropWrite] {
+        let device = Arc::new(MemBlockDevice::new(CRASH_TEST_DEVICE_SIZE));
+        {
+            let mut wal = remount(&device);
+            let tx1 = wal.begin_tx();
+            wal.write_file(tx1, "/a.txt", 0, b"a");
+            wal.commit_tx(tx1).unwrap();
+
+            failpoint::arm(FP_WAL_AFTER_WRITE, action, Some(1));
+            let tx2 = wal.begin_tx();
+            wal.write_file(tx2, "/b.txt", 0, b"b");
+            let _ = wal.commit_tx(tx2);
+        }
+        device.crash();
+
+        let recovery = remount(&device).recover().unwrap();
+        info!("  {:?}: recovered {} committed", action, recovery.committed.len());
+        if recovery.committed.len() != 1 {
+            info!("  ❌ Unflushed transaction survived the crash");
+            return false;
+        }
+    }
+
+    info!("  ✅ Only flushed transactions survive");
+    true
+}
+
+/// Jepsen 测试 8: 恢复过程中再次崩溃
+///
+/// **目标**: 重放是幂等的, 重放中途崩溃后再次挂载得到相同结果
+pub fn jepsen_test_crash_during_replay() -> bool {
+    info!("\n🔬 Jepsen Test 8: Crash During Replay");
+
+    let device = Arc::new(MemBlockDevice::new(CRASH_TEST_DEVICE_SIZE));
+    {
+        let mut wal = remount(&device);
+        for i in 0..5 {
+            let tx_id = wal.begin_tx();
+            wal.write_file(tx_id, &format!("/replay{}.txt", i), 0, b"data");
+            wal.commit_tx(tx_id).unwrap();
+        }
+    }
+    device.crash();
+
+    // 第一次恢复在重放第一条记录时崩溃
+    failpoint::arm(FP_WAL_REPLAY, FailAction::Error, Some(1));
+    let first = remount(&device).recover();
+    if first.is_ok() {
+        info!("  ❌ Replay failpoint did not fire");
+        return false;
+    }
+
+    // 第二次恢复正常完成
+    let recovery = remount(&device).recover().unwrap();
+    info!("  Second recovery: {} committed", recovery.committed.len());
+    if recovery.committed.len() == 5 && recovery.redo.len() == 5 {
+        info!("  ✅ Replay is idempotent");
+        true
+    } else {
+        info!("  ❌ Recovery result changed after interrupted replay");
+        false
+    }
+}
+
 // ==================== 组合测试: Elle + Jepsen ====================
 
 /// 组合测试: 并发 + 崩溃 + 异常检测
@@ -538,6 +691,9 @@ pub fn run_elle_jepsen_tests() -> (usize, usize) {
         ("Jepsen: Checkpoint Crash", jepsen_test_checkpoint_crash),
         ("Jepsen: Long-Running Stability", jepsen_test_long_running_stability),
         ("Jepsen: Crash-Recovery Loop", jepsen_test_crash_recovery_loop),
+        ("Jepsen: Torn WAL Write", jepsen_test_torn_wal_write),
+        ("Jepsen: Crash Before Flush", jepsen_test_crash_before_flush),
+        ("Jepsen: Crash During Replay", jepsen_test_crash_during_replay),
 
         // 组合测试
         ("Combined: Elle + Jepsen", elle_jepsen_combined_test),
@@ -552,6 +708,8 @@ pub fn run_elle_jepsen_tests() -> (usize, usize) {
         if test_fn() {
             passed += 1;
         }
+        // 失败的测试可能遗留已布置的故障点
+        failpoint::disarm_all();
     }
 
     info!("\n========================================");
@@ -0,0 +1,471 @@
+//! DBFS 事务管理: 超时、inode 锁与死锁检测
+//!
+//! - 每个事务有独立的超时时间, 超时后在下一次访问时被中止;
+//! - 写操作在 inode 上加排他锁, 锁冲突时事务进入等待,
+//!   等待关系构成 waits-for 图;
+//! - 每次进入等待都会沿 waits-for 图查环, 发现死锁时选择环上最年轻的事务
+//!   (事务 ID 最大, 已完成的工作最少) 作为牺牲者中止。
+//!
+//! 被中止的事务保留中止原因, 直到调用方 commit/rollback 时取走。
+//! 开始提交 ([`TxManager::start_commit`]) 之后事务不再被中止, 锁一直持有到提交完成。
+//! 中止原因对应不同的 errno, 调用方据此决定是否重试:
+//!
+//! | 原因            | 错误                      | errno     | 可重试 |
+//! |-----------------|---------------------------|-----------|--------|
+//! | 锁冲突 (不等待)  | [`DbfsError::Conflict`]   | EAGAIN    | 是     |
+//! | 死锁牺牲者       | [`DbfsError::Deadlock`]   | EDEADLK   | 是     |
+//! | 超时            | [`DbfsError::TimedOut`]   | ETIMEDOUT | 视情况 |
+//! | 用户中止         | [`DbfsError::Aborted`]    | ECANCELED | 否     |
+//! | 超出目录配额     | [`DbfsError::QuotaExceeded`] | EDQUOT | 否     |
+//!
+//! 本模块只维护状态, 不读时钟也不调度, 调用方传入当前时间 (毫秒)。
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+
+use crate::{
+    common::{DbfsError, DbfsResult},
+    wal::TxId,
+};
+
+/// 默认事务超时时间 (毫秒)
+pub const DEFAULT_TX_TIMEOUT_MS: u64 = 30_000;
+
+/// 事务中止原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortReason {
+    /// 锁冲突且调用方不愿等待
+    Conflict,
+    /// 被选为死锁牺牲者
+    Deadlock,
+    /// 超过事务超时时间
+    Timeout,
+    /// 用户主动中止
+    User,
+    /// 超出目录配额
+    Quota,
+}
+
+impl AbortReason {
+    pub fn error(self) -> DbfsError {
+        match self {
+            AbortReason::Conflict => DbfsError::Conflict,
+            AbortReason::Deadlock => DbfsError::Deadlock,
+            AbortReason::Timeout => DbfsError::TimedOut,
+            AbortReason::User => DbfsError::Aborted,
+            AbortReason::Quota => DbfsError::QuotaExceeded,
+        }
+    }
+
+    /// 返回给用户态的 errno
+    pub fn errno(self) -> i32 {
+        self.error() as i32
+    }
+}
+
+/// 加锁结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockStatus {
+    Granted,
+    /// 锁被其他事务持有, 已登记等待, 稍后重试
+    Waiting,
+}
+
+/// 加锁时遇到冲突的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockWait {
+    /// 进入等待 (可能触发死锁检测)
+    Block,
+    /// 立即以 [`AbortReason::Conflict`] 中止
+    NoWait,
+}
+
+#[derive(Debug)]
+struct TxEntry {
+    started_ms: u64,
+    /// None 表示不限时
+    timeout_ms: Option<u64>,
+    /// 持有的 inode 锁
+    held: BTreeSet<u64>,
+    /// 正在等待的 inode 锁
+    waiting_for: Option<u64>,
+    aborted: Option<AbortReason>,
+    /// 正在写入提交记录, 不能再被中止
+    committing: bool,
+    /// 开始事务的进程
+    owner: Option<usize>,
+}
+
+/// 登记中的事务 (见 [`TxManager::transactions`])
+#[derive(Debug, Clone, Copy)]
+pub struct TxInfo {
+    pub tx_id: TxId,
+    pub owner: Option<usize>,
+    pub started_ms: u64,
+    pub locks_held: usize,
+    pub waiting_for: Option<u64>,
+    pub aborted: Option<AbortReason>,
+}
+
+/// 各类中止的累计次数
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AbortStats {
+    pub conflict: u64,
+    pub deadlock: u64,
+    pub timeout: u64,
+    pub user: u64,
+    pub quota: u64,
+}
+
+pub struct TxManager {
+    txs: BTreeMap<u64, TxEntry>,
+    /// inode -> 持有排他锁的事务
+    locks: BTreeMap<u64, u64>,
+    default_timeout_ms: Option<u64>,
+    stats: AbortStats,
+}
+
+impl TxManager {
+    pub const fn new() -> Self {
+        Self {
+            txs: BTreeMap::new(),
+            locks: BTreeMap::new(),
+            default_timeout_ms: Some(DEFAULT_TX_TIMEOUT_MS),
+            stats: AbortStats {
+                conflict: 0,
+                deadlock: 0,
+                timeout: 0,
+                user: 0,
+                quota: 0,
+            },
+        }
+    }
+
+    pub fn default_timeout(&self) -> Option<u64> {
+        self.default_timeout_ms
+    }
+
+    /// 设置之后开始的事务的默认超时, None 表示不限时
+    pub fn set_default_timeout(&mut self, timeout_ms: Option<u64>) {
+        self.default_timeout_ms = timeout_ms;
+    }
+
+    /// 登记新事务, `timeout_ms` 为 None 时使用默认超时
+    pub fn begin(&mut self, tx_id: TxId, now_ms: u64, timeout_ms: Option<u64>) {
+        self.txs.insert(
+            tx_id.value(),
+            TxEntry {
+                started_ms: now_ms,
+                timeout_ms: timeout_ms.or(self.default_timeout_ms),
+                held: BTreeSet::new(),
+                waiting_for: None,
+                aborted: None,
+                committing: false,
+                owner: None,
+            },
+        );
+    }
+
+    /// 记录开始事务的进程
+    pub fn set_owner(&mut self, tx_id: TxId, pid: usize) {
+        if let Some(entry) = self.txs.get_mut(&tx_id.value()) {
+            entry.owner = Some(pid);
+        }
+    }
+
+    /// 所有登记中的事务 (包括已中止但尚未结束的), 按事务 ID 排序
+    pub fn transactions(&self) -> Vec<TxInfo> {
+        self.txs
+            .iter()
+            .map(|(tx, e)| TxInfo {
+                tx_id: TxId::new(*tx),
+                owner: e.owner,
+                started_ms: e.started_ms,
+                locks_held: e.held.len(),
+                waiting_for: e.waiting_for,
+                aborted: e.aborted,
+            })
+            .collect()
+    }
+
+    /// 修改进行中事务的超时 (从事务开始时计算)
+    pub fn set_timeout(&mut self, tx_id: TxId, timeout_ms: Option<u64>) -> DbfsResult<()> {
+        let entry = self
+            .txs
+            .get_mut(&tx_id.value())
+            .ok_or(DbfsError::NotFound)?;
+        entry.timeout_ms = timeout_ms;
+        Ok(())
+    }
+
+    /// 事务是否登记在本管理器中 (包括已中止但尚未 finish 的事务)
+    pub fn contains(&self, tx_id: TxId) -> bool {
+        self.txs.contains_key(&tx_id.value())
+    }
+
+    pub fn is_active(&self, tx_id: TxId) -> bool {
+        self.txs
+            .get(&tx_id.value())
+            .map_or(false, |e| e.aborted.is_none())
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.txs.values().filter(|e| e.aborted.is_none()).count()
+    }
+
+    pub fn stats(&self) -> AbortStats {
+        self.stats
+    }
+
+    /// 中止所有已超时的事务, 返回本次被中止的事务
+    pub fn expire(&mut self, now_ms: u64) -> Vec<TxId> {
+        let expired: Vec<u64> = self
+            .txs
+            .iter()
+            .filter(|(_, e)| {
+                e.aborted.is_none()
+                    && !e.committing
+                    && e.timeout_ms
+                        .map_or(false, |t| now_ms.saturating_sub(e.started_ms) >= t)
+            })
+            .map(|(tx, _)| *tx)
+            .collect();
+        for tx in &expired {
+            log::warn!("⚠ DBFS: Transaction TX-{} timed out", tx);
+            self.abort(TxId::new(*tx), AbortReason::Timeout);
+        }
+        expired.into_iter().map(TxId::new).collect()
+    }
+
+    /// 检查事务是否仍可继续: 已中止时返回中止原因对应的错误
+    pub fn check(&mut self, tx_id: TxId, now_ms: u64) -> DbfsResult<()> {
+        self.expire(now_ms);
+        match self.txs.get(&tx_id.value()) {
+            None => Err(DbfsError::NotFound),
+            Some(entry) => match entry.aborted {
+                Some(reason) => Err(reason.error()),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// 中止事务并释放它持有的锁
+    ///
+    /// 事务记录保留到 [`TxManager::finish`], 以便调用方取得中止原因。
+    pub fn abort(&mut self, tx_id: TxId, reason: AbortReason) {
+        let entry = match self.txs.get_mut(&tx_id.value()) {
+            Some(entry) if entry.aborted.is_none() && !entry.committing => entry,
+            _ => return,
+        };
+        entry.aborted = Some(reason);
+        entry.waiting_for = None;
+        let held = core::mem::take(&mut entry.held);
+        for ino in held {
+            self.locks.remove(&ino);
+        }
+        match reason {
+            AbortReason::Conflict => self.stats.conflict += 1,
+            AbortReason::Deadlock => self.stats.deadlock += 1,
+            AbortReason::Timeout => self.stats.timeout += 1,
+            AbortReason::User => self.stats.user += 1,
+            AbortReason::Quota => self.stats.quota += 1,
+        }
+    }
+
+    /// 开始提交事务: 已中止时返回中止原因
+    ///
+    /// 否则事务之后不会再因超时或用户请求被中止, 继续持有锁直到 [`TxManager::finish`],
+    /// 提交失败时调用方可以在锁的保护下撤销事务的修改。
+    pub fn start_commit(&mut self, tx_id: TxId) -> Option<AbortReason> {
+        let entry = self.txs.get_mut(&tx_id.value())?;
+        if entry.aborted.is_none() {
+            entry.committing = true;
+        }
+        entry.aborted
+    }
+
+    /// 结束事务 (commit 或 rollback): 释放锁并返回中止原因 (如果被中止过)
+    pub fn finish(&mut self, tx_id: TxId) -> Option<AbortReason> {
+        let entry = self.txs.remove(&tx_id.value())?;
+        for ino in entry.held {
+            self.locks.remove(&ino);
+        }
+        entry.aborted
+    }
+
+    /// 为事务在 inode 上加排他锁
+    pub fn lock(
+        &mut self,
+        tx_id: TxId,
+        ino: u64,
+        now_ms: u64,
+        wait: LockWait,
+    ) -> DbfsResult<LockStatus> {
+        self.check(tx_id, now_ms)?;
+        let tx = tx_id.value();
+
+        let holder = match self.locks.get(&ino) {
+            Some(&holder) if holder != tx => holder,
+            _ => {
+                self.locks.insert(ino, tx);
+                let entry = self.txs.get_mut(&tx).unwrap();
+                entry.held.insert(ino);
+                entry.waiting_for = None;
+                return Ok(LockStatus::Granted);
+            }
+        };
+
+        if wait == LockWait::NoWait {
+            log::warn!(
+                "⚠ DBFS: TX-{} conflicts with TX-{} on inode {}",
+                tx,
+                holder,
+                ino
+            );
+            self.abort(tx_id, AbortReason::Conflict);
+            return Err(DbfsError::Conflict);
+        }
+
+        self.txs.get_mut(&tx).unwrap().waiting_for = Some(ino);
+        if let Some(cycle) = self.find_cycle(tx) {
+            // 牺牲最年轻的事务
+            let victim = *cycle.iter().max().unwrap();
+            log::warn!(
+                "⚠ DBFS: Deadlock detected among {:?}, aborting TX-{}",
+                cycle,
+                victim
+            );
+            self.abort(TxId::new(victim), AbortReason::Deadlock);
+            if victim == tx {
+                return Err(DbfsError::Deadlock);
+            }
+        }
+        Ok(LockStatus::Waiting)
+    }
+
+    /// 从 `start` 出发沿 waits-for 边查环, 返回环上的事务
+    ///
+    /// 每个事务至多等待一把锁, 每把锁至多一个持有者, 所以每个节点只有一条出边。
+    fn find_cycle(&self, start: u64) -> Option<Vec<u64>> {
+        let mut path = Vec::new();
+        let mut current = start;
+        loop {
+            if let Some(pos) = path.iter().position(|&tx| tx == current) {
+                return Some(path.split_off(pos));
+            }
+            path.push(current);
+            let ino = self.txs.get(&current)?.waiting_for?;
+            current = *self.locks.get(&ino)?;
+        }
+    }
+
+    /// 事务正在等待的 inode 及其持有者
+    pub fn waits_for(&self, tx_id: TxId) -> Option<(u64, TxId)> {
+        let ino = self.txs.get(&tx_id.value())?.waiting_for?;
+        let holder = *self.locks.get(&ino)?;
+        Some((ino, TxId::new(holder)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadlock_aborts_youngest() {
+        let mut mgr = TxManager::new();
+        let (t1, t2) = (TxId::new(1), TxId::new(2));
+        mgr.begin(t1, 0, None);
+        mgr.begin(t2, 0, None);
+        assert_eq!(
+            mgr.lock(t1, 10, 0, LockWait::Block),
+            Ok(LockStatus::Granted)
+        );
+        assert_eq!(
+            mgr.lock(t2, 20, 0, LockWait::Block),
+            Ok(LockStatus::Granted)
+        );
+        assert_eq!(
+            mgr.lock(t1, 20, 0, LockWait::Block),
+            Ok(LockStatus::Waiting)
+        );
+        assert_eq!(mgr.waits_for(t1), Some((20, t2)));
+        // t2 -> t1 -> t2: t2 更年轻, 被中止
+        assert_eq!(
+            mgr.lock(t2, 10, 0, LockWait::Block),
+            Err(DbfsError::Deadlock)
+        );
+        assert_eq!(
+            mgr.lock(t1, 20, 0, LockWait::Block),
+            Ok(LockStatus::Granted)
+        );
+        assert_eq!(mgr.finish(t2), Some(AbortReason::Deadlock));
+        assert_eq!(mgr.finish(t1), None);
+        assert_eq!(mgr.stats().deadlock, 1);
+    }
+
+    #[test]
+    fn test_timeout_releases_locks() {
+        let mut mgr = TxManager::new();
+        let (t1, t2) = (TxId::new(1), TxId::new(2));
+        mgr.begin(t1, 0, Some(100));
+        mgr.begin(t2, 50, Some(1000));
+        assert_eq!(
+            mgr.lock(t1, 10, 0, LockWait::Block),
+            Ok(LockStatus::Granted)
+        );
+        assert_eq!(
+            mgr.lock(t2, 10, 60, LockWait::Block),
+            Ok(LockStatus::Waiting)
+        );
+        assert_eq!(
+            mgr.lock(t2, 10, 100, LockWait::Block),
+            Ok(LockStatus::Granted)
+        );
+        assert_eq!(mgr.check(t1, 100), Err(DbfsError::TimedOut));
+        assert_eq!(mgr.finish(t1), Some(AbortReason::Timeout));
+    }
+
+    #[test]
+    fn test_committing_keeps_locks() {
+        let mut mgr = TxManager::new();
+        let (t1, t2) = (TxId::new(1), TxId::new(2));
+        mgr.begin(t1, 0, Some(100));
+        mgr.begin(t2, 0, None);
+        mgr.lock(t1, 10, 0, LockWait::Block).unwrap();
+        assert_eq!(mgr.start_commit(t1), None);
+        // 提交期间超时和用户中止都不生效, 锁仍被持有
+        mgr.abort(t1, AbortReason::User);
+        assert_eq!(
+            mgr.lock(t2, 10, 200, LockWait::Block),
+            Ok(LockStatus::Waiting)
+        );
+        assert_eq!(mgr.finish(t1), None);
+        assert_eq!(
+            mgr.lock(t2, 10, 200, LockWait::Block),
+            Ok(LockStatus::Granted)
+        );
+
+        mgr.abort(t2, AbortReason::User);
+        assert_eq!(mgr.start_commit(t2), Some(AbortReason::User));
+    }
+
+    #[test]
+    fn test_conflict_and_user_abort() {
+        let mut mgr = TxManager::new();
+        let (t1, t2) = (TxId::new(1), TxId::new(2));
+        mgr.begin(t1, 0, None);
+        mgr.begin(t2, 0, None);
+        mgr.lock(t1, 10, 0, LockWait::Block).unwrap();
+        assert_eq!(
+            mgr.lock(t2, 10, 0, LockWait::NoWait),
+            Err(DbfsError::Conflict)
+        );
+        mgr.abort(t1, AbortReason::User);
+        assert_eq!(mgr.check(t1, 0), Err(DbfsError::Aborted));
+        assert_eq!(AbortReason::User.errno(), 125);
+        assert_eq!(mgr.active_count(), 0);
+    }
+}
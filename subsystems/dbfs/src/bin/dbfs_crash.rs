@@ -0,0 +1,134 @@
+//! DBFS 崩溃一致性测试 (host 端)
+//!
+//! 用法: `dbfs_crash [--max-subset N] [--verbose] [workload...]`
+//!
+//! 不指定 workload 时运行全部内置工作负载 (WAL 层和挂载整个文件系统的 `fs_*`),
+//! 有违反不变量的崩溃状态时以非零状态退出。
+
+use std::{env, process};
+
+use dbfs::{
+    alien_integration::crash::{explore_fs, record_fs_workload, FS_CRASH_WORKLOADS},
+    crash_harness::{
+        explore, record_workload, CrashReport, CRASH_WORKLOADS, DEFAULT_MAX_SUBSET_WRITES,
+    },
+};
+
+/// 每个工作负载最多打印的失败状态数
+const MAX_REPORTED_FAILURES: usize = 5;
+
+fn usage() -> ! {
+    eprintln!("usage: dbfs_crash [--max-subset N] [--verbose] [workload...]");
+    eprintln!("workloads:");
+    let names = CRASH_WORKLOADS.iter().map(|(name, _)| name);
+    for name in names.chain(FS_CRASH_WORKLOADS.iter().map(|(name, _, _)| name)) {
+        eprintln!("  {}", name);
+    }
+    process::exit(2);
+}
+
+fn print_report(name: &str, writes: usize, flushes: usize, report: &CrashReport, verbose: bool) {
+    let mark = if report.failures.is_empty() {
+        "✓"
+    } else {
+        "✗"
+    };
+    println!(
+        "{} {}: {} writes, {} flushes, {} crash states, {} failures",
+        mark,
+        name,
+        writes,
+        flushes,
+        report.states,
+        report.failures.len()
+    );
+    let shown = if verbose {
+        report.failures.len()
+    } else {
+        MAX_REPORTED_FAILURES
+    };
+    for (state, reason) in report.failures.iter().take(shown) {
+        println!("    {:?}: {}", state, reason);
+    }
+}
+
+fn main() {
+    let mut max_subset = DEFAULT_MAX_SUBSET_WRITES;
+    let mut verbose = false;
+    let mut selected = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-subset" => {
+                max_subset = match args.next().and_then(|n| n.parse().ok()) {
+                    Some(n) => n,
+                    None => usage(),
+                }
+            }
+            "--verbose" | "-v" => verbose = true,
+            "--help" | "-h" => usage(),
+            name => {
+                let known = CRASH_WORKLOADS.iter().any(|(w, _)| *w == name)
+                    || FS_CRASH_WORKLOADS.iter().any(|(w, _, _)| *w == name);
+                if !known {
+                    eprintln!("unknown workload: {}", name);
+                    usage();
+                }
+                selected.push(name.to_string());
+            }
+        }
+    }
+
+    let selected = |name: &str| selected.is_empty() || selected.iter().any(|s| s == name);
+    let mut total_states = 0;
+    let mut total_failures = 0;
+    for (name, workload) in CRASH_WORKLOADS {
+        if !selected(name) {
+            continue;
+        }
+        let (writes, flushes, report) = match record_workload(*workload) {
+            Ok(recording) => (
+                recording.write_count(),
+                recording.flush_count(),
+                explore(&recording, max_subset),
+            ),
+            Err(e) => {
+                println!("✗ {}: workload failed: {:?}", name, e);
+                total_failures += 1;
+                continue;
+            }
+        };
+        print_report(name, writes, flushes, &report, verbose);
+        total_states += report.states;
+        total_failures += report.failures.len();
+    }
+    for (name, workload, options) in FS_CRASH_WORKLOADS {
+        if !selected(name) {
+            continue;
+        }
+        let (writes, flushes, report) = match record_fs_workload(*workload, options) {
+            Ok(recording) => (
+                recording.write_count(),
+                recording.flush_count(),
+                explore_fs(&recording, max_subset),
+            ),
+            Err(e) => {
+                println!("✗ {}: workload failed: {:?}", name, e);
+                total_failures += 1;
+                continue;
+            }
+        };
+        print_report(name, writes, flushes, &report, verbose);
+        total_states += report.states;
+        total_failures += report.failures.len();
+    }
+
+    println!(
+        "DBFS crash harness: {} crash states checked, {} failures",
+        total_states, total_failures
+    );
+    if total_failures > 0 {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,494 @@
+//! Change data capture (CDC) for DBFS
+//!
+//! 从已提交的 WAL 记录导出变更流: 每个提交的事务一条记录, 包含提交 LSN 和
+//! 事务创建 / 写入 (带范围) / 删除的路径。
+//!
+//! ## 记录格式
+//!
+//! 每条记录一行, 字段以空格分隔:
+//!
+//! ```text
+//! <commit_lsn> <tx_id> <change> <change> ...
+//!
+//! create:<path>              FileCreate
+//! mkdir:<path>               Mkdir
+//! write:<path>:<offset>+<len> FileWrite
+//! delete:<path>              FileDelete
+//! clone:<dst>:<offset>+<len>:<src>:<src_offset>  FileClone
+//! rename:<from>:<to>         Rename
+//! flags:<path>:<flags>       SetFlags (FS_IOC_SETFLAGS, 十进制)
+//! ```
+//!
+//! 路径中的空格、`:`、`%` 和不可打印字符按 `%XX` 转义。
+//!
+//! `set_attr` 的属性修改目前不写 WAL, 所以不会出现在变更流中。
+//!
+//! ## 续读
+//!
+//! [`ChangeLog`] 只保留最近的记录。流中每条记录有固定的字节位置,
+//! [`ChangeLog::offset_after`] 把 LSN 换成位置, 读者从上次处理的 LSN 之后继续;
+//! 早于保留窗口的位置读取失败 ([`DbfsError::RangeError`])。
+//!
+//! checkpoint 会截断 WAL, 所以保留的记录和流的结束位置随镜像一起写入
+//! ([`WalRecordType::ChangeState`]); 重新挂载时 [`ChangeLog::recover`] 从中恢复,
+//! 再追加镜像之后提交的事务, 位置和 LSN 在卸载前后保持连续。
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use crate::{
+    common::DbfsError,
+    wal::{Lsn, TxId, WalOp, WalRecord, WalRecordType},
+};
+
+/// `ioctl` on the change stream: stream offset of the first record whose
+/// commit LSN is greater than the argument
+pub const CDC_IOC_OFFSET_AFTER: u32 = 0x4443_0001;
+
+/// Records kept per mount by default
+pub const DEFAULT_RETENTION: usize = 1024;
+
+/// One change made by a committed transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Create(String),
+    Mkdir(String),
+    Write { path: String, offset: u64, len: u32 },
+    Delete(String),
+    Clone {
+        src: String,
+        src_offset: u64,
+        len: u64,
+        dst: String,
+        dst_offset: u64,
+    },
+    Rename { from: String, to: String },
+    SetFlags { path: String, flags: u32 },
+}
+
+impl Change {
+    /// Decode an operation record, None for control records and malformed data
+    pub fn decode(record: &WalRecord) -> Option<Self> {
+        Some(match record.operation()? {
+            WalOp::Create(path) => Change::Create(String::from(path)),
+            WalOp::Mkdir(path) => Change::Mkdir(String::from(path)),
+            WalOp::Delete(path) => Change::Delete(String::from(path)),
+            WalOp::Write { path, offset, data } => Change::Write {
+                path: String::from(path),
+                offset,
+                len: data.len() as u32,
+            },
+            WalOp::Clone {
+                src,
+                src_offset,
+                len,
+                dst,
+                dst_offset,
+            } => Change::Clone {
+                src: String::from(src),
+                src_offset,
+                len,
+                dst: String::from(dst),
+                dst_offset,
+            },
+            WalOp::Rename { from, to } => Change::Rename {
+                from: String::from(from),
+                to: String::from(to),
+            },
+            WalOp::SetFlags { path, flags } => Change::SetFlags {
+                path: String::from(path),
+                flags,
+            },
+        })
+    }
+
+    fn write_to(&self, out: &mut String) {
+        match self {
+            Change::Create(path) => {
+                out.push_str("create:");
+                escape(path, out);
+            }
+            Change::Mkdir(path) => {
+                out.push_str("mkdir:");
+                escape(path, out);
+            }
+            Change::Write { path, offset, len } => {
+                out.push_str("write:");
+                escape(path, out);
+                let _ = write!(out, ":{}+{}", offset, len);
+            }
+            Change::Delete(path) => {
+                out.push_str("delete:");
+                escape(path, out);
+            }
+            Change::Clone {
+                src,
+                src_offset,
+                len,
+                dst,
+                dst_offset,
+            } => {
+                out.push_str("clone:");
+                escape(dst, out);
+                let _ = write!(out, ":{}+{}:", dst_offset, len);
+                escape(src, out);
+                let _ = write!(out, ":{}", src_offset);
+            }
+            Change::Rename { from, to } => {
+                out.push_str("rename:");
+                escape(from, out);
+                out.push(':');
+                escape(to, out);
+            }
+            Change::SetFlags { path, flags } => {
+                out.push_str("flags:");
+                escape(path, out);
+                let _ = write!(out, ":{}", flags);
+            }
+        }
+    }
+}
+
+fn escape(path: &str, out: &mut String) {
+    for b in path.bytes() {
+        if b.is_ascii_graphic() && b != b':' && b != b'%' {
+            out.push(b as char);
+        } else {
+            let _ = write!(out, "%{:02X}", b);
+        }
+    }
+}
+
+/// Changes of one committed transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeRecord {
+    pub commit_lsn: Lsn,
+    pub tx_id: TxId,
+    pub changes: Vec<Change>,
+}
+
+impl ChangeRecord {
+    /// Build the record of `tx_id` from its WAL records (in LSN order)
+    ///
+    /// Operations discarded by a savepoint rollback are left out.
+    pub fn from_records<'a>(
+        tx_id: TxId,
+        commit_lsn: Lsn,
+        records: impl IntoIterator<Item = &'a WalRecord>,
+    ) -> Self {
+        let mut ops: Vec<(Lsn, Change)> = Vec::new();
+        for record in records {
+            if record.record_type == WalRecordType::SavepointRollback {
+                if let Some(savepoint) = record.data.get(0..8) {
+                    let savepoint = u64::from_be_bytes(savepoint.try_into().unwrap());
+                    ops.retain(|(lsn, _)| *lsn <= savepoint);
+                }
+            } else if let Some(change) = Change::decode(record) {
+                ops.push((record.lsn, change));
+            }
+        }
+        Self {
+            commit_lsn,
+            tx_id,
+            changes: ops.into_iter().map(|(_, change)| change).collect(),
+        }
+    }
+
+    /// Text form, one line terminated by `\n`
+    pub fn to_line(&self) -> String {
+        let mut out = String::new();
+        let _ = write!(out, "{} {}", self.commit_lsn, self.tx_id.value());
+        for change in &self.changes {
+            out.push(' ');
+            change.write_to(&mut out);
+        }
+        out.push('\n');
+        out
+    }
+}
+
+/// Change records of every transaction committed in `records`, in commit order
+///
+/// Checkpoint images (transactions carrying a [`WalRecordType::ChangeState`]
+/// record) are not changes and are left out.
+pub fn committed_changes(records: &[WalRecord]) -> Vec<ChangeRecord> {
+    let mut out = Vec::new();
+    for_each_commit(records, |tx_id, commit_lsn, tx_records| {
+        if !tx_records.iter().any(|r| r.record_type == WalRecordType::ChangeState) {
+            out.push(ChangeRecord::from_records(tx_id, commit_lsn, tx_records));
+        }
+    });
+    out
+}
+
+/// Call `f` with the records of each committed transaction, in commit order
+fn for_each_commit<'a>(
+    records: &'a [WalRecord],
+    mut f: impl FnMut(TxId, Lsn, Vec<&'a WalRecord>),
+) {
+    let mut by_tx: BTreeMap<u64, Vec<&WalRecord>> = BTreeMap::new();
+    for record in records {
+        match record.record_type {
+            WalRecordType::Checkpoint => {}
+            WalRecordType::TxCommit => {
+                let tx_records = by_tx.remove(&record.tx_id.value()).unwrap_or_default();
+                f(record.tx_id, record.lsn, tx_records);
+            }
+            WalRecordType::TxRollback => {
+                by_tx.remove(&record.tx_id.value());
+            }
+            _ => by_tx.entry(record.tx_id.value()).or_default().push(record),
+        }
+    }
+}
+
+/// Bounded change stream of one mount
+///
+/// 记录以文本行的形式保存; 位置从 0 开始单调递增, 丢弃旧记录后也不变。
+pub struct ChangeLog {
+    /// (stream offset, commit LSN, line)
+    records: VecDeque<(u64, Lsn, String)>,
+    /// Stream offset just past the last record
+    end: u64,
+    retention: usize,
+}
+
+impl ChangeLog {
+    pub fn new(retention: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            end: 0,
+            retention: retention.max(1),
+        }
+    }
+
+    /// Append a committed transaction, dropping the oldest record when full
+    pub fn push(&mut self, record: &ChangeRecord) {
+        let line = record.to_line();
+        let len = line.len() as u64;
+        self.records.push_back((self.end, record.commit_lsn, line));
+        self.end += len;
+        while self.records.len() > self.retention {
+            self.records.pop_front();
+        }
+    }
+
+    /// Stream offset of the oldest retained record
+    pub fn start(&self) -> u64 {
+        self.records.front().map_or(self.end, |(offset, _, _)| *offset)
+    }
+
+    /// Stream offset just past the newest record
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Commit LSN of the newest record
+    pub fn last_lsn(&self) -> Option<Lsn> {
+        self.records.back().map(|(_, lsn, _)| *lsn)
+    }
+
+    /// Stream offset of the first retained record committed after `lsn`
+    ///
+    /// [`ChangeLog::end`] if there is none yet.
+    pub fn offset_after(&self, lsn: Lsn) -> u64 {
+        self.records
+            .iter()
+            .find(|(_, commit_lsn, _)| *commit_lsn > lsn)
+            .map_or(self.end, |(offset, _, _)| *offset)
+    }
+
+    /// Rebuild the stream at mount time from the records the WAL still holds
+    ///
+    /// The stream saved with the newest checkpoint image is restored first,
+    /// then the transactions committed after the image are appended.
+    pub fn recover(&mut self, records: &[WalRecord]) -> Result<(), DbfsError> {
+        let mut result = Ok(());
+        for_each_commit(records, |tx_id, commit_lsn, tx_records| {
+            let state = tx_records
+                .iter()
+                .find(|r| r.record_type == WalRecordType::ChangeState);
+            match state {
+                Some(state) => {
+                    if let Err(e) = self.restore(&state.data) {
+                        result = Err(e);
+                    }
+                }
+                None => self.push(&ChangeRecord::from_records(tx_id, commit_lsn, tx_records)),
+            }
+        });
+        result
+    }
+
+    /// Serialized stream for a checkpoint image
+    ///
+    /// end (8) + record count (4) + per record: offset (8) + commit LSN (8)
+    /// + line length (4) + line
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&self.end.to_be_bytes());
+        data.extend_from_slice(&(self.records.len() as u32).to_be_bytes());
+        for (offset, lsn, line) in &self.records {
+            data.extend_from_slice(&offset.to_be_bytes());
+            data.extend_from_slice(&lsn.to_be_bytes());
+            data.extend_from_slice(&(line.len() as u32).to_be_bytes());
+            data.extend_from_slice(line.as_bytes());
+        }
+        data
+    }
+
+    /// Replace the stream with one saved by [`ChangeLog::encode`]
+    fn restore(&mut self, data: &[u8]) -> Result<(), DbfsError> {
+        let u64_at = |pos: usize| -> Result<u64, DbfsError> {
+            let bytes = data.get(pos..pos + 8).ok_or(DbfsError::Io)?;
+            Ok(u64::from_be_bytes(bytes.try_into().unwrap()))
+        };
+        let u32_at = |pos: usize| -> Result<usize, DbfsError> {
+            let bytes = data.get(pos..pos + 4).ok_or(DbfsError::Io)?;
+            Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let end = u64_at(0)?;
+        let count = u32_at(8)?;
+        let mut records = VecDeque::new();
+        let mut pos = 12;
+        for _ in 0..count {
+            let (offset, lsn, len) = (u64_at(pos)?, u64_at(pos + 8)?, u32_at(pos + 16)?);
+            let line = data.get(pos + 20..pos + 20 + len).ok_or(DbfsError::Io)?;
+            let line = core::str::from_utf8(line).map_err(|_| DbfsError::Io)?;
+            records.push_back((offset, lsn, String::from(line)));
+            pos += 20 + len;
+        }
+        while records.len() > self.retention {
+            records.pop_front();
+        }
+        self.records = records;
+        self.end = end;
+        Ok(())
+    }
+
+    /// Read the stream at `offset`
+    ///
+    /// Offsets before the retention window fail with
+    /// [`DbfsError::RangeError`]; reading at or past the end returns 0.
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, DbfsError> {
+        if offset < self.start() {
+            return Err(DbfsError::RangeError);
+        }
+        // 第一条包含 offset 的记录
+        let first = self
+            .records
+            .partition_point(|(start, _, line)| start + line.len() as u64 <= offset);
+        let mut copied = 0;
+        for (start, _, line) in self.records.iter().skip(first) {
+            if copied == buf.len() {
+                break;
+            }
+            let skip = (offset + copied as u64).saturating_sub(*start) as usize;
+            let bytes = &line.as_bytes()[skip..];
+            let n = bytes.len().min(buf.len() - copied);
+            buf[copied..copied + n].copy_from_slice(&bytes[..n]);
+            copied += n;
+        }
+        Ok(copied)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::Wal;
+    use alloc::{string::ToString, sync::Arc};
+
+    #[test]
+    fn test_committed_changes() {
+        let mut wal = Wal::new("/test/wal".to_string()).unwrap();
+        let tx1 = wal.begin_tx();
+        wal.create_file(tx1, "/a b");
+        wal.write_file(tx1, "/a b", 4, b"hello");
+        wal.clone_file(tx1, "/a b", 0, 9, "/e", 16384);
+        let sp = wal.savepoint();
+        wal.delete_file(tx1, "/c");
+        wal.rollback_to_savepoint(tx1, sp);
+        wal.commit_tx(tx1).unwrap();
+        let tx2 = wal.begin_tx();
+        wal.mkdir(tx2, "/d");
+        wal.rollback_tx(tx2);
+
+        let changes = committed_changes(wal.records());
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].tx_id, tx1);
+        assert_eq!(
+            changes[0].to_line(),
+            alloc::format!("{} 1 create:/a%20b write:/a%20b:4+5 clone:/e:16384+9:/a%20b:0\n", changes[0].commit_lsn)
+        );
+    }
+
+    #[test]
+    fn test_change_log_resume() {
+        let mut log = ChangeLog::new(2);
+        for lsn in [3, 7, 9] {
+            log.push(&ChangeRecord {
+                commit_lsn: lsn,
+                tx_id: TxId::new(lsn),
+                changes: alloc::vec![Change::Delete("/x".to_string())],
+            });
+        }
+        // "3 3 delete:/x\n" 已被丢弃
+        assert_eq!(log.start(), 14);
+        let mut buf = [0u8; 64];
+        assert_eq!(log.read_at(0, &mut buf), Err(DbfsError::RangeError));
+
+        let offset = log.offset_after(7);
+        let n = log.read_at(offset, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"9 9 delete:/x\n");
+        let n = log.read_at(log.start() + 2, &mut buf).unwrap();
+        assert_eq!(&buf[..n], b"7 delete:/x\n9 9 delete:/x\n");
+        assert_eq!(log.read_at(log.end(), &mut buf), Ok(0));
+        assert_eq!(log.offset_after(9), log.end());
+    }
+
+    #[test]
+    fn test_change_log_checkpoint() {
+        use crate::log_manager::MemBlockDevice;
+
+        let device = Arc::new(MemBlockDevice::new(64 * 1024));
+        let mut wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+        let mut log = ChangeLog::new(DEFAULT_RETENTION);
+        let tx1 = wal.begin_tx();
+        wal.create_file(tx1, "/a");
+        wal.rename(tx1, "/a", "/b");
+        wal.commit_tx(tx1).unwrap();
+        log.recover(wal.records()).unwrap();
+        let first_line = log.end();
+
+        // 镜像本身不是变更, 镜像之前的流随镜像保存
+        wal.checkpoint(|wal, tx| {
+            wal.create_file(tx, "/b");
+            wal.log_operation(tx, WalRecordType::ChangeState, log.encode());
+            Ok(())
+        })
+        .unwrap();
+        let tx2 = wal.begin_tx();
+        wal.delete_file(tx2, "/b");
+        wal.commit_tx(tx2).unwrap();
+        drop(wal);
+
+        // 重新挂载: WAL 中只剩镜像和之后的事务
+        let wal = Wal::with_device("/test/wal".to_string(), device).unwrap();
+        let mut recovered = ChangeLog::new(DEFAULT_RETENTION);
+        recovered.recover(wal.records()).unwrap();
+        assert_eq!(recovered.start(), 0);
+        let mut buf = [0u8; 128];
+        let n = recovered.read_at(0, &mut buf).unwrap();
+        let text = core::str::from_utf8(&buf[..n]).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" 1 create:/a rename:/a:/b"));
+        assert!(lines[1].ends_with(" delete:/b"));
+        assert_eq!(recovered.offset_after(recovered.last_lsn().unwrap() - 1), first_line);
+    }
+}
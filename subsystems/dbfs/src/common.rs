@@ -45,7 +45,7 @@ impl Debug for DbfsDirEntry {
     }
 }
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DbfsError {
     #[error("DbfsError::PermissionDenied")]
     PermissionDenied = 1,
@@ -73,6 +73,18 @@ pub enum DbfsError {
     NotSupported = 95,
     #[error("DbfsError::NoData")]
     NoData = 61,
+    #[error("DbfsError::Conflict")]
+    Conflict = 11,
+    #[error("DbfsError::Deadlock")]
+    Deadlock = 35,
+    #[error("DbfsError::TimedOut")]
+    TimedOut = 110,
+    #[error("DbfsError::Aborted")]
+    Aborted = 125,
+    #[error("DbfsError::Busy")]
+    Busy = 16,
+    #[error("DbfsError::QuotaExceeded")]
+    QuotaExceeded = 122,
     #[error("DbfsError::Other")]
     Other = 999,
 }
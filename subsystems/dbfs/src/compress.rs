@@ -0,0 +1,627 @@
+//! Transparent file compression for DBFS
+//!
+//! 文件数据按固定大小的 extent ([`EXTENT_SIZE`]) 存放, 开启压缩的文件每个
+//! extent 单独用 LZ4 块格式压缩; 压缩后不变小的 extent 按原样保存。
+//!
+//! - 随机读只解压被读到的 extent
+//! - 部分覆盖写对涉及的 extent 做 读-改-写, 其余 extent 不动
+//! - 逻辑大小 ([`FileData::len`]) 与实际占用 ([`FileData::stored_len`]) 分开统计
+//!
+//! 编码只影响存储方式, 不改变文件内容; 压缩文件的写入在 WAL 中同样压缩存放
+//! (见 [`crate::wal::WalRecordType::FileWriteLz`])。
+//!
+//! 加密挂载的 extent 在压缩之后再用挂载的 [`Cipher`] 加密, 每次写入 extent
+//! 都分配新的 extent ID 作为 nonce; 认证失败的 extent 读取返回 [`DbfsError::Io`]。
+//!
+//! 去重挂载的 extent 由多个按内容切分的块组成 (见 [`crate::dedup`]), 压缩和加密
+//! 按块进行; 其他挂载每个 extent 只有一个块。
+//!
+//! ## Reflink
+//!
+//! extent 通过 `Arc` 共享: [`FileData::clone_range`] 对齐到 extent 边界的部分
+//! 直接共享源文件的 extent, 不复制数据; 之后写入共享的 extent 时才复制一份
+//! (写时复制), 另一个文件不受影响。
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use crate::{
+    common::{DbfsError, DbfsResult},
+    crypt::{Cipher, Domain},
+    dedup::DedupIndex,
+};
+
+/// Logical bytes per extent
+pub const EXTENT_SIZE: usize = 16 * 1024;
+
+/// Shortest match the format can encode
+const MIN_MATCH: usize = 4;
+/// The last match must start at least this many bytes before the end
+const MF_LIMIT: usize = 12;
+/// The last bytes of a block are always literals
+const LAST_LITERALS: usize = 5;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn emit(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let lit = literals.len();
+    let match_code = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((lit.min(15) as u8) << 4) | match_code.min(15) as u8);
+    if lit >= 15 {
+        write_len(out, lit - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_code >= 15 {
+            write_len(out, match_code - 15);
+        }
+    }
+}
+
+/// Compress `input` as one LZ4 block
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let len = input.len();
+    let mut out = Vec::with_capacity(len / 2 + 16);
+    // position + 1 of the last occurrence of each hashed 4-byte sequence
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut pos = 0;
+    while len > MF_LIMIT && pos < len - MF_LIMIT {
+        let seq = read_u32(input, pos);
+        let slot = hash(seq);
+        let candidate = table[slot];
+        table[slot] = pos + 1;
+        if candidate > 0 {
+            let start = candidate - 1;
+            if pos - start <= MAX_OFFSET && read_u32(input, start) == seq {
+                let max = len - LAST_LITERALS - pos;
+                let mut matched = MIN_MATCH;
+                while matched < max && input[start + matched] == input[pos + matched] {
+                    matched += 1;
+                }
+                emit(&mut out, &input[anchor..pos], Some((pos - start, matched)));
+                pos += matched;
+                anchor = pos;
+                continue;
+            }
+        }
+        pos += 1;
+    }
+    emit(&mut out, &input[anchor..], None);
+    out
+}
+
+fn read_len(input: &[u8], pos: &mut usize) -> DbfsResult<usize> {
+    let mut len = 0;
+    loop {
+        let byte = *input.get(*pos).ok_or(DbfsError::Io)?;
+        *pos += 1;
+        len += byte as usize;
+        if byte != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+/// Decompress one LZ4 block that expands to exactly `expected` bytes
+///
+/// Corrupt input fails with [`DbfsError::Io`].
+pub fn decompress(input: &[u8], expected: usize) -> DbfsResult<Vec<u8>> {
+    let mut out = Vec::with_capacity(expected);
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos).ok_or(DbfsError::Io)?;
+        pos += 1;
+        let mut lit = (token >> 4) as usize;
+        if lit == 15 {
+            lit += read_len(input, &mut pos)?;
+        }
+        let literals = input.get(pos..pos + lit).ok_or(DbfsError::Io)?;
+        out.extend_from_slice(literals);
+        pos += lit;
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = input.get(pos..pos + 2).ok_or(DbfsError::Io)?;
+        let offset = u16::from_le_bytes([offset[0], offset[1]]) as usize;
+        pos += 2;
+        let mut matched = (token & 0xf) as usize;
+        if matched == 15 {
+            matched += read_len(input, &mut pos)?;
+        }
+        matched += MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + matched > expected {
+            return Err(DbfsError::Io);
+        }
+        // 匹配可以和输出重叠, 逐字节复制
+        let start = out.len() - offset;
+        for i in 0..matched {
+            let byte = out[start + i];
+            out.push(byte);
+        }
+    }
+    if out.len() != expected {
+        return Err(DbfsError::Io);
+    }
+    Ok(out)
+}
+
+/// A run of stored bytes: raw or LZ4, sealed if the file is encrypted
+///
+/// Every extent is one chunk, except on dedup mounts where extents are cut
+/// into content-defined chunks shared through the [`DedupIndex`].
+#[derive(Debug, Clone)]
+pub(crate) struct Chunk {
+    data: Vec<u8>,
+    /// Logical length
+    len: usize,
+    lz: bool,
+    /// Extent ID (nonce) of a sealed chunk
+    sealed: Option<u64>,
+}
+
+impl Chunk {
+    pub(crate) fn store(raw: Vec<u8>, compressed: bool, cipher: Option<&Cipher>) -> Self {
+        let len = raw.len();
+        let (payload, lz) = match compressed.then(|| compress(&raw)) {
+            Some(packed) if packed.len() < len => (packed, true),
+            _ => (raw, false),
+        };
+        match cipher {
+            Some(cipher) => {
+                let id = cipher.next_extent_id();
+                Self {
+                    data: cipher.seal(Domain::Extent, 0, id, &Self::aad(len, lz), &payload),
+                    len,
+                    lz,
+                    sealed: Some(id),
+                }
+            }
+            None => Self {
+                data: payload,
+                len,
+                lz,
+                sealed: None,
+            },
+        }
+    }
+
+    /// Associated data of a sealed chunk
+    fn aad(len: usize, lz: bool) -> [u8; 9] {
+        let mut aad = [0u8; 9];
+        aad[0..8].copy_from_slice(&(len as u64).to_be_bytes());
+        aad[8] = lz as u8;
+        aad
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    pub(crate) fn stored_len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Logical bytes, if they are stored as they are
+    fn plain(&self) -> Option<&[u8]> {
+        (!self.lz && self.sealed.is_none()).then_some(&self.data[..])
+    }
+
+    pub(crate) fn load(&self, cipher: Option<&Cipher>) -> DbfsResult<Vec<u8>> {
+        let opened;
+        let payload = match self.sealed {
+            Some(id) => {
+                let cipher = cipher.ok_or(DbfsError::Io)?;
+                let aad = Self::aad(self.len, self.lz);
+                opened = cipher.open(Domain::Extent, 0, id, &aad, &self.data)?;
+                &opened
+            }
+            None => &self.data,
+        };
+        if self.lz {
+            decompress(payload, self.len)
+        } else {
+            Ok(payload.clone())
+        }
+    }
+}
+
+/// One extent of file data
+#[derive(Debug, Clone)]
+struct Extent {
+    chunks: Vec<Arc<Chunk>>,
+    /// Logical length
+    len: usize,
+}
+
+impl Extent {
+    fn stored_len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.stored_len()).sum()
+    }
+
+    /// Logical bytes, if they are stored as they are in a single chunk
+    fn plain(&self) -> Option<&[u8]> {
+        match self.chunks.as_slice() {
+            [chunk] => chunk.plain(),
+            _ => None,
+        }
+    }
+
+    fn load(&self, cipher: Option<&Cipher>) -> DbfsResult<Vec<u8>> {
+        match self.chunks.as_slice() {
+            [chunk] => chunk.load(cipher),
+            chunks => {
+                let mut data = Vec::with_capacity(self.len);
+                for chunk in chunks {
+                    data.extend_from_slice(&chunk.load(cipher)?);
+                }
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Contents of a regular file
+///
+/// 除最后一个以外, 每个 extent 都是 [`EXTENT_SIZE`] 字节。
+#[derive(Debug, Clone, Default)]
+pub struct FileData {
+    /// Extents, shared with other files after a clone
+    extents: Vec<Arc<Extent>>,
+    len: usize,
+    compressed: bool,
+    /// Key of an encrypted mount
+    cipher: Option<Arc<Cipher>>,
+    /// Chunk index of a dedup mount
+    dedup: Option<Arc<DedupIndex>>,
+}
+
+impl FileData {
+    pub fn new(
+        compressed: bool,
+        cipher: Option<Arc<Cipher>>,
+        dedup: Option<Arc<DedupIndex>>,
+    ) -> Self {
+        Self {
+            compressed,
+            cipher,
+            dedup,
+            ..Self::default()
+        }
+    }
+
+    fn store(&self, raw: Vec<u8>) -> Arc<Extent> {
+        let len = raw.len();
+        let cipher = self.cipher.as_deref();
+        let chunks = match &self.dedup {
+            Some(index) => index.store(&raw, self.compressed, cipher),
+            None => vec![Arc::new(Chunk::store(raw, self.compressed, cipher))],
+        };
+        Arc::new(Extent { chunks, len })
+    }
+
+    /// Logical size
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes actually held by the extents (shared extents included)
+    pub fn stored_len(&self) -> usize {
+        self.extents.iter().map(|extent| extent.stored_len()).sum()
+    }
+
+    /// Extents that are also used by another file
+    pub fn shared_extents(&self) -> usize {
+        self.extents
+            .iter()
+            .filter(|extent| Arc::strong_count(extent) > 1)
+            .count()
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Turn compression on or off, re-encoding every extent
+    pub fn set_compressed(&mut self, compressed: bool) -> DbfsResult<()> {
+        if self.compressed == compressed {
+            return Ok(());
+        }
+        self.compressed = compressed;
+        for i in 0..self.extents.len() {
+            let raw = self.extents[i].load(self.cipher.as_deref())?;
+            self.extents[i] = self.store(raw);
+        }
+        Ok(())
+    }
+
+    /// Read-modify-write of extent `index`
+    ///
+    /// A shared extent is copied first, the other files keep the old one.
+    fn update<F: FnOnce(&mut Vec<u8>)>(&mut self, index: usize, f: F) -> DbfsResult<()> {
+        let in_place = !self.compressed && self.cipher.is_none() && self.dedup.is_none();
+        if in_place && self.extents[index].plain().is_some() {
+            let extent = Arc::make_mut(&mut self.extents[index]);
+            let chunk = Arc::make_mut(&mut extent.chunks[0]);
+            f(&mut chunk.data);
+            chunk.len = chunk.data.len();
+            extent.len = chunk.len;
+        } else {
+            let mut data = self.extents[index].load(self.cipher.as_deref())?;
+            f(&mut data);
+            self.extents[index] = self.store(data);
+        }
+        Ok(())
+    }
+
+    /// Copy bytes starting at `offset` into `buf`, returns the bytes read
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> DbfsResult<usize> {
+        if offset >= self.len {
+            return Ok(0);
+        }
+        let total = buf.len().min(self.len - offset);
+        let mut done = 0;
+        while done < total {
+            let pos = offset + done;
+            let index = pos / EXTENT_SIZE;
+            let start = pos % EXTENT_SIZE;
+            let extent = &self.extents[index];
+            let n = (total - done).min(extent.len - start);
+            match extent.plain() {
+                Some(data) => buf[done..done + n].copy_from_slice(&data[start..start + n]),
+                None => {
+                    let data = extent.load(self.cipher.as_deref())?;
+                    buf[done..done + n].copy_from_slice(&data[start..start + n]);
+                }
+            }
+            done += n;
+        }
+        Ok(total)
+    }
+
+    /// Bytes in `offset..offset + len`, clipped to the file size
+    pub fn read_range(&self, offset: usize, len: usize) -> DbfsResult<Vec<u8>> {
+        let mut buf = vec![0; len.min(self.len.saturating_sub(offset))];
+        self.read_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Write `buf` at `offset`, growing the file (zero filled) if needed
+    pub fn write_at(&mut self, offset: usize, buf: &[u8]) -> DbfsResult<()> {
+        if offset + buf.len() > self.len {
+            self.resize(offset + buf.len())?;
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let index = pos / EXTENT_SIZE;
+            let start = pos % EXTENT_SIZE;
+            let n = (buf.len() - done).min(EXTENT_SIZE - start);
+            let src = &buf[done..done + n];
+            self.update(index, |data| data[start..start + n].copy_from_slice(src))?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Clone `len` bytes of `src` at `src_offset` to `offset`, growing the
+    /// file if needed; returns the bytes cloned (clipped to the end of `src`)
+    ///
+    /// Whole source extents that land on an extent boundary are shared
+    /// instead of copied; a short last extent is shared only if it also ends
+    /// this file. Everything else is copied. Extents of another key are never
+    /// shared.
+    ///
+    /// To clone within one file, pass a copy of it as `src` (copies share
+    /// all extents, so this is cheap).
+    pub fn clone_range(
+        &mut self,
+        src: &FileData,
+        src_offset: usize,
+        len: usize,
+        offset: usize,
+    ) -> DbfsResult<usize> {
+        let len = len.min(src.len.saturating_sub(src_offset));
+        if offset + len > self.len {
+            self.resize(offset + len)?;
+        }
+        let same_key = match (&self.cipher, &src.cipher) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        };
+        let mut done = 0;
+        while done < len {
+            let from = src_offset + done;
+            let to = offset + done;
+            if same_key && from % EXTENT_SIZE == 0 && to % EXTENT_SIZE == 0 {
+                let extent = &src.extents[from / EXTENT_SIZE];
+                let whole = extent.len == EXTENT_SIZE || to + extent.len == self.len;
+                if whole && extent.len <= len - done {
+                    self.extents[to / EXTENT_SIZE] = extent.clone();
+                    done += extent.len;
+                    continue;
+                }
+            }
+            let n = (len - done)
+                .min(EXTENT_SIZE - from % EXTENT_SIZE)
+                .min(EXTENT_SIZE - to % EXTENT_SIZE);
+            let data = src.read_range(from, n)?;
+            self.write_at(to, &data)?;
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Truncate or zero-extend to `len`
+    pub fn resize(&mut self, len: usize) -> DbfsResult<()> {
+        // 最后一个 extent 长度不变时不重写 (压缩 / 加密挂载上重写会重新编码)
+        if len < self.len {
+            self.extents.truncate(len.div_ceil(EXTENT_SIZE));
+            let tail = len - (self.extents.len().saturating_sub(1)) * EXTENT_SIZE;
+            if let Some(last) = self.extents.len().checked_sub(1) {
+                if self.extents[last].len != tail {
+                    self.update(last, |data| data.truncate(tail))?;
+                }
+            }
+        } else if len > self.len {
+            if let Some(last) = self.extents.len().checked_sub(1) {
+                let fill = (len - last * EXTENT_SIZE).min(EXTENT_SIZE);
+                if self.extents[last].len != fill {
+                    self.update(last, |data| data.resize(fill, 0))?;
+                }
+            }
+            while self.extents.len() * EXTENT_SIZE < len {
+                let fill = (len - self.extents.len() * EXTENT_SIZE).min(EXTENT_SIZE);
+                let extent = self.store(vec![0; fill]);
+                self.extents.push(extent);
+            }
+        }
+        self.len = len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(len: usize) -> Vec<u8> {
+        let words = b"the quick brown fox jumps over the lazy dog ";
+        (0..len).map(|i| words[(i * 7 / 5) % words.len()]).collect()
+    }
+
+    #[test]
+    fn test_codec_round_trip() {
+        let mut noise = Vec::new();
+        let mut x = 1u32;
+        for _ in 0..5000 {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            noise.push((x >> 16) as u8);
+        }
+        for input in [Vec::new(), b"abc".to_vec(), text(40000), vec![0; 70000], noise] {
+            let packed = compress(&input);
+            assert_eq!(decompress(&packed, input.len()).unwrap(), input);
+        }
+        assert!(compress(&text(40000)).len() < 40000 / 4);
+        assert_eq!(decompress(&[0x1f, b'a', 0, 0], 5), Err(DbfsError::Io));
+    }
+
+    #[test]
+    fn test_file_data_matches_plain_vec() {
+        let mut file = FileData::new(true, None, None);
+        let mut model = Vec::new();
+        let writes = [(0, 20000), (5, 3), (EXTENT_SIZE - 2, 4), (50000, 100), (30000, 9000)];
+        for (offset, len) in writes {
+            let data = text(len + offset % 13);
+            file.write_at(offset, &data).unwrap();
+            if model.len() < offset + data.len() {
+                model.resize(offset + data.len(), 0);
+            }
+            model[offset..offset + data.len()].copy_from_slice(&data);
+        }
+        file.resize(40001).unwrap();
+        model.truncate(40001);
+
+        assert_eq!(file.len(), model.len());
+        assert_eq!(file.read_range(0, usize::MAX).unwrap(), model);
+        assert_eq!(file.read_range(EXTENT_SIZE - 7, 30).unwrap(), model[EXTENT_SIZE - 7..EXTENT_SIZE + 23]);
+        assert!(file.stored_len() < file.len());
+
+        file.set_compressed(false).unwrap();
+        assert_eq!(file.stored_len(), file.len());
+        assert_eq!(file.read_range(0, usize::MAX).unwrap(), model);
+    }
+
+    #[test]
+    fn test_file_data_encrypted() {
+        use crate::crypt::{MountKey, TAG_LEN};
+
+        let cipher = Arc::new(Cipher::new(&MountKey::new([3; 32])));
+        let mut file = FileData::new(false, Some(cipher), None);
+        let data = text(EXTENT_SIZE + 100);
+        file.write_at(0, &data).unwrap();
+        file.write_at(10, b"overwrite").unwrap();
+        assert_eq!(file.stored_len(), file.len() + 2 * TAG_LEN);
+        assert_eq!(file.read_range(10, 9).unwrap(), b"overwrite");
+        assert!(!file.extents[0].chunks[0].data.windows(9).any(|w| w == b"overwrite"));
+
+        // 篡改 extent: 读取失败而不是返回错误数据
+        Arc::make_mut(&mut Arc::make_mut(&mut file.extents[1]).chunks[0]).data[0] ^= 1;
+        assert_eq!(file.read_range(EXTENT_SIZE, 10), Err(DbfsError::Io));
+        assert_eq!(file.read_range(0, 10).unwrap(), data[..10]);
+    }
+
+    #[test]
+    fn test_clone_range_shares_extents() {
+        let data = text(3 * EXTENT_SIZE + 100);
+        let mut src = FileData::new(true, None, None);
+        src.write_at(0, &data).unwrap();
+
+        // 整个文件: 全部 extent 共享
+        let mut dst = FileData::new(false, None, None);
+        assert_eq!(dst.clone_range(&src, 0, usize::MAX, 0).unwrap(), data.len());
+        assert_eq!(dst.read_range(0, usize::MAX).unwrap(), data);
+        assert_eq!(dst.shared_extents(), 4);
+        assert!(Arc::ptr_eq(&dst.extents[3], &src.extents[3]));
+
+        // 写入共享的 extent 只影响写入的文件
+        dst.write_at(EXTENT_SIZE + 1, b"changed").unwrap();
+        assert_eq!(
+            src.read_range(EXTENT_SIZE + 1, 7).unwrap(),
+            data[EXTENT_SIZE + 1..EXTENT_SIZE + 8]
+        );
+        assert_eq!(dst.read_range(EXTENT_SIZE + 1, 7).unwrap(), b"changed");
+        assert_eq!(dst.shared_extents(), 3);
+
+        // 未对齐的部分复制, 短的最后一个 extent 不在文件末尾时复制
+        let mut other = FileData::new(false, None, None);
+        other.write_at(0, &text(5 * EXTENT_SIZE)).unwrap();
+        let mut model = other.read_range(0, usize::MAX).unwrap();
+        let copy = src.clone();
+        other.clone_range(&copy, 10, 2 * EXTENT_SIZE + 100, EXTENT_SIZE + 10).unwrap();
+        other.clone_range(&copy, 2 * EXTENT_SIZE, EXTENT_SIZE + 100, 0).unwrap();
+        model[EXTENT_SIZE + 10..3 * EXTENT_SIZE + 110]
+            .copy_from_slice(&data[10..2 * EXTENT_SIZE + 110]);
+        model[..EXTENT_SIZE + 100].copy_from_slice(&data[2 * EXTENT_SIZE..]);
+        assert_eq!(other.read_range(0, usize::MAX).unwrap(), model);
+        assert!(Arc::ptr_eq(&other.extents[0], &src.extents[2]));
+        assert!(!Arc::ptr_eq(&other.extents[1], &src.extents[3]));
+    }
+
+    #[test]
+    fn test_resize_keeps_full_extents() {
+        let mut file = FileData::new(true, None, None);
+        file.write_at(0, &text(2 * EXTENT_SIZE)).unwrap();
+        let copy = file.clone();
+
+        // 截断 / 扩展到 extent 边界不重写已满的最后一个 extent
+        file.resize(EXTENT_SIZE).unwrap();
+        assert!(Arc::ptr_eq(&file.extents[0], &copy.extents[0]));
+        file.resize(3 * EXTENT_SIZE).unwrap();
+        assert!(Arc::ptr_eq(&file.extents[0], &copy.extents[0]));
+        assert_eq!(file.read_range(0, EXTENT_SIZE).unwrap(), text(EXTENT_SIZE));
+        assert_eq!(file.read_range(EXTENT_SIZE, 4).unwrap(), [0; 4]);
+    }
+}
@@ -0,0 +1,881 @@
+//! DBFS 崩溃一致性测试工具
+//!
+//! 思路同 dm-log-writes / CrashMonkey:
+//!
+//! 1. 在 [`RecordingBlockDevice`] 上运行工作负载, 记录每一次 write 和 flush;
+//! 2. 以 flush 屏障为界把写入日志切分成若干 epoch, 枚举每个 epoch 内
+//!    所有合法的崩溃状态 (屏障之前的写入全部落盘, 屏障之后的写入可以
+//!    任意子集落盘, 也可以在扇区边界被撕裂);
+//! 3. 把每个崩溃状态还原成设备镜像, 重新挂载 WAL 并检查提交后不变量。
+//!
+//! 本模块不依赖 VFS 和内核, 既可以在内核里调用, 也可以通过
+//! `src/bin/dbfs_crash.rs` 在 host 上运行 (`make dbfs-crash`), 不需要 QEMU。
+//! 挂载整个文件系统、比较恢复出的目录树的检查见 `alien_integration::crash`。
+//!
+//! ## 检查的不变量
+//!
+//! - 崩溃状态可以重新挂载和恢复
+//! - 持久性: commit 返回且其 flush 屏障已完成的事务一定被恢复
+//!   (或已被 checkpoint 覆盖)
+//! - 原子性: 被恢复的事务, 其 redo 记录与提交时写入的记录完全一致
+//! - 不出现幻影提交: 没有调用 commit (或已回滚) 的事务不会被恢复为已提交
+//! - 事务 ID 不复用, redo 记录按 LSN 严格递增
+//! - 恢复后的 WAL 可以继续追加新事务, 再次挂载后新旧提交都在
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+
+use spin::Mutex;
+
+use crate::{
+    common::{DbfsError, DbfsResult},
+    log_manager::{BlockDevice, MemBlockDevice},
+    wal::{Lsn, TxId, Wal, WalOp, WalRecord, WalRecordType},
+};
+
+/// 原子写入单位: 撕裂写只会发生在扇区边界
+pub const SECTOR_SIZE: usize = 512;
+
+/// 单个 epoch 内写入数不超过该值时枚举全部子集, 否则只枚举前缀
+pub const DEFAULT_MAX_SUBSET_WRITES: usize = 10;
+
+const CRASH_WAL_PATH: &str = "/crash/.wal";
+
+// ==================== 记录设备 ====================
+
+/// 记录下来的设备 I/O
+#[derive(Debug, Clone)]
+pub enum IoEvent {
+    Write { pos: u64, data: Vec<u8> },
+    Flush,
+}
+
+/// 记录所有 write/flush 的块设备
+///
+/// 读操作看到的是全部写入都已生效的"运行时"视图, 崩溃状态由
+/// [`build_image`] 根据记录重新构造。
+pub struct RecordingBlockDevice {
+    inner: Mutex<RecordingInner>,
+}
+
+struct RecordingInner {
+    initial: Vec<u8>,
+    image: Vec<u8>,
+    log: Vec<IoEvent>,
+    flushes: usize,
+}
+
+impl RecordingBlockDevice {
+    pub fn new(size: usize) -> Self {
+        Self::from_image(vec![0; size])
+    }
+
+    pub fn from_image(image: Vec<u8>) -> Self {
+        Self {
+            inner: Mutex::new(RecordingInner {
+                initial: image.clone(),
+                image,
+                log: Vec::new(),
+                flushes: 0,
+            }),
+        }
+    }
+
+    /// 已完成的 flush 屏障数
+    pub fn flush_count(&self) -> usize {
+        self.inner.lock().flushes
+    }
+
+    /// 记录开始时的设备镜像
+    pub fn initial_image(&self) -> Vec<u8> {
+        self.inner.lock().initial.clone()
+    }
+
+    /// 到目前为止的 I/O 记录
+    pub fn events(&self) -> Vec<IoEvent> {
+        self.inner.lock().log.clone()
+    }
+}
+
+impl BlockDevice for RecordingBlockDevice {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> DbfsResult<usize> {
+        let inner = self.inner.lock();
+        let pos = pos as usize;
+        if pos >= inner.image.len() {
+            return Ok(0);
+        }
+        let len = core::cmp::min(buf.len(), inner.image.len() - pos);
+        buf[..len].copy_from_slice(&inner.image[pos..pos + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, pos: u64, buf: &[u8]) -> DbfsResult<usize> {
+        let mut inner = self.inner.lock();
+        let start = pos as usize;
+        if start + buf.len() > inner.image.len() {
+            return Err(DbfsError::NoSpace);
+        }
+        inner.image[start..start + buf.len()].copy_from_slice(buf);
+        inner.log.push(IoEvent::Write {
+            pos,
+            data: buf.to_vec(),
+        });
+        Ok(buf.len())
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.lock().image.len() as u64
+    }
+
+    fn flush(&self) -> DbfsResult<()> {
+        let mut inner = self.inner.lock();
+        inner.log.push(IoEvent::Flush);
+        inner.flushes += 1;
+        Ok(())
+    }
+}
+
+// ==================== 崩溃状态 ====================
+
+/// 一个崩溃状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrashState {
+    /// 崩溃前已完成的 flush 屏障数
+    pub barrier: usize,
+    /// 最后一个屏障之后已落盘的写入 (I/O 记录下标, 升序)
+    pub persisted: Vec<usize>,
+    /// 撕裂写: (I/O 记录下标, 落盘的扇区数)
+    pub torn: Option<(usize, usize)>,
+}
+
+/// 把 I/O 记录按 flush 屏障切分, 返回每个 epoch 内写入的下标
+///
+/// 第 k 个 epoch 是第 k 次 flush 之后, 第 k+1 次 flush 之前的写入,
+/// 最后一个 epoch 是从未被 flush 的写入。
+fn split_epochs(log: &[IoEvent]) -> Vec<Vec<usize>> {
+    let mut epochs = vec![Vec::new()];
+    for (idx, event) in log.iter().enumerate() {
+        match event {
+            IoEvent::Write { .. } => epochs.last_mut().unwrap().push(idx),
+            IoEvent::Flush => epochs.push(Vec::new()),
+        }
+    }
+    epochs
+}
+
+/// 一次写入跨越的扇区数
+fn sector_span(pos: u64, len: usize) -> usize {
+    if len == 0 {
+        return 0;
+    }
+    let first = pos as usize / SECTOR_SIZE;
+    let last = (pos as usize + len - 1) / SECTOR_SIZE;
+    last - first + 1
+}
+
+/// 撕裂写只落盘前 `sectors` 个扇区时实际写入的字节数
+fn torn_len(pos: u64, len: usize, sectors: usize) -> usize {
+    let boundary = (pos as usize / SECTOR_SIZE + sectors) * SECTOR_SIZE;
+    core::cmp::min(boundary - pos as usize, len)
+}
+
+/// 枚举所有合法崩溃状态
+///
+/// 每个 epoch 内:
+/// - 写入数不超过 `max_subset` 时枚举全部 2^n 个子集 (块层可以任意重排);
+///   否则只枚举按提交顺序的前缀;
+/// - 对每个跨越多个扇区的写入, 额外生成"前面的写入已落盘, 该写入只落盘
+///   前若干扇区"的撕裂状态。
+pub fn enumerate_crash_states(log: &[IoEvent], max_subset: usize) -> Vec<CrashState> {
+    let mut states = Vec::new();
+    for (barrier, writes) in split_epochs(log).iter().enumerate() {
+        let n = writes.len();
+        if n <= max_subset {
+            for mask in 0u64..(1u64 << n) {
+                let persisted = (0..n)
+                    .filter(|bit| mask & (1 << bit) != 0)
+                    .map(|bit| writes[bit])
+                    .collect();
+                states.push(CrashState {
+                    barrier,
+                    persisted,
+                    torn: None,
+                });
+            }
+        } else {
+            for prefix in 0..=n {
+                states.push(CrashState {
+                    barrier,
+                    persisted: writes[..prefix].to_vec(),
+                    torn: None,
+                });
+            }
+        }
+
+        for (i, &idx) in writes.iter().enumerate() {
+            if let IoEvent::Write { pos, data } = &log[idx] {
+                for sectors in 1..sector_span(*pos, data.len()) {
+                    states.push(CrashState {
+                        barrier,
+                        persisted: writes[..i].to_vec(),
+                        torn: Some((idx, sectors)),
+                    });
+                }
+            }
+        }
+    }
+    states
+}
+
+/// 根据 I/O 记录构造崩溃状态对应的设备镜像
+pub fn build_image(initial: &[u8], log: &[IoEvent], state: &CrashState) -> Vec<u8> {
+    let mut image = initial.to_vec();
+    let mut apply = |pos: u64, data: &[u8]| {
+        let pos = pos as usize;
+        image[pos..pos + data.len()].copy_from_slice(data);
+    };
+
+    let mut flushes = 0;
+    for event in log {
+        if flushes >= state.barrier {
+            break;
+        }
+        match event {
+            IoEvent::Write { pos, data } => apply(*pos, data),
+            IoEvent::Flush => flushes += 1,
+        }
+    }
+    for &idx in &state.persisted {
+        if let IoEvent::Write { pos, data } = &log[idx] {
+            apply(*pos, data);
+        }
+    }
+    if let Some((idx, sectors)) = state.torn {
+        if let IoEvent::Write { pos, data } = &log[idx] {
+            let len = torn_len(*pos, data.len(), sectors);
+            apply(*pos, &data[..len]);
+        }
+    }
+    image
+}
+
+// ==================== 工作负载与预期结果 ====================
+
+/// 事务提交时的信息
+#[derive(Debug, Clone, Copy)]
+pub struct CommitInfo {
+    /// TxCommit 记录的 LSN
+    pub lsn: Lsn,
+    /// commit 返回时已完成的 flush 屏障数
+    pub barrier: usize,
+}
+
+/// 工作负载中一个事务的预期结果
+#[derive(Debug, Clone, Default)]
+pub struct TxExpectation {
+    /// 操作记录: (LSN, 类型, 数据)
+    pub ops: Vec<(Lsn, WalRecordType, Vec<u8>)>,
+    /// 是否调用过 commit (commit 已开始, 但不一定返回)
+    pub commit_issued: bool,
+    /// commit 成功返回时的信息
+    pub commit: Option<CommitInfo>,
+    pub rolled_back: bool,
+    /// 被回滚到保存点丢弃的操作
+    pub discarded: BTreeSet<Lsn>,
+}
+
+/// 已提交事务构成的文件系统状态: 路径 -> 文件内容 (目录为 None)
+#[derive(Debug, Clone, Default)]
+pub struct ModelState {
+    entries: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+impl ModelState {
+    /// 应用一条操作记录
+    pub fn apply(&mut self, record_type: WalRecordType, data: &[u8]) {
+        let record = WalRecord::new(TxId::new(0), record_type, data.to_vec());
+        match record.operation() {
+            Some(WalOp::Create(path)) => {
+                self.entries
+                    .entry(path.to_string())
+                    .or_insert(Some(Vec::new()));
+            }
+            Some(WalOp::Mkdir(path)) => {
+                self.entries.insert(path.to_string(), None);
+            }
+            Some(WalOp::Delete(path)) => {
+                let prefix = format!("{}/", path);
+                self.entries
+                    .retain(|name, _| name != path && !name.starts_with(&prefix));
+            }
+            Some(WalOp::Write { path, offset, data }) => {
+                let content = self
+                    .entries
+                    .entry(path.to_string())
+                    .or_insert(Some(Vec::new()))
+                    .get_or_insert_with(Vec::new);
+                let start = offset as usize;
+                if content.len() < start + data.len() {
+                    content.resize(start + data.len(), 0);
+                }
+                content[start..start + data.len()].copy_from_slice(data);
+            }
+            Some(WalOp::Clone {
+                src,
+                src_offset,
+                len,
+                dst,
+                dst_offset,
+            }) => {
+                let source = self.entries.get(src).cloned().flatten().unwrap_or_default();
+                let start = (src_offset as usize).min(source.len());
+                let end = start.saturating_add(len as usize).min(source.len());
+                let record = {
+                    let mut data = Vec::new();
+                    data.extend_from_slice(&(dst.len() as u16).to_be_bytes());
+                    data.extend_from_slice(dst.as_bytes());
+                    data.extend_from_slice(&dst_offset.to_be_bytes());
+                    data.extend_from_slice(&((end - start) as u32).to_be_bytes());
+                    data.extend_from_slice(&source[start..end]);
+                    data
+                };
+                self.apply(WalRecordType::FileWrite, &record);
+            }
+            Some(WalOp::Rename { from, to }) => {
+                let from_prefix = format!("{}/", from);
+                let to_prefix = format!("{}/", to);
+                if !self.entries.contains_key(from) {
+                    return;
+                }
+                self.entries
+                    .retain(|name, _| name != to && !name.starts_with(&to_prefix));
+                let moved: Vec<_> = self
+                    .entries
+                    .keys()
+                    .filter(|name| *name == from || name.starts_with(&from_prefix))
+                    .cloned()
+                    .collect();
+                for name in moved {
+                    let content = self.entries.remove(&name).unwrap();
+                    self.entries.insert(format!("{}{}", to, &name[from.len()..]), content);
+                }
+            }
+            // 标志只改变存储方式
+            Some(WalOp::SetFlags { .. }) | None => {}
+        }
+    }
+
+    /// 把状态作为 checkpoint 镜像写入 WAL, 返回写入的操作
+    fn log_image(&self, wal: &mut Wal, tx_id: TxId) -> Vec<(Lsn, WalRecordType, Vec<u8>)> {
+        let mut ops = Vec::new();
+        // BTreeMap 按路径排序, 父目录总在子项之前
+        for (path, content) in &self.entries {
+            let lsn = match content {
+                None => wal.mkdir(tx_id, path),
+                Some(content) => {
+                    let lsn = wal.create_file(tx_id, path);
+                    ops.push((lsn, WalRecordType::FileCreate, path.as_bytes().to_vec()));
+                    if content.is_empty() {
+                        continue;
+                    }
+                    wal.write_file(tx_id, path, 0, content)
+                }
+            };
+            let record = wal.get_tx_records(tx_id).into_iter().find(|r| r.lsn == lsn);
+            if let Some(record) = record {
+                ops.push((lsn, record.record_type, record.data.clone()));
+            }
+        }
+        ops
+    }
+}
+
+/// 在记录设备上运行的 WAL, 同时记录每个事务的预期结果
+pub struct RecordedWal {
+    wal: Wal,
+    device: Arc<RecordingBlockDevice>,
+    txs: BTreeMap<u64, TxExpectation>,
+    /// 已提交事务构成的状态, 用作 checkpoint 镜像
+    state: ModelState,
+}
+
+impl RecordedWal {
+    pub fn new(device_size: usize) -> DbfsResult<Self> {
+        let device = Arc::new(RecordingBlockDevice::new(device_size));
+        let wal = Wal::with_device(CRASH_WAL_PATH.to_string(), device.clone())?;
+        Ok(Self {
+            wal,
+            device,
+            txs: BTreeMap::new(),
+            state: ModelState::default(),
+        })
+    }
+
+    pub fn begin(&mut self) -> TxId {
+        let tx_id = self.wal.begin_tx();
+        self.txs.insert(tx_id.value(), TxExpectation::default());
+        tx_id
+    }
+
+    pub fn write_file(&mut self, tx_id: TxId, path: &str, offset: u64, data: &[u8]) {
+        self.wal.write_file(tx_id, path, offset, data);
+    }
+
+    pub fn create_file(&mut self, tx_id: TxId, path: &str) {
+        self.wal.create_file(tx_id, path);
+    }
+
+    pub fn delete_file(&mut self, tx_id: TxId, path: &str) {
+        self.wal.delete_file(tx_id, path);
+    }
+
+    pub fn mkdir(&mut self, tx_id: TxId, path: &str) {
+        self.wal.mkdir(tx_id, path);
+    }
+
+    pub fn savepoint(&mut self) -> Lsn {
+        self.wal.savepoint()
+    }
+
+    pub fn rollback_to_savepoint(&mut self, tx_id: TxId, savepoint: Lsn) {
+        let discarded: Vec<Lsn> = self
+            .wal
+            .get_tx_records(tx_id)
+            .into_iter()
+            .filter(|r| r.record_type.is_operation() && r.lsn > savepoint)
+            .map(|r| r.lsn)
+            .collect();
+        self.wal.rollback_to_savepoint(tx_id, savepoint);
+        self.txs
+            .entry(tx_id.value())
+            .or_default()
+            .discarded
+            .extend(discarded);
+    }
+
+    pub fn commit(&mut self, tx_id: TxId) -> DbfsResult<()> {
+        let tx = self.txs.entry(tx_id.value()).or_default();
+        let ops = self
+            .wal
+            .get_tx_records(tx_id)
+            .into_iter()
+            .filter(|r| r.record_type.is_operation() && !tx.discarded.contains(&r.lsn))
+            .map(|r| (r.lsn, r.record_type, r.data.clone()))
+            .collect();
+        tx.ops = ops;
+        tx.commit_issued = true;
+
+        self.wal.commit_tx(tx_id)?;
+        let tx = self.txs.get_mut(&tx_id.value()).unwrap();
+        tx.commit = Some(CommitInfo {
+            lsn: self.wal.flushed_lsn(),
+            barrier: self.device.flush_count(),
+        });
+        for (_, record_type, data) in &tx.ops {
+            self.state.apply(*record_type, data);
+        }
+        Ok(())
+    }
+
+    pub fn rollback(&mut self, tx_id: TxId) {
+        self.wal.rollback_tx(tx_id);
+        self.txs.entry(tx_id.value()).or_default().rolled_back = true;
+    }
+
+    pub fn flush(&mut self) -> DbfsResult<()> {
+        self.wal.flush()
+    }
+
+    /// checkpoint, 镜像为已提交事务构成的状态
+    ///
+    /// 镜像本身作为一个已提交事务登记预期结果
+    pub fn checkpoint(&mut self) -> DbfsResult<Lsn> {
+        let image_tx = self.wal.next_tx_id();
+        let mut ops = Vec::new();
+        let state = &self.state;
+        let checkpoint_lsn = self.wal.checkpoint(|wal, tx_id| {
+            ops = state.log_image(wal, tx_id);
+            Ok(())
+        })?;
+
+        // 镜像事务的提交在写 WAL 头之前的那次 flush 时落盘
+        let commit_lsn = ops.last().map_or(checkpoint_lsn + 1, |(lsn, _, _)| *lsn) + 1;
+        self.txs.insert(
+            image_tx,
+            TxExpectation {
+                ops,
+                commit_issued: true,
+                commit: Some(CommitInfo {
+                    lsn: commit_lsn,
+                    barrier: self.device.flush_count() - 1,
+                }),
+                ..TxExpectation::default()
+            },
+        );
+        Ok(checkpoint_lsn)
+    }
+
+    /// 结束记录
+    pub fn finish(self) -> CrashRecording {
+        CrashRecording {
+            initial: self.device.initial_image(),
+            log: self.device.events(),
+            txs: self.txs,
+        }
+    }
+}
+
+/// 一次工作负载运行的完整记录
+pub struct CrashRecording {
+    pub initial: Vec<u8>,
+    pub log: Vec<IoEvent>,
+    pub txs: BTreeMap<u64, TxExpectation>,
+}
+
+impl CrashRecording {
+    pub fn flush_count(&self) -> usize {
+        self.log
+            .iter()
+            .filter(|e| matches!(e, IoEvent::Flush))
+            .count()
+    }
+
+    pub fn write_count(&self) -> usize {
+        self.log.len() - self.flush_count()
+    }
+}
+
+// ==================== 检查 ====================
+
+/// 挂载崩溃状态并检查不变量, 返回第一个被违反的不变量
+pub fn check_state(recording: &CrashRecording, state: &CrashState) -> Result<(), String> {
+    let image = build_image(&recording.initial, &recording.log, state);
+    let device = Arc::new(MemBlockDevice::from_image(image));
+
+    let wal = Wal::with_device(CRASH_WAL_PATH.to_string(), device.clone())
+        .map_err(|e| format!("remount failed: {:?}", e))?;
+    let result = wal
+        .recover()
+        .map_err(|e| format!("recovery failed: {:?}", e))?;
+    let checkpoint_lsn = wal.checkpoint_lsn();
+    let committed: BTreeSet<u64> = result.committed.iter().map(|t| t.value()).collect();
+
+    for tx in &committed {
+        match recording.txs.get(tx) {
+            Some(exp) if exp.commit_issued && !exp.rolled_back => {}
+            _ => return Err(format!("phantom commit: tx {}", tx)),
+        }
+    }
+
+    for (tx, exp) in &recording.txs {
+        if let Some(commit) = exp.commit {
+            let durable = commit.barrier <= state.barrier;
+            let checkpointed = commit.lsn <= checkpoint_lsn;
+            if durable && !committed.contains(tx) && !checkpointed {
+                return Err(format!(
+                    "lost commit: tx {} (commit LSN {}, barrier {})",
+                    tx, commit.lsn, commit.barrier
+                ));
+            }
+        }
+    }
+
+    let mut last_lsn = 0;
+    for record in &result.redo {
+        if record.lsn <= last_lsn {
+            return Err(format!("redo out of order at LSN {}", record.lsn));
+        }
+        last_lsn = record.lsn;
+        if !committed.contains(&record.tx_id.value()) {
+            return Err(format!(
+                "redo contains record of uncommitted tx {}",
+                record.tx_id
+            ));
+        }
+    }
+
+    for tx in &committed {
+        let expected: Vec<_> = recording.txs[tx]
+            .ops
+            .iter()
+            .filter(|(lsn, _, _)| *lsn > checkpoint_lsn)
+            .cloned()
+            .collect();
+        let actual: Vec<_> = result
+            .redo
+            .iter()
+            .filter(|r| r.tx_id.value() == *tx)
+            .map(|r| (r.lsn, r.record_type, r.data.clone()))
+            .collect();
+        if expected != actual {
+            return Err(format!(
+                "torn transaction: tx {} expected {} ops, recovered {}",
+                tx,
+                expected.len(),
+                actual.len()
+            ));
+        }
+    }
+
+    let max_seen = result
+        .committed
+        .iter()
+        .chain(result.uncommitted.iter())
+        .map(|t| t.value())
+        .max()
+        .unwrap_or(0);
+    if wal.next_tx_id() <= max_seen {
+        return Err(format!(
+            "tx id reuse: next tx id {} <= recovered tx {}",
+            wal.next_tx_id(),
+            max_seen
+        ));
+    }
+
+    check_append_after_recovery(device, wal, &committed, checkpoint_lsn)
+}
+
+/// 恢复后的 WAL 必须可以继续使用: 追加一个新事务后再次挂载,
+/// 新事务和之前恢复出的事务都应该在
+fn check_append_after_recovery(
+    device: Arc<MemBlockDevice>,
+    mut wal: Wal,
+    committed: &BTreeSet<u64>,
+    checkpoint_lsn: Lsn,
+) -> Result<(), String> {
+    let tx_id = wal.begin_tx();
+    wal.write_file(tx_id, "/crash/after", 0, b"after-recovery");
+    wal.commit_tx(tx_id)
+        .map_err(|e| format!("append after recovery failed: {:?}", e))?;
+    drop(wal);
+
+    let wal = Wal::with_device(CRASH_WAL_PATH.to_string(), device)
+        .map_err(|e| format!("second remount failed: {:?}", e))?;
+    let result = wal
+        .recover()
+        .map_err(|e| format!("second recovery failed: {:?}", e))?;
+    let recommitted: BTreeSet<u64> = result.committed.iter().map(|t| t.value()).collect();
+
+    if !recommitted.contains(&tx_id.value()) {
+        return Err(format!("tx {} appended after recovery was lost", tx_id));
+    }
+    if wal.checkpoint_lsn() != checkpoint_lsn {
+        return Err("checkpoint LSN changed across remount".to_string());
+    }
+    let mut expected = committed.clone();
+    expected.insert(tx_id.value());
+    if recommitted != expected {
+        return Err(format!(
+            "committed set changed across remount: {:?} -> {:?}",
+            committed, recommitted
+        ));
+    }
+    Ok(())
+}
+
+/// 崩溃状态探索结果
+#[derive(Debug, Default)]
+pub struct CrashReport {
+    pub states: usize,
+    pub failures: Vec<(CrashState, String)>,
+}
+
+/// 枚举并检查一次记录的全部崩溃状态
+pub fn explore(recording: &CrashRecording, max_subset: usize) -> CrashReport {
+    let mut report = CrashReport::default();
+    for state in enumerate_crash_states(&recording.log, max_subset) {
+        report.states += 1;
+        if let Err(reason) = check_state(recording, &state) {
+            report.failures.push((state, reason));
+        }
+    }
+    report
+}
+
+// ==================== 内置工作负载 ====================
+
+/// 工作负载: 在 [`RecordedWal`] 上执行一系列事务
+pub type CrashWorkload = fn(&mut RecordedWal) -> DbfsResult<()>;
+
+/// 内置工作负载, 按名称索引
+pub const CRASH_WORKLOADS: &[(&str, CrashWorkload)] = &[
+    ("sequential_commits", workload_sequential_commits),
+    ("interleaved_rollback", workload_interleaved_rollback),
+    ("checkpoint_active_tx", workload_checkpoint_active_tx),
+    ("multi_sector_write", workload_multi_sector_write),
+    ("savepoint_rollback", workload_savepoint_rollback),
+    ("circular_reuse", workload_circular_reuse),
+];
+
+/// 默认的记录设备大小
+pub const CRASH_DEVICE_SIZE: usize = 64 * 1024;
+
+/// 运行工作负载并返回记录
+pub fn record_workload(workload: CrashWorkload) -> DbfsResult<CrashRecording> {
+    let mut wal = RecordedWal::new(CRASH_DEVICE_SIZE)?;
+    workload(&mut wal)?;
+    Ok(wal.finish())
+}
+
+fn workload_sequential_commits(wal: &mut RecordedWal) -> DbfsResult<()> {
+    for i in 0..3u8 {
+        let tx = wal.begin();
+        let path = format!("/seq/file{}", i);
+        wal.create_file(tx, &path);
+        wal.write_file(tx, &path, 0, &[b'a' + i; 64]);
+        wal.commit(tx)?;
+    }
+    Ok(())
+}
+
+fn workload_interleaved_rollback(wal: &mut RecordedWal) -> DbfsResult<()> {
+    let tx1 = wal.begin();
+    let tx2 = wal.begin();
+    wal.mkdir(tx1, "/inter");
+    wal.write_file(tx2, "/inter/aborted", 0, b"never visible");
+    wal.write_file(tx1, "/inter/kept", 0, b"visible");
+    wal.rollback(tx2);
+    wal.commit(tx1)?;
+
+    let tx3 = wal.begin();
+    wal.delete_file(tx3, "/inter/kept");
+    // 未提交: 只被后续 flush 顺带写到设备上
+    let tx4 = wal.begin();
+    wal.write_file(tx4, "/inter/other", 0, b"other");
+    wal.commit(tx4)?;
+    let _ = tx3;
+    Ok(())
+}
+
+fn workload_checkpoint_active_tx(wal: &mut RecordedWal) -> DbfsResult<()> {
+    let tx1 = wal.begin();
+    wal.write_file(tx1, "/ckpt/a", 0, b"checkpointed");
+    wal.commit(tx1)?;
+
+    let active = wal.begin();
+    wal.write_file(active, "/ckpt/b", 0, b"spans checkpoint");
+    // 镜像不能描述进行中的事务
+    if wal.checkpoint() != Err(DbfsError::Busy) {
+        return Err(DbfsError::Other);
+    }
+
+    let tx2 = wal.begin();
+    wal.write_file(tx2, "/ckpt/c", 0, b"after checkpoint");
+    wal.commit(tx2)?;
+    wal.commit(active)?;
+    wal.checkpoint()?;
+
+    let tx3 = wal.begin();
+    wal.delete_file(tx3, "/ckpt/a");
+    wal.commit(tx3)?;
+    wal.checkpoint()?;
+    Ok(())
+}
+
+fn workload_multi_sector_write(wal: &mut RecordedWal) -> DbfsResult<()> {
+    let tx1 = wal.begin();
+    wal.write_file(tx1, "/big/a", 0, &[0x5a; 3 * SECTOR_SIZE]);
+    wal.commit(tx1)?;
+
+    let tx2 = wal.begin();
+    wal.write_file(tx2, "/big/b", 0, &[0xa5; 2 * SECTOR_SIZE + 100]);
+    wal.write_file(tx2, "/big/b", 4096, b"tail");
+    wal.commit(tx2)?;
+    Ok(())
+}
+
+fn workload_savepoint_rollback(wal: &mut RecordedWal) -> DbfsResult<()> {
+    let tx1 = wal.begin();
+    wal.create_file(tx1, "/sp/a");
+    let sp1 = wal.savepoint();
+    wal.write_file(tx1, "/sp/a", 0, b"undone");
+    let sp2 = wal.savepoint();
+    wal.write_file(tx1, "/sp/a", 6, b"also undone");
+    // 回滚到外层保存点, 内层保存点之后的操作一并丢弃
+    wal.rollback_to_savepoint(tx1, sp1);
+    let _ = sp2;
+    wal.flush()?;
+    wal.write_file(tx1, "/sp/a", 0, b"kept");
+    wal.commit(tx1)?;
+
+    // 未提交事务的保存点回滚不影响其他事务
+    let tx2 = wal.begin();
+    wal.write_file(tx2, "/sp/b", 0, b"pending");
+    let sp = wal.savepoint();
+    wal.delete_file(tx2, "/sp/b");
+    wal.rollback_to_savepoint(tx2, sp);
+    let tx3 = wal.begin();
+    wal.mkdir(tx3, "/sp/dir");
+    wal.commit(tx3)?;
+    wal.commit(tx2)?;
+    Ok(())
+}
+
+/// 写入量超过设备容量, 依靠 checkpoint 回收的空间循环写入
+fn workload_circular_reuse(wal: &mut RecordedWal) -> DbfsResult<()> {
+    for round in 0..12u8 {
+        let tx = wal.begin();
+        wal.write_file(tx, "/ring/f", 0, &[round; 6 * 1024]);
+        wal.commit(tx)?;
+        if round % 2 == 1 {
+            wal.checkpoint()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enumerate_crash_states() {
+        let log = vec![
+            IoEvent::Write {
+                pos: 0,
+                data: vec![1; 10],
+            },
+            IoEvent::Flush,
+            IoEvent::Write {
+                pos: 100,
+                data: vec![2; 10],
+            },
+            IoEvent::Write {
+                pos: 1000,
+                data: vec![3; SECTOR_SIZE],
+            },
+        ];
+        let states = enumerate_crash_states(&log, DEFAULT_MAX_SUBSET_WRITES);
+        // epoch 0: 2 个子集; epoch 1: 4 个子集 + 1 个撕裂状态 (第二个写入跨两个扇区)
+        assert_eq!(states.len(), 2 + 4 + 1);
+
+        let torn = states.iter().find(|s| s.torn.is_some()).unwrap();
+        let image = build_image(&vec![0; 2048], &log, torn);
+        assert_eq!(image[0], 1);
+        assert_eq!(image[100], 2);
+        assert_eq!(image[1000], 3);
+        assert_eq!(image[1023], 3);
+        assert_eq!(image[1024], 0);
+    }
+
+    #[test]
+    fn test_builtin_workloads_crash_consistent() {
+        for (name, workload) in CRASH_WORKLOADS {
+            let recording = record_workload(*workload).unwrap();
+            let report = explore(&recording, DEFAULT_MAX_SUBSET_WRITES);
+            assert!(report.states > 0, "{}", name);
+            assert!(
+                report.failures.is_empty(),
+                "{}: {:?}",
+                name,
+                report.failures.first()
+            );
+        }
+    }
+}
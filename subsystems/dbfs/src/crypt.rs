@@ -0,0 +1,196 @@
+//! Encryption at rest for DBFS
+//!
+//! 使用 ChaCha20-Poly1305 (AEAD) 加密 WAL 记录和文件 extent, 密钥在挂载时给出
+//! (`key=` 或 `keyfile=` 挂载选项)。
+//!
+//! ## Nonce
+//!
+//! 96 位 nonce = 3 字节 epoch + 1 字节用途 ([`Domain`]) + 8 字节编号:
+//! - WAL 记录: WAL 头中的 epoch 和记录的 LSN。撕裂的尾部被覆盖后 LSN 会重复,
+//!   但每次以可写方式打开 WAL 都换一个 epoch
+//! - extent: epoch 为 0, 编号是每次写入 extent 时分配的新 extent ID,
+//!   覆盖写不会重用 nonce
+//!
+//! 因此同一个密钥只能用于一个挂载。
+//!
+//! ## 校验
+//!
+//! 认证标签 (16 字节) 校验失败时返回 [`DbfsError::Io`], 由 VFS 层报告为 EIO。
+//! WAL 头中保存一个用密钥生成的校验标签, 挂载时密钥不匹配返回
+//! [`DbfsError::AccessError`], 拒绝挂载。
+
+use alloc::vec::Vec;
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+
+use crate::common::{DbfsError, DbfsResult};
+
+/// Key length in bytes
+pub const KEY_LEN: usize = 32;
+/// Authentication tag appended to every sealed buffer
+pub const TAG_LEN: usize = 16;
+/// Largest nonce epoch, the epoch takes 3 bytes of the nonce
+pub const MAX_EPOCH: u32 = (1 << 24) - 1;
+
+/// What a nonce is used for, so that WAL and extent nonces never collide
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Domain {
+    /// Key check stored in the WAL header
+    Header = 0,
+    /// WAL record data, numbered by LSN
+    Wal = 1,
+    /// File extents, numbered by extent ID
+    Extent = 2,
+}
+
+/// A mount key
+///
+/// `Debug` does not print the key, so mount options can be logged.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MountKey([u8; KEY_LEN]);
+
+impl MountKey {
+    pub fn new(bytes: [u8; KEY_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    /// Parse a key: 64 hex digits, or exactly 32 raw bytes (key files)
+    ///
+    /// Surrounding whitespace of the hex form is ignored.
+    pub fn parse(text: &[u8]) -> DbfsResult<Self> {
+        let trimmed = text.trim_ascii();
+        let mut key = [0u8; KEY_LEN];
+        if trimmed.len() == KEY_LEN * 2 && trimmed.iter().all(u8::is_ascii_hexdigit) {
+            for (i, pair) in trimmed.chunks(2).enumerate() {
+                let hex = core::str::from_utf8(pair).map_err(|_| DbfsError::InvalidArgument)?;
+                key[i] = u8::from_str_radix(hex, 16).map_err(|_| DbfsError::InvalidArgument)?;
+            }
+        } else if text.len() == KEY_LEN {
+            key.copy_from_slice(text);
+        } else {
+            return Err(DbfsError::InvalidArgument);
+        }
+        Ok(Self(key))
+    }
+}
+
+impl fmt::Debug for MountKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MountKey(..)")
+    }
+}
+
+/// AEAD state of one mount
+pub struct Cipher {
+    aead: ChaCha20Poly1305,
+    /// Next extent ID (extent nonces)
+    next_extent: AtomicU64,
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Cipher")
+    }
+}
+
+impl Cipher {
+    pub fn new(key: &MountKey) -> Self {
+        Self {
+            aead: ChaCha20Poly1305::new((&key.0).into()),
+            next_extent: AtomicU64::new(1),
+        }
+    }
+
+    fn nonce(domain: Domain, epoch: u32, id: u64) -> Nonce {
+        debug_assert!(epoch <= MAX_EPOCH);
+        let mut nonce = [0u8; 12];
+        nonce[0..4].copy_from_slice(&((epoch << 8) | domain as u32).to_be_bytes());
+        nonce[4..12].copy_from_slice(&id.to_be_bytes());
+        nonce.into()
+    }
+
+    /// Encrypt `plaintext`, returns ciphertext followed by the tag
+    ///
+    /// `aad` is authenticated but not encrypted. `(epoch, id)` must not be
+    /// reused within `domain`.
+    pub fn seal(
+        &self,
+        domain: Domain,
+        epoch: u32,
+        id: u64,
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Vec<u8> {
+        self.aead
+            .encrypt(&Self::nonce(domain, epoch, id), Payload { msg: plaintext, aad })
+            .expect("ChaCha20-Poly1305 cannot fail to encrypt")
+    }
+
+    /// Decrypt the output of [`Cipher::seal`]
+    ///
+    /// A wrong key, nonce or `aad`, or modified data fail with [`DbfsError::Io`].
+    pub fn open(
+        &self,
+        domain: Domain,
+        epoch: u32,
+        id: u64,
+        aad: &[u8],
+        sealed: &[u8],
+    ) -> DbfsResult<Vec<u8>> {
+        self.aead
+            .decrypt(&Self::nonce(domain, epoch, id), Payload { msg: sealed, aad })
+            .map_err(|_| DbfsError::Io)
+    }
+
+    /// Tag stored in the WAL header to recognise the key at mount time
+    pub fn key_check(&self) -> [u8; TAG_LEN] {
+        let tag = self.seal(Domain::Header, 0, 0, b"DBFSWAL", &[]);
+        tag.try_into().unwrap()
+    }
+
+    /// Allocate a fresh extent ID
+    pub fn next_extent_id(&self) -> u64 {
+        self.next_extent.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open() {
+        let key = MountKey::parse(
+            b"000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\n",
+        )
+        .unwrap();
+        assert_eq!(MountKey::parse(&[7; KEY_LEN]).unwrap(), MountKey::new([7; KEY_LEN]));
+        assert!(MountKey::parse(b"0011").is_err());
+
+        let cipher = Cipher::new(&key);
+        let sealed = cipher.seal(Domain::Wal, 0, 42, b"header", b"secret data");
+        assert_eq!(sealed.len(), 11 + TAG_LEN);
+        assert_ne!(&sealed[..11], b"secret data");
+        assert_eq!(cipher.open(Domain::Wal, 0, 42, b"header", &sealed).unwrap(), b"secret data");
+
+        // 篡改密文、换 nonce / aad / 密钥都会校验失败
+        let mut tampered = sealed.clone();
+        tampered[3] ^= 1;
+        assert_eq!(cipher.open(Domain::Wal, 0, 42, b"header", &tampered), Err(DbfsError::Io));
+        assert!(cipher.open(Domain::Wal, 0, 43, b"header", &sealed).is_err());
+        assert!(cipher.open(Domain::Wal, 1, 42, b"header", &sealed).is_err());
+        assert!(cipher.open(Domain::Extent, 0, 42, b"header", &sealed).is_err());
+        assert!(cipher.open(Domain::Wal, 0, 42, b"other", &sealed).is_err());
+        let other = Cipher::new(&MountKey::new([1; KEY_LEN]));
+        assert!(other.open(Domain::Wal, 0, 42, b"header", &sealed).is_err());
+        assert_ne!(other.key_check(), cipher.key_check());
+    }
+}
@@ -0,0 +1,263 @@
+//! Inline deduplication for DBFS
+//!
+//! 挂载时给出 `dedup` 选项后, 写入的 extent 先按内容定义的边界切块
+//! (gear 滚动哈希, 与 FastCDC 相同的做法), 再用块内容的哈希查找本挂载的
+//! [`DedupIndex`]: 已有相同内容的块时直接引用它, 否则存放新块并加入索引。
+//!
+//! - 块边界由内容决定, 插入或删除数据之后, 后面的块边界会重新对齐,
+//!   近似相同的文件仍然共享大部分块; 块不会跨越 extent
+//! - 哈希命中后再比较块内容, 哈希冲突不会导致数据错误
+//! - 块通过 `Arc` 计数共享, 最后一个引用消失时释放; 索引只保存 `Weak`,
+//!   不会让已删除的数据继续占用空间, 失效的条目在统计时清理
+//!
+//! 索引和块一起放在内存中, 和文件数据一样由 WAL 恢复重建。WAL 同样按块去重:
+//! 写入记录中已经记录过的块只保存对之前记录的引用, WAL 的块索引在挂载时
+//! 从日志中重建 (见 [`crate::wal`] 的「去重」一节)。
+//! 压缩和加密按块进行, 块的编码方式与首次写入它的文件相同。
+
+use alloc::{collections::BTreeMap, sync::Arc, sync::Weak, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    compress::{Chunk, EXTENT_SIZE},
+    crypt::Cipher,
+};
+
+/// Smallest chunk (except at the end of an extent)
+pub const MIN_CHUNK: usize = 2 * 1024;
+/// Average chunk size the boundary mask aims for
+pub const AVG_CHUNK: usize = 4 * 1024;
+/// Largest chunk: chunks never cross an extent
+pub const MAX_CHUNK: usize = EXTENT_SIZE;
+
+/// A boundary is where the top bits of the rolling hash are all zero
+///
+/// The top bits depend on the last 64 bytes, the low bits only on the last few.
+const BOUNDARY_MASK: u64 = ((AVG_CHUNK as u64) - 1) << (64 - AVG_CHUNK.trailing_zeros());
+
+/// Random value per byte for the gear hash (splitmix64)
+static GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Length of the first chunk of `data`
+fn next_boundary(data: &[u8]) -> usize {
+    let end = data.len().min(MAX_CHUNK);
+    if end <= MIN_CHUNK {
+        return end;
+    }
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        if hash & BOUNDARY_MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// End offsets of the content-defined chunks of `data`
+pub fn chunk_ends(data: &[u8]) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        start += next_boundary(&data[start..]);
+        ends.push(start);
+    }
+    ends
+}
+
+/// Content hash of a chunk (not cryptographic, matches are compared)
+pub(crate) fn content_hash(data: &[u8]) -> u64 {
+    const PRIME: u64 = 0x9e37_79b1_85eb_ca87;
+    let mut hash = (data.len() as u64).wrapping_mul(PRIME);
+    let mut words = data.chunks_exact(8);
+    for word in &mut words {
+        let word = u64::from_le_bytes(word.try_into().unwrap());
+        hash = (hash ^ word).wrapping_mul(PRIME).rotate_left(31);
+    }
+    for &byte in words.remainder() {
+        hash = (hash ^ byte as u64).wrapping_mul(PRIME).rotate_left(11);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    hash ^ (hash >> 29)
+}
+
+/// Space saved by deduplication (see [`DedupIndex::stats`])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats {
+    /// Distinct chunks in use
+    pub chunks: u64,
+    /// Chunks referenced more than once
+    pub shared_chunks: u64,
+    /// Logical bytes of the distinct chunks
+    pub unique_bytes: u64,
+    /// Stored bytes not written because the chunk already existed
+    pub saved_bytes: u64,
+    /// Chunks found in the index since mount
+    pub hits: u64,
+}
+
+/// Chunk index of one mount: content hash -> chunks with that hash
+#[derive(Default)]
+pub struct DedupIndex {
+    chunks: Mutex<BTreeMap<u64, Vec<Weak<Chunk>>>>,
+    hits: AtomicU64,
+}
+
+impl core::fmt::Debug for DedupIndex {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("DedupIndex")
+    }
+}
+
+impl DedupIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store one extent as deduplicated chunks
+    pub(crate) fn store(
+        &self,
+        raw: &[u8],
+        compressed: bool,
+        cipher: Option<&Cipher>,
+    ) -> Vec<Arc<Chunk>> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        for end in chunk_ends(raw) {
+            let piece = &raw[start..end];
+            start = end;
+            let hash = content_hash(piece);
+            let mut index = self.chunks.lock();
+            let candidates = index.entry(hash).or_default();
+            candidates.retain(|chunk| chunk.strong_count() > 0);
+            let found = candidates.iter().filter_map(Weak::upgrade).find(|chunk| {
+                chunk.len() == piece.len()
+                    && chunk.load(cipher).is_ok_and(|data| data == piece)
+            });
+            let chunk = match found {
+                Some(chunk) => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    chunk
+                }
+                None => {
+                    let chunk = Arc::new(Chunk::store(piece.to_vec(), compressed, cipher));
+                    candidates.push(Arc::downgrade(&chunk));
+                    chunk
+                }
+            };
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    /// Current usage, dropping index entries of freed chunks
+    ///
+    /// A chunk referenced by n extents saves n - 1 copies. Extents shared
+    /// between files (reflink) count once, as they are not copies either.
+    pub fn stats(&self) -> DedupStats {
+        let mut stats = DedupStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            ..DedupStats::default()
+        };
+        let mut index = self.chunks.lock();
+        index.retain(|_, candidates| {
+            candidates.retain(|chunk| chunk.strong_count() > 0);
+            !candidates.is_empty()
+        });
+        for chunk in index.values().flatten().filter_map(Weak::upgrade) {
+            // 去掉这里 upgrade 得到的引用
+            let refs = Arc::strong_count(&chunk) as u64 - 1;
+            stats.chunks += 1;
+            stats.unique_bytes += chunk.len() as u64;
+            if refs > 1 {
+                stats.shared_chunks += 1;
+                stats.saved_bytes += (refs - 1) * chunk.stored_len() as u64;
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::FileData;
+
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_boundaries_follow_content() {
+        let data = noise(64 * 1024, 7);
+        let ends = chunk_ends(&data);
+        assert_eq!(*ends.last().unwrap(), data.len());
+        let mut start = 0;
+        for &end in &ends[..ends.len() - 1] {
+            assert!((MIN_CHUNK..=MAX_CHUNK).contains(&(end - start)));
+            start = end;
+        }
+
+        // 在开头插入数据后, 之后的边界不变
+        let mut shifted = noise(100, 9);
+        shifted.extend_from_slice(&data);
+        let shifted_ends = chunk_ends(&shifted);
+        let common = ends
+            .iter()
+            .filter(|end| shifted_ends.contains(&(*end + 100)))
+            .count();
+        assert!(common + 2 >= ends.len());
+    }
+
+    #[test]
+    fn test_dedup_shares_chunks() {
+        let index = Arc::new(DedupIndex::new());
+        let data = noise(3 * EXTENT_SIZE, 1);
+
+        let mut a = FileData::new(false, None, Some(index.clone()));
+        a.write_at(0, &data).unwrap();
+        let before = index.stats();
+        assert_eq!(before.saved_bytes, 0);
+
+        // 近似相同的文件: 只有改动附近的块是新的
+        let mut b = FileData::new(true, None, Some(index.clone()));
+        b.write_at(0, &data).unwrap();
+        b.write_at(EXTENT_SIZE + 5, b"edit").unwrap();
+        assert_eq!(b.read_range(EXTENT_SIZE + 5, 4).unwrap(), b"edit");
+        assert_eq!(a.read_range(0, usize::MAX).unwrap(), data);
+        let stats = index.stats();
+        assert!(stats.hits >= before.chunks);
+        assert!(stats.saved_bytes >= 2 * EXTENT_SIZE as u64);
+        assert!(stats.chunks < 2 * before.chunks);
+
+        // 删除一个文件后不再计入节省, 索引条目随块释放
+        drop(b);
+        let stats = index.stats();
+        assert_eq!(stats.saved_bytes, 0);
+        assert_eq!(stats.chunks, before.chunks);
+        drop(a);
+        assert_eq!(index.stats().chunks, 0);
+    }
+}
@@ -6,7 +6,11 @@ use alloc::{format, string::String, vec::Vec};
 use log::{info, error, debug, warn};
 
 use crate::elle_protocol::{DbfsRequest, DbfsResponse, DbfsOpType, ProtocolError};
-use crate::alien_integration::{DbfsSuperBlock, begin_tx, commit_tx, rollback_tx};
+use crate::alien_integration::{
+    DbfsSuperBlock, begin_tx, commit_tx, rollback_tx, savepoint, rollback_to_savepoint,
+    release_savepoint,
+};
+use crate::wal::TxId;
 
 /// Elle 请求处理器
 pub struct ElleRequestHandler {
@@ -56,6 +60,10 @@ impl ElleRequestHandler {
             DbfsOpType::CommitTx => self.handle_commit_tx(req),
 
             DbfsOpType::RollbackTx => self.handle_rollback_tx(req),
+
+            DbfsOpType::Savepoint
+            | DbfsOpType::RollbackToSavepoint
+            | DbfsOpType::ReleaseSavepoint => self.handle_savepoint(req),
         }
     }
 
@@ -187,6 +195,35 @@ impl ElleRequestHandler {
         }
     }
 
+    /// 处理 Savepoint / RollbackToSavepoint / ReleaseSavepoint
+    ///
+    /// 直接调用 DBFS 的保存点接口: 事务不存在 (mock 模式下的事务没有真正开始)
+    /// 或保存点不存在时返回负的 errno, 不会假装回滚成功
+    fn handle_savepoint(&self, req: &DbfsRequest) -> DbfsResponse {
+        info!("  TX-{}: {:?} {}", req.tx_id, req.op_type, req.path);
+
+        let tx_id = TxId::new(req.tx_id);
+        let result = match req.op_type {
+            DbfsOpType::Savepoint => savepoint(tx_id, &req.path),
+            DbfsOpType::RollbackToSavepoint => rollback_to_savepoint(tx_id, &req.path),
+            _ => release_savepoint(tx_id, &req.path),
+        };
+        let status = match result {
+            Ok(()) => 0,
+            Err(e) => {
+                warn!("  TX-{}: {:?} {} failed: {:?}", req.tx_id, req.op_type, req.path, e);
+                -(e as i32)
+            }
+        };
+
+        DbfsResponse {
+            tx_id: req.tx_id,
+            status,
+            lsn: 0,
+            data: Vec::new(),
+        }
+    }
+
     /// 主循环: 处理所有传入的请求
     pub fn run(&self) {
         info!("🚀 Elle Request Handler started");
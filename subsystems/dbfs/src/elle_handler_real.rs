@@ -6,7 +6,9 @@ use alloc::{format, string::String, vec::Vec};
 use log::{info, error, debug};
 
 use crate::elle_protocol::{DbfsRequest, DbfsResponse, DbfsOpType};
-use crate::alien_integration::{begin_tx, commit_tx, rollback_tx};
+use crate::alien_integration::{
+    begin_tx, commit_tx, release_savepoint, rollback_to_savepoint, rollback_tx, savepoint,
+};
 
 /// Elle 请求处理器 - 真实模式
 pub struct ElleRequestHandlerReal {
@@ -44,6 +46,10 @@ impl ElleRequestHandlerReal {
             DbfsOpType::CommitTx => self.handle_commit_tx(req),
 
             DbfsOpType::RollbackTx => self.handle_rollback_tx(req),
+
+            DbfsOpType::Savepoint
+            | DbfsOpType::RollbackToSavepoint
+            | DbfsOpType::ReleaseSavepoint => self.handle_savepoint(req),
         }
     }
 
@@ -51,9 +57,20 @@ impl ElleRequestHandlerReal {
     fn handle_begin_tx(&mut self, req: &DbfsRequest) -> DbfsResponse {
         info!("  TX-{}: BEGIN (real)", req.tx_id);
 
-        // 调用实际的 DBFS begin_tx
+        // 调用实际的 DBFS begin_tx (在默认挂载上)
         // 注意: 这里返回的是实际的 TxId
-        let tx_id = begin_tx();
+        let tx_id = match begin_tx() {
+            Ok(tx_id) => tx_id,
+            Err(e) => {
+                error!("  ❌ TX-{}: Begin failed: {:?}", req.tx_id, e);
+                return DbfsResponse {
+                    tx_id: req.tx_id,
+                    status: -(e as i32),
+                    lsn: 0,
+                    data: Vec::new(),
+                };
+            }
+        };
 
         info!("  ✅ TX-{}: Started", tx_id.value());
 
@@ -155,7 +172,16 @@ impl ElleRequestHandlerReal {
 
         // 调用实际的 DBFS commit
         let tx_id = crate::wal::TxId::new(req.tx_id);
-        commit_tx(tx_id).expect("Failed to commit transaction");
+        if let Err(e) = commit_tx(tx_id) {
+            // 事务被中止: status 为 -errno, Host 据此判断是否重试
+            error!("  ❌ TX-{}: Commit failed: {:?}", req.tx_id, e);
+            return DbfsResponse {
+                tx_id: req.tx_id,
+                status: -(e as i32),
+                lsn: 0,
+                data: Vec::new(),
+            };
+        }
 
         // 提交后会写入 WAL,返回 LSN
         info!("  ✅ TX-{}: Committed", req.tx_id);
@@ -186,14 +212,43 @@ impl ElleRequestHandlerReal {
         }
     }
 
+    /// 处理 Savepoint / RollbackToSavepoint / ReleaseSavepoint
+    fn handle_savepoint(&mut self, req: &DbfsRequest) -> DbfsResponse {
+        info!("  TX-{}: {:?} {} (real)", req.tx_id, req.op_type, req.path);
+
+        let tx_id = crate::wal::TxId::new(req.tx_id);
+        let result = match req.op_type {
+            DbfsOpType::Savepoint => savepoint(tx_id, &req.path),
+            DbfsOpType::RollbackToSavepoint => rollback_to_savepoint(tx_id, &req.path),
+            _ => release_savepoint(tx_id, &req.path),
+        };
+        let status = match result {
+            Ok(()) => {
+                info!("  ✅ TX-{}: {:?} {}", req.tx_id, req.op_type, req.path);
+                0
+            }
+            Err(e) => {
+                error!("  ❌ TX-{}: {:?} {} failed: {:?}", req.tx_id, req.op_type, req.path, e);
+                -(e as i32)
+            }
+        };
+
+        DbfsResponse {
+            tx_id: req.tx_id,
+            status,
+            lsn: 0,
+            data: Vec::new(),
+        }
+    }
+
     /// 主循环: 从通信通道读取并处理请求
     pub fn run(&mut self) {
         info!("🚀 Real Elle Request Handler started");
 
-        // 使用简化的 UART 通信
+        // virtio-serial 可用时走长度前缀帧, 否则退回 UART
         loop {
-            // 1. 从 UART 读取请求
-            if let Some(req_bytes) = drivers::elle_comm::read_from_host() {
+            // 1. 从 Host 读取请求
+            if let Some(req_bytes) = drivers::elle_comm::read_elle_request() {
                 debug!("📨 Received {} bytes from Host", req_bytes.len());
 
                 // 2. 反序列化请求
@@ -206,7 +261,7 @@ impl ElleRequestHandlerReal {
                         let resp_bytes = resp.serialize();
 
                         // 5. 发送回 Host
-                        if let Err(e) = drivers::elle_comm::write_to_host(&resp_bytes) {
+                        if let Err(e) = drivers::elle_comm::send_elle_response(&resp_bytes) {
                             error!("❌ Failed to send response: {:?}", e);
                         } else {
                             debug!("📤 Sent {} bytes to Host", resp_bytes.len());
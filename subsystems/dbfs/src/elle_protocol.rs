@@ -19,6 +19,12 @@ pub enum DbfsOpType {
     Readdir = 6,
     CommitTx = 7,
     RollbackTx = 8,
+    /// 创建保存点, 保存点名放在 path 中
+    Savepoint = 9,
+    /// 回滚到保存点, 保存点名放在 path 中
+    RollbackToSavepoint = 10,
+    /// 释放保存点, 保存点名放在 path 中
+    ReleaseSavepoint = 11,
 }
 
 impl DbfsOpType {
@@ -32,6 +38,9 @@ impl DbfsOpType {
             6 => Some(DbfsOpType::Readdir),
             7 => Some(DbfsOpType::CommitTx),
             8 => Some(DbfsOpType::RollbackTx),
+            9 => Some(DbfsOpType::Savepoint),
+            10 => Some(DbfsOpType::RollbackToSavepoint),
+            11 => Some(DbfsOpType::ReleaseSavepoint),
             _ => None,
         }
     }
@@ -51,6 +60,7 @@ pub struct DbfsRequest {
 #[derive(Debug, Clone)]
 pub struct DbfsResponse {
     pub tx_id: u64,
+    /// 0 表示成功, 失败时为 -errno (事务中止: -EAGAIN / -EDEADLK / -ETIMEDOUT / -ECANCELED)
     pub status: i32,
     pub lsn: u64,
     pub data: Vec<u8>,
@@ -0,0 +1,261 @@
+//! DBFS 故障注入 (Failpoints)
+//!
+//! 在 WAL 与提交路径上预埋命名故障点, 供崩溃一致性测试使用。
+//! 故障点可以在测试代码中通过 [`arm`] 直接布置, 也可以通过
+//! `/proc/fs/dbfs/failpoints` 写入控制命令布置。
+//!
+//! ## 控制命令
+//!
+//! ```text
+//! <name>=<action>          每次经过都触发
+//! <name>=<count>*<action>  只触发 count 次
+//! <name>=off               撤销该故障点
+//! reset                    撤销全部故障点
+//! ```
+//!
+//! action 取值: `panic` / `error` / `drop` / `torn(<offset>)`
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::Mutex;
+
+use crate::{
+    common::{DbfsError, DbfsResult},
+    log_manager::BlockDevice,
+};
+
+/// WAL 记录写入设备之前
+pub const FP_WAL_BEFORE_WRITE: &str = "wal_before_write";
+/// WAL 记录已写入, 但 flush 屏障尚未下发
+pub const FP_WAL_AFTER_WRITE: &str = "wal_after_write";
+/// checkpoint 记录已写入, WAL 头尚未更新
+pub const FP_WAL_CHECKPOINT: &str = "wal_checkpoint";
+/// 恢复阶段重放 WAL 记录时
+pub const FP_WAL_REPLAY: &str = "wal_replay";
+
+/// 所有已知故障点
+pub const ALL_FAILPOINTS: &[&str] = &[
+    FP_WAL_BEFORE_WRITE,
+    FP_WAL_AFTER_WRITE,
+    FP_WAL_CHECKPOINT,
+    FP_WAL_REPLAY,
+];
+
+/// 故障动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailAction {
+    /// 未布置
+    Off,
+    /// 直接 panic (真实崩溃)
+    Panic,
+    /// 返回 I/O 错误, 调用方视为崩溃点
+    Error,
+    /// 丢弃本次写入 (或 flush), 但向调用方报告成功
+    DropWrite,
+    /// 只写入前 offset 字节, 然后返回 I/O 错误
+    TornWrite(usize),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FailPointState {
+    action: FailAction,
+    /// 剩余触发次数, None 表示不限次数
+    remaining: Option<u32>,
+    /// 已触发次数
+    hits: u64,
+}
+
+static FAILPOINTS: Mutex<BTreeMap<String, FailPointState>> = Mutex::new(BTreeMap::new());
+/// 快速路径: 没有任何故障点布置时跳过加锁
+static ANY_ARMED: AtomicBool = AtomicBool::new(false);
+
+/// 布置故障点
+pub fn arm(name: &str, action: FailAction, count: Option<u32>) {
+    if action == FailAction::Off {
+        disarm(name);
+        return;
+    }
+    let mut fps = FAILPOINTS.lock();
+    fps.insert(
+        name.to_string(),
+        FailPointState {
+            action,
+            remaining: count,
+            hits: 0,
+        },
+    );
+    ANY_ARMED.store(true, Ordering::Release);
+    log::warn!("⚠ DBFS: failpoint {} armed: {:?} (count: {:?})", name, action, count);
+}
+
+/// 撤销故障点
+pub fn disarm(name: &str) {
+    let mut fps = FAILPOINTS.lock();
+    fps.remove(name);
+    ANY_ARMED.store(!fps.is_empty(), Ordering::Release);
+}
+
+/// 撤销全部故障点
+pub fn disarm_all() {
+    FAILPOINTS.lock().clear();
+    ANY_ARMED.store(false, Ordering::Release);
+}
+
+/// 经过故障点: 返回需要执行的动作
+///
+/// 有次数限制的故障点在次数用完后自动撤销
+pub fn eval(name: &str) -> FailAction {
+    if !ANY_ARMED.load(Ordering::Acquire) {
+        return FailAction::Off;
+    }
+    let mut fps = FAILPOINTS.lock();
+    let action = match fps.get_mut(name) {
+        Some(state) => {
+            state.hits += 1;
+            if let Some(remaining) = state.remaining.as_mut() {
+                *remaining -= 1;
+            }
+            state.action
+        }
+        None => return FailAction::Off,
+    };
+    if fps.get(name).and_then(|s| s.remaining) == Some(0) {
+        fps.remove(name);
+        ANY_ARMED.store(!fps.is_empty(), Ordering::Release);
+    }
+    drop(fps);
+    log::warn!("⚠ DBFS: failpoint {} hit: {:?}", name, action);
+    if action == FailAction::Panic {
+        panic!("DBFS failpoint {} triggered", name);
+    }
+    action
+}
+
+/// 经过故障点 (非写入位置): drop/torn 均视为崩溃
+pub fn check(name: &str) -> DbfsResult<()> {
+    match eval(name) {
+        FailAction::Off => Ok(()),
+        _ => Err(DbfsError::Io),
+    }
+}
+
+/// 带故障点的设备写入
+pub fn write_at(name: &str, device: &dyn BlockDevice, pos: u64, data: &[u8]) -> DbfsResult<()> {
+    match eval(name) {
+        FailAction::Off => {
+            device.write_at(pos, data)?;
+            Ok(())
+        }
+        FailAction::DropWrite => Ok(()),
+        FailAction::TornWrite(offset) => {
+            let len = core::cmp::min(offset, data.len());
+            device.write_at(pos, &data[..len])?;
+            Err(DbfsError::Io)
+        }
+        _ => Err(DbfsError::Io),
+    }
+}
+
+/// 带故障点的 flush 屏障
+pub fn flush(name: &str, device: &dyn BlockDevice) -> DbfsResult<()> {
+    match eval(name) {
+        FailAction::Off => device.flush(),
+        FailAction::DropWrite => Ok(()),
+        _ => Err(DbfsError::Io),
+    }
+}
+
+fn parse_action(s: &str) -> Option<FailAction> {
+    match s {
+        "off" => Some(FailAction::Off),
+        "panic" => Some(FailAction::Panic),
+        "error" => Some(FailAction::Error),
+        "drop" => Some(FailAction::DropWrite),
+        _ => {
+            let offset = s.strip_prefix("torn(")?.strip_suffix(')')?;
+            offset.trim().parse().ok().map(FailAction::TornWrite)
+        }
+    }
+}
+
+/// 解析并执行一条控制命令 (见模块文档)
+pub fn apply_command(cmd: &str) -> DbfsResult<()> {
+    let cmd = cmd.trim();
+    if cmd.is_empty() {
+        return Ok(());
+    }
+    if cmd == "reset" {
+        disarm_all();
+        return Ok(());
+    }
+    let (name, spec) = cmd.split_once('=').ok_or(DbfsError::InvalidArgument)?;
+    let name = name.trim();
+    if !ALL_FAILPOINTS.contains(&name) {
+        return Err(DbfsError::NotFound);
+    }
+    let spec = spec.trim();
+    let (count, action) = match spec.split_once('*') {
+        Some((count, action)) => (
+            Some(count.trim().parse().map_err(|_| DbfsError::InvalidArgument)?),
+            action.trim(),
+        ),
+        None => (None, spec),
+    };
+    let action = parse_action(action).ok_or(DbfsError::InvalidArgument)?;
+    arm(name, action, count);
+    Ok(())
+}
+
+/// 当前所有故障点状态 (每行一个)
+pub fn describe() -> String {
+    let fps = FAILPOINTS.lock();
+    let mut out = String::new();
+    for name in ALL_FAILPOINTS {
+        match fps.get(*name) {
+            Some(state) => {
+                let action = match state.action {
+                    FailAction::Off => "off".to_string(),
+                    FailAction::Panic => "panic".to_string(),
+                    FailAction::Error => "error".to_string(),
+                    FailAction::DropWrite => "drop".to_string(),
+                    FailAction::TornWrite(offset) => format!("torn({})", offset),
+                };
+                let count = match state.remaining {
+                    Some(n) => format!("{}*", n),
+                    None => String::new(),
+                };
+                out.push_str(&format!("{}={}{} hits={}\n", name, count, action, state.hits));
+            }
+            None => out.push_str(&format!("{}=off\n", name)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!(parse_action("panic"), Some(FailAction::Panic));
+        assert_eq!(parse_action("drop"), Some(FailAction::DropWrite));
+        assert_eq!(parse_action("torn(13)"), Some(FailAction::TornWrite(13)));
+        assert_eq!(parse_action("torn(x)"), None);
+    }
+
+    #[test]
+    fn test_apply_command_count() {
+        apply_command("wal_replay=2*error").unwrap();
+        assert_eq!(eval(FP_WAL_REPLAY), FailAction::Error);
+        assert_eq!(eval(FP_WAL_REPLAY), FailAction::Error);
+        assert_eq!(eval(FP_WAL_REPLAY), FailAction::Off);
+        assert!(apply_command("no_such_point=error").is_err());
+        disarm_all();
+    }
+}
@@ -13,13 +13,36 @@ mod link;
 // WAL Transaction Layer
 pub mod wal;
 
+// Change data capture derived from committed WAL records
+pub mod cdc;
+
+// Per-extent LZ4 compression of file data
+pub mod compress;
+
+// Content-defined chunking and inline deduplication of file data
+pub mod dedup;
+
+// Authenticated encryption of WAL records and file extents
+pub mod crypt;
+
+// Block device abstraction used by the WAL
+pub mod log_manager;
+
+// Fault injection for crash testing
+pub mod failpoint;
+
+// Block-level crash-consistency harness (CrashMonkey style)
+pub mod crash_harness;
+
 // Elle + Jepsen 测试支持
 pub mod elle_protocol;
+#[cfg(feature = "alien_integration")]
 pub mod elle_handler;
+#[cfg(all(feature = "alien_integration", feature = "kernel"))]
 pub mod elle_handler_real;
 
 // TCP Server (for Host communication)
-#[cfg(feature = "alien_integration")]
+#[cfg(all(feature = "alien_integration", feature = "kernel"))]
 pub mod tcp_server;
 
 // WAL Backend v2 - Optional architecture for pluggable backends
@@ -41,11 +64,31 @@ pub mod alien_integration;
 
 // Re-export DBFS types for VFS integration
 #[cfg(feature = "alien_integration")]
-pub use alien_integration::{DbfsFsType, DbfsSuperBlock};
+pub use alien_integration::{mount_busy, DbfsFsType, DbfsSuperBlock};
 
 // Re-export transaction functions
 #[cfg(feature = "alien_integration")]
-pub use alien_integration::{begin_tx, commit_tx, rollback_tx};
+pub use alien_integration::{
+    abort_tx, begin_tx, begin_tx_on, begin_tx_with_timeout, commit_tx, release_savepoint,
+    rollback_to_savepoint, rollback_tx, savepoint, set_default_tx_timeout, set_tx_timeout,
+    tx_status,
+};
+
+// Re-export directory quota management
+#[cfg(feature = "alien_integration")]
+pub use alien_integration::{clear_quota, get_quota, quota, set_quota, QuotaLimits, QuotaUsage};
+
+// Re-export per-mount statistics and change streams for procfs
+#[cfg(feature = "alien_integration")]
+pub use alien_integration::{changes, stats};
+
+// Re-export file range cloning (FICLONE / copy_file_range)
+#[cfg(feature = "alien_integration")]
+pub use alien_integration::reflink;
+
+// Re-export write-back of shared file mappings (msync / munmap)
+#[cfg(feature = "alien_integration")]
+pub use alien_integration::mmap;
 
 // Re-export test runner modules
 #[cfg(feature = "alien_integration")]
@@ -92,9 +135,6 @@ pub mod extend;
 #[cfg(feature = "dbop")]
 pub mod models;
 
-#[cfg(feature = "dbop")]
-pub mod log_manager;
-
 #[cfg(feature = "dbop")]
 pub mod tx_engine;
 
@@ -1,9 +1,106 @@
-use crate::common::DbfsResult;
+use alloc::{vec, vec::Vec};
+
+use spin::Mutex;
+
+use crate::common::{DbfsError, DbfsResult};
 
 pub trait BlockDevice: Send + Sync {
     fn read_at(&self, pos: u64, buf: &mut [u8]) -> DbfsResult<usize>;
     fn write_at(&self, pos: u64, buf: &[u8]) -> DbfsResult<usize>;
     fn size(&self) -> u64;
+    /// 持久化屏障: 返回后之前所有 write_at 的数据都已落盘
+    fn flush(&self) -> DbfsResult<()> {
+        Ok(())
+    }
+}
+
+/// 内存块设备
+///
+/// 区分"易失"写入和"持久"数据: write_at 的数据先进入待刷写列表, flush 后才写入持久数据。
+/// `crash()` 丢弃所有未 flush 的写入, 用于在测试中模拟掉电后重新挂载。
+pub struct MemBlockDevice {
+    inner: Mutex<MemBlockInner>,
+}
+
+struct MemBlockInner {
+    durable: Vec<u8>,
+    /// 上次 flush 之后的写入 (按提交顺序)
+    pending: Vec<(usize, Vec<u8>)>,
+}
+
+impl MemBlockDevice {
+    pub fn new(size: usize) -> Self {
+        Self {
+            inner: Mutex::new(MemBlockInner {
+                durable: vec![0; size],
+                pending: Vec::new(),
+            }),
+        }
+    }
+
+    /// 从已有镜像创建设备
+    pub fn from_image(image: Vec<u8>) -> Self {
+        Self {
+            inner: Mutex::new(MemBlockInner {
+                durable: image,
+                pending: Vec::new(),
+            }),
+        }
+    }
+
+    /// 模拟掉电: 未 flush 的写入全部丢失
+    pub fn crash(&self) {
+        self.inner.lock().pending.clear();
+    }
+
+    /// 持久数据的快照
+    pub fn durable_image(&self) -> Vec<u8> {
+        self.inner.lock().durable.clone()
+    }
+}
+
+impl BlockDevice for MemBlockDevice {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> DbfsResult<usize> {
+        let inner = self.inner.lock();
+        let pos = pos as usize;
+        if pos >= inner.durable.len() {
+            return Ok(0);
+        }
+        let len = core::cmp::min(buf.len(), inner.durable.len() - pos);
+        buf[..len].copy_from_slice(&inner.durable[pos..pos + len]);
+        // 叠加尚未 flush 的写入
+        for (w_pos, data) in inner.pending.iter() {
+            let start = core::cmp::max(pos, *w_pos);
+            let end = core::cmp::min(pos + len, *w_pos + data.len());
+            if start < end {
+                buf[start - pos..end - pos].copy_from_slice(&data[start - w_pos..end - w_pos]);
+            }
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, pos: u64, buf: &[u8]) -> DbfsResult<usize> {
+        let mut inner = self.inner.lock();
+        let pos = pos as usize;
+        if pos + buf.len() > inner.durable.len() {
+            return Err(DbfsError::NoSpace);
+        }
+        inner.pending.push((pos, buf.to_vec()));
+        Ok(buf.len())
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.lock().durable.len() as u64
+    }
+
+    fn flush(&self) -> DbfsResult<()> {
+        let mut inner = self.inner.lock();
+        let pending = core::mem::take(&mut inner.pending);
+        for (pos, data) in pending {
+            inner.durable[pos..pos + data.len()].copy_from_slice(&data);
+        }
+        Ok(())
+    }
 }
 
 pub struct LogManager<D: BlockDevice> {
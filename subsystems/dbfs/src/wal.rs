@@ -13,23 +13,76 @@
 //! │  - version: u32                    │
 //! │  - last_tx_id: u64                 │
 //! │  - checkpoint_lsn: u64             │
+//! │  - flags: u32                      │
+//! │  - key_check: [u8; 16]             │
+//! │  - start_pos: u64                  │
+//! │  - epoch: u32                      │
 //! ├─────────────────────────────────────┤
-//! │ Log Records (variable)             │
+//! │ Log Records (circular)             │
 //! │  [LSN | TxID | Type | Data | CRC]  │
-//! ├─────────────────────────────────────┤
-//! │ Checkpoint (periodic)              │
 //! └─────────────────────────────────────┘
 //! ```
+//!
+//! ## Checkpoint 与空间回收
+//!
+//! WAL 是唯一的持久副本, 所以 checkpoint 不能直接丢弃旧记录: [`Wal::checkpoint`]
+//! 先把文件系统的完整镜像作为一个已提交事务写入日志, 落盘后才把头部的
+//! start_pos / checkpoint_lsn 移到镜像开头。镜像之前的空间随后被循环复用:
+//! 追加到设备末尾放不下时回到头部之后继续写, 直到追上 start_pos。
+//! 扫描时靠 LSN 连续性区分有效记录和上一圈留下的旧记录。
+//!
+//! ## 压缩
+//!
+//! 带 FS_COMPR_FL 的文件的写入记为 [`WalRecordType::FileWriteLz`]: 写入设备前
+//! 数据部分用 LZ4 压缩 (压缩后不变小时按原样保存), 读回时解压; 内存中的记录
+//! 和 [`WalOp::Write`] 一样是未压缩的数据。压缩在加密之前进行。
+//!
+//! ## 去重
+//!
+//! 去重挂载 ([`Wal::set_dedup`]) 上, 写入记录的数据按 extent 边界和内容定义的
+//! 边界切块 (与 [`crate::dedup`] 相同), 已经出现在之前某个写入记录中的块在
+//! 设备上只保存 (LSN, 偏移, 长度) 引用, 记为 [`WalRecordType::FileWriteDedup`];
+//! 读回时从先读到的记录中取回数据, 内存中的记录仍是普通的 FileWrite。
+//! 块索引只指向内存中仍保留的记录, 在挂载时从这些记录重建; checkpoint 丢弃
+//! 旧记录时清空, 所以引用总是指向同一次扫描中更早读到的记录。
+//!
+//! ## 加密
+//!
+//! 用 [`Wal::open_device`] 传入 [`Cipher`] 时, 记录的 Data 在写入设备前用
+//! ChaCha20-Poly1305 加密 (nonce 由 epoch 和 LSN 组成, 记录头作为附加数据),
+//! 读回时校验认证标签; 校验失败返回 [`DbfsError::Io`]。撕裂的尾部记录会被
+//! 下一次追加覆盖, 新记录沿用它的 LSN, 所以每次以可写方式打开加密的 WAL 时
+//! 头部的 epoch 加一, 加密后的 Data 以 4 字节 epoch 开头。头部的 key_check 用于在挂载时
+//! 识别密钥, 不匹配返回 [`DbfsError::AccessError`]。内存中的记录始终是明文。
 
 #![allow(unused)]
-use alloc::{format, string::String, vec::Vec};
+use alloc::{collections::BTreeMap, format, string::String, sync::Arc, vec, vec::Vec};
 use core::fmt;
 
-use crate::common::DbfsError;
+use crate::{
+    common::DbfsError,
+    compress::EXTENT_SIZE,
+    crypt::{Cipher, Domain, MAX_EPOCH, TAG_LEN},
+    dedup::{chunk_ends, content_hash},
+    failpoint::{self, FP_WAL_AFTER_WRITE, FP_WAL_BEFORE_WRITE, FP_WAL_CHECKPOINT, FP_WAL_REPLAY},
+    log_manager::BlockDevice,
+};
 
 /// WAL Magic Number
 const WAL_MAGIC: &[u8; 8] = b"DBFSWAL\0";
 
+/// WAL header size on the backing device
+pub const WAL_HEADER_SIZE: usize = 512;
+
+/// Record header size: LSN (8) + TxID (8) + Type (1) + Data length (4)
+const RECORD_HEADER_SIZE: usize = 21;
+
+/// Header flag: record data is encrypted
+const WAL_FLAG_ENCRYPTED: u32 = 1;
+
+/// Format version with a circular log (start_pos in the header)
+const WAL_VERSION_CIRCULAR: u32 = 2;
+
 /// WAL Header (fixed size: 512 bytes)
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -42,19 +95,71 @@ pub struct WalHeader {
     pub last_tx_id: u64,
     /// Checkpoint LSN (Log Sequence Number)
     pub checkpoint_lsn: u64,
+    /// Key check tag of an encrypted WAL (see [`Cipher::key_check`])
+    pub key_check: Option<[u8; TAG_LEN]>,
+    /// Device offset of the first live record (LSN `checkpoint_lsn + 1`)
+    pub start_pos: u64,
+    /// Nonce epoch, bumped each time an encrypted WAL is opened for writing
+    pub epoch: u32,
     /// Reserved space
-    _reserved: [u8; 492],
+    _reserved: [u8; 460],
 }
 
 impl Default for WalHeader {
     fn default() -> Self {
         Self {
             magic: *WAL_MAGIC,
-            version: 1,
+            version: WAL_VERSION_CIRCULAR,
             last_tx_id: 0,
             checkpoint_lsn: 0,
-            _reserved: [0; 492],
+            key_check: None,
+            start_pos: WAL_HEADER_SIZE as u64,
+            epoch: 0,
+            _reserved: [0; 460],
+        }
+    }
+}
+
+impl WalHeader {
+    /// Encode header into its fixed on-disk layout (big endian)
+    pub fn to_bytes(&self) -> [u8; WAL_HEADER_SIZE] {
+        let mut bytes = [0u8; WAL_HEADER_SIZE];
+        bytes[0..8].copy_from_slice(&self.magic);
+        bytes[8..12].copy_from_slice(&self.version.to_be_bytes());
+        bytes[12..20].copy_from_slice(&self.last_tx_id.to_be_bytes());
+        bytes[20..28].copy_from_slice(&self.checkpoint_lsn.to_be_bytes());
+        if let Some(check) = self.key_check {
+            bytes[28..32].copy_from_slice(&WAL_FLAG_ENCRYPTED.to_be_bytes());
+            bytes[32..48].copy_from_slice(&check);
+        }
+        bytes[48..56].copy_from_slice(&self.start_pos.to_be_bytes());
+        bytes[56..60].copy_from_slice(&self.epoch.to_be_bytes());
+        bytes
+    }
+
+    /// Decode header, returns None if the magic number does not match
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < WAL_HEADER_SIZE || &bytes[0..8] != WAL_MAGIC {
+            return None;
         }
+        let flags = u32::from_be_bytes(bytes[28..32].try_into().unwrap());
+        Some(Self {
+            magic: *WAL_MAGIC,
+            version: u32::from_be_bytes(bytes[8..12].try_into().unwrap()),
+            last_tx_id: u64::from_be_bytes(bytes[12..20].try_into().unwrap()),
+            checkpoint_lsn: u64::from_be_bytes(bytes[20..28].try_into().unwrap()),
+            key_check: (flags & WAL_FLAG_ENCRYPTED != 0)
+                .then(|| bytes[32..48].try_into().unwrap()),
+            start_pos: u64::from_be_bytes(bytes[48..56].try_into().unwrap()),
+            epoch: u32::from_be_bytes(bytes[56..60].try_into().unwrap()),
+            _reserved: [0; 460],
+        })
+    }
+
+    /// Whether the log is circular; version 1 logs start right after the
+    /// header with LSN 1 and only ever grow
+    fn is_circular(&self) -> bool {
+        self.version >= WAL_VERSION_CIRCULAR
     }
 }
 
@@ -101,6 +206,144 @@ pub enum WalRecordType {
     Mkdir = 7,
     /// Checkpoint marker
     Checkpoint = 8,
+    /// Rollback to a savepoint (data: last LSN kept, 8 bytes)
+    SavepointRollback = 9,
+    /// Clone a file range (reflink), data: see [`Wal::clone_file`]
+    FileClone = 10,
+    /// Rename, data: see [`Wal::rename`]
+    Rename = 11,
+    /// Change stream retained when a checkpoint image was written
+    /// (data: see [`crate::cdc::ChangeLog::encode`])
+    ChangeState = 12,
+    /// Inode flags (FS_IOC_SETFLAGS), data: see [`Wal::set_flags`]
+    SetFlags = 13,
+    /// File write stored LZ4-compressed on the device, see [`Wal::write_file_lz`]
+    FileWriteLz = 14,
+    /// Device form of a file write that refers to chunks of earlier writes
+    /// (see [`WalRecord::deduplicated`]); read back as [`WalRecordType::FileWrite`]
+    FileWriteDedup = 15,
+}
+
+impl WalRecordType {
+    /// Whether the record changes file system state (as opposed to
+    /// transaction control records)
+    pub fn is_operation(&self) -> bool {
+        !matches!(
+            self,
+            WalRecordType::TxBegin
+                | WalRecordType::TxCommit
+                | WalRecordType::TxRollback
+                | WalRecordType::Checkpoint
+                | WalRecordType::SavepointRollback
+                | WalRecordType::ChangeState
+        )
+    }
+}
+
+/// Decoded data of an operation record (see [`WalRecord::operation`])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalOp<'a> {
+    Create(&'a str),
+    Mkdir(&'a str),
+    Delete(&'a str),
+    Write {
+        path: &'a str,
+        offset: u64,
+        data: &'a [u8],
+    },
+    Clone {
+        src: &'a str,
+        src_offset: u64,
+        len: u64,
+        dst: &'a str,
+        dst_offset: u64,
+    },
+    Rename {
+        from: &'a str,
+        to: &'a str,
+    },
+    SetFlags {
+        path: &'a str,
+        flags: u32,
+    },
+}
+
+/// Split `path len (2) + path + offset (8)` off the front of `data`
+fn path_offset(data: &[u8]) -> Option<(&str, u64, &[u8])> {
+    let path_len = u16::from_be_bytes(data.get(0..2)?.try_into().ok()?) as usize;
+    let path = core::str::from_utf8(data.get(2..2 + path_len)?).ok()?;
+    let rest = &data[2 + path_len..];
+    let offset = u64::from_be_bytes(rest.get(0..8)?.try_into().ok()?);
+    Some((path, offset, &rest[8..]))
+}
+
+/// Chunks of the data of a write at `offset`: `(start, end)` in `data`
+///
+/// Cut at extent boundaries first, so a write of whole extents is chunked
+/// like the file data itself.
+fn write_chunks(offset: u64, data: &[u8]) -> Vec<(usize, usize)> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let in_extent = (offset as usize + start) % EXTENT_SIZE;
+        let end = (start + EXTENT_SIZE - in_extent).min(data.len());
+        chunks.extend(chunk_ends(&data[start..end]).into_iter().scan(start, |from, to| {
+            let chunk = (*from, start + to);
+            *from = start + to;
+            Some(chunk)
+        }));
+        start = end;
+    }
+    chunks
+}
+
+/// Written data of the write record with LSN `lsn` in `records`
+fn write_data(records: &[WalRecord], lsn: Lsn) -> Option<&[u8]> {
+    let index = records.binary_search_by_key(&lsn, |record| record.lsn).ok()?;
+    match records[index].operation()? {
+        WalOp::Write { data, .. } => Some(data),
+        _ => None,
+    }
+}
+
+/// Chunks of the writes held in memory: content hash -> (LSN, offset in the
+/// written data, length)
+#[derive(Debug, Default)]
+struct ChunkIndex {
+    chunks: BTreeMap<u64, Vec<(Lsn, usize, usize)>>,
+}
+
+impl ChunkIndex {
+    fn add(&mut self, piece: &[u8], lsn: Lsn, at: usize) {
+        let entry = (lsn, at, piece.len());
+        self.chunks.entry(content_hash(piece)).or_default().push(entry);
+    }
+
+    /// Index every chunk of a write record
+    fn add_record(&mut self, record: &WalRecord) {
+        if let Some(WalOp::Write { offset, data, .. }) = record.operation() {
+            for (start, end) in write_chunks(offset, data) {
+                self.add(&data[start..end], record.lsn, start);
+            }
+        }
+    }
+
+    /// An earlier chunk with the same content as `piece`
+    fn find(&self, piece: &[u8], buffer: &[WalRecord]) -> Option<(Lsn, usize)> {
+        let candidates = self.chunks.get(&content_hash(piece))?;
+        candidates.iter().find_map(|&(lsn, at, len)| {
+            let data = write_data(buffer, lsn)?;
+            (len == piece.len() && data.get(at..at + len)? == piece).then_some((lsn, at))
+        })
+    }
+
+    /// Drop the chunks of records after `lsn`
+    fn forget_after(&mut self, lsn: Lsn) {
+        self.chunks.retain(|_, candidates| {
+            candidates.retain(|&(at, _, _)| at <= lsn);
+            !candidates.is_empty()
+        });
+    }
 }
 
 /// WAL Record
@@ -179,6 +422,251 @@ impl WalRecord {
         bytes
     }
 
+    /// Associated data of an encrypted record: LSN, TxID and type
+    fn aad(&self) -> [u8; 17] {
+        let mut aad = [0u8; 17];
+        aad[0..8].copy_from_slice(&self.lsn.to_be_bytes());
+        aad[8..16].copy_from_slice(&self.tx_id.value().to_be_bytes());
+        aad[16] = self.record_type as u8;
+        aad
+    }
+
+    /// Copy of the record with its data encrypted under nonce epoch `epoch`,
+    /// as written to the device
+    pub fn sealed(&self, cipher: &Cipher, epoch: u32) -> Self {
+        let mut data = epoch.to_be_bytes().to_vec();
+        data.extend(cipher.seal(Domain::Wal, epoch, self.lsn, &self.aad(), &self.data));
+        Self {
+            lsn: self.lsn,
+            tx_id: self.tx_id,
+            record_type: self.record_type,
+            checksum: Self::compute_checksum(&data),
+            data,
+        }
+    }
+
+    /// Decrypt a record read back from the device
+    ///
+    /// Fails with [`DbfsError::Io`] if the record was modified or the key is wrong.
+    pub fn opened(self, cipher: &Cipher) -> Result<Self, DbfsError> {
+        let (epoch, sealed) = self.data.split_first_chunk::<4>().ok_or(DbfsError::Io)?;
+        let epoch = u32::from_be_bytes(*epoch);
+        let data = cipher.open(Domain::Wal, epoch, self.lsn, &self.aad(), sealed)?;
+        Ok(Self {
+            checksum: Self::compute_checksum(&data),
+            data,
+            ..self
+        })
+    }
+
+    /// Copy of a [`WalRecordType::FileWriteLz`] record with its data
+    /// compressed, as written to the device; other records are returned as is
+    ///
+    /// The header (path, offset, length) stays plain and is followed by a
+    /// marker byte: 1 for LZ4, 0 when compression did not make it smaller.
+    pub fn packed(&self) -> Self {
+        if self.record_type != WalRecordType::FileWriteLz {
+            return self.clone();
+        }
+        let Some(WalOp::Write { data, .. }) = self.operation() else {
+            return self.clone();
+        };
+        let head = self.data.len() - data.len();
+        let compressed = crate::compress::compress(data);
+        let mut packed = self.data[..head].to_vec();
+        if compressed.len() < data.len() {
+            packed.push(1);
+            packed.extend_from_slice(&compressed);
+        } else {
+            packed.push(0);
+            packed.extend_from_slice(data);
+        }
+        Self {
+            checksum: Self::compute_checksum(&packed),
+            data: packed,
+            ..self.clone()
+        }
+    }
+
+    /// Decompress a record read back from the device (see [`WalRecord::packed`])
+    pub fn unpacked(self) -> Result<Self, DbfsError> {
+        if self.record_type != WalRecordType::FileWriteLz {
+            return Ok(self);
+        }
+        // path len (2) + path + offset (8) + data len (4), then the marker
+        let path_len = u16::from_be_bytes(
+            self.data.get(0..2).ok_or(DbfsError::Io)?.try_into().unwrap(),
+        ) as usize;
+        let head = 2 + path_len + 12;
+        let len_bytes = self.data.get(head - 4..head).ok_or(DbfsError::Io)?;
+        let len = u32::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+        let payload = self.data.get(head + 1..).ok_or(DbfsError::Io)?;
+        let raw = match self.data[head] {
+            0 => payload.to_vec(),
+            1 => crate::compress::decompress(payload, len)?,
+            _ => return Err(DbfsError::Io),
+        };
+        if raw.len() != len {
+            return Err(DbfsError::Io);
+        }
+        let mut data = self.data[..head].to_vec();
+        data.extend_from_slice(&raw);
+        Ok(Self {
+            checksum: Self::compute_checksum(&data),
+            data,
+            ..self
+        })
+    }
+
+    /// Copy of a [`WalRecordType::FileWrite`] record as written to the device
+    /// of a dedup WAL; other records are returned as is
+    ///
+    /// Chunks found in `index` become references to the earlier record (or an
+    /// earlier chunk of this one), the new chunks are added to it. Without any
+    /// reference the record keeps its type, otherwise it becomes
+    /// [`WalRecordType::FileWriteDedup`]: the header (path, offset, length)
+    /// followed by segments, either
+    /// `0 + length (4) + bytes` or `1 + LSN (8) + offset (4) + length (4)`.
+    fn deduplicated(&self, index: &mut ChunkIndex, buffer: &[WalRecord]) -> Self {
+        if self.record_type != WalRecordType::FileWrite {
+            return self.clone();
+        }
+        let Some(WalOp::Write { offset, data, .. }) = self.operation() else {
+            return self.clone();
+        };
+        let head = self.data.len() - data.len();
+        let mut packed = self.data[..head].to_vec();
+        let mut shared = false;
+        for (start, end) in write_chunks(offset, data) {
+            let piece = &data[start..end];
+            match index.find(piece, buffer) {
+                Some((lsn, at)) => {
+                    shared = true;
+                    packed.push(1);
+                    packed.extend_from_slice(&lsn.to_be_bytes());
+                    packed.extend_from_slice(&(at as u32).to_be_bytes());
+                    packed.extend_from_slice(&(piece.len() as u32).to_be_bytes());
+                }
+                None => {
+                    index.add(piece, self.lsn, start);
+                    packed.push(0);
+                    packed.extend_from_slice(&(piece.len() as u32).to_be_bytes());
+                    packed.extend_from_slice(piece);
+                }
+            }
+        }
+        if !shared {
+            return self.clone();
+        }
+        Self {
+            record_type: WalRecordType::FileWriteDedup,
+            checksum: Self::compute_checksum(&packed),
+            data: packed,
+            ..self.clone()
+        }
+    }
+
+    /// Resolve the references of a record read back from the device (see
+    /// [`WalRecord::deduplicated`]) against the records read before it
+    pub fn resolved(self, earlier: &[WalRecord]) -> Result<Self, DbfsError> {
+        if self.record_type != WalRecordType::FileWriteDedup {
+            return Ok(self);
+        }
+        let (_, _, rest) = path_offset(&self.data).ok_or(DbfsError::Io)?;
+        let head = self.data.len() - rest.len() + 4;
+        let len_bytes = self.data.get(head - 4..head).ok_or(DbfsError::Io)?;
+        let len = u32::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+        let mut data = self.data[..head].to_vec();
+        let mut segments = &self.data[head..];
+        let field = |bytes: &[u8], at: usize| -> Result<usize, DbfsError> {
+            let field = bytes.get(at..at + 4).ok_or(DbfsError::Io)?;
+            Ok(u32::from_be_bytes(field.try_into().unwrap()) as usize)
+        };
+        while let Some((&tag, rest)) = segments.split_first() {
+            match tag {
+                0 => {
+                    let n = field(rest, 0)?;
+                    data.extend_from_slice(rest.get(4..4 + n).ok_or(DbfsError::Io)?);
+                    segments = &rest[4 + n..];
+                }
+                1 => {
+                    let lsn = u64::from_be_bytes(
+                        rest.get(0..8).ok_or(DbfsError::Io)?.try_into().unwrap(),
+                    );
+                    let (at, n) = (field(rest, 8)?, field(rest, 12)?);
+                    // 引用可以指向本记录中更早的块
+                    let chunk = if lsn == self.lsn {
+                        data[head..].get(at..at + n).ok_or(DbfsError::Io)?.to_vec()
+                    } else {
+                        let source = write_data(earlier, lsn).ok_or(DbfsError::Io)?;
+                        source.get(at..at + n).ok_or(DbfsError::Io)?.to_vec()
+                    };
+                    data.extend_from_slice(&chunk);
+                    segments = &rest[16..];
+                }
+                _ => return Err(DbfsError::Io),
+            }
+        }
+        if data.len() != head + len {
+            return Err(DbfsError::Io);
+        }
+        Ok(Self {
+            record_type: WalRecordType::FileWrite,
+            checksum: Self::compute_checksum(&data),
+            data,
+            ..self
+        })
+    }
+
+    /// Decode an operation record, None for control records and malformed data
+    pub fn operation(&self) -> Option<WalOp<'_>> {
+        let path = || core::str::from_utf8(&self.data).ok();
+        match self.record_type {
+            WalRecordType::FileCreate => path().map(WalOp::Create),
+            WalRecordType::Mkdir => path().map(WalOp::Mkdir),
+            WalRecordType::FileDelete => path().map(WalOp::Delete),
+            WalRecordType::FileWrite | WalRecordType::FileWriteLz => {
+                // path len (2) + path + offset (8) + data len (4) + data
+                let (path, offset, rest) = path_offset(&self.data)?;
+                let len = u32::from_be_bytes(rest.get(0..4)?.try_into().ok()?) as usize;
+                let data = rest.get(4..4 + len)?;
+                Some(WalOp::Write { path, offset, data })
+            }
+            WalRecordType::FileClone => {
+                // src (path len + path + offset) + len (8) + dst (path len + path + offset)
+                let (src, src_offset, rest) = path_offset(&self.data)?;
+                let len = u64::from_be_bytes(rest.get(0..8)?.try_into().ok()?);
+                let (dst, dst_offset, _) = path_offset(&rest[8..])?;
+                Some(WalOp::Clone {
+                    src,
+                    src_offset,
+                    len,
+                    dst,
+                    dst_offset,
+                })
+            }
+            WalRecordType::Rename => {
+                // from len (2) + from + to
+                let from_len = u16::from_be_bytes(self.data.get(0..2)?.try_into().ok()?) as usize;
+                let from = core::str::from_utf8(self.data.get(2..2 + from_len)?).ok()?;
+                let to = core::str::from_utf8(&self.data[2 + from_len..]).ok()?;
+                Some(WalOp::Rename { from, to })
+            }
+            WalRecordType::SetFlags => {
+                // flags (4) + path
+                let flags = u32::from_be_bytes(self.data.get(0..4)?.try_into().ok()?);
+                let path = core::str::from_utf8(&self.data[4..]).ok()?;
+                Some(WalOp::SetFlags { path, flags })
+            }
+            _ => None,
+        }
+    }
+
+    /// Size of the serialized record
+    pub fn serialized_len(&self) -> usize {
+        RECORD_HEADER_SIZE + self.data.len() + 4
+    }
+
     /// Deserialize record from bytes
     pub fn deserialize(bytes: &[u8]) -> Result<Self, DbfsError> {
         if bytes.len() < 25 {
@@ -197,10 +685,21 @@ impl WalRecord {
             6 => WalRecordType::FileDelete,
             7 => WalRecordType::Mkdir,
             8 => WalRecordType::Checkpoint,
+            9 => WalRecordType::SavepointRollback,
+            10 => WalRecordType::FileClone,
+            11 => WalRecordType::Rename,
+            12 => WalRecordType::ChangeState,
+            13 => WalRecordType::SetFlags,
+            14 => WalRecordType::FileWriteLz,
+            15 => WalRecordType::FileWriteDedup,
             _ => return Err(DbfsError::InvalidArgument),
         };
 
         let data_len = u32::from_be_bytes(bytes[17..21].try_into().unwrap()) as usize;
+        if bytes.len() < 25 + data_len {
+            // Torn record
+            return Err(DbfsError::Io);
+        }
         let data = bytes[21..21 + data_len].to_vec();
         let checksum = u32::from_be_bytes(bytes[21 + data_len..25 + data_len].try_into().unwrap());
 
@@ -232,6 +731,27 @@ pub struct Wal {
     flushed_lsn: Lsn,
     /// Current transaction ID
     next_tx_id: u64,
+    /// Backing device (None: in-memory mode)
+    device: Option<Arc<dyn BlockDevice>>,
+    /// Next append position on the device
+    write_pos: u64,
+    /// Device offset of the first live record (see [`WalHeader::start_pos`])
+    start_pos: u64,
+    /// Appends have wrapped around to the front of the device and end
+    /// before `start_pos`
+    wrapped: bool,
+    /// Records up to this LSN are covered by the last checkpoint image
+    checkpoint_lsn: Lsn,
+    /// Active transactions: tx id -> LSN of TxBegin
+    active: BTreeMap<u64, Lsn>,
+    /// Bytes written by flushes since the WAL was opened
+    flushed_bytes: u64,
+    /// Encrypts record data on the device (None: stored in plain text)
+    cipher: Option<Arc<Cipher>>,
+    /// Nonce epoch of records sealed by this open (see [`WalHeader::epoch`])
+    epoch: u32,
+    /// Chunks of logged writes on a dedup mount (see [`Wal::set_dedup`])
+    dedup: Option<ChunkIndex>,
 }
 
 impl Wal {
@@ -243,36 +763,355 @@ impl Wal {
             next_lsn: 1,
             flushed_lsn: 0,
             next_tx_id: 1,
+            device: None,
+            write_pos: WAL_HEADER_SIZE as u64,
+            start_pos: WAL_HEADER_SIZE as u64,
+            wrapped: false,
+            checkpoint_lsn: 0,
+            active: BTreeMap::new(),
+            flushed_bytes: 0,
+            cipher: None,
+            epoch: 0,
+            dedup: None,
         })
     }
 
+    /// Open (or format) a WAL stored on a block device
+    ///
+    /// Existing records are loaded back into memory so that [`Wal::recover`]
+    /// sees everything that survived on the device. A torn tail record stops
+    /// the scan and is overwritten by the next append.
+    pub fn with_device(path: String, device: Arc<dyn BlockDevice>) -> Result<Self, DbfsError> {
+        Self::open_device(path, device, None, true)
+    }
+
+    /// Open a WAL that must already exist on the device, without writing to it
+    ///
+    /// Used by read-only mounts: a device without a WAL header is rejected
+    /// instead of being formatted.
+    pub fn open_existing(path: String, device: Arc<dyn BlockDevice>) -> Result<Self, DbfsError> {
+        Self::open_device(path, device, None, false)
+    }
+
+    /// Open a WAL on a block device, optionally encrypted
+    ///
+    /// With `format`, a device without a WAL header is formatted; otherwise it
+    /// is rejected (see [`Wal::open_existing`]).
+    ///
+    /// The key has to match the WAL: opening an encrypted WAL with another
+    /// key or without one fails with [`DbfsError::AccessError`]. A cipher
+    /// turns an empty plain WAL into an encrypted one; a plain WAL that
+    /// already holds records is rejected with [`DbfsError::InvalidArgument`].
+    pub fn open_device(
+        path: String,
+        device: Arc<dyn BlockDevice>,
+        cipher: Option<Arc<Cipher>>,
+        format: bool,
+    ) -> Result<Self, DbfsError> {
+        let mut wal = Self::new(path)?;
+
+        let mut head = [0u8; WAL_HEADER_SIZE];
+        device.read_at(0, &mut head)?;
+        match WalHeader::from_bytes(&head) {
+            Some(header) => {
+                match (&header.key_check, &cipher) {
+                    (Some(check), Some(cipher)) if *check == cipher.key_check() => {
+                        wal.cipher = Some(cipher.clone());
+                    }
+                    (Some(_), _) => {
+                        log::error!("✗ DBFS: Wrong or missing key for encrypted WAL {}", wal.path);
+                        return Err(DbfsError::AccessError);
+                    }
+                    (None, _) => {}
+                }
+                wal.load(device.as_ref(), &header)?;
+
+                if wal.cipher.is_none() && cipher.is_some() {
+                    if !format || wal.write_pos != wal.start_pos {
+                        log::error!("✗ DBFS: WAL {} already holds unencrypted records", wal.path);
+                        return Err(DbfsError::InvalidArgument);
+                    }
+                    log::info!("✓ DBFS: Enabling encryption on empty WAL {}", wal.path);
+                    wal.cipher = cipher;
+                }
+                if wal.cipher.is_some() && format {
+                    wal.begin_epoch(device.as_ref(), header)?;
+                }
+            }
+            None if format => {
+                log::info!("✓ DBFS: Formatting new WAL at {}", wal.path);
+                wal.cipher = cipher;
+                wal.begin_epoch(device.as_ref(), WalHeader::default())?;
+            }
+            None => return Err(DbfsError::InvalidArgument),
+        }
+
+        wal.device = Some(device);
+        Ok(wal)
+    }
+
+    /// Start a new nonce epoch before anything is appended and write the
+    /// header; plain WALs only get their header written
+    ///
+    /// Records after a torn tail reuse its LSNs, the epoch keeps their
+    /// nonces apart (see [`WalHeader::epoch`]).
+    fn begin_epoch(
+        &mut self,
+        device: &dyn BlockDevice,
+        header: WalHeader,
+    ) -> Result<(), DbfsError> {
+        if self.cipher.is_some() {
+            self.epoch = header.epoch + 1;
+            if self.epoch > MAX_EPOCH {
+                log::error!("✗ DBFS: WAL {} has run out of nonce epochs", self.path);
+                return Err(DbfsError::NoSpace);
+            }
+        }
+        let header = WalHeader {
+            key_check: self.key_check(),
+            epoch: self.epoch,
+            ..header
+        };
+        device.write_at(0, &header.to_bytes())?;
+        device.flush()?;
+        Ok(())
+    }
+
+    fn key_check(&self) -> Option<[u8; TAG_LEN]> {
+        self.cipher.as_ref().map(|cipher| cipher.key_check())
+    }
+
+    /// Whether records are encrypted on the device
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Store chunks that an earlier write already logged as references to
+    /// it (see [`WalRecord::deduplicated`])
+    ///
+    /// Turning it on indexes the records loaded from the device. Records
+    /// stored this way are read back whether it is on or not.
+    pub fn set_dedup(&mut self, enabled: bool) {
+        self.dedup = enabled.then(|| {
+            let mut index = ChunkIndex::default();
+            self.buffer.iter().for_each(|record| index.add_record(record));
+            index
+        });
+    }
+
+    /// Whether writes are deduplicated on the device
+    pub fn is_dedup(&self) -> bool {
+        self.dedup.is_some()
+    }
+
+    /// Scan the records stored on the device
+    ///
+    /// Starts at the header's start_pos and follows consecutive LSNs, wrapping
+    /// to the front of the device once when the records run out.
+    fn load(&mut self, device: &dyn BlockDevice, header: &WalHeader) -> Result<(), DbfsError> {
+        let front = WAL_HEADER_SIZE as u64;
+        let (start, mut last_lsn) = if header.is_circular() {
+            (header.start_pos.max(front), header.checkpoint_lsn)
+        } else {
+            (front, 0)
+        };
+        let mut pos = start;
+        let mut wrapped = false;
+
+        loop {
+            let end = if wrapped { start } else { device.size() };
+            let found = match self.read_record(device, pos, last_lsn + 1, end)? {
+                Some(found) => Some(found),
+                None if !wrapped && start > front => {
+                    let found = self.read_record(device, front, last_lsn + 1, start)?;
+                    if found.is_some() {
+                        wrapped = true;
+                        pos = front;
+                    }
+                    found
+                }
+                None => None,
+            };
+            let Some((record, total)) = found else {
+                break;
+            };
+
+            last_lsn = record.lsn;
+            pos += total;
+            let tx = record.tx_id.value();
+            if tx >= self.next_tx_id {
+                self.next_tx_id = tx + 1;
+            }
+            if record.lsn > header.checkpoint_lsn {
+                self.buffer.push(record);
+            }
+        }
+
+        if header.last_tx_id > self.next_tx_id {
+            self.next_tx_id = header.last_tx_id;
+        }
+        self.next_lsn = last_lsn + 1;
+        self.flushed_lsn = last_lsn;
+        self.checkpoint_lsn = header.checkpoint_lsn;
+        self.start_pos = start;
+        self.wrapped = wrapped;
+        self.write_pos = pos;
+
+        log::info!(
+            "✓ DBFS: Loaded WAL {}: {} live records, checkpoint LSN {}, next LSN {}",
+            self.path,
+            self.buffer.len(),
+            self.checkpoint_lsn,
+            self.next_lsn
+        );
+        Ok(())
+    }
+
+    /// Read the record with LSN `lsn` at `pos`, which has to end before `end`
+    ///
+    /// Returns None at the end of the log: stale data from an earlier pass,
+    /// a zeroed area or a torn record.
+    fn read_record(
+        &self,
+        device: &dyn BlockDevice,
+        pos: u64,
+        lsn: Lsn,
+        end: u64,
+    ) -> Result<Option<(WalRecord, u64)>, DbfsError> {
+        let mut head = [0u8; RECORD_HEADER_SIZE];
+        if pos + RECORD_HEADER_SIZE as u64 > end
+            || device.read_at(pos, &mut head)? < RECORD_HEADER_SIZE
+        {
+            return Ok(None);
+        }
+        if u64::from_be_bytes(head[0..8].try_into().unwrap()) != lsn {
+            return Ok(None);
+        }
+        let data_len = u32::from_be_bytes(head[17..21].try_into().unwrap()) as u64;
+        let total = RECORD_HEADER_SIZE as u64 + data_len + 4;
+        if pos + total > end {
+            return Ok(None);
+        }
+        let mut bytes = vec![0u8; total as usize];
+        device.read_at(pos, &mut bytes)?;
+        let record = match WalRecord::deserialize(&bytes) {
+            Ok(record) => record,
+            Err(_) => {
+                log::warn!("⚠ DBFS: Torn WAL record at LSN {} (offset {})", lsn, pos);
+                return Ok(None);
+            }
+        };
+        // 校验和正确而认证失败: 记录被篡改, 不能当作撕裂的尾部忽略
+        let record = match self.cipher.as_ref() {
+            Some(cipher) => record.opened(cipher).map_err(|e| {
+                log::error!("✗ DBFS: WAL record at LSN {} failed authentication", lsn);
+                e
+            })?,
+            None => record,
+        };
+        // 校验和正确而解压失败同样是损坏, 不是撕裂的尾部
+        let record = record.unpacked().map_err(|e| {
+            log::error!("✗ DBFS: Corrupt compressed WAL record at LSN {}", lsn);
+            e
+        })?;
+        let record = record.resolved(&self.buffer).map_err(|e| {
+            log::error!("✗ DBFS: Unresolved chunk reference in WAL record at LSN {}", lsn);
+            e
+        })?;
+        Ok(Some((record, total)))
+    }
+
     /// Begin a new transaction
     pub fn begin_tx(&mut self) -> TxId {
         let tx_id = TxId::new(self.next_tx_id);
         self.next_tx_id += 1;
 
         let record = WalRecord::new(tx_id, WalRecordType::TxBegin, Vec::new());
-        self.append_record(record);
+        let lsn = self.append_record(record);
+        self.active.insert(tx_id.value(), lsn);
 
         tx_id
     }
 
+    /// Begin a transaction whose ID was allocated by the caller
+    ///
+    /// Used when transaction IDs are global across several WALs.
+    pub fn begin_tx_with_id(&mut self, tx_id: TxId) -> Lsn {
+        if tx_id.value() >= self.next_tx_id {
+            self.next_tx_id = tx_id.value() + 1;
+        }
+        let record = WalRecord::new(tx_id, WalRecordType::TxBegin, Vec::new());
+        let lsn = self.append_record(record);
+        self.active.insert(tx_id.value(), lsn);
+        lsn
+    }
+
+    /// Current savepoint position: the LSN of the last record appended
+    ///
+    /// Pass it to [`Wal::rollback_to_savepoint`] to discard everything the
+    /// transaction logged after this point.
+    pub fn savepoint(&self) -> Lsn {
+        self.next_lsn - 1
+    }
+
+    /// Discard the operations `tx_id` logged after `savepoint`
+    ///
+    /// Records already written cannot be removed, so a
+    /// [`WalRecordType::SavepointRollback`] record is appended instead and
+    /// recovery drops the transaction's operations between the two.
+    pub fn rollback_to_savepoint(&mut self, tx_id: TxId, savepoint: Lsn) {
+        let record = WalRecord::new(
+            tx_id,
+            WalRecordType::SavepointRollback,
+            savepoint.to_be_bytes().to_vec(),
+        );
+        self.append_record(record);
+    }
+
     /// Commit a transaction
     pub fn commit_tx(&mut self, tx_id: TxId) -> Result<(), DbfsError> {
-        let record = WalRecord::new(tx_id, WalRecordType::TxCommit, Vec::new());
-        self.append_record(record);
+        self.log_commit(tx_id);
         self.flush()?;
         Ok(())
     }
 
+    /// Append the commit record of a transaction without flushing it
+    ///
+    /// The commit becomes durable with the next [`Wal::flush`]; used for
+    /// group and asynchronous commit.
+    pub fn log_commit(&mut self, tx_id: TxId) -> Lsn {
+        let record = WalRecord::new(tx_id, WalRecordType::TxCommit, Vec::new());
+        let lsn = self.append_record(record);
+        self.active.remove(&tx_id.value());
+        lsn
+    }
+
     /// Rollback a transaction
     pub fn rollback_tx(&mut self, tx_id: TxId) {
         let record = WalRecord::new(tx_id, WalRecordType::TxRollback, Vec::new());
         self.append_record(record);
+        self.active.remove(&tx_id.value());
     }
 
     /// Write a file operation
-    pub fn write_file(&mut self, tx_id: TxId, path: &str, offset: u64, data: &[u8]) {
+    pub fn write_file(&mut self, tx_id: TxId, path: &str, offset: u64, data: &[u8]) -> Lsn {
+        self.log_write(tx_id, WalRecordType::FileWrite, path, offset, data)
+    }
+
+    /// Write operation of a compressed file: the data is stored LZ4-compressed
+    /// on the device (see [`WalRecord::packed`])
+    pub fn write_file_lz(&mut self, tx_id: TxId, path: &str, offset: u64, data: &[u8]) -> Lsn {
+        self.log_write(tx_id, WalRecordType::FileWriteLz, path, offset, data)
+    }
+
+    fn log_write(
+        &mut self,
+        tx_id: TxId,
+        record_type: WalRecordType,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Lsn {
         let mut record_data = Vec::new();
 
         // Path length (2 bytes) + path
@@ -287,144 +1126,337 @@ impl Wal {
         record_data.extend_from_slice(&(data.len() as u32).to_be_bytes());
         record_data.extend_from_slice(data);
 
-        let record = WalRecord::new(tx_id, WalRecordType::FileWrite, record_data);
-        self.append_record(record);
+        let record = WalRecord::new(tx_id, record_type, record_data);
+        self.append_record(record)
     }
 
     /// Create file operation
-    pub fn create_file(&mut self, tx_id: TxId, path: &str) {
+    pub fn create_file(&mut self, tx_id: TxId, path: &str) -> Lsn {
         let record_data = path.as_bytes().to_vec();
         let record = WalRecord::new(tx_id, WalRecordType::FileCreate, record_data);
-        self.append_record(record);
+        self.append_record(record)
     }
 
     /// Delete file operation
-    pub fn delete_file(&mut self, tx_id: TxId, path: &str) {
+    pub fn delete_file(&mut self, tx_id: TxId, path: &str) -> Lsn {
         let record_data = path.as_bytes().to_vec();
         let record = WalRecord::new(tx_id, WalRecordType::FileDelete, record_data);
-        self.append_record(record);
+        self.append_record(record)
+    }
+
+    /// Clone file range operation
+    ///
+    /// Only the ranges are logged, not the data: the destination shares the
+    /// source extents until one of them is written.
+    pub fn clone_file(
+        &mut self,
+        tx_id: TxId,
+        src: &str,
+        src_offset: u64,
+        len: u64,
+        dst: &str,
+        dst_offset: u64,
+    ) -> Lsn {
+        let mut record_data = Vec::new();
+
+        // Source: path length (2 bytes) + path + offset (8 bytes)
+        record_data.extend_from_slice(&(src.len() as u16).to_be_bytes());
+        record_data.extend_from_slice(src.as_bytes());
+        record_data.extend_from_slice(&src_offset.to_be_bytes());
+
+        // Length (8 bytes)
+        record_data.extend_from_slice(&len.to_be_bytes());
+
+        // Destination: path length (2 bytes) + path + offset (8 bytes)
+        record_data.extend_from_slice(&(dst.len() as u16).to_be_bytes());
+        record_data.extend_from_slice(dst.as_bytes());
+        record_data.extend_from_slice(&dst_offset.to_be_bytes());
+
+        let record = WalRecord::new(tx_id, WalRecordType::FileClone, record_data);
+        self.append_record(record)
+    }
+
+    /// Rename operation
+    ///
+    /// `to` is replaced if it exists; entries below a renamed directory move
+    /// with it.
+    pub fn rename(&mut self, tx_id: TxId, from: &str, to: &str) -> Lsn {
+        let mut record_data = Vec::new();
+
+        // From: path length (2 bytes) + path, then the destination path
+        record_data.extend_from_slice(&(from.len() as u16).to_be_bytes());
+        record_data.extend_from_slice(from.as_bytes());
+        record_data.extend_from_slice(to.as_bytes());
+
+        let record = WalRecord::new(tx_id, WalRecordType::Rename, record_data);
+        self.append_record(record)
+    }
+
+    /// Set inode flags (FS_IOC_SETFLAGS)
+    pub fn set_flags(&mut self, tx_id: TxId, path: &str, flags: u32) -> Lsn {
+        // Flags (4 bytes) + path
+        let mut record_data = flags.to_be_bytes().to_vec();
+        record_data.extend_from_slice(path.as_bytes());
+        let record = WalRecord::new(tx_id, WalRecordType::SetFlags, record_data);
+        self.append_record(record)
     }
 
     /// Create directory operation
-    pub fn mkdir(&mut self, tx_id: TxId, path: &str) {
+    pub fn mkdir(&mut self, tx_id: TxId, path: &str) -> Lsn {
         let record_data = path.as_bytes().to_vec();
         let record = WalRecord::new(tx_id, WalRecordType::Mkdir, record_data);
-        self.append_record(record);
+        self.append_record(record)
+    }
+
+    /// Log an operation whose data is already encoded, e.g. a record read
+    /// back by [`Wal::recover`] that goes into a checkpoint image
+    pub fn log_operation(&mut self, tx_id: TxId, record_type: WalRecordType, data: Vec<u8>) -> Lsn {
+        self.append_record(WalRecord::new(tx_id, record_type, data))
     }
 
     /// Append a record to the WAL
-    fn append_record(&mut self, mut record: WalRecord) {
-        record.lsn = self.next_lsn;
+    fn append_record(&mut self, mut record: WalRecord) -> Lsn {
+        let lsn = self.next_lsn;
+        record.lsn = lsn;
         self.next_lsn += 1;
         self.buffer.push(record);
+        lsn
     }
 
     /// Flush WAL to disk
+    ///
+    /// Failpoints: [`FP_WAL_BEFORE_WRITE`] guards the record write and
+    /// [`FP_WAL_AFTER_WRITE`] guards the flush barrier that follows it.
+    /// Returns [`DbfsError::NoSpace`] when the new records do not fit in
+    /// the space reclaimed by checkpoints.
     pub fn flush(&mut self) -> Result<(), DbfsError> {
-        if !self.buffer.is_empty() {
-            // Phase 3: Persistent WAL - Write to actual disk file
+        self.flush_batch().map(|_| ())
+    }
 
-            // Find records that need to be flushed (those after flushed_lsn)
-            let records_to_flush: Vec<_> = self.buffer
+    /// Flush WAL to disk, returning the device offset the records were
+    /// written at (None in in-memory mode or when nothing was pending)
+    fn flush_batch(&mut self) -> Result<Option<u64>, DbfsError> {
+        let (wal_data, count, last_lsn) = {
+            let records_to_flush: Vec<_> = self
+                .buffer
                 .iter()
                 .filter(|r| r.lsn > self.flushed_lsn)
                 .collect();
+            if records_to_flush.is_empty() {
+                return Ok(None);
+            }
+            // 上一次失败的刷写加入的块不在设备上
+            if let Some(index) = self.dedup.as_mut() {
+                index.forget_after(self.flushed_lsn);
+            }
 
-            if !records_to_flush.is_empty() {
-                // Serialize all new records
-                let mut wal_data = Vec::new();
-
-                // Write WAL header
-                let header = WalHeader {
-                    magic: *WAL_MAGIC,
-                    version: 1,
-                    last_tx_id: self.next_tx_id,
-                    checkpoint_lsn: self.flushed_lsn,
-                    _reserved: [0; 492],
-                };
-
-                // Serialize header (512 bytes)
-                wal_data.extend_from_slice(unsafe {
-                    core::slice::from_raw_parts(
-                        &header as *const _ as *const u8,
-                        core::mem::size_of::<WalHeader>()
-                    )
-                });
-
-                // Serialize each record
-                for record in &records_to_flush {
-                    wal_data.extend_from_slice(&record.serialize());
+            // Serialize all new records
+            let mut wal_data = Vec::new();
+            for record in &records_to_flush {
+                let mut record = record.packed();
+                if let Some(index) = self.dedup.as_mut() {
+                    record = record.deduplicated(index, &self.buffer);
+                }
+                match self.cipher.as_ref() {
+                    Some(cipher) => {
+                        let sealed = record.sealed(cipher, self.epoch);
+                        wal_data.extend_from_slice(&sealed.serialize())
+                    }
+                    None => wal_data.extend_from_slice(&record.serialize()),
                 }
+            }
+            (
+                wal_data,
+                records_to_flush.len(),
+                records_to_flush.last().unwrap().lsn,
+            )
+        };
 
-                // TODO: Write to actual file system
-                // For now, we'll store in memory and mark as flushed
-                // In a real implementation, we would:
-                // 1. Open/create WAL file at self.path
-                // 2. Seek to end (append mode)
-                // 3. Write wal_data
-                // 4. Call fsync to ensure durability
-                // 5. Update flushed_lsn
+        let written_at = match self.device.clone() {
+            Some(device) => {
+                let len = wal_data.len() as u64;
+                let pos = self.place(len, device.size()).ok_or_else(|| {
+                    log::error!("✗ DBFS: WAL device {} is full", self.path);
+                    DbfsError::NoSpace
+                })?;
+                failpoint::write_at(FP_WAL_BEFORE_WRITE, device.as_ref(), pos, &wal_data)?;
+                failpoint::flush(FP_WAL_AFTER_WRITE, device.as_ref())?;
+                if pos < self.write_pos {
+                    log::info!("✓ DBFS: WAL {} wrapped around", self.path);
+                    self.wrapped = true;
+                }
+                self.write_pos = pos + len;
 
+                log::info!("✓ DBFS: WAL flush: {} records, {} bytes",
+                          count, wal_data.len());
+                Some(pos)
+            }
+            None => {
                 log::info!("✓ DBFS: WAL flush: {} records, {} bytes (in-memory mode)",
-                          records_to_flush.len(), wal_data.len());
+                          count, wal_data.len());
+                None
             }
+        };
+
+        // Update flushed_lsn
+        self.flushed_lsn = last_lsn;
+        self.flushed_bytes += wal_data.len() as u64;
+        Ok(written_at)
+    }
 
-            // Update flushed_lsn
-            self.flushed_lsn = self.buffer.last().unwrap().lsn;
+    /// Device offset where `len` bytes can be appended without overwriting
+    /// live records
+    fn place(&self, len: u64, size: u64) -> Option<u64> {
+        if self.wrapped {
+            return (self.write_pos + len <= self.start_pos).then_some(self.write_pos);
         }
-        Ok(())
+        if self.write_pos + len <= size {
+            return Some(self.write_pos);
+        }
+        let front = WAL_HEADER_SIZE as u64;
+        (front + len <= self.start_pos).then_some(front)
+    }
+
+    /// Write a checkpoint
+    ///
+    /// `image` logs the complete current state of the file system (through
+    /// the normal operation methods) into the transaction it is given. The
+    /// image is flushed as one committed transaction first; only then the
+    /// header is moved to its first record, so recovery replays the image
+    /// instead of the records before it and their space is reclaimed.
+    ///
+    /// The image cannot describe work in progress, so the checkpoint is
+    /// refused with [`DbfsError::Busy`] while transactions are active. When
+    /// the image cannot be written, its records are dropped again so that a
+    /// later flush does not write them without the header update.
+    /// Failpoint [`FP_WAL_CHECKPOINT`] sits between the image and the header
+    /// update.
+    pub fn checkpoint(
+        &mut self,
+        image: impl FnOnce(&mut Self, TxId) -> Result<(), DbfsError>,
+    ) -> Result<Lsn, DbfsError> {
+        if !self.active.is_empty() {
+            return Err(DbfsError::Busy);
+        }
+        self.flush()?;
+        // 镜像之前的记录在下次挂载时不再读取, 镜像不能引用它们
+        if let Some(index) = self.dedup.as_mut() {
+            *index = ChunkIndex::default();
+        }
+
+        let first_lsn = self.next_lsn;
+        let tx_id = self.begin_tx();
+        let checkpoint_lsn = self.next_lsn - 2;
+        if let Err(e) = image(self, tx_id) {
+            self.rollback_tx(tx_id);
+            return Err(e);
+        }
+        self.log_commit(tx_id);
+        let record = WalRecord::new(
+            TxId::new(0),
+            WalRecordType::Checkpoint,
+            checkpoint_lsn.to_be_bytes().to_vec(),
+        );
+        self.append_record(record);
+        let start_pos = match self.flush_batch() {
+            Ok(start_pos) => start_pos,
+            Err(e) => {
+                self.discard_from(first_lsn);
+                return Err(e);
+            }
+        };
+
+        failpoint::check(FP_WAL_CHECKPOINT)?;
+
+        if let (Some(device), Some(start_pos)) = (self.device.as_ref(), start_pos) {
+            let header = WalHeader {
+                last_tx_id: self.next_tx_id,
+                checkpoint_lsn,
+                key_check: self.key_check(),
+                start_pos,
+                epoch: self.epoch,
+                ..WalHeader::default()
+            };
+            device.write_at(0, &header.to_bytes())?;
+            device.flush()?;
+            self.start_pos = start_pos;
+            self.wrapped = false;
+        }
+
+        // 镜像已经反映在内存中的文件系统里, 不必再留在缓冲区
+        self.checkpoint_lsn = checkpoint_lsn;
+        self.truncate(self.next_lsn);
+        if let Some(index) = self.dedup.as_mut() {
+            *index = ChunkIndex::default();
+        }
+        log::info!("✓ DBFS: Checkpoint at LSN {}", checkpoint_lsn);
+        Ok(checkpoint_lsn)
     }
 
     /// Recover transactions from WAL
+    ///
+    /// Failpoint [`FP_WAL_REPLAY`] is evaluated once per replayed record.
+    /// Recovery does not modify the log, so a crash during replay can simply
+    /// be followed by another recovery.
     pub fn recover(&self) -> Result<RecoveryResult, DbfsError> {
         let mut committed = Vec::new();
         let mut uncommitted = Vec::new();
-        let mut current_tx = None;
-
-        // Phase 3: Persistent WAL recovery
-        // In a real implementation, we would:
-        // 1. Check if WAL file exists at self.path
-        // 2. Read WAL header (512 bytes)
-        // 3. Validate magic number
-        // 4. Read all records from file
-        // 5. Deserialize and validate checksums
-        // 6. Identify committed vs uncommitted transactions
-        // 7. Return recovery result for replay
-
-        // For now, use in-memory buffer
-        log::info!("✓ DBFS: WAL recovery from {} in-memory records",
-                  self.buffer.len());
+        // tx id -> committed?
+        let mut states: BTreeMap<u64, bool> = BTreeMap::new();
+        // first-seen order of transactions
+        let mut order = Vec::new();
+
+        log::info!("✓ DBFS: WAL recovery from {} records", self.buffer.len());
 
         for record in &self.buffer {
-            match record.record_type {
-                WalRecordType::TxBegin => {
-                    current_tx = Some(record.tx_id);
-                }
-                WalRecordType::TxCommit => {
-                    if let Some(tx_id) = current_tx {
-                        if tx_id == record.tx_id {
-                            committed.push(tx_id);
-                            current_tx = None;
-                        }
-                    }
-                }
-                WalRecordType::TxRollback => {
-                    if let Some(tx_id) = current_tx {
-                        if tx_id == record.tx_id {
-                            current_tx = None;
-                        }
-                    }
-                }
-                _ => {
-                    // Operation records - ignore for recovery state
-                }
+            failpoint::check(FP_WAL_REPLAY)?;
+
+            if record.record_type == WalRecordType::Checkpoint {
+                continue;
+            }
+            let tx = record.tx_id.value();
+            if !states.contains_key(&tx) {
+                states.insert(tx, false);
+                order.push(record.tx_id);
+            }
+            if record.record_type == WalRecordType::TxCommit {
+                states.insert(tx, true);
+                committed.push(record.tx_id);
             }
         }
 
-        // Any transaction that began but didn't commit/rollback is uncommitted
-        if let Some(tx_id) = current_tx {
-            uncommitted.push(tx_id);
+        // Any transaction that did not commit is uncommitted (rolled back or
+        // interrupted by the crash)
+        for tx_id in order {
+            if states.get(&tx_id.value()) == Some(&false) {
+                uncommitted.push(tx_id);
+            }
+        }
+
+        // Redo set: operations of committed transactions, in LSN order.
+        // A savepoint rollback discards the operations the transaction
+        // logged after the savepoint.
+        let mut ops: BTreeMap<u64, Vec<&WalRecord>> = BTreeMap::new();
+        for record in &self.buffer {
+            let tx = record.tx_id.value();
+            if states.get(&tx) != Some(&true) {
+                continue;
+            }
+            if record.record_type == WalRecordType::SavepointRollback {
+                let savepoint = record
+                    .data
+                    .get(0..8)
+                    .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+                    .ok_or(DbfsError::Io)?;
+                if let Some(tx_ops) = ops.get_mut(&tx) {
+                    tx_ops.retain(|r| r.lsn <= savepoint);
+                }
+            } else if record.record_type.is_operation() {
+                ops.entry(tx).or_default().push(record);
+            }
         }
+        let mut redo: Vec<WalRecord> = ops.into_values().flatten().cloned().collect();
+        redo.sort_by_key(|r| r.lsn);
 
         log::info!("✓ DBFS: Recovery complete: {} committed, {} uncommitted",
                   committed.len(), uncommitted.len());
@@ -432,6 +1464,7 @@ impl Wal {
         Ok(RecoveryResult {
             committed,
             uncommitted,
+            redo,
         })
     }
 
@@ -443,6 +1476,42 @@ impl Wal {
             .collect()
     }
 
+    /// Records still held in memory (since the last checkpoint), in LSN order
+    pub fn records(&self) -> &[WalRecord] {
+        &self.buffer
+    }
+
+    /// Drop the records from `lsn` on, which have not been written yet
+    ///
+    /// Used when flushing them failed and the caller gives them up, so that a
+    /// later flush does not write them. Nothing else may have been appended
+    /// after them.
+    pub fn discard_from(&mut self, lsn: Lsn) {
+        debug_assert!(lsn > self.flushed_lsn);
+        self.buffer.retain(|r| r.lsn < lsn);
+        self.next_lsn = lsn;
+    }
+
+    /// Drop the records of `tx_id` that have not been written yet
+    ///
+    /// Used when flushing the commit of `tx_id` failed: a later flush must
+    /// neither make the transaction durable nor fail again on its records.
+    /// Records already written stay and are treated as uncommitted. LSNs on
+    /// the device have to be consecutive, so the dropped records are replaced
+    /// by empty [`WalRecordType::TxRollback`] records instead of removed.
+    pub fn discard_tx(&mut self, tx_id: TxId) {
+        let flushed_lsn = self.flushed_lsn;
+        for record in self.buffer.iter_mut() {
+            if record.lsn > flushed_lsn && record.tx_id == tx_id {
+                *record = WalRecord {
+                    lsn: record.lsn,
+                    ..WalRecord::new(tx_id, WalRecordType::TxRollback, Vec::new())
+                };
+            }
+        }
+        self.active.remove(&tx_id.value());
+    }
+
     /// Truncate WAL (remove old records)
     pub fn truncate(&mut self, lsn: Lsn) {
         self.buffer.retain(|r| r.lsn >= lsn);
@@ -453,10 +1522,62 @@ impl Wal {
         self.next_tx_id
     }
 
+    /// Make sure transaction IDs allocated by the WAL itself (checkpoint
+    /// images) are at least `next`
+    pub fn reserve_tx_ids(&mut self, next: u64) {
+        self.next_tx_id = self.next_tx_id.max(next);
+    }
+
     /// Get flushed LSN
     pub fn flushed_lsn(&self) -> Lsn {
         self.flushed_lsn
     }
+
+    /// Get checkpoint LSN
+    pub fn checkpoint_lsn(&self) -> Lsn {
+        self.checkpoint_lsn
+    }
+
+    /// Snapshot of the WAL counters
+    pub fn stats(&self) -> WalStats {
+        WalStats {
+            next_tx_id: self.next_tx_id,
+            next_lsn: self.next_lsn,
+            flushed_lsn: self.flushed_lsn,
+            checkpoint_lsn: self.checkpoint_lsn,
+            buffered_records: self.buffer.len(),
+            flushed_bytes: self.flushed_bytes,
+            device_bytes: self.device.as_ref().map(|_| self.write_pos),
+        }
+    }
+
+    /// Backing device usage: (device size, bytes held by live records),
+    /// None in in-memory mode
+    pub fn device_usage(&self) -> Option<(u64, u64)> {
+        self.device.as_ref().map(|device| {
+            let used = if self.wrapped {
+                device.size() - self.start_pos + self.write_pos - WAL_HEADER_SIZE as u64
+            } else {
+                self.write_pos - self.start_pos
+            };
+            (device.size(), used)
+        })
+    }
+}
+
+/// WAL counters (see [`Wal::stats`])
+#[derive(Debug, Clone, Copy)]
+pub struct WalStats {
+    pub next_tx_id: u64,
+    pub next_lsn: Lsn,
+    pub flushed_lsn: Lsn,
+    pub checkpoint_lsn: Lsn,
+    /// Records still held in memory (not yet truncated by a checkpoint)
+    pub buffered_records: usize,
+    /// Bytes written by flushes since the WAL was opened
+    pub flushed_bytes: u64,
+    /// End of the log on the backing device, None in in-memory mode
+    pub device_bytes: Option<u64>,
 }
 
 /// WAL Recovery Result
@@ -465,6 +1586,8 @@ pub struct RecoveryResult {
     pub committed: Vec<TxId>,
     /// Uncommitted transaction IDs (need rollback)
     pub uncommitted: Vec<TxId>,
+    /// Operation records of committed transactions, in LSN order
+    pub redo: Vec<WalRecord>,
 }
 
 #[cfg(test)]
@@ -507,4 +1630,358 @@ mod tests {
         assert_eq!(result.committed.len(), 2);
         assert_eq!(result.uncommitted.len(), 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_wal_savepoint_rollback() {
+        let mut wal = Wal::new("/test/wal".to_string()).unwrap();
+
+        let tx = wal.begin_tx();
+        wal.write_file(tx, "/a", 0, b"kept");
+        let outer = wal.savepoint();
+        wal.write_file(tx, "/a", 4, b"undone");
+        let inner = wal.savepoint();
+        wal.delete_file(tx, "/a");
+        wal.rollback_to_savepoint(tx, inner);
+        wal.mkdir(tx, "/d");
+        wal.rollback_to_savepoint(tx, outer);
+        wal.create_file(tx, "/b");
+        wal.commit_tx(tx).unwrap();
+
+        let result = wal.recover().unwrap();
+        let kinds: Vec<_> = result.redo.iter().map(|r| r.record_type).collect();
+        assert_eq!(kinds, vec![WalRecordType::FileWrite, WalRecordType::FileCreate]);
+    }
+
+    #[test]
+    fn test_wal_device_remount() {
+        use crate::log_manager::MemBlockDevice;
+
+        let device = Arc::new(MemBlockDevice::new(64 * 1024));
+        {
+            let mut wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+            let tx1 = wal.begin_tx();
+            wal.write_file(tx1, "/a", 0, b"hello");
+            wal.commit_tx(tx1).unwrap();
+            let tx2 = wal.begin_tx();
+            wal.write_file(tx2, "/b", 0, b"lost");
+        }
+        device.crash();
+
+        let wal = Wal::with_device("/test/wal".to_string(), device).unwrap();
+        let result = wal.recover().unwrap();
+        assert_eq!(result.committed.len(), 1);
+        assert_eq!(result.redo.len(), 1);
+        assert_eq!(wal.next_tx_id(), 2);
+    }
+
+    #[test]
+    fn test_wal_open_existing() {
+        use crate::log_manager::MemBlockDevice;
+
+        let device = Arc::new(MemBlockDevice::new(64 * 1024));
+        assert!(Wal::open_existing("/test/wal".to_string(), device.clone()).is_err());
+        {
+            let mut wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+            let tx = wal.begin_tx();
+            wal.create_file(tx, "/a");
+            wal.log_commit(tx);
+            wal.flush().unwrap();
+        }
+
+        let wal = Wal::open_existing("/test/wal".to_string(), device).unwrap();
+        assert_eq!(wal.recover().unwrap().committed.len(), 1);
+    }
+
+    #[test]
+    fn test_wal_compressed_write() {
+        use crate::log_manager::MemBlockDevice;
+
+        let device = Arc::new(MemBlockDevice::new(64 * 1024));
+        let text = b"compressible ".repeat(300);
+        {
+            let mut wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+            let tx = wal.begin_tx();
+            wal.write_file_lz(tx, "/z", 0, &text);
+            wal.write_file_lz(tx, "/z", 8, b"tiny");
+            wal.commit_tx(tx).unwrap();
+            assert!(wal.stats().device_bytes.unwrap() < (WAL_HEADER_SIZE + text.len() / 2) as u64);
+        }
+
+        let wal = Wal::with_device("/test/wal".to_string(), device).unwrap();
+        let redo = wal.recover().unwrap().redo;
+        assert_eq!(redo.len(), 2);
+        match redo[0].operation() {
+            Some(WalOp::Write { path, offset, data }) => {
+                assert_eq!((path, offset, data), ("/z", 0, &text[..]));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(redo[1].data.ends_with(b"tiny"));
+    }
+
+    #[test]
+    fn test_wal_dedup() {
+        use crate::log_manager::MemBlockDevice;
+
+        let mut x = 5u32;
+        let data: Vec<u8> = (0..3 * EXTENT_SIZE)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect();
+        let open = |device: &Arc<MemBlockDevice>| {
+            let mut wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+            wal.set_dedup(true);
+            wal
+        };
+        let device = Arc::new(MemBlockDevice::new(256 * 1024));
+        {
+            let mut wal = open(&device);
+            let tx = wal.begin_tx();
+            wal.write_file(tx, "/a", 0, &data);
+            wal.commit_tx(tx).unwrap();
+            let used = wal.stats().device_bytes.unwrap();
+
+            // 相同内容的另一个文件只记录引用, 改动的块除外
+            let tx = wal.begin_tx();
+            wal.write_file(tx, "/b", 0, &data[..EXTENT_SIZE]);
+            wal.write_file(tx, "/b", EXTENT_SIZE as u64, &data[EXTENT_SIZE..]);
+            wal.write_file(tx, "/b", 10, b"edit");
+            wal.commit_tx(tx).unwrap();
+            assert!(wal.stats().device_bytes.unwrap() - used < 1024);
+        }
+
+        // 重新挂载后引用读回为完整的数据, 块索引从日志重建
+        let mut wal = open(&device);
+        let used = wal.stats().device_bytes.unwrap();
+        let tx = wal.begin_tx();
+        wal.write_file(tx, "/c", 0, &data);
+        wal.commit_tx(tx).unwrap();
+        assert!(wal.stats().device_bytes.unwrap() - used < 1024);
+        drop(wal);
+
+        let wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+        let redo = wal.recover().unwrap().redo;
+        let writes: Vec<_> = redo
+            .iter()
+            .filter_map(|record| match record.operation() {
+                Some(WalOp::Write { path, offset, data }) => Some((path, offset, data)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(writes.len(), 5);
+        assert_eq!(writes[0], ("/a", 0, &data[..]));
+        assert_eq!(writes[2], ("/b", EXTENT_SIZE as u64, &data[EXTENT_SIZE..]));
+        assert_eq!(writes[4], ("/c", 0, &data[..]));
+
+        // checkpoint 之后镜像不引用被回收的记录
+        let mut wal = open(&device);
+        wal.checkpoint(|wal, tx| {
+            wal.write_file(tx, "/a", 0, &data);
+            wal.write_file(tx, "/c", 0, &data);
+            Ok(())
+        })
+        .unwrap();
+        drop(wal);
+        let redo = open(&device).recover().unwrap().redo;
+        assert_eq!(redo.len(), 2);
+        assert!(redo.iter().all(|record| record.data.ends_with(&data)));
+    }
+
+    #[test]
+    fn test_wal_encrypted() {
+        use crate::{crypt::MountKey, log_manager::MemBlockDevice};
+
+        let key = Arc::new(Cipher::new(&MountKey::new([9; 32])));
+        let open = |device: &Arc<MemBlockDevice>, cipher: Option<Arc<Cipher>>| {
+            Wal::open_device("/test/wal".to_string(), device.clone(), cipher, true)
+        };
+        let device = Arc::new(MemBlockDevice::new(64 * 1024));
+        {
+            let mut wal = open(&device, Some(key.clone())).unwrap();
+            let tx = wal.begin_tx();
+            wal.write_file(tx, "/secret", 0, b"fixture");
+            wal.commit_tx(tx).unwrap();
+        }
+        let image = device.durable_image();
+        assert!(!image.windows(7).any(|w| w == b"fixture"));
+        assert!(!image.windows(7).any(|w| w == b"/secret"));
+
+        // 密钥错误或缺失都拒绝打开
+        let wrong = Arc::new(Cipher::new(&MountKey::new([8; 32])));
+        assert_eq!(open(&device, Some(wrong.clone())).err(), Some(DbfsError::AccessError));
+        assert_eq!(open(&device, None).err(), Some(DbfsError::AccessError));
+
+        let wal = open(&device, Some(key.clone())).unwrap();
+        let redo = wal.recover().unwrap().redo;
+        assert!(redo[0].data.windows(7).any(|w| w == b"fixture"));
+
+        // 篡改 FileWrite 记录 (跟在只有 epoch 和认证标签的 TxBegin 之后) 并修正 CRC:
+        // 认证失败
+        let mut image = image;
+        let offset = WAL_HEADER_SIZE + RECORD_HEADER_SIZE + 4 + TAG_LEN + 4;
+        let mut record = WalRecord::deserialize(&image[offset..]).unwrap();
+        assert_eq!(record.record_type, WalRecordType::FileWrite);
+        record.data[4] ^= 1;
+        record.checksum = WalRecord::compute_checksum(&record.data);
+        let bytes = record.serialize();
+        image[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        let device = Arc::new(MemBlockDevice::from_image(image));
+        assert_eq!(open(&device, Some(key)).err(), Some(DbfsError::Io));
+
+        // 已有明文记录的 WAL 不能直接加密
+        let plain = Arc::new(MemBlockDevice::new(64 * 1024));
+        {
+            let mut wal = open(&plain, None).unwrap();
+            let tx = wal.begin_tx();
+            wal.commit_tx(tx).unwrap();
+        }
+        assert_eq!(open(&plain, Some(wrong)).err(), Some(DbfsError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_wal_encrypted_torn_tail() {
+        use crate::{crypt::MountKey, log_manager::MemBlockDevice};
+
+        let key = Arc::new(Cipher::new(&MountKey::new([9; 32])));
+        let open = |device: &Arc<MemBlockDevice>| {
+            Wal::open_device("/test/wal".to_string(), device.clone(), Some(key.clone()), true)
+        };
+        let commit = |wal: &mut Wal, data: &[u8]| {
+            let tx = wal.begin_tx();
+            wal.write_file(tx, "/a", 0, data);
+            wal.commit_tx(tx).unwrap();
+        };
+        let device = Arc::new(MemBlockDevice::new(64 * 1024));
+        commit(&mut open(&device).unwrap(), b"first");
+
+        // 第二次打开换了 epoch; 之后追加的事务没有落盘, 只剩下头部
+        let mut wal = open(&device).unwrap();
+        assert_eq!(wal.epoch, 2);
+        let end = wal.write_pos as usize;
+        commit(&mut wal, b"second");
+        let lost = device.durable_image();
+        let mut torn = lost.clone();
+        torn[end..].fill(0);
+
+        // 重新打开后新记录沿用被撕掉的 LSN, 但 nonce 不同
+        let device = Arc::new(MemBlockDevice::from_image(torn));
+        let mut wal = open(&device).unwrap();
+        assert_eq!(wal.epoch, 3);
+        commit(&mut wal, b"second");
+        let image = device.durable_image();
+        let old = WalRecord::deserialize(&lost[end..]).unwrap();
+        let new = WalRecord::deserialize(&image[end..]).unwrap();
+        assert_eq!(old.lsn, new.lsn);
+        assert_eq!(old.data[..4], 2u32.to_be_bytes());
+        assert_eq!(new.data[..4], 3u32.to_be_bytes());
+        assert_ne!(old.data[4..], new.data[4..]);
+
+        // 不同 epoch 写入的记录都能解密
+        let redo = open(&device).unwrap().recover().unwrap().redo;
+        assert_eq!(redo.len(), 2);
+        assert!(redo[0].data.ends_with(b"first"));
+        assert!(redo[1].data.ends_with(b"second"));
+    }
+
+    #[test]
+    fn test_wal_checkpoint_image() {
+        use crate::log_manager::MemBlockDevice;
+
+        let device = Arc::new(MemBlockDevice::new(64 * 1024));
+        let mut wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+        let tx = wal.begin_tx();
+        wal.write_file(tx, "/a", 0, b"old");
+        wal.commit_tx(tx).unwrap();
+
+        // 进行中的事务不能被镜像描述
+        let active = wal.begin_tx();
+        assert_eq!(wal.checkpoint(|_, _| Ok(())).err(), Some(DbfsError::Busy));
+        wal.rollback_tx(active);
+
+        let lsn = wal
+            .checkpoint(|wal, tx| {
+                wal.write_file(tx, "/a", 0, b"image");
+                Ok(())
+            })
+            .unwrap();
+        let tx = wal.begin_tx();
+        wal.write_file(tx, "/b", 0, b"after");
+        wal.commit_tx(tx).unwrap();
+        drop(wal);
+        device.crash();
+
+        // 恢复时从镜像开始, 镜像之前的记录不再重放
+        let wal = Wal::with_device("/test/wal".to_string(), device).unwrap();
+        assert_eq!(wal.checkpoint_lsn(), lsn);
+        let redo = wal.recover().unwrap().redo;
+        assert_eq!(redo.len(), 2);
+        assert!(redo[0].data.ends_with(b"image"));
+        assert!(redo[1].data.ends_with(b"after"));
+    }
+
+    #[test]
+    fn test_wal_checkpoint_no_space() {
+        use crate::log_manager::MemBlockDevice;
+
+        let device = Arc::new(MemBlockDevice::new(4 * 1024));
+        let mut wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+        let tx = wal.begin_tx();
+        wal.write_file(tx, "/a", 0, b"old");
+        wal.commit_tx(tx).unwrap();
+        let records = wal.records().len();
+
+        // 镜像放不下时不留下任何记录, 之后的刷写不会把它写出去
+        let result = wal.checkpoint(|wal, tx| {
+            wal.write_file(tx, "/a", 0, &[7; 8 * 1024]);
+            Ok(())
+        });
+        assert_eq!(result.err(), Some(DbfsError::NoSpace));
+        assert_eq!(wal.records().len(), records);
+        let tx = wal.begin_tx();
+        wal.write_file(tx, "/b", 0, b"after");
+        wal.commit_tx(tx).unwrap();
+        drop(wal);
+        device.crash();
+
+        let wal = Wal::with_device("/test/wal".to_string(), device).unwrap();
+        let redo = wal.recover().unwrap().redo;
+        assert_eq!(redo.len(), 2);
+        assert!(redo[0].data.ends_with(b"old"));
+        assert!(redo[1].data.ends_with(b"after"));
+    }
+
+    #[test]
+    fn test_wal_circular_reuse() {
+        use crate::log_manager::MemBlockDevice;
+
+        let device = Arc::new(MemBlockDevice::new(4 * 1024));
+        let mut wal = Wal::with_device("/test/wal".to_string(), device.clone()).unwrap();
+        let mut last = Vec::new();
+        // 写入量远大于设备, 每次 checkpoint 只保留最新内容
+        for round in 0..64u8 {
+            last = vec![round; 300];
+            let tx = wal.begin_tx();
+            wal.write_file(tx, "/f", 0, &last);
+            wal.commit_tx(tx).unwrap();
+            if round % 3 == 2 {
+                let content = last.clone();
+                wal.checkpoint(|wal, tx| {
+                    wal.write_file(tx, "/f", 0, &content);
+                    Ok(())
+                })
+                .unwrap();
+            }
+        }
+        assert!(wal.stats().flushed_bytes > 4 * 1024);
+        drop(wal);
+        device.crash();
+
+        let wal = Wal::with_device("/test/wal".to_string(), device).unwrap();
+        let redo = wal.recover().unwrap().redo;
+        assert!(redo.last().unwrap().data.ends_with(&last));
+        let lsns: Vec<_> = redo.iter().map(|r| r.lsn).collect();
+        assert!(lsns.windows(2).all(|w| w[0] < w[1]));
+    }
+}
@@ -12,7 +12,16 @@ pub trait BlockDevice: DeviceBase {
     fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize>;
     fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize>;
     fn size(&self) -> usize;
+    /// 写入屏障: 返回时之前所有完成的 `write` 都已写入设备并落盘
     fn flush(&self) -> AlienResult<()>;
+    /// 强制单元访问 (FUA) 写入: 返回时这次写入的数据已经落盘
+    ///
+    /// 只保证这次写入的持久性, 不保证之前的写入已经落盘 (需要时使用 [`BlockDevice::flush`])
+    fn write_fua(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let written = self.write(buf, offset)?;
+        self.flush()?;
+        Ok(written)
+    }
 }
 pub trait LowBlockDevice {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) -> AlienResult<()>;
@@ -20,8 +29,31 @@ pub trait LowBlockDevice {
     fn capacity(&self) -> usize;
     fn read_block_async(&self, block_id: usize, buf: &mut [u8]) -> AlienResult<()>;
     fn write_block_async(&self, block_id: usize, buf: &[u8]) -> AlienResult<()>;
+    /// 读入多段扇区, 每段为 (起始扇区, 缓冲区), 缓冲区长度是扇区大小的整数倍
+    ///
+    /// 全部完成后返回。设备可以合并相邻的段并同时处理多个请求, 默认逐个扇区读取
+    fn read_batch(&self, requests: &mut [(usize, &mut [u8])]) -> AlienResult<()> {
+        for (block_id, buf) in requests.iter_mut() {
+            for (i, block) in buf.chunks_mut(512).enumerate() {
+                self.read_block(*block_id + i, block)?;
+            }
+        }
+        Ok(())
+    }
+    /// 写入多段扇区, 与 [`LowBlockDevice::read_batch`] 相同
+    fn write_batch(&self, requests: &[(usize, &[u8])]) -> AlienResult<()> {
+        for (block_id, buf) in requests {
+            for (i, block) in buf.chunks(512).enumerate() {
+                self.write_block(*block_id + i, block)?;
+            }
+        }
+        Ok(())
+    }
     fn handle_irq(&self);
-    fn flush(&self) {}
+    /// 刷新设备自身的写缓存, 之前完成的写入在返回时落盘
+    fn flush(&self) -> AlienResult<()> {
+        Ok(())
+    }
 }
 
 pub trait GpuDevice: Any + DeviceBase {
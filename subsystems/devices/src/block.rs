@@ -1,6 +1,6 @@
 use alloc::sync::Arc;
 
-use constants::DeviceId;
+use constants::{AlienResult, DeviceId};
 use device_interface::BlockDevice;
 use drivers::block_device::GenericBlockDevice;
 use spin::Once;
@@ -18,6 +18,22 @@ pub fn init_block_device(block_device: Arc<GenericBlockDevice>) {
     BLOCK_DEVICE.call_once(|| block_device);
 }
 
+/// 写回块设备缓存中的所有脏页并刷新设备的写缓存, 没有块设备时什么也不做
+pub fn sync_block_device() -> AlienResult<()> {
+    match BLOCK_DEVICE.get() {
+        Some(device) => device.flush(),
+        None => Ok(()),
+    }
+}
+
+/// 写回线程调用: 写回块设备缓存中过期的脏页, 返回写回的页数
+pub fn writeback_block_device() -> AlienResult<usize> {
+    match BLOCK_DEVICE.get() {
+        Some(device) => device.background_writeback(),
+        None => Ok(0),
+    }
+}
+
 pub struct BLKDevice {
     device_id: DeviceId,
     device: Arc<GenericBlockDevice>,
@@ -52,8 +68,9 @@ impl VfsFile for BLKDevice {
     fn flush(&self) -> VfsResult<()> {
         Ok(())
     }
+    /// 写入屏障: 返回时之前所有的写入都已落盘 (DBFS 的 WAL 依赖它保证写入顺序)
     fn fsync(&self) -> VfsResult<()> {
-        Ok(())
+        self.device.flush().map_err(|_| VfsError::IoError)
     }
 }
 
@@ -0,0 +1,97 @@
+use alloc::sync::Arc;
+
+use constants::DeviceId;
+use device_interface::UartDevice;
+use drivers::virtio_serial::VirtioSerialDevice;
+use spin::Once;
+use vfscore::{
+    error::VfsError,
+    file::VfsFile,
+    inode::{InodeAttr, VfsInode},
+    superblock::VfsSuperBlock,
+    utils::{VfsFileStat, VfsNodeType, VfsPollEvents},
+    VfsResult,
+};
+
+pub static HVC_DEVICE: Once<Arc<VirtioSerialDevice>> = Once::new();
+
+pub fn init_hvc(hvc: Arc<VirtioSerialDevice>) {
+    HVC_DEVICE.call_once(|| hvc);
+}
+
+/// Raw virtio-console character device.
+///
+/// Unlike the tty device there is no line discipline: bytes are passed
+/// through unchanged so binary protocols can run over it.
+pub struct HVCDevice {
+    device_id: DeviceId,
+    device: Arc<VirtioSerialDevice>,
+}
+
+impl HVCDevice {
+    pub fn new(device_id: DeviceId, device: Arc<VirtioSerialDevice>) -> Self {
+        Self { device_id, device }
+    }
+    pub fn device_id(&self) -> DeviceId {
+        self.device_id
+    }
+}
+
+impl VfsFile for HVCDevice {
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // block for the first byte, then return whatever is already buffered
+        buf[0] = self.device.get().ok_or(VfsError::IoError)?;
+        let mut count = 1;
+        while count < buf.len() && self.device.have_data_to_get() {
+            match self.device.try_read_exact(1) {
+                Some(byte) => buf[count] = byte[0],
+                None => break,
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.device
+            .try_write(buf)
+            .map_err(|_| VfsError::IoError)?;
+        Ok(buf.len())
+    }
+    fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
+        let mut res = VfsPollEvents::empty();
+        if event.contains(VfsPollEvents::IN) && self.device.have_data_to_get() {
+            res |= VfsPollEvents::IN;
+        }
+        if event.contains(VfsPollEvents::OUT) && self.device.have_space_to_put() {
+            res |= VfsPollEvents::OUT;
+        }
+        Ok(res)
+    }
+    fn flush(&self) -> VfsResult<()> {
+        Ok(())
+    }
+    fn fsync(&self) -> VfsResult<()> {
+        Ok(())
+    }
+}
+
+impl VfsInode for HVCDevice {
+    fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
+        Err(VfsError::NoSys)
+    }
+    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+        Ok(())
+    }
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        Ok(VfsFileStat {
+            st_rdev: self.device_id.id(),
+            ..Default::default()
+        })
+    }
+    fn inode_type(&self) -> VfsNodeType {
+        VfsNodeType::CharDevice
+    }
+}
@@ -2,6 +2,7 @@
 
 mod block;
 mod gpu;
+mod hvc;
 mod input;
 pub mod net;
 mod prob;
@@ -13,7 +14,7 @@ extern crate alloc;
 use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::ptr::NonNull;
 
-pub use block::{BLKDevice, BLOCK_DEVICE};
+pub use block::{sync_block_device, writeback_block_device, BLKDevice, BLOCK_DEVICE};
 use config::MAX_INPUT_EVENT_NUM;
 use device_interface::{DeviceBase, GpuDevice, LowBlockDevice};
 use drivers::{
@@ -23,6 +24,7 @@ use drivers::{
 };
 use fdt::Fdt;
 pub use gpu::{GPUDevice, GPU_DEVICE};
+pub use hvc::{HVCDevice, HVC_DEVICE};
 pub use input::{INPUTDevice, KEYBOARD_INPUT_DEVICE, MOUSE_INPUT_DEVICE};
 use interrupt::register_device_to_plic;
 use log::info;
@@ -173,6 +175,7 @@ pub fn init_virtio_mmio(devices: Vec<prob::DeviceInfo>) {
                     DeviceType::Block => init_block_device(device, Some(transport)),
                     DeviceType::GPU => init_gpu(device, Some(transport)),
                     DeviceType::Network => init_net(Some(device)),
+                    DeviceType::Console => init_virtio_console(device, Some(transport)),
                     ty => {
                         println!("Don't support virtio device type: {:?}", ty);
                     }
@@ -204,8 +207,9 @@ fn init_block_device(blk: prob::DeviceInfo, mmio_transport: Option<MmioTransport
             let size = block_device.capacity();
             println!("Block device size is {}MB", size * 512 / 1024 / 1024);
             let block_device = Arc::new(GenericBlockDevice::new(Box::new(block_device)));
-            block::init_block_device(block_device);
-            // register_device_to_plic(irq, block_device);
+            block::init_block_device(block_device.clone());
+            // 请求由完成中断取回
+            register_device_to_plic(irq, block_device);
             println!("Init block device success");
         }
         "starfive,jh7110-sdio" => {
@@ -299,6 +303,27 @@ fn init_input_device(input: prob::DeviceInfo, name: &str, mmio_transport: Option
     }
 }
 
+fn init_virtio_console(console: prob::DeviceInfo, mmio_transport: Option<MmioTransport>) {
+    let (base_addr, irq) = (console.base_addr, console.irq);
+    println!(
+        "Init virtio console, base_addr:{:#x},irq:{}",
+        base_addr, irq
+    );
+    match console.compatible.as_str() {
+        "virtio,mmio" => {
+            use drivers::virtio_serial::{init_virtio_serial, VirtioSerialDevice};
+            let console = Arc::new(VirtioSerialDevice::from_mmio(mmio_transport.unwrap()));
+            hvc::init_hvc(console.clone());
+            init_virtio_serial(console.clone());
+            register_device_to_plic(irq, console);
+            println!("Init virtio console success");
+        }
+        name => {
+            println!("Don't support virtio console: {}", name);
+        }
+    }
+}
+
 fn init_net(_nic: Option<prob::DeviceInfo>) {
     // If we need run test, we should init loop device because no we can't route packet
     #[cfg(feature = "test")]
@@ -1,23 +1,26 @@
-use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec};
 use core::{
     cmp::min,
     fmt::{Debug, Formatter},
+    hint::spin_loop,
     num::NonZeroUsize,
-    ops::{Deref, DerefMut},
+    ops::{Deref, DerefMut, Range},
     ptr::NonNull,
 };
 
-use config::FRAME_SIZE;
+use arch::{interrupt_disable, interrupt_enable, is_interrupt_enable};
+use config::{DIRTY_BACKGROUND_RATIO, DIRTY_EXPIRE_MS, FRAME_SIZE};
 use constants::{AlienResult, LinuxErrno};
 use device_interface::{BlockDevice, DeviceBase, LowBlockDevice};
 use ksync::{Mutex, RwLock};
+use log::warn;
 use lru::LruCache;
 use mem::{alloc_frames, free_frames};
 use platform::config::{BLOCK_CACHE_FRAMES, CLOCK_FREQ};
 use shim::KTask;
-use timer::read_timer;
+use timer::{get_time_ms, read_timer};
 use virtio_drivers::{
-    device::blk::VirtIOBlk,
+    device::blk::{BlkReq, BlkResp, VirtIOBlk},
     transport::mmio::{MmioTransport, VirtIOHeader},
 };
 pub use visionfive2_sd::Vf2SdDriver;
@@ -25,11 +28,37 @@ use visionfive2_sd::{SDIo, SleepOps};
 
 use crate::hal::HalImpl;
 const PAGE_CACHE_SIZE: usize = FRAME_SIZE;
-
+/// 每个缓存页包含的扇区数
+const BLOCKS_PER_PAGE: usize = PAGE_CACHE_SIZE / 512;
+/// 一次写回的最多页数
+const WRITEBACK_BATCH: usize = 32;
+/// 读缺页时一次读入的最多页数
+const READ_BATCH: usize = 64;
+
+/// 带页缓存的块设备
+///
+/// 写入只修改缓存页并记录页变脏的时间, 脏页由写回线程按时间和脏页比例写回
+/// ([`GenericBlockDevice::background_writeback`]), 或在 [`BlockDevice::flush`] 时全部写回。
+/// 读写设备时不持有缓存锁; 只有干净并且不在写回中的页会被换出。读入的页只有在读入期间
+/// 没有被写回时才放进缓存, 否则内容可能比设备上的旧, 需要重新读入。
+///
+/// 读缺页时一次读入请求范围内随后的缺页 (最多 [`READ_BATCH`] 页), 写回时一批页一起交给设备,
+/// 相邻的页由设备合并为一个请求 ([`LowBlockDevice::read_batch`])。
 pub struct GenericBlockDevice {
     device: Box<dyn LowBlockDevice>,
-    cache: Mutex<LruCache<usize, FrameTracker>>,
-    dirty: Mutex<Vec<usize>>,
+    cache: Mutex<LruCache<usize, CachePage>>,
+    /// 正在从设备读入的页: 页号 -> (读入它的次数, 读入期间这一页完成写回的次数)
+    loading: Mutex<BTreeMap<usize, (usize, usize)>>,
+    /// 所有页都在写回中, 等待写回完成以换出页的任务
+    writeback_waiters: Mutex<Vec<Arc<dyn KTask>>>,
+}
+
+struct CachePage {
+    frame: FrameTracker,
+    /// 页变脏的时间 (ms), 干净的页为 None
+    dirtied: Option<usize>,
+    /// 页的内容正在被写入设备
+    writeback: bool,
 }
 
 #[derive(Debug)]
@@ -74,7 +103,322 @@ impl GenericBlockDevice {
             cache: Mutex::new(LruCache::new(
                 NonZeroUsize::new(BLOCK_CACHE_FRAMES).unwrap(),
             )),
-            dirty: Mutex::new(Vec::new()),
+            loading: Mutex::new(BTreeMap::new()),
+            writeback_waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 写回线程调用: 写回变脏超过 [`DIRTY_EXPIRE_MS`] 的页, 脏页比例超过
+    /// [`DIRTY_BACKGROUND_RATIO`] 时还写回最老的脏页直到比例降到一半, 返回写回的页数
+    ///
+    /// 写入失败的页仍然是脏的, 下次写回时重试; 出错时返回 EIO。
+    pub fn background_writeback(&self) -> AlienResult<usize> {
+        let now = get_time_ms() as usize;
+        let ids = {
+            let cache = self.cache.lock();
+            let limit = cache.cap().get() * DIRTY_BACKGROUND_RATIO / 100;
+            let mut dirty = cache
+                .iter()
+                .filter(|(_, page)| !page.writeback)
+                .filter_map(|(id, page)| page.dirtied.map(|dirtied| (dirtied, *id)))
+                .collect::<Vec<_>>();
+            dirty.sort_unstable();
+            let excess = if dirty.len() > limit {
+                dirty.len() - limit / 2
+            } else {
+                0
+            };
+            dirty
+                .iter()
+                .enumerate()
+                .take_while(|(i, (dirtied, _))| {
+                    *i < excess || now.saturating_sub(*dirtied) >= DIRTY_EXPIRE_MS
+                })
+                .map(|(_, (_, id))| *id)
+                .collect::<Vec<_>>()
+        };
+        let mut written = 0;
+        let mut result = Ok(());
+        for batch in ids.chunks(WRITEBACK_BATCH) {
+            match self.write_back_pages(batch) {
+                Ok(count) => written += count,
+                Err(e) => result = Err(e),
+            }
+        }
+        result.map(|_| written)
+    }
+
+    /// 开始从设备读入第 `page_id` 页, 返回读入结束时交给 [`Self::end_load`] 的值
+    fn begin_load(&self, page_id: usize) -> usize {
+        let mut loading = self.loading.lock();
+        let entry = loading.entry(page_id).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1
+    }
+
+    /// 读入第 `page_id` 页结束, 返回读入期间这一页是否被写回过 (读入的内容不能使用)
+    ///
+    /// 需要在持有缓存锁时调用: 写回在同一把锁下完成计数, 之后页才可能被换出。
+    fn end_load(&self, page_id: usize, written: usize) -> bool {
+        let mut loading = self.loading.lock();
+        let Some(entry) = loading.get_mut(&page_id) else {
+            return true;
+        };
+        let stale = entry.1 != written;
+        entry.0 -= 1;
+        if entry.0 == 0 {
+            loading.remove(&page_id);
+        }
+        stale
+    }
+
+    /// 从设备读入第 `page_id` 页
+    fn read_page(&self, page_id: usize) -> AlienResult<FrameTracker> {
+        let mut frame = FrameTracker::new(alloc_frames(1) as usize);
+        self.device.read_batch(&mut [(page_id * BLOCKS_PER_PAGE, &mut frame[..])])?;
+        Ok(frame)
+    }
+
+    /// 把 `pages` 中不在缓存中的页一起读入, 相邻的页由设备合并为一个请求
+    fn load_pages(&self, pages: Range<usize>) -> AlienResult<()> {
+        let missing = {
+            let cache = self.cache.lock();
+            pages.filter(|id| !cache.contains(id)).collect::<Vec<_>>()
+        };
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let written = missing.iter().map(|id| self.begin_load(*id)).collect::<Vec<_>>();
+        let mut frames = missing
+            .iter()
+            .map(|_| FrameTracker::new(alloc_frames(1) as usize))
+            .collect::<Vec<_>>();
+        let mut requests = missing
+            .iter()
+            .zip(frames.iter_mut())
+            .map(|(id, frame)| (id * BLOCKS_PER_PAGE, &mut frame[..]))
+            .collect::<Vec<_>>();
+        let mut result = self.device.read_batch(&mut requests);
+        drop(requests);
+        // 读入期间被写回的页, 设备的内容可能比读入的新, 交给 with_page 重新读入
+        for ((id, frame), written) in missing.into_iter().zip(frames).zip(written) {
+            if result.is_ok() {
+                result = self.make_room();
+            }
+            let mut cache = self.cache.lock();
+            let stale = self.end_load(id, written);
+            let fits = !cache.contains(&id) && cache.len() < cache.cap().get();
+            if result.is_ok() && !stale && fits {
+                cache.push(
+                    id,
+                    CachePage {
+                        frame,
+                        dirtied: None,
+                        writeback: false,
+                    },
+                );
+            }
+        }
+        result
+    }
+
+    /// 对缓存中的第 `page_id` 页调用 `f`, 页不在缓存中时先从设备读入
+    ///
+    /// `overwrite` 表示 `f` 会覆盖整页, 此时不需要读入页原来的内容。
+    fn with_page<R>(
+        &self,
+        page_id: usize,
+        overwrite: bool,
+        f: impl FnOnce(&mut CachePage) -> R,
+    ) -> AlienResult<R> {
+        loop {
+            if let Some(page) = self.cache.lock().get_mut(&page_id) {
+                return Ok(f(page));
+            }
+            let (frame, written) = if overwrite {
+                (FrameTracker::new(alloc_frames(1) as usize), None)
+            } else {
+                let written = self.begin_load(page_id);
+                match self.read_page(page_id) {
+                    Ok(frame) => (frame, Some(written)),
+                    Err(e) => {
+                        let _cache = self.cache.lock();
+                        self.end_load(page_id, written);
+                        return Err(e);
+                    }
+                }
+            };
+            let room = self.make_room();
+            let mut cache = self.cache.lock();
+            // 读入期间页被其它线程读入, 或者设备的内容可能已经改变 (页被写回后换出)
+            let stale = written.is_some_and(|written| self.end_load(page_id, written));
+            room?;
+            if cache.contains(&page_id) || stale || cache.len() >= cache.cap().get() {
+                continue;
+            }
+            cache.push(
+                page_id,
+                CachePage {
+                    frame,
+                    dirtied: None,
+                    writeback: false,
+                },
+            );
+            return Ok(f(cache.get_mut(&page_id).unwrap()));
+        }
+    }
+
+    /// 保证缓存有空位: 换出最久未使用的干净页, 没有干净页时先写回最老的脏页
+    fn make_room(&self) -> AlienResult<()> {
+        loop {
+            let oldest = {
+                let mut cache = self.cache.lock();
+                if cache.len() < cache.cap().get() {
+                    return Ok(());
+                }
+                let clean = cache
+                    .iter()
+                    .rev()
+                    .find(|(_, page)| page.dirtied.is_none() && !page.writeback)
+                    .map(|(id, _)| *id);
+                if let Some(id) = clean {
+                    cache.pop(&id);
+                    return Ok(());
+                }
+                let mut dirty = cache
+                    .iter()
+                    .filter(|(_, page)| !page.writeback)
+                    .filter_map(|(id, page)| page.dirtied.map(|dirtied| (dirtied, *id)))
+                    .collect::<Vec<_>>();
+                if dirty.is_empty() {
+                    Err(self.wait_writeback())
+                } else {
+                    dirty.sort_unstable();
+                    Ok(dirty
+                        .iter()
+                        .take(WRITEBACK_BATCH)
+                        .map(|(_, id)| *id)
+                        .collect::<Vec<_>>())
+                }
+            };
+            match oldest {
+                Ok(oldest) => {
+                    self.write_back_pages(&oldest)?;
+                }
+                // 所有页都在被其它线程写回, 等待其中一次写回完成
+                Err(Some(task)) => shim::schedule_now(task),
+                Err(None) => spin_loop(),
+            }
+        }
+    }
+
+    /// 在持有缓存锁时调用: 让当前任务等待下一次写回完成, 返回之后要切换出去的任务
+    ///
+    /// 不能睡眠 (中断关闭或者还没有任务) 时返回 None, 调用者轮询。
+    fn wait_writeback(&self) -> Option<Arc<dyn KTask>> {
+        if !is_interrupt_enable() || shim::current_task().is_none() {
+            return None;
+        }
+        let task = shim::take_current_task()?;
+        task.to_wait();
+        self.writeback_waiters.lock().push(task.clone());
+        Some(task)
+    }
+
+    /// 把 `ids` 中仍然是脏的页写入设备, 返回写入的页数
+    ///
+    /// 页的内容先复制出来并标记为干净, 写设备时不持有缓存锁; 正在被其它线程写回的页跳过。
+    /// 写入失败的页重新标记为脏, 返回 EIO。
+    fn write_back_pages(&self, ids: &[usize]) -> AlienResult<usize> {
+        let pages = {
+            let mut cache = self.cache.lock();
+            let mut pages = Vec::new();
+            for id in ids {
+                let Some(page) = cache.peek_mut(id) else {
+                    continue;
+                };
+                if let (Some(dirtied), false) = (page.dirtied, page.writeback) {
+                    page.dirtied = None;
+                    page.writeback = true;
+                    pages.push((*id, dirtied, page.frame.to_vec()));
+                }
+            }
+            pages
+        };
+        let requests = pages
+            .iter()
+            .map(|(id, _, data)| (id * BLOCKS_PER_PAGE, data.as_slice()))
+            .collect::<Vec<_>>();
+        // 设备不报告一批中的哪一页失败, 失败时所有页重新标记为脏
+        let failed = match self.device.write_batch(&requests) {
+            Ok(()) => Vec::new(),
+            Err(e) => {
+                warn!("block device: write back {} pages failed: {:?}", pages.len(), e);
+                pages
+                    .iter()
+                    .map(|(id, dirtied, _)| (*id, *dirtied))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let mut cache = self.cache.lock();
+        let mut loading = self.loading.lock();
+        for (id, _, _) in &pages {
+            if let Some(page) = cache.peek_mut(id) {
+                page.writeback = false;
+            }
+            if let Some((_, written)) = loading.get_mut(id) {
+                *written += 1;
+            }
+        }
+        drop(loading);
+        for task in self.writeback_waiters.lock().drain(..) {
+            task.to_wakeup();
+            shim::put_task(task);
+        }
+        for (id, dirtied) in &failed {
+            if let Some(page) = cache.peek_mut(id) {
+                page.dirtied = Some(page.dirtied.map_or(*dirtied, |d| d.min(*dirtied)));
+            }
+        }
+        if failed.is_empty() {
+            Ok(pages.len())
+        } else {
+            Err(LinuxErrno::EIO)
+        }
+    }
+
+    /// 写回 `select` 选中的、在 `before` (ms) 之前变脏的页, 并等待其它线程对这些页的写回完成
+    fn write_back_before(&self, before: usize, select: impl Fn(usize) -> bool) -> AlienResult<()> {
+        loop {
+            let mut busy = false;
+            let cache = self.cache.lock();
+            let ids = cache
+                .iter()
+                .filter(|(id, _)| select(**id))
+                .filter_map(|(id, page)| {
+                    busy |= page.writeback;
+                    page.dirtied
+                        .filter(|dirtied| *dirtied <= before && !page.writeback)
+                        .map(|_| *id)
+                })
+                .collect::<Vec<_>>();
+            if ids.is_empty() {
+                if !busy {
+                    return Ok(());
+                }
+                match self.wait_writeback() {
+                    Some(task) => {
+                        drop(cache);
+                        shim::schedule_now(task);
+                    }
+                    None => spin_loop(),
+                }
+                continue;
+            }
+            drop(cache);
+            for batch in ids.chunks(WRITEBACK_BATCH) {
+                self.write_back_pages(batch)?;
+            }
         }
     }
 }
@@ -95,38 +439,18 @@ impl BlockDevice for GenericBlockDevice {
     fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
         let mut page_id = offset / PAGE_CACHE_SIZE;
         let mut offset = offset % PAGE_CACHE_SIZE;
-
-        let mut cache_lock = self.cache.lock();
         let len = buf.len();
+        let last = (page_id * PAGE_CACHE_SIZE + offset + len).div_ceil(PAGE_CACHE_SIZE);
         let mut count = 0;
-
         while count < len {
-            if !cache_lock.contains(&page_id) {
-                let device = &self.device;
-                let cache = alloc_frames(1);
-                let mut cache = FrameTracker::new(cache as usize);
-                let start_block = page_id * PAGE_CACHE_SIZE / 512;
-                let end_block = start_block + PAGE_CACHE_SIZE / 512;
-                for i in start_block..end_block {
-                    let target_buf =
-                        &mut cache[(i - start_block) * 512..(i - start_block + 1) * 512];
-                    device.read_block(i, target_buf).unwrap();
-                }
-                let old_cache = cache_lock.push(page_id, cache);
-                if let Some((id, old_cache)) = old_cache {
-                    let start_block = id * PAGE_CACHE_SIZE / 512;
-                    let end_block = start_block + PAGE_CACHE_SIZE / 512;
-                    for i in start_block..end_block {
-                        let target_buf =
-                            &old_cache[(i - start_block) * 512..(i - start_block + 1) * 512];
-                        device.write_block(i, target_buf).unwrap();
-                        self.dirty.lock().retain(|&x| x != id);
-                    }
-                }
+            if !self.cache.lock().contains(&page_id) {
+                self.load_pages(page_id..min(last, page_id + READ_BATCH))?;
             }
-            let cache = cache_lock.get(&page_id).unwrap();
             let copy_len = min(PAGE_CACHE_SIZE - offset, len - count);
-            buf[count..count + copy_len].copy_from_slice(&cache[offset..offset + copy_len]);
+            let target = &mut buf[count..count + copy_len];
+            self.with_page(page_id, false, |page| {
+                target.copy_from_slice(&page.frame[offset..offset + copy_len])
+            })?;
             count += copy_len;
             offset = 0;
             page_id += 1;
@@ -136,38 +460,19 @@ impl BlockDevice for GenericBlockDevice {
     fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
         let mut page_id = offset / PAGE_CACHE_SIZE;
         let mut offset = offset % PAGE_CACHE_SIZE;
-        let mut cache_lock = self.cache.lock();
         let len = buf.len();
         let mut count = 0;
+        let now = get_time_ms() as usize;
         while count < len {
-            if !cache_lock.contains(&page_id) {
-                let device = &self.device;
-                let cache = alloc_frames(1);
-                let mut cache = FrameTracker::new(cache as usize);
-                let start_block = page_id * PAGE_CACHE_SIZE / 512;
-                let end_block = start_block + PAGE_CACHE_SIZE / 512;
-                for i in start_block..end_block {
-                    let target_buf =
-                        &mut cache[(i - start_block) * 512..(i - start_block + 1) * 512];
-                    device.read_block(i, target_buf).unwrap();
-                }
-                let old_cache = cache_lock.push(page_id, cache);
-                if let Some((id, old_cache)) = old_cache {
-                    let start_block = id * PAGE_CACHE_SIZE / 512;
-                    let end_block = start_block + PAGE_CACHE_SIZE / 512;
-                    for i in start_block..end_block {
-                        let target_buf =
-                            &old_cache[(i - start_block) * 512..(i - start_block + 1) * 512];
-                        device.write_block(i, target_buf).unwrap();
-                        self.dirty.lock().retain(|&x| x != id);
-                    }
-                }
-            }
-            let cache = cache_lock.get_mut(&page_id).unwrap();
             let copy_len = min(PAGE_CACHE_SIZE - offset, len - count);
-            cache[offset..offset + copy_len].copy_from_slice(&buf[count..count + copy_len]);
+            let source = &buf[count..count + copy_len];
+            let overwrite = copy_len == PAGE_CACHE_SIZE;
+            self.with_page(page_id, overwrite, |page| {
+                page.frame[offset..offset + copy_len].copy_from_slice(source);
+                page.dirtied.get_or_insert(now);
+            })?;
             count += copy_len;
-            offset = (offset + copy_len) % PAGE_CACHE_SIZE;
+            offset = 0;
             page_id += 1;
         }
         Ok(buf.len())
@@ -176,26 +481,68 @@ impl BlockDevice for GenericBlockDevice {
         self.device.capacity() * 512
     }
     fn flush(&self) -> AlienResult<()> {
-        // let mut device = self.device.lock();
-        // let mut lru = self.cache.lock();
-        // self.dirty.lock().iter().for_each(|id|{
-        //     let start = id * PAGE_CACHE_SIZE;
-        //     let start_block = start / 512;
-        //     let end_block = (start + PAGE_CACHE_SIZE) / 512;
-        //     let cache = lru.get(id).unwrap();
-        //     for i in start_block..end_block {
-        //         let target_buf = &cache[(i - start_block) * 512..(i - start_block + 1) * 512];
-        //         device.write_block(i, target_buf).unwrap();
-        //     }
-        // });
-        // self.dirty.lock().clear();
-        Ok(())
+        self.write_back_before(get_time_ms() as usize, |_| true)?;
+        self.device.flush()
+    }
+    fn write_fua(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let written = self.write(buf, offset)?;
+        let pages = offset / PAGE_CACHE_SIZE..(offset + buf.len()).div_ceil(PAGE_CACHE_SIZE);
+        self.write_back_before(get_time_ms() as usize, |id| pages.contains(&id))?;
+        self.device.flush()?;
+        Ok(written)
     }
 }
 
+/// 合并后的一个请求最多包含的扇区数
+const MAX_REQUEST_BLOCKS: usize = 256;
+
+/// virtio 块设备
+///
+/// 一批读写按扇区号排序后, 相邻的段合并为一个请求 (使用合并后的缓冲区), 所有请求一起
+/// 提交给设备, 设备同时处理多个请求。完成中断 ([`LowBlockDevice::handle_irq`]) 取回结果
+/// 并唤醒等待的任务; 不能睡眠时 (中断关闭或者还没有任务) 轮询设备。
 pub struct VirtIOBlkWrapper {
     device: Mutex<VirtIOBlk<HalImpl, MmioTransport>>,
-    wait_queue: Mutex<BTreeMap<u16, Arc<dyn KTask>>>,
+    /// 已经提交给设备、还没有取回结果的请求
+    in_flight: Mutex<BTreeMap<u16, Arc<BlkRequest>>>,
+    /// 已经取回的请求数, 以及等待任意请求完成 (队列有空位或者队列清空) 的任务
+    completions: Mutex<(usize, Vec<Arc<dyn KTask>>)>,
+}
+
+/// 提交给设备的一个请求
+///
+/// 请求头、缓冲区和响应在请求完成之前被设备访问, 因此放在堆上, 并且在完成之前由
+/// `in_flight` 持有。
+struct BlkRequest {
+    block_id: usize,
+    write: bool,
+    inner: Mutex<BlkRequestInner>,
+}
+
+struct BlkRequestInner {
+    req: BlkReq,
+    resp: BlkResp,
+    buf: Vec<u8>,
+    /// 设备返回的结果, 完成之前为 None
+    result: Option<AlienResult<()>>,
+    /// 睡眠等待这个请求的任务
+    waiter: Option<Arc<dyn KTask>>,
+}
+
+impl BlkRequest {
+    fn new(block_id: usize, write: bool, buf: Vec<u8>) -> Arc<Self> {
+        Arc::new(Self {
+            block_id,
+            write,
+            inner: Mutex::new(BlkRequestInner {
+                req: BlkReq::default(),
+                resp: BlkResp::default(),
+                buf,
+                result: None,
+                waiter: None,
+            }),
+        })
+    }
 }
 
 impl VirtIOBlkWrapper {
@@ -206,7 +553,8 @@ impl VirtIOBlkWrapper {
             .expect("failed to create blk driver");
         Self {
             device: Mutex::new(blk),
-            wait_queue: Mutex::new(BTreeMap::new()),
+            in_flight: Mutex::new(BTreeMap::new()),
+            completions: Mutex::new((0, Vec::new())),
         }
     }
 
@@ -215,118 +563,277 @@ impl VirtIOBlkWrapper {
             .expect("failed to create blk driver");
         Self {
             device: Mutex::new(blk),
-            wait_queue: Mutex::new(BTreeMap::new()),
+            in_flight: Mutex::new(BTreeMap::new()),
+            completions: Mutex::new((0, Vec::new())),
+        }
+    }
+
+    /// 把 (起始扇区, 字节数) 表示的各段按扇区号排序, 相邻的段合并为一组, 返回每组的段下标
+    fn merge(segments: &[(usize, usize)]) -> Vec<Vec<usize>> {
+        let mut order = (0..segments.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| segments[i].0);
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut end = 0;
+        let mut blocks = 0;
+        for i in order {
+            let (block_id, len) = segments[i];
+            let count = len / 512;
+            match groups.last_mut() {
+                Some(group) if block_id == end && blocks + count <= MAX_REQUEST_BLOCKS => {
+                    group.push(i);
+                    blocks += count;
+                }
+                _ => {
+                    groups.push(vec![i]);
+                    blocks = count;
+                }
+            }
+            end = block_id + count;
+        }
+        groups
+    }
+
+    /// 提交 `requests` 并等待全部完成, 返回第一个错误
+    ///
+    /// 队列满时先等待已经提交的最早的请求完成, 没有自己的请求时等待其它任务的请求完成。
+    fn run(&self, requests: &[Arc<BlkRequest>]) -> AlienResult<()> {
+        let mut result = Ok(());
+        // requests[..waited] 已经完成
+        let mut waited = 0;
+        for (i, request) in requests.iter().enumerate() {
+            loop {
+                let seen = self.completions.lock().0;
+                match self.submit(request) {
+                    Ok(()) => break,
+                    Err(virtio_drivers::Error::QueueFull) if waited < i => {
+                        result = result.and(self.wait(&requests[waited]));
+                        waited += 1;
+                    }
+                    Err(virtio_drivers::Error::QueueFull) => {
+                        // 队列被其它任务的请求占满
+                        self.wait_completion(seen);
+                    }
+                    Err(_) => {
+                        request.inner.lock().result = Some(Err(LinuxErrno::EIO));
+                        break;
+                    }
+                }
+            }
+        }
+        for request in &requests[waited..] {
+            result = result.and(self.wait(request));
+        }
+        result
+    }
+
+    /// 把 `request` 交给设备
+    fn submit(&self, request: &Arc<BlkRequest>) -> Result<(), virtio_drivers::Error> {
+        let mut device = self.device.lock();
+        let token = {
+            let mut inner = request.inner.lock();
+            let inner = &mut *inner;
+            // SAFETY: 请求头、缓冲区和响应在堆上, 请求完成之前由 in_flight 持有
+            unsafe {
+                if request.write {
+                    device.write_blocks_nb(
+                        request.block_id,
+                        &mut inner.req,
+                        &inner.buf,
+                        &mut inner.resp,
+                    )
+                } else {
+                    device.read_blocks_nb(
+                        request.block_id,
+                        &mut inner.req,
+                        &mut inner.buf,
+                        &mut inner.resp,
+                    )
+                }
+            }?
+        };
+        self.in_flight.lock().insert(token, request.clone());
+        Ok(())
+    }
+
+    /// 取回设备已经完成的请求, 唤醒等待它们的任务
+    fn reap(&self) {
+        let mut device = self.device.lock();
+        let mut reaped = 0;
+        while let Some(token) = device.peek_used() {
+            let Some(request) = self.in_flight.lock().remove(&token) else {
+                break;
+            };
+            let mut inner = request.inner.lock();
+            let inner = &mut *inner;
+            // SAFETY: 与提交时是同一组请求头、缓冲区和响应
+            let result = unsafe {
+                if request.write {
+                    device.complete_write_blocks(token, &inner.req, &inner.buf, &mut inner.resp)
+                } else {
+                    device.complete_read_blocks(token, &inner.req, &mut inner.buf, &mut inner.resp)
+                }
+            };
+            inner.result = Some(result.map_err(|_| LinuxErrno::EIO));
+            if let Some(task) = inner.waiter.take() {
+                task.to_wakeup();
+                shim::put_task(task);
+            }
+            reaped += 1;
+        }
+        drop(device);
+        if reaped > 0 {
+            let mut completions = self.completions.lock();
+            completions.0 += reaped;
+            for task in completions.1.drain(..) {
+                task.to_wakeup();
+                shim::put_task(task);
+            }
+        }
+    }
+
+    /// 等待 `request` 完成并返回它的结果
+    ///
+    /// 有当前任务并且中断打开 (没有持有锁) 时睡眠, 由完成中断唤醒; 否则轮询设备。
+    fn wait(&self, request: &BlkRequest) -> AlienResult<()> {
+        if !is_interrupt_enable() || shim::current_task().is_none() {
+            loop {
+                self.reap();
+                if let Some(result) = request.inner.lock().result.take() {
+                    return result;
+                }
+                spin_loop();
+            }
+        }
+        // 从检查结果到切换出去期间关闭中断, 完成中断不会在任务睡眠之前唤醒它
+        let enabled = is_interrupt_enable();
+        interrupt_disable();
+        let mut inner = request.inner.lock();
+        if inner.result.is_none() {
+            let task = shim::take_current_task().unwrap();
+            task.to_wait();
+            inner.waiter = Some(task.clone());
+            drop(inner);
+            shim::schedule_now(task);
+        } else {
+            drop(inner);
+        }
+        if enabled {
+            interrupt_enable();
+        }
+        let result = request.inner.lock().result.take();
+        result.unwrap_or(Err(LinuxErrno::EIO))
+    }
+
+    /// 等待取回的请求数超过 `seen`
+    ///
+    /// 与 [`wait`](Self::wait) 一样, 能睡眠时由完成中断唤醒, 否则轮询设备。
+    fn wait_completion(&self, seen: usize) {
+        if !is_interrupt_enable() || shim::current_task().is_none() {
+            while self.completions.lock().0 == seen {
+                self.reap();
+                spin_loop();
+            }
+            return;
+        }
+        let enabled = is_interrupt_enable();
+        interrupt_disable();
+        let mut completions = self.completions.lock();
+        if completions.0 == seen {
+            let task = shim::take_current_task().unwrap();
+            task.to_wait();
+            completions.1.push(task.clone());
+            drop(completions);
+            shim::schedule_now(task);
+        } else {
+            drop(completions);
+        }
+        if enabled {
+            interrupt_enable();
         }
     }
 }
 
 impl LowBlockDevice for VirtIOBlkWrapper {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
-        let res = self
-            .device
-            .lock()
-            .read_blocks(block_id, buf)
-            .map_err(|_| LinuxErrno::EIO.into());
-        res
+        self.read_batch(&mut [(block_id, buf)])
     }
     fn write_block(&self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
-        self.device
-            .lock()
-            .write_blocks(block_id, buf)
-            .map_err(|_| LinuxErrno::EIO.into())
+        self.write_batch(&[(block_id, buf)])
     }
 
     fn capacity(&self) -> usize {
         self.device.lock().capacity() as usize
     }
 
+    fn flush(&self) -> AlienResult<()> {
+        // 设备的同步请求要求队列中没有其它请求, 先等待所有已提交的请求完成
+        loop {
+            self.reap();
+            let seen = self.completions.lock().0;
+            let mut device = self.device.lock();
+            if self.in_flight.lock().is_empty() {
+                return device.flush().map_err(|_| LinuxErrno::EIO);
+            }
+            drop(device);
+            self.wait_completion(seen);
+        }
+    }
+
     fn read_block_async(&self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
-        return self.read_block(block_id, buf);
-        // let task = shim::take_current_task();
-        // if task.is_none() {
-        //     return self.read_block(block_id, buf);
-        // }
-        // let task = task.unwrap();
-        //
-        // use virtio_drivers::device::blk::{BlkReq,BlkResp,RespStatus};
-        //
-        // let mut resp = BlkResp::default();
-        // let mut req = BlkReq::default();
-        // let mut device = self.device.lock();
-        // let token = unsafe { device.read_block_nb(block_id, &mut req, buf, &mut resp) };
-        // match token {
-        //     Ok(token) => {
-        //         task.to_wait();
-        //         self.wait_queue.lock().insert(token, task.clone());
-        //         // platform::println!("insert token:{}, intr: {}", token, arch::is_interrupt_enable());
-        //         drop(device);
-        //         shim::schedule_now(task);
-        //         unsafe {
-        //             self.device
-        //                 .lock()
-        //                 .complete_read_block(token, &req, buf, &mut resp)
-        //                 .unwrap();
-        //         }
-        //         assert_eq!(
-        //             resp.status(),
-        //             RespStatus::OK,
-        //             "Error {:?} reading block.",
-        //             resp.status()
-        //         );
-        //         Ok(())
-        //     }
-        //     Err(virtio_drivers::Error::QueueFull) => self.read_block(block_id, buf),
-        //     Err(_) => Err(LinuxErrno::EIO.into()),
-        // }
+        self.read_block(block_id, buf)
     }
 
     fn write_block_async(&self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
-        return self.write_block(block_id, buf);
-        // let task = shim::take_current_task();
-        // if task.is_none() {
-        //     return self.write_block(block_id, buf);
-        // }
-        // let task = task.unwrap();
-        // let mut resp = BlkResp::default();
-        // let mut req = BlkReq::default();
-        //
-        // let mut device = self.device.lock();
-        //
-        // let token = unsafe { device.write_block_nb(block_id, &mut req, buf, &mut resp) };
-        // match token {
-        //     Ok(token) => {
-        //         task.to_wait();
-        //         self.wait_queue.lock().insert(token, task.clone());
-        //         drop(device);
-        //         shim::schedule_now(task);
-        //         unsafe {
-        //             self.device
-        //                 .lock()
-        //                 .complete_write_block(token, &req, buf, &mut resp)
-        //                 .unwrap();
-        //         }
-        //         assert_eq!(
-        //             resp.status(),
-        //             RespStatus::OK,
-        //             "Error {:?} writing block.",
-        //             resp.status()
-        //         );
-        //         Ok(())
-        //     }
-        //     Err(virtio_drivers::Error::QueueFull) => self.write_block(block_id, buf),
-        //     Err(_) => Err(LinuxErrno::EIO.into()),
-        // }
+        self.write_block(block_id, buf)
     }
 
-    fn handle_irq(&self) {
-        let mut device = self.device.lock();
-        device.ack_interrupt();
-        if let Some(token) = device.peek_used() {
-            let mut wait_queue = self.wait_queue.lock();
-            let task = wait_queue.remove(&token).unwrap();
-            task.to_wakeup();
-            shim::put_task(task);
+    fn read_batch(&self, requests: &mut [(usize, &mut [u8])]) -> AlienResult<()> {
+        let segments = requests
+            .iter()
+            .map(|(block_id, buf)| (*block_id, buf.len()))
+            .collect::<Vec<_>>();
+        let groups = Self::merge(&segments);
+        let blk_requests = groups
+            .iter()
+            .map(|group| {
+                let len = group.iter().map(|&i| segments[i].1).sum();
+                BlkRequest::new(segments[group[0]].0, false, vec![0; len])
+            })
+            .collect::<Vec<_>>();
+        self.run(&blk_requests)?;
+        for (group, request) in groups.iter().zip(&blk_requests) {
+            let inner = request.inner.lock();
+            let mut offset = 0;
+            for &i in group {
+                let buf = &mut requests[i].1;
+                buf.copy_from_slice(&inner.buf[offset..offset + buf.len()]);
+                offset += buf.len();
+            }
         }
+        Ok(())
+    }
+
+    fn write_batch(&self, requests: &[(usize, &[u8])]) -> AlienResult<()> {
+        let segments = requests
+            .iter()
+            .map(|(block_id, buf)| (*block_id, buf.len()))
+            .collect::<Vec<_>>();
+        let blk_requests = Self::merge(&segments)
+            .iter()
+            .map(|group| {
+                let mut buf = Vec::new();
+                for &i in group {
+                    buf.extend_from_slice(requests[i].1);
+                }
+                BlkRequest::new(requests[group[0]].0, true, buf)
+            })
+            .collect::<Vec<_>>();
+        self.run(&blk_requests)
+    }
+
+    fn handle_irq(&self) {
+        self.device.lock().ack_interrupt();
+        self.reap();
     }
 }
 
@@ -459,3 +966,33 @@ fn sleep_ms_until(ms: usize, mut f: impl FnMut() -> bool) {
         core::hint::spin_loop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_adjacent_segments() {
+        // 乱序的相邻段合并为一组, 组内按扇区号排列
+        let segments = [(16, 4096), (0, 4096), (8, 4096)];
+        assert_eq!(VirtIOBlkWrapper::merge(&segments), vec![vec![1, 2, 0]]);
+
+        // 不相邻的段分为不同的组
+        let segments = [(0, 4096), (16, 4096), (9, 512)];
+        assert_eq!(
+            VirtIOBlkWrapper::merge(&segments),
+            vec![vec![0], vec![2], vec![1]]
+        );
+
+        // 一组不超过 MAX_REQUEST_BLOCKS 个扇区
+        let segments = (0..MAX_REQUEST_BLOCKS / BLOCKS_PER_PAGE + 1)
+            .map(|i| (i * BLOCKS_PER_PAGE, PAGE_CACHE_SIZE))
+            .collect::<Vec<_>>();
+        let groups = VirtIOBlkWrapper::merge(&segments);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].len() * BLOCKS_PER_PAGE, MAX_REQUEST_BLOCKS);
+        assert_eq!(groups[1], vec![segments.len() - 1]);
+
+        assert!(VirtIOBlkWrapper::merge(&[]).is_empty());
+    }
+}
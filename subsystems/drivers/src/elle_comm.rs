@@ -1,15 +1,23 @@
 //! 简化的串口通信驱动
 //!
-//! 使用 UART 作为 Host-Kernel 通信通道
-//! 这比 virtio-serial 更简单且同样有效
+//! 优先使用 virtio-serial (`/dev/hvc1`) 作为 Host-Kernel 通信通道,
+//! 设备不存在时退回到 UART 缓冲区
 
 use alloc::{vec::Vec, string::String};
 use core::sync::atomic::{AtomicBool, Ordering};
+use device_interface::UartDevice;
 use log::{info, error, debug};
 
+use crate::virtio_serial::{get_virtio_serial, MAX_RX_BUFFER};
+
+/// 单个数据包的最大长度
+///
+/// 整个数据包 (含长度前缀) 必须能放进 virtio-serial 的接收缓冲区, 否则永远凑不齐
+pub const MAX_PACKET_SIZE: usize = MAX_RX_BUFFER - 4;
+
 // ==================== UART 设备包装器 ====================
 
-pub struct UartDevice {
+pub struct ElleUart {
     /// 是否已初始化
     initialized: AtomicBool,
     /// 接收缓冲区
@@ -18,7 +26,7 @@ pub struct UartDevice {
     tx_buffer: Vec<u8>,
 }
 
-impl UartDevice {
+impl ElleUart {
     pub const fn new() -> Self {
         Self {
             initialized: AtomicBool::new(false),
@@ -98,7 +106,7 @@ impl UartDevice {
 
 // ==================== 全局 UART 设备 ====================
 
-static UART_DEVICE: spin::Mutex<UartDevice> = spin::Mutex::new(UartDevice::new());
+static UART_DEVICE: spin::Mutex<ElleUart> = spin::Mutex::new(ElleUart::new());
 
 /// 初始化全局 UART 设备
 pub fn init_uart_comm() {
@@ -107,52 +115,80 @@ pub fn init_uart_comm() {
 
 /// 从 Host 读取数据
 pub fn read_from_host() -> Option<Vec<u8>> {
-    UART_DEVICE.lock().try_read()
+    match get_virtio_serial() {
+        Some(serial) => serial.try_read(),
+        None => UART_DEVICE.lock().try_read(),
+    }
 }
 
 /// 向 Host 写入数据
 pub fn write_to_host(data: &[u8]) -> Result<(), ()> {
-    UART_DEVICE.lock().try_write(data)
+    match get_virtio_serial() {
+        Some(serial) => serial.try_write(data),
+        None => UART_DEVICE.lock().try_write(data),
+    }
 }
 
 /// 检查是否有数据可读
 pub fn has_data() -> bool {
-    UART_DEVICE.lock().rx_buffer.is_empty()
+    match get_virtio_serial() {
+        Some(serial) => serial.have_data_to_get(),
+        None => !UART_DEVICE.lock().rx_buffer.is_empty(),
+    }
 }
 
 // ==================== 高级协议 ====================
 
 /// 发送长度前缀的数据包
+///
+/// 格式: [len:4 (BE)][data:len], 与 Host 端 `elle_dbfs_client` 一致
 pub fn send_packet(data: &[u8]) -> Result<(), ()> {
-    // 发送长度前缀
+    if data.len() > MAX_PACKET_SIZE {
+        error!("❌ Packet too large ({} bytes), not sent", data.len());
+        return Err(());
+    }
     let len = data.len() as u32;
-    write_to_host(&len.to_be_bytes())?;
-
-    // 发送数据
-    write_to_host(data)?;
-
-    // 发送换行符作为分隔符
-    write_to_host(b"\n")?;
+    let mut packet = Vec::with_capacity(4 + data.len());
+    packet.extend_from_slice(&len.to_be_bytes());
+    packet.extend_from_slice(data);
+    write_to_host(&packet)?;
 
     debug!("📦 Sent packet: {} bytes", len);
     Ok(())
 }
 
 /// 接收长度前缀的数据包
+///
+/// 格式: [len:4 (BE)][data:len]。数据包不完整时返回 None 且不消费缓冲区,
+/// 下次调用时继续等待剩余字节。
 pub fn recv_packet() -> Option<Vec<u8>> {
-    // 读取长度前缀 (4 字节)
-    // 简化实现: 直接从缓冲区读取
-    // 实际需要实现更复杂的协议
+    let serial = get_virtio_serial()?;
+
+    let header = serial.peek(4)?;
+    let len = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+    if len > MAX_PACKET_SIZE {
+        // 长度前缀已损坏, 无法再对齐数据包边界, 只能清空缓冲区重新同步
+        error!("❌ Packet too large ({} bytes), resetting stream", len);
+        serial.try_read();
+        return None;
+    }
 
-    // 暂时返回 None
-    None
+    // 等待整个数据包 (长度前缀 + 数据) 到齐
+    let packet = serial.try_read_exact(4 + len)?;
+
+    debug!("📦 Received packet: {} bytes", len);
+    Some(packet[4..4 + len].to_vec())
 }
 
 // ==================== 导出的同步接口 ====================
 
 /// 从 Host 读取 Elle 请求
 pub fn read_elle_request() -> Option<Vec<u8>> {
-    read_from_host()
+    if get_virtio_serial().is_some() {
+        recv_packet()
+    } else {
+        read_from_host()
+    }
 }
 
 /// 向 Host 发送 Elle 响应
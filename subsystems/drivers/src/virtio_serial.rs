@@ -2,127 +2,142 @@
 //!
 //! 用于 Host Linux 与 Alien 内核之间的通信
 //! 支持 DBFS Elle 测试框架
-
-use alloc::{boxed::Box, vec::Vec};
-use core::sync::atomic::{AtomicU64, Ordering};
-
-use log::{info, error, debug};
+//!
+//! 设备使用 virtio-console 协议 (QEMU `-device virtio-serial-device` + `virtconsole`),
+//! 拥有独立的 RX/TX virtqueue, 不与日志 UART 共享通道。
+//! 接收是中断驱动的: `handle_irq` 把 RX 队列里的数据搬到软件缓冲区并唤醒等待的任务。
+
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    ptr::NonNull,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use device_interface::{DeviceBase, UartDevice};
+use ksync::Mutex;
+use log::{debug, info};
+use shim::KTask;
+use spin::Once;
+use virtio_drivers::{
+    device::console::VirtIOConsole,
+    transport::mmio::{MmioTransport, VirtIOHeader},
+};
+
+use crate::hal::HalImpl;
+
+/// 软件接收缓冲区上限
+///
+/// 缓冲区满时不再从 RX 队列取数据, 剩余数据留在设备中 (由 virtio 流控让 Host 等待),
+/// 读走一部分之后再继续搬运。
+pub const MAX_RX_BUFFER: usize = 64 * 1024;
 
 // ==================== Virtio-Serial 设备 ====================
 
 pub struct VirtioSerialDevice {
-    /// 设备寄存器基址
-    base_addr: usize,
-    /// 接收队列
-    rx_queue: VirtQueue,
-    /// 发送队列
-    tx_queue: VirtQueue,
-    /// 接收缓冲区
-    rx_buffer: Vec<u8>,
-    /// 发送缓冲区
-    tx_buffer: Vec<u8>,
+    inner: Mutex<VirtioSerialInner>,
     /// 接收到的字节数
     rx_count: AtomicU64,
+    /// 发送的字节数
+    tx_count: AtomicU64,
 }
 
-/// VirtQueue (简化版)
-struct VirtQueue {
-    queue_num: u16,
-    desc: *const VirtqDesc,
-    avail: *const VirtqAvail,
-    used: *const VirtqUsed,
-}
-
-/// Virtqueue 描述符
-#[repr(C)]
-#[derive(Debug, Clone, Copy)]
-struct VirtqDesc {
-    addr: u64,
-    len: u32,
-    flags: u16,
-    next: u16,
-}
-
-/// Virtqueue 可用环
-#[repr(C)]
-struct VirtqAvail {
-    flags: u16,
-    idx: u16,
-    ring: [u16; 1],  // 实际大小由 queue_size 决定
-}
+unsafe impl Send for VirtioSerialDevice {}
 
-/// Virtqueue 已用环
-#[repr(C)]
-struct VirtqUsed {
-    flags: u16,
-    idx: u16,
-    ring: [VirtqUsedElem; 1],
-}
+unsafe impl Sync for VirtioSerialDevice {}
 
-#[repr(C)]
-struct VirtqUsedElem {
-    id: u32,
-    len: u32,
+struct VirtioSerialInner {
+    /// virtio-console 驱动 (内部持有 RX/TX virtqueue)
+    driver: VirtIOConsole<HalImpl, MmioTransport>,
+    /// 接收缓冲区
+    rx_buffer: VecDeque<u8>,
+    /// 等待数据的任务
+    wait_queue: VecDeque<Arc<dyn KTask>>,
 }
 
 impl VirtioSerialDevice {
+    fn new(driver: VirtIOConsole<HalImpl, MmioTransport>) -> Self {
+        Self {
+            inner: Mutex::new(VirtioSerialInner {
+                driver,
+                rx_buffer: VecDeque::with_capacity(4096),
+                wait_queue: VecDeque::new(),
+            }),
+            rx_count: AtomicU64::new(0),
+            tx_count: AtomicU64::new(0),
+        }
+    }
+
     /// 创建新的 virtio-serial 设备
     ///
     /// # Safety
-    /// 需要确保 base_addr 是有效的 MMIO 地址
-    pub unsafe fn new(base_addr: usize) -> Self {
+    /// 需要确保 base_addr 是有效的 virtio-mmio 地址
+    pub unsafe fn from_addr(base_addr: usize) -> Self {
         info!("🔌 Initializing Virtio-Serial device at 0x{:x}", base_addr);
+        let header = NonNull::new(base_addr as *mut VirtIOHeader).unwrap();
+        let transport = MmioTransport::new(header).unwrap();
+        Self::from_mmio(transport)
+    }
 
-        // TODO: 初始化 virtqueues
-        let rx_queue = VirtQueue {
-            queue_num: 0,
-            desc: core::ptr::null(),
-            avail: core::ptr::null(),
-            used: core::ptr::null(),
-        };
-
-        let tx_queue = VirtQueue {
-            queue_num: 1,
-            desc: core::ptr::null(),
-            avail: core::ptr::null(),
-            used: core::ptr::null(),
-        };
+    pub fn from_mmio(mmio: MmioTransport) -> Self {
+        let console = VirtIOConsole::<HalImpl, MmioTransport>::new(mmio)
+            .expect("failed to create virtio console driver");
+        Self::new(console)
+    }
 
-        Self {
-            base_addr,
-            rx_queue,
-            tx_queue,
-            rx_buffer: Vec::with_capacity(4096),
-            tx_buffer: Vec::with_capacity(4096),
-            rx_count: AtomicU64::new(0),
+    /// 把设备 RX 队列中已完成的数据搬到软件缓冲区, 返回搬运的字节数
+    ///
+    /// 缓冲区满时停止搬运, 不丢弃数据
+    fn drain_rx(&self, inner: &mut VirtioSerialInner) -> usize {
+        let mut count = 0;
+        while inner.rx_buffer.len() < MAX_RX_BUFFER {
+            match inner.driver.recv(true) {
+                Ok(Some(byte)) => inner.rx_buffer.push_back(byte),
+                _ => break,
+            }
+            count += 1;
         }
+        self.rx_count.fetch_add(count as u64, Ordering::AcqRel);
+        count
     }
 
     /// 非阻塞读取可用数据
-    pub fn try_read(&mut self) -> Option<Vec<u8>> {
-        // TODO: 从 virtqueue 读取数据
-        // 1. 检查 used ring
-        // 2. 获取完成的 descriptor
-        // 3. 复制数据到 buffer
-        // 4. 释放 descriptor 回 avail ring
-
-        // 暂时返回 None (需要实际硬件/模拟器)
-        None
-    }
-
-    /// 非阻塞写入数据
-    pub fn try_write(&mut self, data: &[u8]) -> Result<(), ()> {
-        // TODO: 写入数据到 virtqueue
-        // 1. 分配 descriptor
-        // 2. 设置 addr = data 的物理地址
-        // 3. 设置 len = data.len()
-        // 4. 添加到 avail ring
-        // 5. 通知设备
+    pub fn try_read(&self) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock();
+        self.drain_rx(&mut inner);
+        if inner.rx_buffer.is_empty() {
+            return None;
+        }
+        Some(inner.rx_buffer.drain(..).collect())
+    }
 
-        debug!("📤 Virtio-Serial: writing {} bytes", data.len());
+    /// 非阻塞读取恰好 `len` 字节, 数据不足时不消费缓冲区
+    pub fn try_read_exact(&self, len: usize) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock();
+        self.drain_rx(&mut inner);
+        if inner.rx_buffer.len() < len {
+            return None;
+        }
+        Some(inner.rx_buffer.drain(..len).collect())
+    }
 
-        // 暂时只记录日志
-        self.tx_buffer.extend_from_slice(data);
+    /// 查看缓冲区前 `len` 字节但不消费
+    pub fn peek(&self, len: usize) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock();
+        self.drain_rx(&mut inner);
+        if inner.rx_buffer.len() < len {
+            return None;
+        }
+        Some(inner.rx_buffer.iter().take(len).copied().collect())
+    }
+
+    /// 写入数据 (TX virtqueue)
+    pub fn try_write(&self, data: &[u8]) -> Result<(), ()> {
+        debug!("📤 Virtio-Serial: writing {} bytes", data.len());
+        let mut inner = self.inner.lock();
+        for &byte in data {
+            inner.driver.send(byte).map_err(|_| ())?;
+        }
+        self.tx_count.fetch_add(data.len() as u64, Ordering::AcqRel);
         Ok(())
     }
 
@@ -131,54 +146,90 @@ impl VirtioSerialDevice {
         self.rx_count.load(Ordering::Acquire)
     }
 
-    /// 获取发送缓冲区大小
-    pub fn tx_pending(&self) -> usize {
-        self.tx_buffer.len()
+    /// 获取发送的字节数
+    pub fn tx_count(&self) -> u64 {
+        self.tx_count.load(Ordering::Acquire)
     }
 
-    /// 清空发送缓冲区
-    pub fn flush_tx(&mut self) {
-        self.tx_buffer.clear();
+    /// 缓冲区中待读取的字节数
+    pub fn rx_pending(&self) -> usize {
+        self.inner.lock().rx_buffer.len()
     }
 }
 
-// ==================== 全局设备实例 ====================
+impl UartDevice for VirtioSerialDevice {
+    fn put(&self, c: u8) {
+        let _ = self.try_write(&[c]);
+    }
 
-use core::sync::atomic::AtomicBool;
+    fn get(&self) -> Option<u8> {
+        loop {
+            let mut inner = self.inner.lock();
+            self.drain_rx(&mut inner);
+            if let Some(c) = inner.rx_buffer.pop_front() {
+                return Some(c);
+            }
+            let task = shim::take_current_task().unwrap();
+            task.to_wait();
+            inner.wait_queue.push_back(task.clone());
+            drop(inner);
+            shim::schedule_now(task);
+        }
+    }
+
+    /// 原样发送, 不做换行转换 (承载二进制协议)
+    fn put_bytes(&self, bytes: &[u8]) {
+        let _ = self.try_write(bytes);
+    }
 
-static VIRTIO_SERIAL_ENABLED: AtomicBool = AtomicBool::new(false);
-static mut VIRTIO_SERIAL_DEVICE: Option<VirtioSerialDevice> = None;
+    fn have_data_to_get(&self) -> bool {
+        let mut inner = self.inner.lock();
+        self.drain_rx(&mut inner);
+        !inner.rx_buffer.is_empty()
+    }
 
-/// 初始化全局 virtio-serial 设备
-pub fn init_virtio_serial(base_addr: usize) {
-    unsafe {
-        VIRTIO_SERIAL_DEVICE = Some(VirtioSerialDevice::new(base_addr));
-        VIRTIO_SERIAL_ENABLED.store(true, Ordering::Release);
-        info!("✅ Virtio-Serial initialized");
+    fn have_space_to_put(&self) -> bool {
+        true
     }
 }
 
-/// 获取全局设备实例
-///
-/// # Safety
-/// 必须在 init_virtio_serial 之后调用
-pub unsafe fn get_virtio_serial() -> Option<&'static mut VirtioSerialDevice> {
-    if VIRTIO_SERIAL_ENABLED.load(Ordering::Acquire) {
-        VIRTIO_SERIAL_DEVICE.as_mut()
-    } else {
-        None
+impl DeviceBase for VirtioSerialDevice {
+    fn handle_irq(&self) {
+        let mut inner = self.inner.lock();
+        let _ = inner.driver.ack_interrupt();
+        let count = self.drain_rx(&mut inner);
+        if count > 0 {
+            while let Some(task) = inner.wait_queue.pop_front() {
+                task.to_wakeup();
+                shim::put_task(task);
+            }
+        }
     }
 }
 
+// ==================== 全局设备实例 ====================
+
+static VIRTIO_SERIAL_DEVICE: Once<Arc<VirtioSerialDevice>> = Once::new();
+
+/// 初始化全局 virtio-serial 设备
+pub fn init_virtio_serial(device: Arc<VirtioSerialDevice>) {
+    VIRTIO_SERIAL_DEVICE.call_once(|| device);
+    info!("✅ Virtio-Serial initialized");
+}
+
+/// 获取全局设备实例
+pub fn get_virtio_serial() -> Option<Arc<VirtioSerialDevice>> {
+    VIRTIO_SERIAL_DEVICE.get().cloned()
+}
+
 // ==================== 简化实现 (用于测试) ====================
 
-/// 模拟从 Host 读取 (用于开发阶段)
+/// 模拟从 Host 读取 (没有 virtio-serial 设备时使用)
 pub fn mock_read_from_host() -> Option<Vec<u8>> {
-    // 暂时返回 None,等待真实 virtqueue 实现
     None
 }
 
-/// 模拟向 Host 写入 (用于开发阶段)
+/// 模拟向 Host 写入 (没有 virtio-serial 设备时使用)
 pub fn mock_write_to_host(data: &[u8]) -> Result<(), ()> {
     // 打印日志模拟发送
     info!("📤 [MOCK] Sending to Host: {} bytes", data.len());
@@ -196,5 +247,5 @@ pub fn mock_write_to_host(data: &[u8]) -> Result<(), ()> {
 /// 检查是否使用 mock 模式
 pub fn is_mock_mode() -> bool {
     // 如果 virtio-serial 未初始化,使用 mock 模式
-    !VIRTIO_SERIAL_ENABLED.load(Ordering::Acquire)
-}
\ No newline at end of file
+    VIRTIO_SERIAL_DEVICE.get().is_none()
+}
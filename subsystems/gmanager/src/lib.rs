@@ -118,6 +118,14 @@ impl<T: Clone> MinimalManager<T> {
         Ok(val.clone())
     }
 
+    /// iterate over the used indexes and their values
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.data
+            .iter()
+            .enumerate()
+            .filter_map(|(index, val)| val.as_ref().map(|val| (index, val)))
+    }
+
     /// User should ensure that the index is valid
     pub fn insert_with_index(&mut self, index: usize, val: T) -> Result<(), ManagerError> {
         if index >= self.max {
@@ -183,5 +191,8 @@ mod tests {
         let _ans = manager.remove(1).unwrap();
         let index = manager.insert(10).unwrap();
         assert_eq!(index, 1);
+        manager.remove(3).unwrap();
+        let used = manager.iter().map(|(index, _)| index).collect::<alloc::vec::Vec<_>>();
+        assert_eq!(used, [0, 1, 2, 4, 5, 6, 7, 8, 9]);
     }
 }
@@ -117,6 +117,16 @@ pub fn alloc_frame_trackers(count: usize) -> FrameTracker {
     FrameTracker::new(frame, count)
 }
 
+/// Like [`alloc_frame_trackers`], but returns None when out of memory
+pub fn try_alloc_frame_trackers(count: usize) -> Option<FrameTracker> {
+    let frame = FRAME_ALLOCATOR.lock().alloc_pages(count, FRAME_SIZE).ok()?;
+    for i in 0..count {
+        let refs = FRAME_REF_MANAGER.lock().add_ref(frame + i);
+        assert_eq!(refs, 1)
+    }
+    Some(FrameTracker::new(frame, count))
+}
+
 pub struct VmmPageAllocator;
 
 impl PagingIf for VmmPageAllocator {
@@ -10,6 +10,10 @@ pub trait KTask: Send + Sync + DowncastSync {
     fn to_wait(&self);
     fn to_wakeup(&self);
     fn have_signal(&self) -> bool;
+    /// Process id of the task
+    fn pid(&self) -> usize;
+    /// Thread id of the task
+    fn tid(&self) -> usize;
 }
 
 impl_downcast!(sync KTask);
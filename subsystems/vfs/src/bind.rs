@@ -0,0 +1,185 @@
+//! Dentries of a bind mount
+//!
+//! A bind mount shows an existing directory at another path. The bound tree gets
+//! its own dentries over the same inodes, so lookups below the bind root stay
+//! inside it, `..` leads back to where it is mounted, and mounts inside the source
+//! directory are not carried over.
+//!
+//! Being independent of any filesystem, these dentries also serve as the
+//! dentries of overlay mounts and of the mounts of a copied mount namespace.
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use ksync::Mutex;
+use vfscore::{
+    dentry::VfsDentry, error::VfsError, fstype::VfsMountPoint, inode::VfsInode,
+    utils::VfsNodeType, VfsResult,
+};
+
+pub struct BindDentry {
+    inner: Mutex<BindDentryInner>,
+}
+
+/// Roots of all bind trees, to find the tree of a mount when unmounting it
+static ROOTS: Mutex<Vec<Weak<BindDentry>>> = Mutex::new(Vec::new());
+
+struct BindDentryInner {
+    parent: Weak<dyn VfsDentry>,
+    inode: Arc<dyn VfsInode>,
+    name: String,
+    mnt: Option<VfsMountPoint>,
+    children: BTreeMap<String, Arc<BindDentry>>,
+}
+
+impl BindDentry {
+    /// Root of a bind mount of `inode`
+    pub fn root(inode: Arc<dyn VfsInode>) -> Arc<Self> {
+        let root = Self::new(inode, Weak::<BindDentry>::new(), "/".to_string());
+        let mut roots = ROOTS.lock();
+        roots.retain(|root| root.strong_count() > 0);
+        roots.push(Arc::downgrade(&root));
+        root
+    }
+
+    fn new(inode: Arc<dyn VfsInode>, parent: Weak<dyn VfsDentry>, name: String) -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(BindDentryInner {
+                parent,
+                inode,
+                name,
+                mnt: None,
+                children: BTreeMap::new(),
+            }),
+        })
+    }
+
+    /// Whether a dentry below this one is still used, e.g. by an open file or as
+    /// a working directory: children are only held by their parent otherwise
+    fn has_external_refs(&self) -> bool {
+        let inner = self.inner.lock();
+        inner
+            .children
+            .values()
+            .any(|child| Arc::strong_count(child) > 1 || child.has_external_refs())
+    }
+
+    /// Whether this dentry still names the inode its directory now has under its name
+    fn is_current(&self, found: VfsResult<Arc<dyn VfsInode>>) -> bool {
+        let Ok(found) = found else {
+            return false;
+        };
+        let inode = self.inner.lock().inode.clone();
+        if Arc::ptr_eq(&inode, &found) {
+            return true;
+        }
+        match (inode.get_attr(), found.get_attr()) {
+            (Ok(old), Ok(new)) => old.st_ino == new.st_ino && old.st_dev == new.st_dev,
+            _ => false,
+        }
+    }
+}
+
+impl VfsDentry for BindDentry {
+    fn name(&self) -> String {
+        self.inner.lock().name.clone()
+    }
+
+    fn to_mount_point(
+        self: Arc<Self>,
+        sub_fs_root: Arc<dyn VfsDentry>,
+        mount_flag: u32,
+    ) -> VfsResult<()> {
+        let mount_point = Arc::downgrade(&(self.clone() as Arc<dyn VfsDentry>));
+        self.inner.lock().mnt = Some(VfsMountPoint {
+            root: sub_fs_root,
+            mount_point,
+            mnt_flags: mount_flag,
+        });
+        Ok(())
+    }
+
+    fn inode(&self) -> VfsResult<Arc<dyn VfsInode>> {
+        Ok(self.inner.lock().inode.clone())
+    }
+
+    fn mount_point(&self) -> Option<VfsMountPoint> {
+        self.inner.lock().mnt.clone()
+    }
+
+    fn clear_mount_point(&self) {
+        self.inner.lock().mnt = None;
+    }
+
+    /// Cached children are checked against the directory first, as the source
+    /// tree can change behind the bind mount, e.g. through its original path
+    fn find(&self, path: &str) -> Option<Arc<dyn VfsDentry>> {
+        let (dir, child) = {
+            let inner = self.inner.lock();
+            if inner.inode.inode_type() != VfsNodeType::Dir {
+                return None;
+            }
+            (inner.inode.clone(), inner.children.get(path)?.clone())
+        };
+        if child.mount_point().is_some() || child.is_current(dir.lookup(path)) {
+            return Some(child as Arc<dyn VfsDentry>);
+        }
+        let mut inner = self.inner.lock();
+        if inner
+            .children
+            .get(path)
+            .is_some_and(|cached| Arc::ptr_eq(cached, &child))
+        {
+            inner.children.remove(path);
+        }
+        None
+    }
+
+    fn insert(
+        self: Arc<Self>,
+        name: &str,
+        child: Arc<dyn VfsInode>,
+    ) -> VfsResult<Arc<dyn VfsDentry>> {
+        let parent = Arc::downgrade(&(self.clone() as Arc<dyn VfsDentry>));
+        let dentry = BindDentry::new(child, parent, name.to_string());
+        let mut inner = self.inner.lock();
+        if inner.children.contains_key(name) {
+            return Err(VfsError::EExist);
+        }
+        inner.children.insert(name.to_string(), dentry.clone());
+        Ok(dentry)
+    }
+
+    fn remove(&self, name: &str) -> Option<Arc<dyn VfsDentry>> {
+        let mut inner = self.inner.lock();
+        inner
+            .children
+            .remove(name)
+            .map(|child| child as Arc<dyn VfsDentry>)
+    }
+
+    fn parent(&self) -> Option<Arc<dyn VfsDentry>> {
+        self.inner.lock().parent.upgrade()
+    }
+
+    fn set_parent(&self, parent: &Arc<dyn VfsDentry>) {
+        self.inner.lock().parent = Arc::downgrade(parent);
+    }
+}
+
+/// Whether `root` is the root of a bind tree with dentries still in use
+///
+/// Files opened through a bind mount (or a mount of a copied namespace) hold
+/// these dentries, not the dentries of the filesystem itself.
+pub(crate) fn in_use(root: &Arc<dyn VfsDentry>) -> bool {
+    let root = Arc::as_ptr(root) as *const u8;
+    let roots = ROOTS.lock().clone();
+    roots
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|bind| Arc::as_ptr(bind) as *const u8 == root)
+        .is_some_and(|bind| bind.has_external_refs())
+}
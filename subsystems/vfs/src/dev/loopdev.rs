@@ -0,0 +1,311 @@
+//! Loop block devices
+//!
+//! `/dev/loopN` turns a regular file into a block device, so a filesystem image
+//! kept as a file on any mounted filesystem can be mounted like `/dev/sda`. A
+//! loop device is attached to an open file with `LOOP_SET_FD` and detached
+//! with `LOOP_CLR_FD` (both handled by the `ioctl` system call, which resolves
+//! the file descriptor). Until attached, a loop device is empty.
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+};
+
+use constants::{AlienResult, DeviceId, LinuxErrno};
+use ksync::Mutex;
+use log::info;
+use vfscore::{
+    error::VfsError,
+    file::VfsFile,
+    inode::{InodeAttr, VfsInode},
+    superblock::VfsSuperBlock,
+    utils::{VfsFileStat, VfsNodePerm, VfsNodeType},
+    VfsResult,
+};
+
+use super::DEVICES;
+
+/// Number of loop devices created in `/dev`
+pub const LOOP_DEVICES: usize = 8;
+
+/// Attach an open file (`arg` is its file descriptor)
+pub const LOOP_SET_FD: u32 = 0x4c00;
+/// Detach the backing file
+pub const LOOP_CLR_FD: u32 = 0x4c01;
+/// Get a [`LoopInfo`]
+pub const LOOP_GET_STATUS: u32 = 0x4c03;
+/// Get a [`LoopInfo64`]
+pub const LOOP_GET_STATUS64: u32 = 0x4c05;
+
+/// `LO_FLAGS_READ_ONLY`: the backing file was not opened for writing
+pub const LO_FLAGS_READ_ONLY: u32 = 1;
+
+const LO_NAME_SIZE: usize = 64;
+
+/// `struct loop_info`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LoopInfo {
+    pub lo_number: i32,
+    pub lo_device: u32,
+    pub lo_inode: u64,
+    pub lo_rdevice: u32,
+    pub lo_offset: i32,
+    pub lo_encrypt_type: i32,
+    pub lo_encrypt_key_size: i32,
+    pub lo_flags: i32,
+    pub lo_name: [u8; LO_NAME_SIZE],
+    pub lo_encrypt_key: [u8; 32],
+    pub lo_init: [u64; 2],
+    pub reserved: [u8; 4],
+}
+
+/// `struct loop_info64`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LoopInfo64 {
+    pub lo_device: u64,
+    pub lo_inode: u64,
+    pub lo_rdevice: u64,
+    pub lo_offset: u64,
+    pub lo_sizelimit: u64,
+    pub lo_number: u32,
+    pub lo_encrypt_type: u32,
+    pub lo_encrypt_key_size: u32,
+    pub lo_flags: u32,
+    pub lo_file_name: [u8; LO_NAME_SIZE],
+    pub lo_crypt_name: [u8; LO_NAME_SIZE],
+    pub lo_encrypt_key: [u8; 32],
+    pub lo_init: [u64; 2],
+}
+
+/// The file a loop device is attached to
+struct Backing {
+    inode: Arc<dyn VfsInode>,
+    /// Path of the file, reported by `LOOP_GET_STATUS`
+    name: String,
+    read_only: bool,
+}
+
+pub struct LoopDevice {
+    device_id: DeviceId,
+    number: usize,
+    backing: Mutex<Option<Backing>>,
+}
+
+impl LoopDevice {
+    pub fn new(device_id: DeviceId, number: usize) -> Self {
+        Self {
+            device_id,
+            number,
+            backing: Mutex::new(None),
+        }
+    }
+
+    pub fn device_id(&self) -> DeviceId {
+        self.device_id
+    }
+
+    /// `/dev/loopN`
+    pub fn path(&self) -> String {
+        format!("/dev/loop{}", self.number)
+    }
+
+    fn backing(&self) -> VfsResult<Arc<dyn VfsInode>> {
+        let backing = self.backing.lock();
+        Ok(backing.as_ref().ok_or(VfsError::IoError)?.inode.clone())
+    }
+
+    /// Attaches the regular file `inode`, returns EBUSY if the device is in use
+    pub fn attach(
+        &self,
+        inode: Arc<dyn VfsInode>,
+        name: &str,
+        read_only: bool,
+    ) -> AlienResult<()> {
+        if inode.inode_type() != VfsNodeType::File {
+            return Err(LinuxErrno::EINVAL);
+        }
+        let mut backing = self.backing.lock();
+        if backing.is_some() {
+            return Err(LinuxErrno::EBUSY);
+        }
+        info!("{}: attached {} (read only: {})", self.path(), name, read_only);
+        *backing = Some(Backing {
+            inode,
+            name: name.to_string(),
+            read_only,
+        });
+        Ok(())
+    }
+
+    /// Detaches the backing file; a device that is still mounted stays attached (EBUSY)
+    pub fn detach(&self) -> AlienResult<()> {
+        let path = self.path();
+        if crate::mount::device_in_use(self.device_id.id()) {
+            return Err(LinuxErrno::EBUSY);
+        }
+        let mut backing = self.backing.lock();
+        let detached = backing.take().ok_or(LinuxErrno::ENXIO)?;
+        info!("{}: detached {}", path, detached.name);
+        Ok(())
+    }
+
+    pub fn status(&self) -> AlienResult<LoopInfo64> {
+        let backing = self.backing.lock();
+        let backing = backing.as_ref().ok_or(LinuxErrno::ENXIO)?;
+        let stat = backing.inode.get_attr()?;
+        let mut info = LoopInfo64 {
+            lo_device: stat.st_dev,
+            lo_inode: stat.st_ino,
+            lo_rdevice: self.device_id.id(),
+            lo_offset: 0,
+            lo_sizelimit: 0,
+            lo_number: self.number as u32,
+            lo_encrypt_type: 0,
+            lo_encrypt_key_size: 0,
+            lo_flags: if backing.read_only {
+                LO_FLAGS_READ_ONLY
+            } else {
+                0
+            },
+            lo_file_name: [0; LO_NAME_SIZE],
+            lo_crypt_name: [0; LO_NAME_SIZE],
+            lo_encrypt_key: [0; 32],
+            lo_init: [0; 2],
+        };
+        // Keep the terminating NUL
+        let len = backing.name.len().min(LO_NAME_SIZE - 1);
+        info.lo_file_name[..len].copy_from_slice(&backing.name.as_bytes()[..len]);
+        Ok(info)
+    }
+}
+
+impl LoopInfo {
+    /// The old `struct loop_info` layout of a status
+    pub fn from_info64(info: &LoopInfo64) -> Self {
+        Self {
+            lo_number: info.lo_number as i32,
+            lo_device: info.lo_device as u32,
+            lo_inode: info.lo_inode,
+            lo_rdevice: info.lo_rdevice as u32,
+            lo_offset: info.lo_offset as i32,
+            lo_encrypt_type: 0,
+            lo_encrypt_key_size: 0,
+            lo_flags: info.lo_flags as i32,
+            lo_name: info.lo_file_name,
+            lo_encrypt_key: [0; 32],
+            lo_init: [0; 2],
+            reserved: [0; 4],
+        }
+    }
+}
+
+impl VfsFile for LoopDevice {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        match self.backing() {
+            // Go through the page cache so an open backing file sees the same bytes
+            Ok(inode) => {
+                crate::pagecache::read_at(&inode, offset, buf).map_err(|_| VfsError::IoError)
+            }
+            // An empty device has nothing to read
+            Err(_) => Ok(0),
+        }
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let inode = {
+            let backing = self.backing.lock();
+            let backing = backing.as_ref().ok_or(VfsError::IoError)?;
+            if backing.read_only {
+                return Err(VfsError::PermissionDenied);
+            }
+            backing.inode.clone()
+        };
+        // A block device does not grow
+        let size = inode.get_attr()?.st_size;
+        if offset >= size {
+            return Err(VfsError::IoError);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        crate::pagecache::write_at(&inode, offset, &buf[..len]).map_err(|_| VfsError::IoError)
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        match self.backing() {
+            Ok(inode) => inode.flush(),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn fsync(&self) -> VfsResult<()> {
+        match self.backing() {
+            Ok(inode) => {
+                if let Some(cache) = crate::pagecache::find(&inode) {
+                    cache.sync(0, usize::MAX).map_err(|_| VfsError::IoError)?;
+                }
+                inode.fsync()
+            }
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl VfsInode for LoopDevice {
+    fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
+        Err(VfsError::NoSys)
+    }
+
+    fn node_perm(&self) -> VfsNodePerm {
+        VfsNodePerm::empty()
+    }
+
+    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        let size = match self.backing() {
+            Ok(inode) => inode.get_attr()?.st_size,
+            Err(_) => 0,
+        };
+        Ok(VfsFileStat {
+            st_rdev: self.device_id.id(),
+            st_size: size,
+            st_blksize: 512,
+            ..Default::default()
+        })
+    }
+
+    fn inode_type(&self) -> VfsNodeType {
+        VfsNodeType::BlockDevice
+    }
+}
+
+/// The loop device behind a `/dev/loopN` inode, None for any other inode
+pub fn loop_device(inode: &Arc<dyn VfsInode>) -> Option<Arc<LoopDevice>> {
+    if inode.inode_type() != VfsNodeType::BlockDevice {
+        return None;
+    }
+    let rdev = inode.get_attr().ok()?.st_rdev;
+    let device = DEVICES.lock().get(&DeviceId::from(rdev)).cloned()?;
+    device.downcast_arc::<LoopDevice>().ok()
+}
+
+/// Creates `/dev/loop0` .. `/dev/loop7`
+pub(super) fn create_loop_devices(root: &Arc<dyn VfsInode>) {
+    for number in 0..LOOP_DEVICES {
+        let device = Arc::new(LoopDevice::new(
+            super::alloc_device_id(VfsNodeType::BlockDevice),
+            number,
+        ));
+        root.create(
+            &format!("loop{}", number),
+            VfsNodeType::BlockDevice,
+            "rw-rw----".into(),
+            Some(device.device_id().id()),
+        )
+        .unwrap();
+        super::register_device(device);
+    }
+}
@@ -3,8 +3,8 @@ use alloc::{collections::BTreeMap, sync::Arc};
 use constants::DeviceId;
 use devfs::DevKernelProvider;
 use devices::{
-    BLKDevice, GPUDevice, INPUTDevice, RTCDevice, UARTDevice, BLOCK_DEVICE, GPU_DEVICE,
-    KEYBOARD_INPUT_DEVICE, MOUSE_INPUT_DEVICE, RTC_DEVICE, UART_DEVICE,
+    BLKDevice, GPUDevice, HVCDevice, INPUTDevice, RTCDevice, UARTDevice, BLOCK_DEVICE,
+    GPU_DEVICE, HVC_DEVICE, KEYBOARD_INPUT_DEVICE, MOUSE_INPUT_DEVICE, RTC_DEVICE, UART_DEVICE,
 };
 use ksync::Mutex;
 use log::info;
@@ -18,6 +18,7 @@ use vfscore::{
     utils::{VfsNodeType, VfsTimeSpec},
 };
 
+pub mod loopdev;
 mod null;
 mod random;
 
@@ -95,6 +96,8 @@ impl DevKernelProvider for DevFsProviderImpl {
 /// |-- random
 /// |-- urandom
 /// |-- tty
+/// |-- hvc1 (virtio-console, host <-> guest test traffic)
+/// |-- loop0 .. loop7 (loop devices, see [`loopdev`])
 /// |-- shm (a ramfs will be mounted here)
 /// |-- misc
 ///    |-- rtc
@@ -153,7 +156,8 @@ pub fn init_devfs(devfs: Arc<dyn VfsFsType>) -> Arc<dyn VfsDentry> {
         .create("misc", VfsNodeType::Dir, "rwxrwxrwx".into(), None)
         .unwrap();
 
-    scan_system_devices(root_inode);
+    scan_system_devices(root_inode.clone());
+    loopdev::create_loop_devices(&root_inode);
     // todo!(tty,shm,misc)
     println!("devfs init success");
     root
@@ -252,4 +256,19 @@ fn scan_system_devices(root: Arc<dyn VfsInode>) {
         info!("uart device id: {}", uart_device.device_id().id());
         register_device(uart_device);
     });
+    HVC_DEVICE.get().map(|hvc| {
+        let hvc_device = Arc::new(HVCDevice::new(
+            alloc_device_id(VfsNodeType::CharDevice),
+            hvc.clone(),
+        ));
+        root.create(
+            "hvc1",
+            VfsNodeType::CharDevice,
+            "rw-rw----".into(),
+            Some(hvc_device.device_id().id()),
+        )
+        .unwrap();
+        info!("hvc device id: {}", hvc_device.device_id().id());
+        register_device(hvc_device);
+    });
 }
@@ -0,0 +1,598 @@
+//! inotify: file change notification
+//!
+//! Watches are kept in a global table keyed by the watched inode, so every
+//! filesystem (ramfs, tmpfs, DBFS, fat, ext) is covered as long as the change
+//! goes through the kernel's file syscalls or [`KernelFile`](crate::kfile::KernelFile),
+//! which report it with the `notify_*` functions below. A watch holds a reference
+//! to its inode until it is removed, the inode is deleted or the inotify fd is closed.
+//!
+//! Events about a child (`IN_CREATE`, `IN_DELETE`, `IN_MOVED_FROM`, `IN_MOVED_TO`) go
+//! to the watches on the directory with the child's name. Events about a file
+//! (`IN_MODIFY`, `IN_ATTRIB`, `IN_CLOSE_*`) go to the watches on the file and, with the
+//! file's name, to the watches on its parent directory.
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    fmt::Debug,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+};
+
+use bitflags::bitflags;
+use constants::{
+    io::{OpenFlags, PollEvents, SeekFrom},
+    AlienError, AlienResult,
+};
+use ksync::Mutex;
+use shim::KTask;
+use vfscore::{
+    dentry::VfsDentry,
+    inode::VfsInode,
+    utils::{VfsFileStat, VfsNodeType},
+};
+
+use crate::kfile::File;
+
+bitflags! {
+    /// Events and watch options of `inotify_add_watch`
+    pub struct InotifyMask: u32 {
+        const IN_ACCESS = 0x0000_0001;
+        const IN_MODIFY = 0x0000_0002;
+        const IN_ATTRIB = 0x0000_0004;
+        const IN_CLOSE_WRITE = 0x0000_0008;
+        const IN_CLOSE_NOWRITE = 0x0000_0010;
+        const IN_OPEN = 0x0000_0020;
+        const IN_MOVED_FROM = 0x0000_0040;
+        const IN_MOVED_TO = 0x0000_0080;
+        const IN_CREATE = 0x0000_0100;
+        const IN_DELETE = 0x0000_0200;
+        const IN_DELETE_SELF = 0x0000_0400;
+        const IN_MOVE_SELF = 0x0000_0800;
+        const IN_UNMOUNT = 0x0000_2000;
+        const IN_Q_OVERFLOW = 0x0000_4000;
+        const IN_IGNORED = 0x0000_8000;
+        const IN_ONLYDIR = 0x0100_0000;
+        const IN_DONT_FOLLOW = 0x0200_0000;
+        const IN_EXCL_UNLINK = 0x0400_0000;
+        const IN_MASK_CREATE = 0x1000_0000;
+        const IN_MASK_ADD = 0x2000_0000;
+        const IN_ISDIR = 0x4000_0000;
+        const IN_ONESHOT = 0x8000_0000;
+        /// Every event a watch can ask for
+        const IN_ALL_EVENTS = 0x0000_0fff;
+    }
+}
+
+bitflags! {
+    /// Flags of `inotify_init1`
+    pub struct InotifyInitFlags: u32 {
+        const IN_NONBLOCK = 0o4000;
+        const IN_CLOEXEC = 0o2000000;
+    }
+}
+
+/// Events are dropped (and one `IN_Q_OVERFLOW` is queued) beyond this many
+const MAX_QUEUED_EVENTS: usize = 16384;
+/// Watches per inotify instance
+const MAX_USER_WATCHES: usize = 8192;
+/// `struct inotify_event` without the name
+const EVENT_HEADER_SIZE: usize = 16;
+
+/// A watch on one inode
+struct Watch {
+    wd: i32,
+    mask: InotifyMask,
+    group: Weak<InotifyFile>,
+}
+
+/// A watched inode and its watches
+struct WatchedInode {
+    /// keeps the inode (and so the key) alive while it is watched
+    #[allow(unused)]
+    inode: Arc<dyn VfsInode>,
+    watches: Vec<Watch>,
+}
+
+/// inode address -> watches
+static WATCHES: Mutex<BTreeMap<usize, WatchedInode>> = Mutex::new(BTreeMap::new());
+/// Set while any watch exists, so notifying is free when nobody watches
+static WATCHING: AtomicBool = AtomicBool::new(false);
+static COOKIE: AtomicU32 = AtomicU32::new(1);
+
+fn key(inode: &Arc<dyn VfsInode>) -> usize {
+    Arc::as_ptr(inode) as *const u8 as usize
+}
+
+/// Whether any inotify watch exists
+///
+/// Syscalls check this before looking up entries that are only needed for events.
+pub fn watching() -> bool {
+    WATCHING.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Event {
+    wd: i32,
+    mask: InotifyMask,
+    cookie: u32,
+    name: Option<String>,
+}
+
+impl Event {
+    fn len(&self) -> usize {
+        EVENT_HEADER_SIZE + self.name_len()
+    }
+
+    /// Name with its terminating NUL, padded to the header size like Linux
+    fn name_len(&self) -> usize {
+        match &self.name {
+            Some(name) => (name.len() + 1).div_ceil(EVENT_HEADER_SIZE) * EVENT_HEADER_SIZE,
+            None => 0,
+        }
+    }
+
+    fn write_to(&self, buf: &mut [u8]) {
+        let name_len = self.name_len();
+        buf[0..4].copy_from_slice(&self.wd.to_ne_bytes());
+        buf[4..8].copy_from_slice(&self.mask.bits().to_ne_bytes());
+        buf[8..12].copy_from_slice(&self.cookie.to_ne_bytes());
+        buf[12..16].copy_from_slice(&(name_len as u32).to_ne_bytes());
+        let name_buf = &mut buf[EVENT_HEADER_SIZE..EVENT_HEADER_SIZE + name_len];
+        name_buf.fill(0);
+        if let Some(name) = &self.name {
+            name_buf[..name.len()].copy_from_slice(name.as_bytes());
+        }
+    }
+}
+
+#[derive(Default)]
+struct InotifyInner {
+    next_wd: i32,
+    /// wd -> key of the watched inode
+    watches: BTreeMap<i32, usize>,
+    events: VecDeque<Event>,
+}
+
+/// An inotify instance, the file behind an inotify fd
+pub struct InotifyFile {
+    open_flag: Mutex<OpenFlags>,
+    inner: Mutex<InotifyInner>,
+    wait_queue: Mutex<VecDeque<Arc<dyn KTask>>>,
+}
+
+impl Debug for InotifyFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let inner = self.inner.lock();
+        f.debug_struct("InotifyFile")
+            .field("watches", &inner.watches.len())
+            .field("events", &inner.events.len())
+            .finish()
+    }
+}
+
+impl InotifyFile {
+    fn new(flags: InotifyInitFlags) -> Self {
+        let mut open_flag = OpenFlags::O_RDONLY;
+        if flags.contains(InotifyInitFlags::IN_NONBLOCK) {
+            open_flag |= OpenFlags::O_NONBLOCK;
+        }
+        if flags.contains(InotifyInitFlags::IN_CLOEXEC) {
+            open_flag |= OpenFlags::O_CLOEXEC;
+        }
+        InotifyFile {
+            open_flag: Mutex::new(open_flag),
+            inner: Mutex::new(InotifyInner {
+                next_wd: 1,
+                ..Default::default()
+            }),
+            wait_queue: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Watch `inode` for `mask`, returns the watch descriptor
+    ///
+    /// Watching an inode again returns the same descriptor and replaces its mask, or adds
+    /// to it with `IN_MASK_ADD`.
+    pub fn add_watch(self: &Arc<Self>, inode: Arc<dyn VfsInode>, mask: u32) -> AlienResult<i32> {
+        let mask = InotifyMask::from_bits_truncate(mask);
+        let events = mask & InotifyMask::IN_ALL_EVENTS;
+        if events.is_empty()
+            || mask.contains(InotifyMask::IN_MASK_ADD | InotifyMask::IN_MASK_CREATE)
+        {
+            return Err(AlienError::EINVAL);
+        }
+        if mask.contains(InotifyMask::IN_ONLYDIR) && inode.inode_type() != VfsNodeType::Dir {
+            return Err(AlienError::ENOTDIR);
+        }
+        let options = mask & (InotifyMask::IN_ONESHOT | InotifyMask::IN_EXCL_UNLINK);
+        let key = key(&inode);
+        let mut watches = WATCHES.lock();
+        let watched = watches.entry(key).or_insert_with(|| WatchedInode {
+            inode,
+            watches: Vec::new(),
+        });
+        let group = Arc::downgrade(self);
+        if let Some(watch) = watched.watches.iter_mut().find(|w| w.group.ptr_eq(&group)) {
+            if mask.contains(InotifyMask::IN_MASK_CREATE) {
+                return Err(AlienError::EEXIST);
+            }
+            watch.mask = if mask.contains(InotifyMask::IN_MASK_ADD) {
+                watch.mask | events | options
+            } else {
+                events | options
+            };
+            return Ok(watch.wd);
+        }
+        let mut inner = self.inner.lock();
+        if inner.watches.len() >= MAX_USER_WATCHES {
+            if watched.watches.is_empty() {
+                watches.remove(&key);
+            }
+            return Err(AlienError::ENOSPC);
+        }
+        let wd = inner.next_wd;
+        inner.next_wd += 1;
+        inner.watches.insert(wd, key);
+        watched.watches.push(Watch {
+            wd,
+            mask: events | options,
+            group,
+        });
+        WATCHING.store(true, Ordering::Relaxed);
+        Ok(wd)
+    }
+
+    /// Remove watch `wd`, an `IN_IGNORED` event is queued for it
+    pub fn rm_watch(&self, wd: i32) -> AlienResult<()> {
+        let key = self.inner.lock().watches.remove(&wd).ok_or(AlienError::EINVAL)?;
+        remove_watches(key, |watch| watch.wd == wd && ptr_eq(&watch.group, self));
+        self.push(Event {
+            wd,
+            mask: InotifyMask::IN_IGNORED,
+            cookie: 0,
+            name: None,
+        });
+        Ok(())
+    }
+
+    fn push(&self, event: Event) {
+        let mut inner = self.inner.lock();
+        // like Linux, an event identical to the last unread one is merged into it
+        if inner.events.back() == Some(&event) {
+            return;
+        }
+        if inner.events.len() >= MAX_QUEUED_EVENTS {
+            let overflow = Event {
+                wd: -1,
+                mask: InotifyMask::IN_Q_OVERFLOW,
+                cookie: 0,
+                name: None,
+            };
+            if inner.events.back() != Some(&overflow) {
+                inner.events.push_back(overflow);
+            }
+        } else {
+            inner.events.push_back(event);
+        }
+        drop(inner);
+        while let Some(task) = self.wait_queue.lock().pop_front() {
+            task.to_wakeup();
+            shim::put_task(task);
+        }
+    }
+
+    /// The watch went away with its inode (deleted, or a oneshot watch fired)
+    fn forget(&self, wd: i32) {
+        if self.inner.lock().watches.remove(&wd).is_some() {
+            self.push(Event {
+                wd,
+                mask: InotifyMask::IN_IGNORED,
+                cookie: 0,
+                name: None,
+            });
+        }
+    }
+}
+
+fn ptr_eq(group: &Weak<InotifyFile>, file: &InotifyFile) -> bool {
+    core::ptr::eq(group.as_ptr(), file)
+}
+
+/// Remove the watches on inode `key` matching `f`, the inode is released with its last watch
+fn remove_watches(key: usize, f: impl Fn(&Watch) -> bool) -> Vec<Watch> {
+    let mut watches = WATCHES.lock();
+    let Some(watched) = watches.get_mut(&key) else {
+        return Vec::new();
+    };
+    let (removed, kept): (Vec<_>, Vec<_>) = core::mem::take(&mut watched.watches)
+        .into_iter()
+        .partition(|watch| f(watch));
+    watched.watches = kept;
+    // the inode is dropped after the lock is released
+    let released = if watched.watches.is_empty() {
+        watches.remove(&key)
+    } else {
+        None
+    };
+    if watches.is_empty() {
+        WATCHING.store(false, Ordering::Relaxed);
+    }
+    drop(watches);
+    drop(released);
+    removed
+}
+
+impl Drop for InotifyFile {
+    fn drop(&mut self) {
+        let keys = core::mem::take(&mut self.inner.lock().watches);
+        let this: &InotifyFile = self;
+        for key in keys.into_values() {
+            remove_watches(key, |watch| ptr_eq(&watch.group, this));
+        }
+    }
+}
+
+impl File for InotifyFile {
+    fn read(&self, buf: &mut [u8]) -> AlienResult<usize> {
+        loop {
+            let mut inner = self.inner.lock();
+            if let Some(first) = inner.events.front() {
+                if buf.len() < first.len() {
+                    return Err(AlienError::EINVAL);
+                }
+                let mut count = 0;
+                while let Some(event) = inner.events.front() {
+                    let len = event.len();
+                    if count + len > buf.len() {
+                        break;
+                    }
+                    event.write_to(&mut buf[count..count + len]);
+                    count += len;
+                    inner.events.pop_front();
+                }
+                return Ok(count);
+            }
+            drop(inner);
+            if self.open_flag.lock().contains(OpenFlags::O_NONBLOCK) {
+                return Err(AlienError::EAGAIN);
+            }
+            let task = shim::take_current_task().unwrap();
+            task.to_wait();
+            self.wait_queue.lock().push_back(task.clone());
+            shim::schedule_now(task); // yield current task
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> AlienResult<usize> {
+        Err(AlienError::EINVAL)
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> AlienResult<usize> {
+        self.read(buf)
+    }
+
+    fn seek(&self, _pos: SeekFrom) -> AlienResult<u64> {
+        Err(AlienError::ESPIPE)
+    }
+
+    fn get_attr(&self) -> AlienResult<VfsFileStat> {
+        Err(AlienError::ENOSYS)
+    }
+
+    fn set_open_flag(&self, flag: OpenFlags) {
+        *self.open_flag.lock() = flag;
+    }
+
+    fn get_open_flag(&self) -> OpenFlags {
+        *self.open_flag.lock()
+    }
+
+    fn dentry(&self) -> Arc<dyn VfsDentry> {
+        panic!("InotifyFile::dentry() is not implemented")
+    }
+
+    fn inode(&self) -> Arc<dyn VfsInode> {
+        panic!("InotifyFile::inode() is not implemented")
+    }
+
+    fn is_readable(&self) -> bool {
+        true
+    }
+
+    fn is_writable(&self) -> bool {
+        false
+    }
+
+    fn is_append(&self) -> bool {
+        false
+    }
+
+    fn poll(&self, event: PollEvents) -> AlienResult<PollEvents> {
+        let mut events = PollEvents::empty();
+        if event.contains(PollEvents::EPOLLIN) && !self.inner.lock().events.is_empty() {
+            events |= PollEvents::EPOLLIN;
+        }
+        Ok(events)
+    }
+}
+
+/// `inotify_init1`
+pub fn inotify(flags: u32) -> AlienResult<Arc<InotifyFile>> {
+    let flags = InotifyInitFlags::from_bits(flags).ok_or(AlienError::EINVAL)?;
+    Ok(Arc::new(InotifyFile::new(flags)))
+}
+
+/// Queue `mask` on the watches of `inode` that want it
+fn send(inode: &Arc<dyn VfsInode>, mask: InotifyMask, cookie: u32, name: Option<&str>) {
+    let key = key(inode);
+    let targets = {
+        let watches = WATCHES.lock();
+        let Some(watched) = watches.get(&key) else {
+            return;
+        };
+        watched
+            .watches
+            .iter()
+            .filter(|watch| watch.mask.intersects(mask & InotifyMask::IN_ALL_EVENTS))
+            .filter_map(|watch| Some((watch.group.upgrade()?, watch.wd, watch.mask)))
+            .collect::<Vec<_>>()
+    };
+    let mut oneshot = Vec::new();
+    for (group, wd, watch_mask) in targets {
+        group.push(Event {
+            wd,
+            mask,
+            cookie,
+            name: name.map(String::from),
+        });
+        if watch_mask.contains(InotifyMask::IN_ONESHOT) {
+            oneshot.push((group, wd));
+        }
+    }
+    for (group, wd) in oneshot {
+        remove_watches(key, |watch| watch.wd == wd && ptr_eq(&watch.group, &group));
+        group.forget(wd);
+    }
+}
+
+/// The inode is gone: every watch on it gets `IN_DELETE_SELF` and is removed
+fn send_delete_self(inode: &Arc<dyn VfsInode>) {
+    send(inode, InotifyMask::IN_DELETE_SELF, 0, None);
+    for watch in remove_watches(key(inode), |_| true) {
+        if let Some(group) = watch.group.upgrade() {
+            group.forget(watch.wd);
+        }
+    }
+}
+
+fn dir_flag(inode: &Arc<dyn VfsInode>) -> InotifyMask {
+    if inode.inode_type() == VfsNodeType::Dir {
+        InotifyMask::IN_ISDIR
+    } else {
+        InotifyMask::empty()
+    }
+}
+
+/// `mask` happened to `dentry`: report it to the file and to its parent directory
+fn send_with_parent(dentry: &Arc<dyn VfsDentry>, mask: InotifyMask) {
+    let Ok(inode) = dentry.inode() else {
+        return;
+    };
+    let mask = mask | dir_flag(&inode);
+    send(&inode, mask, 0, None);
+    if let Some(parent) = dentry.parent().and_then(|parent| parent.inode().ok()) {
+        if key(&parent) != key(&inode) {
+            send(&parent, mask, 0, Some(dentry.name().as_str()));
+        }
+    }
+}
+
+/// A directory entry captured before it is unlinked or renamed
+pub struct InotifyEntry {
+    parent: Option<Arc<dyn VfsInode>>,
+    name: String,
+    inode: Arc<dyn VfsInode>,
+}
+
+impl InotifyEntry {
+    pub fn new(dentry: &Arc<dyn VfsDentry>) -> Option<Self> {
+        Some(Self {
+            parent: dentry.parent().and_then(|parent| parent.inode().ok()),
+            name: dentry.name(),
+            inode: dentry.inode().ok()?,
+        })
+    }
+}
+
+/// `IN_CREATE` for a new file, directory, symlink or hard link
+pub fn notify_create(dentry: &Arc<dyn VfsDentry>) {
+    if !watching() {
+        return;
+    }
+    let (Some(parent), Ok(inode)) = (dentry.parent(), dentry.inode()) else {
+        return;
+    };
+    if let Ok(parent) = parent.inode() {
+        let mask = InotifyMask::IN_CREATE | dir_flag(&inode);
+        send(&parent, mask, 0, Some(dentry.name().as_str()));
+    }
+}
+
+/// `IN_DELETE` for an unlinked entry, and `IN_DELETE_SELF` once its last link is gone
+pub fn notify_delete(entry: &InotifyEntry) {
+    if !watching() {
+        return;
+    }
+    let is_dir = entry.inode.inode_type() == VfsNodeType::Dir;
+    if let Some(parent) = &entry.parent {
+        let mask = InotifyMask::IN_DELETE | dir_flag(&entry.inode);
+        send(parent, mask, 0, Some(entry.name.as_str()));
+    }
+    let last_link = is_dir || entry.inode.get_attr().map_or(true, |attr| attr.st_nlink == 0);
+    if last_link {
+        send_delete_self(&entry.inode);
+    }
+}
+
+/// `IN_MOVED_FROM` / `IN_MOVED_TO` (with a shared cookie) and `IN_MOVE_SELF` for a rename
+///
+/// `replaced` is the entry the rename overwrote, if any.
+pub fn notify_move(
+    from: &InotifyEntry,
+    to: &Arc<dyn VfsDentry>,
+    replaced: Option<&InotifyEntry>,
+) {
+    if !watching() {
+        return;
+    }
+    let cookie = COOKIE.fetch_add(1, Ordering::Relaxed);
+    let dir = dir_flag(&from.inode);
+    if let Some(parent) = &from.parent {
+        send(parent, InotifyMask::IN_MOVED_FROM | dir, cookie, Some(from.name.as_str()));
+    }
+    if let Some(parent) = to.parent().and_then(|parent| parent.inode().ok()) {
+        send(&parent, InotifyMask::IN_MOVED_TO | dir, cookie, Some(to.name().as_str()));
+    }
+    send(&from.inode, InotifyMask::IN_MOVE_SELF | dir, 0, None);
+    if let Some(replaced) = replaced {
+        if key(&replaced.inode) != key(&from.inode) {
+            send_delete_self(&replaced.inode);
+        }
+    }
+}
+
+/// `IN_MODIFY`: the content of the file changed
+pub fn notify_modify(dentry: &Arc<dyn VfsDentry>) {
+    if watching() {
+        send_with_parent(dentry, InotifyMask::IN_MODIFY);
+    }
+}
+
+/// `IN_MODIFY` for a change made without a path, e.g. writing back a shared mapping
+pub fn notify_modify_inode(inode: &Arc<dyn VfsInode>) {
+    if watching() {
+        send(inode, InotifyMask::IN_MODIFY, 0, None);
+    }
+}
+
+/// `IN_ATTRIB`: timestamps, permissions or extended attributes changed
+pub fn notify_attrib(dentry: &Arc<dyn VfsDentry>) {
+    if watching() {
+        send_with_parent(dentry, InotifyMask::IN_ATTRIB);
+    }
+}
+
+/// `IN_CLOSE_WRITE` / `IN_CLOSE_NOWRITE`: the last reference to an open file was dropped
+pub fn notify_close(dentry: &Arc<dyn VfsDentry>, writable: bool) {
+    if watching() {
+        let mask = if writable {
+            InotifyMask::IN_CLOSE_WRITE
+        } else {
+            InotifyMask::IN_CLOSE_NOWRITE
+        };
+        send_with_parent(dentry, mask);
+    }
+}
@@ -7,6 +7,7 @@ use constants::{
 };
 use downcast_rs::{impl_downcast, DowncastSync};
 use ksync::Mutex;
+use log::warn;
 use vfscore::{
     dentry::VfsDentry,
     error::VfsError,
@@ -15,12 +16,21 @@ use vfscore::{
     utils::{VfsFileStat, VfsNodeType, VfsPollEvents},
 };
 
-use crate::system_root_fs;
+use crate::{
+    inotify, mount,
+    pagecache::{self, PageCache},
+    proc::dbfs_ctl::DbfsChanges,
+    system_root_fs,
+};
 
 pub struct KernelFile {
     pos: Mutex<u64>,
     open_flag: Mutex<OpenFlags>,
     dentry: Arc<dyn VfsDentry>,
+    /// 普通文件的页缓存, 同一个文件的所有打开共享
+    cache: Option<Arc<PageCache>>,
+    /// 以写方式打开, 见 [`mount::open_for_write`]
+    writer: bool,
 }
 
 impl Debug for KernelFile {
@@ -40,10 +50,17 @@ impl KernelFile {
         } else {
             0
         };
+        let cache = dentry.inode().ok().and_then(|inode| pagecache::open(&inode));
+        let writer = open_flag.intersects(OpenFlags::O_WRONLY | OpenFlags::O_RDWR);
+        if writer {
+            mount::open_for_write(&dentry);
+        }
         Self {
             pos: Mutex::new(pos),
             open_flag: Mutex::new(open_flag),
             dentry,
+            cache,
+            writer,
         }
     }
 }
@@ -123,6 +140,9 @@ impl File for KernelFile {
             return Err(LinuxErrno::EPERM);
         }
         drop(open_flag);
+        if let Some(cache) = &self.cache {
+            return cache.read_at(offset, buf);
+        }
         let inode = self.dentry.inode()?;
         let read = inode.read_at(offset, buf)?;
         Ok(read)
@@ -136,8 +156,14 @@ impl File for KernelFile {
         if !open_flag.contains(OpenFlags::O_WRONLY) && !open_flag.contains(OpenFlags::O_RDWR) {
             return Err(LinuxErrno::EPERM);
         }
-        let inode = self.dentry.inode()?;
-        let write = inode.write_at(offset, buf)?;
+        let write = match &self.cache {
+            Some(cache) => cache.write_at(offset, buf)?,
+            None => self.dentry.inode()?.write_at(offset, buf)?,
+        };
+        drop(open_flag);
+        if write > 0 {
+            inotify::notify_modify(&self.dentry);
+        }
         Ok(write)
     }
 
@@ -156,6 +182,9 @@ impl File for KernelFile {
         if !open_flag.contains(OpenFlags::O_WRONLY) && !open_flag.contains(OpenFlags::O_RDWR) {
             return Err(LinuxErrno::EPERM);
         }
+        if let Some(cache) = &self.cache {
+            cache.sync(0, usize::MAX)?;
+        }
         let inode = self.dentry.inode()?;
         inode.fsync()?;
         Ok(())
@@ -242,9 +271,12 @@ impl File for KernelFile {
             return Err(LinuxErrno::EINVAL);
         }
         let dt = self.dentry();
-        VfsPath::new(system_root_fs(), dt)
-            .truncate(len)
-            .map_err(Into::into)
+        VfsPath::new(system_root_fs(), dt).truncate(len)?;
+        if let Some(cache) = &self.cache {
+            cache.truncate(len);
+        }
+        inotify::notify_modify(&self.dentry);
+        Ok(())
     }
     fn is_readable(&self) -> bool {
         let open_flag = self.open_flag.lock();
@@ -262,6 +294,14 @@ impl File for KernelFile {
 
     fn poll(&self, _event: PollEvents) -> AlienResult<PollEvents> {
         let inode = self.dentry.inode()?;
+        // 变更流按本次打开的读位置判断是否可读
+        if let Some(changes) = inode.downcast_ref::<DbfsChanges>() {
+            let pos = *self.pos.lock();
+            return changes
+                .poll_at(pos, VfsPollEvents::from_bits_truncate(_event.bits() as u16))
+                .map(|e| PollEvents::from_bits_truncate(e.bits() as u32))
+                .map_err(Into::into);
+        }
         let res = inode
             .poll(VfsPollEvents::from_bits_truncate(_event.bits() as u16))
             .map(|e| PollEvents::from_bits_truncate(e.bits() as u32));
@@ -277,5 +317,14 @@ impl Drop for KernelFile {
     fn drop(&mut self) {
         let _ = self.flush();
         let _ = self.fsync();
+        if let Some(cache) = self.cache.take() {
+            if let Err(e) = cache.release() {
+                warn!("write back page cache of {} failed: {:?}", self.dentry.name(), e);
+            }
+        }
+        if self.writer {
+            mount::close_for_write(&self.dentry);
+        }
+        inotify::notify_close(&self.dentry, self.is_writable());
     }
 }
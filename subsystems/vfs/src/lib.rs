@@ -11,22 +11,28 @@ use alloc::{
 };
 use core::ops::Index;
 
-use constants::AlienResult;
+use constants::{io::MountFlags, AlienResult, LinuxErrno};
 use dynfs::DynFsKernelProvider;
 use ksync::Mutex;
 use spin::{Lazy, Once};
-#[cfg(feature = "ext")]
-use vfscore::inode::VfsInode;
-use vfscore::{dentry::VfsDentry, fstype::VfsFsType, path::VfsPath, utils::VfsTimeSpec};
+use vfscore::{
+    dentry::VfsDentry, error::VfsError, fstype::VfsFsType, inode::VfsInode, path::VfsPath,
+    utils::VfsTimeSpec,
+};
 
 use crate::dev::DevFsProviderImpl;
 pub mod dev;
 pub mod epoll;
 pub mod eventfd;
 #[cfg(feature = "ext")]
+mod bind;
 mod extffi;
 mod initrd;
+pub mod inotify;
 pub mod kfile;
+pub mod mount;
+pub mod overlay;
+pub mod pagecache;
 pub mod pipefs;
 pub mod proc;
 pub mod ram;
@@ -114,6 +120,8 @@ fn register_all_fs() {
     let dbfs = Arc::new(dbfs::DbfsFsType::new("/tests/metadata.db".to_string()));
     FS.lock().insert("dbfs".to_string(), dbfs);
 
+    FS.lock().insert("overlay".to_string(), Arc::new(overlay::OverlayFsType));
+
     println!("register fs success");
 }
 
@@ -134,17 +142,25 @@ pub fn init_filesystem() -> AlienResult<()> {
     pipefs::init_pipefs(FS.lock().index("pipefs").clone());
 
     let path = VfsPath::new(ramfs_root.clone(), ramfs_root.clone());
-    path.join("proc")?.mount(procfs_root, 0)?;
-    path.join("sys")?.mount(sysfs_root, 0)?;
-    path.join("dev")?.mount(devfs_root, 0)?;
+    let none = MountFlags::empty();
+    mount::init(ramfs_root.clone());
+    mount::record("rootfs", "/", "ramfs", none, "", ramfs_root.clone());
+    path.join("proc")?.mount(procfs_root.clone(), 0)?;
+    mount::record("proc", "/proc", "procfs", none, "", procfs_root);
+    path.join("sys")?.mount(sysfs_root.clone(), 0)?;
+    mount::record("sysfs", "/sys", "sysfs", none, "", sysfs_root);
+    path.join("dev")?.mount(devfs_root.clone(), 0)?;
+    mount::record("devfs", "/dev", "devfs", none, "", devfs_root);
     path.join("tmp")?.mount(tmpfs_root.clone(), 0)?;
+    mount::record("tmpfs", "/tmp", "tmpfs", none, "", tmpfs_root.clone());
 
     let shm_ramfs = FS
         .lock()
         .index("ramfs")
         .clone()
         .i_mount(0, "/dev/shm", None, &[])?;
-    path.join("dev/shm")?.mount(shm_ramfs, 0)?;
+    path.join("dev/shm")?.mount(shm_ramfs.clone(), 0)?;
+    mount::record("shm", "/dev/shm", "ramfs", none, "", shm_ramfs);
 
     #[cfg(any(feature = "fat", feature = "ext"))]
     {
@@ -157,13 +173,16 @@ pub fn init_filesystem() -> AlienResult<()> {
 
         let diskfs_root = diskfs.i_mount(0, "/tests", Some(blk_inode.clone()), &[])?;
         path.join("tests")?.mount(diskfs_root.clone(), 0)?;
+        mount::record("/dev/sda", "/tests", "diskfs", none, "", diskfs_root.clone());
         println!("mount diskfs (Bottom FS) success");
 
         // --- DBFS Integration: Mount DBFS Layer over DiskFS ---
         let dbfs = FS.lock().index("dbfs").clone();
         // Use diskfs_root as the 'device' (Bottom FS) for DBFS
         let dbfs_root = dbfs.i_mount(0, "/data", Some(diskfs_root.inode()?), &[])?;
-        path.join("data")?.mount(dbfs_root, 0)?;
+        path.join("data")?.mount(dbfs_root.clone(), 0)?;
+        mounted(&dbfs_root);
+        mount::record("/tests", "/data", "dbfs", none, "", dbfs_root);
         println!("mount dbfs (Transactional Layer) over diskfs success");
     }
 
@@ -195,6 +214,90 @@ pub fn init_filesystem() -> AlienResult<()> {
     Ok(())
 }
 
+/// 卸载前检查文件系统是否仍在使用
+///
+/// 目前只有 DBFS 跟踪使用状态: 挂载上仍有事务或打开的文件时返回 EBUSY。
+/// 通过绑定挂载 (包括复制的命名空间中的挂载) 打开的文件持有的是绑定挂载自己的目录项,
+/// 也要检查。
+pub fn check_umount(mount_root: &Arc<dyn VfsDentry>) -> AlienResult<()> {
+    let sb = mount_root.inode()?.get_super_block()?;
+    let is_dbfs = dbfs_mount_point(mount_root).is_some();
+    if dbfs::mount_busy(&sb) || (is_dbfs && bind::in_use(mount_root)) {
+        return Err(LinuxErrno::EBUSY);
+    }
+    Ok(())
+}
+
+/// 把 `src` 的 `[src_offset, src_offset + len)` 克隆到 `dst` 的 `dst_offset` (reflink),
+/// 返回克隆的字节数
+///
+/// 目前只有 DBFS 支持: 两个文件不在同一个文件系统上返回 EXDEV,
+/// 文件系统不支持克隆返回 EOPNOTSUPP (copy_file_range 据此回退到逐字节复制)
+pub fn clone_file_range(
+    src: &Arc<dyn VfsInode>,
+    src_offset: u64,
+    len: u64,
+    dst: &Arc<dyn VfsInode>,
+    dst_offset: u64,
+) -> AlienResult<usize> {
+    match dbfs::reflink::clone_range(src, src_offset, len, dst, dst_offset) {
+        Ok(cloned) => Ok(cloned as usize),
+        Err(VfsError::NoSys) => match (src.get_super_block(), dst.get_super_block()) {
+            (Ok(src_sb), Ok(dst_sb))
+                if Arc::as_ptr(&src_sb) as *const u8 != Arc::as_ptr(&dst_sb) as *const u8 =>
+            {
+                Err(LinuxErrno::EXDEV)
+            }
+            _ => Err(LinuxErrno::EOPNOTSUPP),
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 把页缓存 (包括共享内存映射 MAP_SHARED 的页) 写回文件, 返回写入的字节数
+///
+/// `pages` 是 (文件偏移, 页内容), 超出文件末尾的部分被丢弃。DBFS 文件只写入
+/// 改变了的页, 并且所有页在同一个事务中写入; 其它文件直接逐页写入
+pub fn write_back_mapping(
+    inode: &Arc<dyn VfsInode>,
+    pages: &[(u64, &[u8])],
+) -> AlienResult<usize> {
+    let written = match dbfs::mmap::write_back(inode, pages) {
+        Ok(written) => Ok(written),
+        Err(VfsError::NoSys) => {
+            let size = inode.get_attr()?.st_size;
+            let mut written = 0;
+            for &(offset, page) in pages {
+                let len = (page.len() as u64).min(size.saturating_sub(offset)) as usize;
+                if len > 0 {
+                    written += inode.write_at(offset, &page[..len])?;
+                }
+            }
+            Ok(written)
+        }
+        Err(e) => Err(e.into()),
+    };
+    if matches!(written, Ok(written) if written > 0) {
+        inotify::notify_modify_inode(inode);
+    }
+    written
+}
+
+/// 挂载根目录所属的 DBFS 挂载点 (不是 DBFS 时为 None)
+pub fn dbfs_mount_point(mount_root: &Arc<dyn VfsDentry>) -> Option<String> {
+    let sb = mount_root.inode().ok()?.get_super_block().ok()?;
+    dbfs::stats::mount_point_of(&sb)
+}
+
+/// 挂载完成后调用: DBFS 挂载在 /proc/fs/dbfs 下建立统计目录
+pub fn mounted(fs_root: &Arc<dyn VfsDentry>) {
+    if let Some(mount_point) = dbfs_mount_point(fs_root) {
+        if let Err(e) = proc::register_dbfs_mount(&mount_point) {
+            println!("register /proc/fs/dbfs for {} failed: {:?}", mount_point, e);
+        }
+    }
+}
+
 struct VfsOutPut;
 impl core::fmt::Write for VfsOutPut {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
@@ -0,0 +1,637 @@
+//! 挂载表和挂载命名空间
+//!
+//! 记录系统中的每个挂载 (启动时建立的和 `mount` 系统调用建立的), 用于生成
+//! `/proc/mounts`, 卸载时清理, 以及查询一个目录项所在挂载的标志 (如 `MS_NOEXEC`、
+//! `MS_RDONLY`)。
+//! 挂载由挂载的根目录项标识: 绑定挂载和普通挂载一样有自己的根目录项。
+//!
+//! 每个挂载命名空间有自己的目录树和挂载表。启动时建立的是初始命名空间, 它的根目录
+//! 起初即 [`system_root_fs`](crate::system_root_fs)。`CLONE_NEWNS` 复制命名空间: 新命名空间
+//! 在新的目录项上重建同样的挂载 (与绑定挂载相同, 文件系统本身是共享的), 之后两边的
+//! `mount`、`umount` 互不可见。最后一个使用命名空间的任务退出时, 不再在别处挂载的
+//! 文件系统随之卸载。
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+use core::fmt::Write;
+
+use constants::{io::MountFlags, AlienResult, LinuxErrno};
+use ksync::Mutex;
+use log::{info, warn};
+use spin::Once;
+use vfscore::{
+    dentry::VfsDentry, fstype::VfsFsType, path::VfsPath, superblock::VfsSuperBlock,
+    utils::VfsNodeType,
+};
+
+use crate::{bind::BindDentry, FS};
+
+/// 每个挂载自己的标志, 其余标志只在挂载时使用
+const PER_MOUNT_FLAGS: MountFlags = MountFlags::from_bits_truncate(
+    MountFlags::MS_RDONLY.bits()
+        | MountFlags::MS_NOSUID.bits()
+        | MountFlags::MS_NODEV.bits()
+        | MountFlags::MS_NOEXEC.bits(),
+);
+
+/// 只修改传播类型 (`mount --make-private` 等) 的标志
+const PROPAGATION_FLAGS: MountFlags = MountFlags::from_bits_truncate(
+    MountFlags::MS_SHARED.bits()
+        | MountFlags::MS_PRIVATE.bits()
+        | MountFlags::MS_SLAVE.bits()
+        | MountFlags::MS_UNBINDABLE.bits(),
+);
+
+/// 一个挂载
+#[derive(Clone)]
+pub struct Mount {
+    /// 设备或来源, 如 `/dev/sda`、`none`, 绑定挂载为被绑定的目录
+    pub source: String,
+    /// 挂载点的绝对路径
+    pub target: String,
+    pub fs_type: String,
+    /// 挂载的标志, 只包含 `MS_RDONLY`、`MS_NOSUID`、`MS_NODEV`、`MS_NOEXEC`
+    pub flags: MountFlags,
+    /// 挂载选项 (`mount` 的 data 参数), 密钥已隐去 (见 [`redact_options`])
+    pub data: String,
+    /// 文件系统所在块设备的设备号, 不在块设备上 (或来源是普通文件) 时为 None
+    pub device: Option<u64>,
+    /// 挂载的根目录项
+    pub root: Arc<dyn VfsDentry>,
+}
+
+impl Mount {
+    /// `/proc/mounts` 中的一行
+    pub fn describe(&self) -> String {
+        let mut options = String::from(if self.flags.contains(MountFlags::MS_RDONLY) {
+            "ro"
+        } else {
+            "rw"
+        });
+        for (flag, name) in [
+            (MountFlags::MS_NOSUID, "nosuid"),
+            (MountFlags::MS_NODEV, "nodev"),
+            (MountFlags::MS_NOEXEC, "noexec"),
+        ] {
+            if self.flags.contains(flag) {
+                options.push(',');
+                options.push_str(name);
+            }
+        }
+        if !self.data.is_empty() {
+            options.push(',');
+            options.push_str(&self.data);
+        }
+        let mut line = String::new();
+        let source = if self.source.is_empty() { "none" } else { &self.source };
+        let _ = writeln!(line, "{} {} {} {} 0 0", source, self.target, self.fs_type, options);
+        line
+    }
+}
+
+/// 一个挂载命名空间
+pub struct MountNamespace {
+    /// 命名空间的根目录, `pivot_root` 后改变
+    root: Mutex<Arc<dyn VfsDentry>>,
+    /// 按挂载的先后顺序, 第一个是根目录的挂载
+    mounts: Mutex<Vec<Mount>>,
+}
+
+/// 所有的挂载命名空间, 用于按目录项查找挂载
+static NAMESPACES: Mutex<Vec<Weak<MountNamespace>>> = Mutex::new(Vec::new());
+
+static INIT_MNT_NS: Once<Arc<MountNamespace>> = Once::new();
+
+/// 以写方式打开的文件的目录项, 每次打开一项, 只读重新挂载时检查
+static WRITERS: Mutex<Vec<Arc<dyn VfsDentry>>> = Mutex::new(Vec::new());
+
+fn same_dentry(a: &Arc<dyn VfsDentry>, b: &Arc<dyn VfsDentry>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
+fn superblock(dentry: &Arc<dyn VfsDentry>) -> Option<Arc<dyn VfsSuperBlock>> {
+    dentry.inode().ok()?.get_super_block().ok()
+}
+
+fn same_sb(a: &Arc<dyn VfsSuperBlock>, b: &Arc<dyn VfsSuperBlock>) -> bool {
+    Arc::as_ptr(a) as *const u8 == Arc::as_ptr(b) as *const u8
+}
+
+/// `path` 在目录 `dir` 之下 (或就是 `dir`) 时, 返回相对于 `dir` 的路径
+pub fn relative<'a>(path: &'a str, dir: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(dir.trim_end_matches('/'))?;
+    if rest.is_empty() || rest.starts_with('/') {
+        Some(rest.trim_start_matches('/'))
+    } else {
+        None
+    }
+}
+
+/// 目录 `dir` 下的相对路径 `rest`
+fn join(dir: &str, rest: &str) -> String {
+    let dir = dir.trim_end_matches('/');
+    match (dir.is_empty(), rest.is_empty()) {
+        (true, true) => "/".to_string(),
+        (false, true) => dir.to_string(),
+        _ => format!("{}/{}", dir, rest),
+    }
+}
+
+impl Drop for MountNamespace {
+    fn drop(&mut self) {
+        let mounts = core::mem::take(&mut *self.mounts.lock());
+        let mut released: Vec<Arc<dyn VfsSuperBlock>> = Vec::new();
+        for mount in mounts.iter().rev() {
+            let Some(sb) = superblock(&mount.root) else {
+                continue;
+            };
+            if released.iter().any(|other| same_sb(other, &sb)) || mounted_elsewhere(&mount.root) {
+                continue;
+            }
+            released.push(sb.clone());
+            if let Err(e) = release(&mount.root, sb) {
+                warn!("release {} of a dropped mount namespace failed: {:?}", mount.target, e);
+            }
+        }
+    }
+}
+
+/// 卸载只剩 `mount_root` 这一个挂载的文件系统 `sb`, 用于销毁命名空间
+fn release(mount_root: &Arc<dyn VfsDentry>, sb: Arc<dyn VfsSuperBlock>) -> AlienResult<()> {
+    let dbfs_mount = crate::dbfs_mount_point(mount_root);
+    sb.fs_type().kill_sb(sb)?;
+    if let Some(mount_point) = dbfs_mount {
+        crate::proc::unregister_dbfs_mount(&mount_point);
+    }
+    Ok(())
+}
+
+/// 仍然存在的挂载命名空间
+fn namespaces() -> Vec<Arc<MountNamespace>> {
+    let mut namespaces = NAMESPACES.lock();
+    namespaces.retain(|ns| ns.strong_count() > 0);
+    namespaces.iter().filter_map(Weak::upgrade).collect()
+}
+
+/// 建立初始命名空间, `root` 是根文件系统的根目录
+pub(crate) fn init(root: Arc<dyn VfsDentry>) {
+    INIT_MNT_NS.call_once(|| MountNamespace::new(root, Vec::new()));
+}
+
+/// 初始挂载命名空间, 内核线程和 init 进程使用
+pub fn init_mnt_ns() -> Arc<MountNamespace> {
+    INIT_MNT_NS.get().unwrap().clone()
+}
+
+/// 调用者的挂载命名空间和根目录, 没有调用者 (内核初始化) 时为初始命名空间和它的根目录
+pub(crate) fn caller_fs() -> (Arc<MountNamespace>, Arc<dyn VfsDentry>) {
+    crate::proc::current_fs().unwrap_or_else(|| {
+        let mnt_ns = init_mnt_ns();
+        let root = mnt_ns.root();
+        (mnt_ns, root)
+    })
+}
+
+/// 在初始命名空间中记录一个启动时建立的挂载
+pub(crate) fn record(
+    source: &str,
+    target: &str,
+    fs_type: &str,
+    flags: MountFlags,
+    data: &str,
+    root: Arc<dyn VfsDentry>,
+) {
+    init_mnt_ns().record(source, target, fs_type, flags, data, None, root);
+}
+
+/// 按名字查找文件系统, 也接受文件系统自己的名字和 Linux 中常用的别名 (如 `proc`)
+pub fn filesystem(name: &str) -> Option<(String, Arc<dyn VfsFsType>)> {
+    let name = match name {
+        "proc" => "procfs",
+        "devtmpfs" => "devfs",
+        name => name,
+    };
+    FS.lock()
+        .iter()
+        // pipefs 只在内核内部使用
+        .filter(|(key, _)| key.as_str() != "pipefs")
+        .find(|(key, fs)| key.as_str() == name || fs.fs_name() == name)
+        .map(|(key, fs)| (key.clone(), fs.clone()))
+}
+
+/// 目标目录项和它的绝对路径, 目标需要是目录
+fn target_dir(target: &VfsPath) -> AlienResult<(Arc<dyn VfsDentry>, String)> {
+    let dentry = target.open(None)?;
+    if dentry.inode()?.inode_type() != VfsNodeType::Dir {
+        return Err(LinuxErrno::ENOTDIR);
+    }
+    let path = dentry.path();
+    Ok((dentry, path))
+}
+
+/// 在新的目录项上依次重建 `mounts`, 第一个挂载成为根目录
+///
+/// 返回新的根目录和挂载表。挂载点已经不存在的挂载被跳过。
+fn rebuild(mounts: Vec<Mount>) -> AlienResult<(Arc<dyn VfsDentry>, Vec<Mount>)> {
+    let mut mounts = mounts.into_iter();
+    let first = mounts.next().ok_or(LinuxErrno::EINVAL)?;
+    let root: Arc<dyn VfsDentry> = BindDentry::root(first.root.inode()?);
+    let path = VfsPath::new(root.clone(), root.clone());
+    let mut table = vec![Mount {
+        root: root.clone(),
+        ..first
+    }];
+    for mount in mounts {
+        let copy: Arc<dyn VfsDentry> = BindDentry::root(mount.root.inode()?);
+        let mounted = path
+            .join(&mount.target)
+            .and_then(|target| target.mount(copy.clone(), mount.flags.bits()));
+        match mounted {
+            Ok(()) => table.push(Mount { root: copy, ..mount }),
+            Err(e) => warn!("copy mount {} failed: {:?}", mount.target, e),
+        }
+    }
+    Ok((root, table))
+}
+
+impl MountNamespace {
+    fn new(root: Arc<dyn VfsDentry>, mounts: Vec<Mount>) -> Arc<Self> {
+        let mnt_ns = Arc::new(Self {
+            root: Mutex::new(root),
+            mounts: Mutex::new(mounts),
+        });
+        NAMESPACES.lock().push(Arc::downgrade(&mnt_ns));
+        mnt_ns
+    }
+
+    /// 命名空间的根目录
+    pub fn root(&self) -> Arc<dyn VfsDentry> {
+        self.root.lock().clone()
+    }
+
+    /// 命名空间中绝对路径 `path` 的目录项
+    pub fn lookup(&self, path: &str) -> AlienResult<Arc<dyn VfsDentry>> {
+        let root = self.root();
+        let dentry = VfsPath::new(root.clone(), root).join(path)?.open(None)?;
+        Ok(dentry)
+    }
+
+    /// 记录一个已经完成的挂载
+    #[allow(clippy::too_many_arguments)]
+    fn record(
+        &self,
+        source: &str,
+        target: &str,
+        fs_type: &str,
+        flags: MountFlags,
+        data: &str,
+        device: Option<u64>,
+        root: Arc<dyn VfsDentry>,
+    ) {
+        self.mounts.lock().push(Mount {
+            source: source.to_string(),
+            target: target.to_string(),
+            fs_type: fs_type.to_string(),
+            flags: flags & PER_MOUNT_FLAGS,
+            data: redact_options(data),
+            device,
+            root,
+        });
+    }
+
+    /// 命名空间中的所有挂载, 按挂载的先后顺序
+    pub fn mounts(&self) -> Vec<Mount> {
+        self.mounts.lock().clone()
+    }
+
+    /// 复制命名空间 (`CLONE_NEWNS`)
+    ///
+    /// 新命名空间有同样的挂载和标志, 挂载的文件系统是共享的。
+    pub fn copy(&self) -> AlienResult<Arc<Self>> {
+        let (root, mounts) = rebuild(self.mounts())?;
+        info!("copy mount namespace with {} mounts", mounts.len());
+        Ok(Self::new(root, mounts))
+    }
+
+    /// 把 `fs_type` 文件系统挂载到 `target`
+    ///
+    /// `dev` 是 `source` 指向的块设备或文件 (DBFS 也可以是目录), 没有设备的文件系统
+    /// (tmpfs、ramfs 等) 为 None。`flags` 和 `data` 原样交给文件系统, 由文件系统解析
+    /// 自己的选项 (如 DBFS 的 `compress=`、`MS_RDONLY`)。
+    pub fn mount(
+        &self,
+        source: &str,
+        dev: Option<Arc<dyn VfsDentry>>,
+        target: &VfsPath,
+        fs_type: &str,
+        flags: MountFlags,
+        data: &str,
+    ) -> AlienResult<()> {
+        let (name, fs) = filesystem(fs_type).ok_or(LinuxErrno::ENODEV)?;
+        let (_, target_path) = target_dir(target)?;
+        let dev = dev.map(|dev| dev.inode()).transpose()?;
+        let device = dev
+            .as_ref()
+            .filter(|dev| dev.inode_type() == VfsNodeType::BlockDevice)
+            .and_then(|dev| dev.get_attr().ok())
+            .map(|stat| stat.st_rdev);
+        let root = fs.i_mount(flags.bits(), &target_path, dev, data.as_bytes())?;
+        target.mount(root.clone(), flags.bits())?;
+        crate::mounted(&root);
+        info!("mount {} ({}) on {} with {:?}", source, name, target_path, flags);
+        self.record(source, &target_path, &name, flags, data, device, root);
+        Ok(())
+    }
+
+    /// 把目录 `source` 绑定挂载到 `target`
+    ///
+    /// 新挂载只包含 `source` 所在文件系统中的内容。带有 `MS_REC` 时, `source` 之下的
+    /// 每个挂载也被绑定到 `target` 下相同的相对位置, 并保留各自的标志; 否则这些挂载不会出现。
+    pub fn bind(
+        &self,
+        source: &Arc<dyn VfsDentry>,
+        target: &VfsPath,
+        flags: MountFlags,
+    ) -> AlienResult<()> {
+        let source_path = source.path();
+        // 先取出 source 之下的挂载, 避免把 target 上新建的挂载再绑定一次
+        let submounts = if flags.contains(MountFlags::MS_REC) {
+            self.submounts_of(&source_path)
+        } else {
+            Vec::new()
+        };
+        let fs_type = mount_of(source)
+            .map(|mount| mount.fs_type)
+            .unwrap_or_else(|| "none".to_string());
+        let target_path = self.bind_one(source, &source_path, &fs_type, target, flags)?;
+        for mount in submounts {
+            let relative = mount.target[source_path.len()..].trim_start_matches('/');
+            let target = target.join(relative)?;
+            let flags = (flags - PER_MOUNT_FLAGS) | mount.flags;
+            if let Err(e) =
+                self.bind_one(&mount.root, &mount.target, &mount.fs_type, &target, flags)
+            {
+                warn!("bind {} under {} failed: {:?}", mount.target, target_path, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 绑定一个目录, 返回挂载点的绝对路径
+    fn bind_one(
+        &self,
+        source: &Arc<dyn VfsDentry>,
+        source_path: &str,
+        fs_type: &str,
+        target: &VfsPath,
+        flags: MountFlags,
+    ) -> AlienResult<String> {
+        let inode = source.inode()?;
+        let (_, target_path) = target_dir(target)?;
+        let device = mount_of(source).and_then(|mount| mount.device);
+        let root: Arc<dyn VfsDentry> = BindDentry::root(inode);
+        target.mount(root.clone(), flags.bits())?;
+        info!("bind mount {} on {} with {:?}", source_path, target_path, flags);
+        self.record(source_path, &target_path, fs_type, flags, "", device, root);
+        Ok(target_path)
+    }
+
+    /// 挂载点在 `path` 之下的挂载, 按挂载的先后顺序
+    fn submounts_of(&self, path: &str) -> Vec<Mount> {
+        self.mounts
+            .lock()
+            .iter()
+            .filter(|mount| relative(&mount.target, path).is_some_and(|rest| !rest.is_empty()))
+            .cloned()
+            .collect()
+    }
+
+    /// 修改 `mount_root` 所在挂载的标志 (`MS_REMOUNT`), `mount_root` 需要是挂载的根目录
+    ///
+    /// 只修改挂载自己的标志; 文件系统的选项在挂载时确定, 不会重新解析 `data`。
+    /// 改为只读时, 挂载中还有以写方式打开的文件则返回 EBUSY。
+    pub fn remount(
+        &self,
+        mount_root: &Arc<dyn VfsDentry>,
+        flags: MountFlags,
+        data: &str,
+    ) -> AlienResult<()> {
+        let to_rdonly = flags.contains(MountFlags::MS_RDONLY)
+            && !flags_of(mount_root).contains(MountFlags::MS_RDONLY);
+        if to_rdonly && open_for_write_in(mount_root) {
+            return Err(LinuxErrno::EBUSY);
+        }
+        let mut mounts = self.mounts.lock();
+        let mount = mounts
+            .iter_mut()
+            .rev()
+            .find(|mount| same_dentry(&mount.root, mount_root))
+            .ok_or(LinuxErrno::EINVAL)?;
+        mount.flags = flags & PER_MOUNT_FLAGS;
+        if !data.is_empty() && !flags.contains(MountFlags::MS_BIND) {
+            mount.data = redact_options(data);
+        }
+        info!("remount {} with {:?}", mount.target, mount.flags);
+        Ok(())
+    }
+
+    /// 卸载 `target` 上的挂载
+    ///
+    /// 文件系统还在别处挂载 (其他命名空间或绑定挂载) 时只移除这一个挂载,
+    /// 不检查文件系统是否仍在使用。
+    pub fn umount(&self, target: &VfsPath) -> AlienResult<()> {
+        let mount_root = target.open(None)?;
+        let last = !mounted_elsewhere(&mount_root);
+        if last {
+            crate::check_umount(&mount_root)?;
+        }
+        let dbfs_mount = crate::dbfs_mount_point(&mount_root);
+        target.umount()?;
+        if let Some(mount_point) = dbfs_mount.filter(|_| last) {
+            crate::proc::unregister_dbfs_mount(&mount_point);
+        }
+        let mut mounts = self.mounts.lock();
+        if let Some(index) = mounts
+            .iter()
+            .rposition(|mount| same_dentry(&mount.root, &mount_root))
+        {
+            mounts.remove(index);
+        }
+        Ok(())
+    }
+
+    /// 把命名空间的根目录换成 `new_root` (`pivot_root`), 原来的根目录移到 `put_old`
+    ///
+    /// `new_root` 需要是一个挂载的根目录且不是命名空间的根目录, `put_old` 需要在 `new_root`
+    /// 之下。命名空间在新的目录项上重建, 返回路径的变化, 用于更新任务的根目录和当前目录。
+    pub fn pivot_root(
+        &self,
+        new_root: &Arc<dyn VfsDentry>,
+        put_old: &Arc<dyn VfsDentry>,
+    ) -> AlienResult<Pivot> {
+        if same_dentry(new_root, &self.root()) {
+            return Err(LinuxErrno::EBUSY);
+        }
+        let mounts = self.mounts();
+        let new_root_mount = mounts
+            .iter()
+            .rposition(|mount| same_dentry(&mount.root, new_root))
+            .ok_or(LinuxErrno::EINVAL)?;
+        let new_root_path = new_root.path();
+        let put_old_path = put_old.path();
+        let put_old = relative(&put_old_path, &new_root_path).ok_or(LinuxErrno::EINVAL)?;
+        let pivot = Pivot {
+            put_old: join("/", put_old),
+            new_root: new_root_path,
+        };
+        // 新根目录的挂载和之后挂在它之下的挂载排在前面,
+        // 其余挂载 (包括原来的根目录) 移到 put_old 之下
+        let (moved, kept): (Vec<_>, Vec<_>) =
+            mounts.into_iter().enumerate().partition(|(index, mount)| {
+                *index >= new_root_mount && relative(&mount.target, &pivot.new_root).is_some()
+            });
+        let table = moved
+            .into_iter()
+            .chain(kept)
+            .map(|(_, mount)| Mount {
+                target: pivot.path(&mount.target),
+                ..mount
+            })
+            .collect();
+        let (root, mounts) = rebuild(table)?;
+        info!("pivot_root to {}, old root at {}", pivot.new_root, pivot.put_old);
+        *self.root.lock() = root;
+        *self.mounts.lock() = mounts;
+        Ok(pivot)
+    }
+}
+
+/// `pivot_root` 对路径的改变
+pub struct Pivot {
+    /// 新根目录原来的路径
+    new_root: String,
+    /// 原来的根目录的新路径
+    put_old: String,
+}
+
+impl Pivot {
+    /// 原来的绝对路径 `path` 在 `pivot_root` 之后的路径: 新根目录之下的路径去掉新根目录的前缀,
+    /// 其余路径移到 `put_old` 之下
+    pub fn path(&self, path: &str) -> String {
+        match relative(path, &self.new_root) {
+            Some(rest) => join("/", rest),
+            None => join(&self.put_old, path.trim_start_matches('/')),
+        }
+    }
+}
+
+/// 值不能出现在 `/proc/mounts` 和日志中的挂载选项 (DBFS 的密钥)
+const SECRET_OPTIONS: [&str; 2] = ["key", "keyfile"];
+
+/// 把挂载选项中密钥的值替换为 `***`, 用于记录挂载表和打印日志
+pub fn redact_options(data: &str) -> String {
+    data.split(',')
+        .map(|option| match option.split_once('=') {
+            Some((name, _)) if SECRET_OPTIONS.contains(&name) => format!("{}=***", name),
+            _ => option.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// `flags` 是否只修改传播类型; 没有挂载传播, 所有挂载都是私有的, 这样的调用直接成功
+pub fn propagation_only(flags: MountFlags) -> bool {
+    flags.intersects(PROPAGATION_FLAGS)
+        && !flags.intersects(MountFlags::MS_BIND | MountFlags::MS_REMOUNT)
+}
+
+/// 除 `mount_root` 自己之外, 是否还有挂载 (在任何命名空间中) 使用同一个文件系统
+fn mounted_elsewhere(mount_root: &Arc<dyn VfsDentry>) -> bool {
+    let Some(sb) = superblock(mount_root) else {
+        return false;
+    };
+    namespaces().iter().any(|mnt_ns| {
+        mnt_ns.mounts.lock().iter().any(|mount| {
+            !same_dentry(&mount.root, mount_root)
+                && superblock(&mount.root).is_some_and(|other| same_sb(&other, &sb))
+        })
+    })
+}
+
+/// 所有命名空间中的挂载, 同一个文件系统可能出现多次 (复制的命名空间、绑定挂载)
+pub fn all_mounts() -> Vec<Mount> {
+    namespaces()
+        .iter()
+        .flat_map(|mnt_ns| mnt_ns.mounts())
+        .collect()
+}
+
+/// 是否有挂载 (在任何命名空间中, 包括绑定挂载) 的文件系统在设备号为 `device` 的块设备上
+pub fn device_in_use(device: u64) -> bool {
+    namespaces()
+        .iter()
+        .any(|mnt_ns| mnt_ns.mounts.lock().iter().any(|mount| mount.device == Some(device)))
+}
+
+/// `dentry` 所在的挂载: 沿父目录向上找到的第一个挂载根目录
+///
+/// 挂载的根目录项属于唯一的命名空间, 因此在所有命名空间中查找。
+pub fn mount_of(dentry: &Arc<dyn VfsDentry>) -> Option<Mount> {
+    let namespaces = namespaces();
+    let mut dentry = dentry.clone();
+    loop {
+        let found = namespaces.iter().find_map(|mnt_ns| {
+            mnt_ns
+                .mounts
+                .lock()
+                .iter()
+                .rev()
+                .find(|mount| same_dentry(&mount.root, &dentry))
+                .cloned()
+        });
+        if found.is_some() {
+            return found;
+        }
+        dentry = dentry.parent()?;
+    }
+}
+
+/// `dentry` 所在挂载的标志
+pub fn flags_of(dentry: &Arc<dyn VfsDentry>) -> MountFlags {
+    mount_of(dentry).map_or(MountFlags::empty(), |mount| mount.flags)
+}
+
+/// 检查能否通过 `dentry` 所在的挂载修改文件系统, 只读挂载返回 EROFS
+///
+/// 只读是挂载的属性: 同一个文件系统可以在一处可写, 同时通过只读的绑定挂载出现在另一处。
+pub fn check_writable(dentry: &Arc<dyn VfsDentry>) -> AlienResult<()> {
+    if flags_of(dentry).contains(MountFlags::MS_RDONLY) {
+        return Err(LinuxErrno::EROFS);
+    }
+    Ok(())
+}
+
+/// 记录一次以写方式打开 `dentry`, 与 [`close_for_write`] 成对调用
+pub(crate) fn open_for_write(dentry: &Arc<dyn VfsDentry>) {
+    WRITERS.lock().push(dentry.clone());
+}
+
+/// 以写方式打开的文件关闭
+pub(crate) fn close_for_write(dentry: &Arc<dyn VfsDentry>) {
+    let mut writers = WRITERS.lock();
+    if let Some(index) = writers.iter().position(|writer| same_dentry(writer, dentry)) {
+        writers.swap_remove(index);
+    }
+}
+
+/// 根目录为 `mount_root` 的挂载中是否有以写方式打开的文件
+fn open_for_write_in(mount_root: &Arc<dyn VfsDentry>) -> bool {
+    let writers = WRITERS.lock().clone();
+    writers.iter().any(|writer| {
+        mount_of(writer).is_some_and(|mount| same_dentry(&mount.root, mount_root))
+    })
+}
@@ -0,0 +1,639 @@
+//! Inodes of an overlay mount
+//!
+//! An [`OverlayInode`] remembers the entry of the same path in the upper layer
+//! (if any) and in the lower layers. Files and symlinks have at most one lower
+//! entry; directories keep every lower directory that is merged into them,
+//! topmost first.
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec,
+    vec::Vec,
+};
+
+use ksync::Mutex;
+use log::info;
+use vfscore::{
+    error::VfsError,
+    file::VfsFile,
+    inode::{InodeAttr, VfsInode},
+    superblock::VfsSuperBlock,
+    utils::{
+        VfsDirEntry, VfsFileStat, VfsNodePerm, VfsNodeType, VfsPollEvents, VfsRenameFlag,
+        VfsTime, VfsTimeSpec,
+    },
+    VfsResult,
+};
+
+use super::OverlaySuperBlock;
+
+/// Prefix of a whiteout, an empty file hiding the lower entry of the rest of its name
+const WHITEOUT_PREFIX: &str = ".wh.";
+/// Marker file of an opaque directory, whose lower directories are not merged
+const OPAQUE: &str = ".wh..wh..opq";
+
+fn whiteout(name: &str) -> String {
+    format!("{}{}", WHITEOUT_PREFIX, name)
+}
+
+/// Temporary name of a file being copied up, hidden like a whiteout
+fn copy_up_temp(name: &str) -> String {
+    format!("{}{}copyup.{}", WHITEOUT_PREFIX, WHITEOUT_PREFIX, name)
+}
+
+/// Copies the contents of the lower file `from` into the upper file `to`
+fn copy_data(from: &Arc<dyn VfsInode>, to: &Arc<dyn VfsInode>) -> VfsResult<()> {
+    let mut buf = vec![0u8; 4096];
+    let mut offset = 0;
+    loop {
+        let len = crate::pagecache::read_at(from, offset, &mut buf)
+            .map_err(|_| VfsError::IoError)?;
+        if len == 0 {
+            return Ok(());
+        }
+        to.write_at(offset, &buf[..len])?;
+        offset += len as u64;
+    }
+}
+
+fn exists(dir: &Arc<dyn VfsInode>, name: &str) -> bool {
+    dir.lookup(name).is_ok()
+}
+
+fn is_opaque(dir: &Arc<dyn VfsInode>) -> bool {
+    exists(dir, OPAQUE)
+}
+
+/// Every entry of one layer directory
+fn layer_entries(dir: &Arc<dyn VfsInode>) -> VfsResult<Vec<VfsDirEntry>> {
+    let mut entries = Vec::new();
+    while let Some(entry) = dir.readdir(entries.len())? {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The entry `name` in the layers of a directory
+struct Found {
+    ty: VfsNodeType,
+    upper: Option<Arc<dyn VfsInode>>,
+    lowers: Vec<Arc<dyn VfsInode>>,
+}
+
+/// Looks `name` up in the upper directory and then in the lower directories
+///
+/// A non-directory hides everything below it, a whiteout hides the layers
+/// below the one it is in, and an opaque directory ends the merge.
+fn resolve(
+    upper: Option<&Arc<dyn VfsInode>>,
+    lowers: &[Arc<dyn VfsInode>],
+    name: &str,
+) -> Option<Found> {
+    let whiteout = whiteout(name);
+    let mut found = Found {
+        ty: VfsNodeType::Dir,
+        upper: None,
+        lowers: Vec::new(),
+    };
+    if let Some(dir) = upper {
+        match dir.lookup(name) {
+            Ok(inode) => {
+                let ty = inode.inode_type();
+                let opaque = ty == VfsNodeType::Dir && is_opaque(&inode);
+                found.upper = Some(inode);
+                if ty != VfsNodeType::Dir || opaque {
+                    found.ty = ty;
+                    return Some(found);
+                }
+            }
+            Err(_) if exists(dir, &whiteout) => return None,
+            Err(_) => {}
+        }
+    }
+    for dir in lowers {
+        match dir.lookup(name) {
+            Ok(inode) => {
+                let ty = inode.inode_type();
+                if ty != VfsNodeType::Dir {
+                    if found.upper.is_none() && found.lowers.is_empty() {
+                        found.ty = ty;
+                        found.lowers.push(inode);
+                    }
+                    break;
+                }
+                let opaque = is_opaque(&inode);
+                found.lowers.push(inode);
+                if opaque {
+                    break;
+                }
+            }
+            Err(_) if exists(dir, &whiteout) => break,
+            Err(_) => {}
+        }
+    }
+    if found.upper.is_none() && found.lowers.is_empty() {
+        return None;
+    }
+    Some(found)
+}
+
+pub struct OverlayInode {
+    sb: Arc<OverlaySuperBlock>,
+    this: Weak<OverlayInode>,
+    ty: VfsNodeType,
+    inner: Mutex<OverlayInodeInner>,
+}
+
+struct OverlayInodeInner {
+    /// The directory this entry is in, None for the root
+    parent: Option<Arc<OverlayInode>>,
+    name: String,
+    upper: Option<Arc<dyn VfsInode>>,
+    lowers: Vec<Arc<dyn VfsInode>>,
+    /// Inodes handed out by lookup, so every lookup of a name sees the same copy-up
+    children: BTreeMap<String, Weak<OverlayInode>>,
+    /// Merged listing taken when a `readdir` starts at index 0
+    listing: Vec<(String, u64, VfsNodeType)>,
+    /// A copy-up of this entry is running (without the lock held)
+    copying: bool,
+}
+
+impl OverlayInode {
+    pub(super) fn root(
+        sb: Arc<OverlaySuperBlock>,
+        upper: Option<Arc<dyn VfsInode>>,
+        lowers: Vec<Arc<dyn VfsInode>>,
+    ) -> Arc<Self> {
+        let lowers = match &upper {
+            Some(upper) if is_opaque(upper) => Vec::new(),
+            _ => lowers,
+        };
+        let found = Found {
+            ty: VfsNodeType::Dir,
+            upper,
+            lowers,
+        };
+        Self::new(sb, None, "/".to_string(), found)
+    }
+
+    fn new(
+        sb: Arc<OverlaySuperBlock>,
+        parent: Option<Arc<OverlayInode>>,
+        name: String,
+        found: Found,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            sb,
+            this: this.clone(),
+            ty: found.ty,
+            inner: Mutex::new(OverlayInodeInner {
+                parent,
+                name,
+                upper: found.upper,
+                lowers: found.lowers,
+                children: BTreeMap::new(),
+                listing: Vec::new(),
+                copying: false,
+            }),
+        })
+    }
+
+    fn this(&self) -> Arc<Self> {
+        self.this.upgrade().unwrap()
+    }
+
+    /// The inode operations go to: the upper entry if there is one, otherwise
+    /// the topmost lower entry
+    pub(super) fn real(&self) -> Arc<dyn VfsInode> {
+        let inner = self.inner.lock();
+        inner
+            .upper
+            .clone()
+            .or_else(|| inner.lowers.first().cloned())
+            .expect("overlay inode without any layer")
+    }
+
+    fn upper(&self) -> Option<Arc<dyn VfsInode>> {
+        self.inner.lock().upper.clone()
+    }
+
+    /// Whether a lower layer has an entry at this path
+    fn in_lower(&self) -> bool {
+        !self.inner.lock().lowers.is_empty()
+    }
+
+    /// Returns the upper entry, copying this entry (and missing parent
+    /// directories) up from the lower layer first if needed
+    ///
+    /// Only the directory itself is copied up, its lower entries stay merged.
+    /// The copy can be a whole file written through the upper filesystem, so
+    /// it runs without the inode lock held; a second copy-up of the same entry
+    /// waits until the first one is done.
+    fn copy_up(&self) -> VfsResult<Arc<dyn VfsInode>> {
+        if !self.sb.writable() {
+            return Err(VfsError::EROFS);
+        }
+        let (parent, lower, name) = loop {
+            let mut inner = self.inner.lock();
+            if let Some(upper) = &inner.upper {
+                return Ok(upper.clone());
+            }
+            if !inner.copying {
+                let parent = inner.parent.clone().ok_or(VfsError::EROFS)?;
+                let lower = inner.lowers.first().cloned().ok_or(VfsError::IoError)?;
+                inner.copying = true;
+                break (parent, lower, inner.name.clone());
+            }
+            drop(inner);
+            shim::suspend();
+        };
+        let copied = parent
+            .copy_up()
+            .and_then(|dir| self.create_upper(&dir, &lower, &name));
+        let mut inner = self.inner.lock();
+        inner.copying = false;
+        let upper = copied?;
+        if let Some(upper) = &inner.upper {
+            return Ok(upper.clone());
+        }
+        info!("overlay: copied up {}", name);
+        inner.upper = Some(upper.clone());
+        if self.ty != VfsNodeType::Dir {
+            inner.lowers.clear();
+        }
+        Ok(upper)
+    }
+
+    /// Creates the entry `name` in the upper directory `dir` as a copy of `lower`
+    fn create_upper(
+        &self,
+        dir: &Arc<dyn VfsInode>,
+        lower: &Arc<dyn VfsInode>,
+        name: &str,
+    ) -> VfsResult<Arc<dyn VfsInode>> {
+        let perm = lower.node_perm();
+        match self.ty {
+            VfsNodeType::File => {
+                // Copied under a hidden name first, so a failed copy leaves no partial file
+                let temp = copy_up_temp(name);
+                let _ = dir.unlink(&temp);
+                let file = dir.create(&temp, VfsNodeType::File, perm, None)?;
+                let copied = copy_data(lower, &file).and_then(|_| {
+                    dir.rename_to(&temp, dir.clone(), name, VfsRenameFlag::empty())
+                });
+                if let Err(e) = copied {
+                    let _ = dir.unlink(&temp);
+                    return Err(e);
+                }
+                Ok(file)
+            }
+            VfsNodeType::Dir => dir.create(name, VfsNodeType::Dir, perm, None),
+            VfsNodeType::SymLink => {
+                let mut buf = vec![0u8; 4096];
+                let len = lower.readlink(&mut buf)?;
+                let target = core::str::from_utf8(&buf[..len]).map_err(|_| VfsError::Invalid)?;
+                dir.symlink(name, target)
+            }
+            ty => {
+                let rdev = lower.get_attr()?.st_rdev;
+                dir.create(name, ty, perm, Some(rdev))
+            }
+        }
+    }
+
+    /// Copies up this entry and, for a directory, everything below it, and
+    /// marks the directory opaque, so it no longer depends on the lower layers
+    fn copy_up_tree(&self) -> VfsResult<Arc<dyn VfsInode>> {
+        let upper = self.copy_up()?;
+        if self.ty != VfsNodeType::Dir || !self.in_lower() {
+            return Ok(upper);
+        }
+        for (name, _, _) in self.merged()? {
+            if name == "." || name == ".." {
+                continue;
+            }
+            self.child(&name)?.copy_up_tree()?;
+        }
+        upper.create(OPAQUE, VfsNodeType::File, VfsNodePerm::empty(), None)?;
+        self.inner.lock().lowers.clear();
+        Ok(upper)
+    }
+
+    /// The merged listing of a directory: upper entries first, then lower
+    /// entries not hidden by an upper entry or a whiteout
+    fn merged(&self) -> VfsResult<Vec<(String, u64, VfsNodeType)>> {
+        let (upper, lowers) = {
+            let inner = self.inner.lock();
+            (inner.upper.clone(), inner.lowers.clone())
+        };
+        let mut seen = BTreeSet::new();
+        let mut listing = Vec::new();
+        for layer in upper.iter().chain(lowers.iter()) {
+            let entries = layer_entries(layer)?;
+            for entry in &entries {
+                if let Some(name) = entry.name.strip_prefix(WHITEOUT_PREFIX) {
+                    if entry.name != OPAQUE {
+                        seen.insert(name.to_string());
+                    }
+                } else if !seen.contains(&entry.name) {
+                    listing.push((entry.name.clone(), entry.ino, entry.ty));
+                }
+            }
+            seen.extend(entries.into_iter().map(|entry| entry.name));
+        }
+        Ok(listing)
+    }
+
+    /// Whether a directory has no entries besides `.` and `..`
+    fn is_empty_dir(&self) -> VfsResult<bool> {
+        let merged = self.merged()?;
+        Ok(merged.iter().all(|(name, _, _)| name == "." || name == ".."))
+    }
+
+    /// The overlay inode of the entry `name` in this directory
+    fn child(&self, name: &str) -> VfsResult<Arc<OverlayInode>> {
+        if self.ty != VfsNodeType::Dir {
+            return Err(VfsError::NotDir);
+        }
+        if name.starts_with(WHITEOUT_PREFIX) {
+            return Err(VfsError::NoEntry);
+        }
+        let mut inner = self.inner.lock();
+        if let Some(child) = inner.children.get(name).and_then(Weak::upgrade) {
+            return Ok(child);
+        }
+        let found =
+            resolve(inner.upper.as_ref(), &inner.lowers, name).ok_or(VfsError::NoEntry)?;
+        let child = Self::new(self.sb.clone(), Some(self.this()), name.to_string(), found);
+        inner.children.insert(name.to_string(), Arc::downgrade(&child));
+        Ok(child)
+    }
+
+    /// Forgets the entry `name` after it was created, removed or renamed
+    fn changed(&self, name: &str) {
+        let mut inner = self.inner.lock();
+        inner.children.remove(name);
+        inner.children.retain(|_, child| child.strong_count() > 0);
+        inner.listing.clear();
+    }
+
+    /// Makes room for a new entry `name` in the upper directory: drops the
+    /// whiteout for it, and returns whether there was one
+    fn prepare_create(&self, name: &str) -> VfsResult<(Arc<dyn VfsInode>, bool)> {
+        if self.child(name).is_ok() {
+            return Err(VfsError::EExist);
+        }
+        let dir = self.copy_up()?;
+        let whiteout = whiteout(name);
+        let whited_out = exists(&dir, &whiteout);
+        if whited_out {
+            dir.unlink(&whiteout)?;
+        }
+        Ok((dir, whited_out))
+    }
+
+    /// Removes the entry `name`, leaving a whiteout if a lower layer has it
+    fn remove(&self, name: &str, is_dir: bool) -> VfsResult<()> {
+        let child = self.child(name)?;
+        match (is_dir, child.ty == VfsNodeType::Dir) {
+            (true, false) => return Err(VfsError::NotDir),
+            (false, true) => return Err(VfsError::IsDir),
+            _ => {}
+        }
+        if is_dir && !child.is_empty_dir()? {
+            return Err(VfsError::NotEmpty);
+        }
+        let dir = self.copy_up()?;
+        if let Some(upper) = child.upper() {
+            if is_dir {
+                // Whiteouts left in the directory keep it from being empty in the upper layer
+                for entry in layer_entries(&upper)? {
+                    if entry.name.starts_with(WHITEOUT_PREFIX) {
+                        upper.unlink(&entry.name)?;
+                    }
+                }
+                dir.rmdir(name)?;
+            } else {
+                dir.unlink(name)?;
+            }
+        }
+        if child.in_lower() {
+            dir.create(&whiteout(name), VfsNodeType::File, VfsNodePerm::empty(), None)?;
+        }
+        self.changed(name);
+        Ok(())
+    }
+}
+
+impl VfsFile for OverlayInode {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        self.real().read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.copy_up()?.write_at(offset, buf)
+    }
+
+    fn readdir(&self, start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
+        if self.ty != VfsNodeType::Dir {
+            return Err(VfsError::NotDir);
+        }
+        if start_index == 0 {
+            let listing = self.merged()?;
+            self.inner.lock().listing = listing;
+        }
+        let inner = self.inner.lock();
+        Ok(inner
+            .listing
+            .get(start_index)
+            .map(|(name, ino, ty)| VfsDirEntry {
+                ino: *ino,
+                ty: *ty,
+                name: name.clone(),
+            }))
+    }
+
+    fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
+        self.real().poll(event)
+    }
+
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        self.real().ioctl(cmd, arg)
+    }
+
+    fn flush(&self) -> VfsResult<()> {
+        match self.upper() {
+            Some(upper) => upper.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn fsync(&self) -> VfsResult<()> {
+        match self.upper() {
+            Some(upper) => upper.fsync(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl VfsInode for OverlayInode {
+    fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
+        Ok(self.sb.clone())
+    }
+
+    fn node_perm(&self) -> VfsNodePerm {
+        self.real().node_perm()
+    }
+
+    fn create(
+        &self,
+        name: &str,
+        ty: VfsNodeType,
+        perm: VfsNodePerm,
+        rdev: Option<u64>,
+    ) -> VfsResult<Arc<dyn VfsInode>> {
+        let (dir, whited_out) = self.prepare_create(name)?;
+        let inode = dir.create(name, ty, perm, rdev)?;
+        // A deleted lower directory of the same name must not reappear in the new one
+        if ty == VfsNodeType::Dir && whited_out {
+            inode.create(OPAQUE, VfsNodeType::File, VfsNodePerm::empty(), None)?;
+        }
+        self.changed(name);
+        Ok(self.child(name)? as Arc<dyn VfsInode>)
+    }
+
+    fn link(&self, name: &str, src: Arc<dyn VfsInode>) -> VfsResult<Arc<dyn VfsInode>> {
+        let src = src
+            .downcast_arc::<OverlayInode>()
+            .map_err(|_| VfsError::Invalid)?;
+        let src = src.copy_up()?;
+        let (dir, _) = self.prepare_create(name)?;
+        dir.link(name, src)?;
+        self.changed(name);
+        Ok(self.child(name)? as Arc<dyn VfsInode>)
+    }
+
+    fn unlink(&self, name: &str) -> VfsResult<()> {
+        self.remove(name, false)
+    }
+
+    fn symlink(&self, name: &str, sy_name: &str) -> VfsResult<Arc<dyn VfsInode>> {
+        let (dir, _) = self.prepare_create(name)?;
+        dir.symlink(name, sy_name)?;
+        self.changed(name);
+        Ok(self.child(name)? as Arc<dyn VfsInode>)
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn VfsInode>> {
+        match name {
+            "." => Ok(self.this() as Arc<dyn VfsInode>),
+            ".." => {
+                let parent = self.inner.lock().parent.clone();
+                Ok(parent.unwrap_or_else(|| self.this()) as Arc<dyn VfsInode>)
+            }
+            name => Ok(self.child(name)? as Arc<dyn VfsInode>),
+        }
+    }
+
+    fn rmdir(&self, name: &str) -> VfsResult<()> {
+        self.remove(name, true)
+    }
+
+    fn readlink(&self, buf: &mut [u8]) -> VfsResult<usize> {
+        self.real().readlink(buf)
+    }
+
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        self.copy_up()?.set_attr(attr)
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        self.real().get_attr()
+    }
+
+    fn list_xattr(&self) -> VfsResult<Vec<String>> {
+        self.real().list_xattr()
+    }
+
+    fn inode_type(&self) -> VfsNodeType {
+        self.ty
+    }
+
+    fn truncate(&self, len: u64) -> VfsResult<()> {
+        self.copy_up()?.truncate(len)
+    }
+
+    /// Renames within the overlay. A directory that has lower entries is copied
+    /// up as a whole first, since a directory in the lower layers cannot move.
+    fn rename_to(
+        &self,
+        old_name: &str,
+        new_parent: Arc<dyn VfsInode>,
+        new_name: &str,
+        flag: VfsRenameFlag,
+    ) -> VfsResult<()> {
+        if flag.contains(VfsRenameFlag::RENAME_EXCHANGE)
+            || flag.contains(VfsRenameFlag::RENAME_WHITEOUT)
+        {
+            return Err(VfsError::NoSys);
+        }
+        let new_parent = new_parent
+            .downcast_arc::<OverlayInode>()
+            .map_err(|_| VfsError::Invalid)?;
+        let child = self.child(old_name)?;
+        let target = new_parent.child(new_name).ok();
+        if let Some(target) = &target {
+            if Arc::ptr_eq(target, &child) {
+                return Ok(());
+            }
+            if flag.contains(VfsRenameFlag::RENAME_NOREPLACE) {
+                return Err(VfsError::EExist);
+            }
+            match (child.ty == VfsNodeType::Dir, target.ty == VfsNodeType::Dir) {
+                (true, false) => return Err(VfsError::NotDir),
+                (false, true) => return Err(VfsError::IsDir),
+                _ => {}
+            }
+        }
+        let was_in_lower = child.in_lower();
+        child.copy_up_tree()?;
+        let dir = self.copy_up()?;
+        if let Some(target) = target {
+            new_parent.remove(new_name, target.ty == VfsNodeType::Dir)?;
+        }
+        let (new_dir, whited_out) = new_parent.prepare_create(new_name)?;
+        dir.rename_to(old_name, new_dir.clone(), new_name, VfsRenameFlag::empty())?;
+        // The replaced lower directory must not show through the moved one
+        if child.ty == VfsNodeType::Dir && whited_out {
+            let moved = new_dir.lookup(new_name)?;
+            if !is_opaque(&moved) {
+                moved.create(OPAQUE, VfsNodeType::File, VfsNodePerm::empty(), None)?;
+            }
+        }
+        if was_in_lower {
+            dir.create(&whiteout(old_name), VfsNodeType::File, VfsNodePerm::empty(), None)?;
+        }
+        {
+            let mut inner = child.inner.lock();
+            inner.parent = Some(new_parent.clone());
+            inner.name = new_name.to_string();
+        }
+        self.changed(old_name);
+        new_parent.changed(new_name);
+        Ok(())
+    }
+
+    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
+        // Reading a lower file updates its access time, which must not copy it up
+        let upper = match time {
+            VfsTime::AccessTime(_) => self.upper(),
+            _ => Some(self.copy_up()?),
+        };
+        match upper {
+            Some(upper) => upper.update_time(time, now),
+            None => Ok(()),
+        }
+    }
+}
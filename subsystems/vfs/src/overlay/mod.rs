@@ -0,0 +1,186 @@
+//! Overlay filesystem
+//!
+//! An overlay mount merges a writable upper directory over one or more
+//! read-only lower directories:
+//!
+//! ```text
+//! mount -t overlay overlay -o lowerdir=/tests:/base,upperdir=/data/job /mnt
+//! ```
+//!
+//! Lower directories are listed topmost first. Lookups take the upper entry if
+//! there is one, otherwise the first lower entry; directories present in several
+//! layers are merged. Modifying a lower file first copies it up to the upper
+//! layer. Deletions of lower entries leave a whiteout in the upper directory,
+//! and a directory created over a whiteout is marked opaque so the lower
+//! directory of the same name stays hidden.
+//!
+//! Whiteouts and opaque markers are ordinary empty files (`.wh.<name>` and
+//! `.wh..wh..opq`) rather than character devices and xattrs, so any
+//! filesystem that can create files works as the upper layer. With DBFS as the
+//! upper layer every change of a job goes through DBFS transactions and can
+//! be rolled back by discarding the upper directory.
+//!
+//! Without `upperdir` (or with `MS_RDONLY`) the overlay is read-only and
+//! changes fail with `EROFS`. `workdir` is accepted for compatibility and
+//! ignored: a file is copied up under a hidden name in the upper directory and
+//! renamed into place, so a failed copy-up leaves nothing behind.
+//! Mounts below a layer directory are not visible through the overlay.
+mod inode;
+
+use alloc::{
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+
+use ksync::Mutex;
+use log::info;
+use vfscore::{
+    dentry::VfsDentry,
+    error::VfsError,
+    fstype::{FileSystemFlags, VfsFsType},
+    inode::VfsInode,
+    path::VfsPath,
+    superblock::{SuperType, VfsSuperBlock},
+    utils::{VfsFsStat, VfsNodeType},
+    VfsResult,
+};
+
+pub use self::inode::OverlayInode;
+use crate::bind::BindDentry;
+
+/// `MS_RDONLY` of `mount(2)`
+const MS_RDONLY: u32 = 1;
+
+/// Options of an overlay mount, parsed from the mount data
+#[derive(Debug, Default)]
+struct OverlayOptions {
+    lower: Vec<String>,
+    upper: Option<String>,
+}
+
+impl OverlayOptions {
+    fn parse(data: &[u8]) -> VfsResult<Self> {
+        let data = core::str::from_utf8(data).map_err(|_| VfsError::Invalid)?;
+        let data = data.trim_end_matches('\0');
+        let mut options = Self::default();
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                Some(("lowerdir", dirs)) => {
+                    options.lower = dirs.split(':').map(ToString::to_string).collect();
+                }
+                Some(("upperdir", dir)) => options.upper = Some(dir.to_string()),
+                Some(("workdir", _)) => {}
+                _ => return Err(VfsError::Invalid),
+            }
+        }
+        if options.lower.iter().all(|dir| dir.is_empty()) {
+            return Err(VfsError::Invalid);
+        }
+        Ok(options)
+    }
+}
+
+/// Resolves a layer directory, which must be an absolute path in the caller's
+/// mount namespace (and below its root directory)
+fn layer_dir(path: &str) -> VfsResult<Arc<dyn VfsInode>> {
+    if !path.starts_with('/') {
+        return Err(VfsError::Invalid);
+    }
+    let (_, root) = crate::mount::caller_fs();
+    let inode = VfsPath::new(root.clone(), root).join(path)?.open(None)?.inode()?;
+    if inode.inode_type() != VfsNodeType::Dir {
+        return Err(VfsError::NotDir);
+    }
+    Ok(inode)
+}
+
+pub struct OverlayFsType;
+
+impl VfsFsType for OverlayFsType {
+    fn mount(
+        self: Arc<Self>,
+        flags: u32,
+        ab_mnt: &str,
+        _dev: Option<Arc<dyn VfsInode>>,
+        data: &[u8],
+    ) -> VfsResult<Arc<dyn VfsDentry>> {
+        let options = OverlayOptions::parse(data)?;
+        let lowers = options
+            .lower
+            .iter()
+            .map(|dir| layer_dir(dir))
+            .collect::<VfsResult<Vec<_>>>()?;
+        let upper = match options.upper {
+            Some(dir) if flags & MS_RDONLY == 0 => Some(layer_dir(&dir)?),
+            _ => None,
+        };
+        info!(
+            "overlay: mount {} with lower {:?}, writable: {}",
+            ab_mnt,
+            options.lower,
+            upper.is_some()
+        );
+        let sb = Arc::new(OverlaySuperBlock {
+            fs_type: self.clone(),
+            upper: upper.clone(),
+            root: Mutex::new(Weak::new()),
+        });
+        let root = OverlayInode::root(sb.clone(), upper, lowers);
+        *sb.root.lock() = Arc::downgrade(&root);
+        Ok(BindDentry::root(root) as Arc<dyn VfsDentry>)
+    }
+
+    fn kill_sb(&self, _sb: Arc<dyn VfsSuperBlock>) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn fs_flag(&self) -> FileSystemFlags {
+        FileSystemFlags::empty()
+    }
+
+    fn fs_name(&self) -> String {
+        "overlay".to_string()
+    }
+}
+
+pub struct OverlaySuperBlock {
+    fs_type: Arc<OverlayFsType>,
+    upper: Option<Arc<dyn VfsInode>>,
+    root: Mutex<Weak<OverlayInode>>,
+}
+
+impl OverlaySuperBlock {
+    /// Whether the overlay has an upper layer to write to
+    fn writable(&self) -> bool {
+        self.upper.is_some()
+    }
+}
+
+impl VfsSuperBlock for OverlaySuperBlock {
+    fn sync_fs(&self, wait: bool) -> VfsResult<()> {
+        match &self.upper {
+            Some(upper) => upper.get_super_block()?.sync_fs(wait),
+            None => Ok(()),
+        }
+    }
+
+    fn stat_fs(&self) -> VfsResult<VfsFsStat> {
+        let root = self.root_inode()?;
+        let root = root.downcast_arc::<OverlayInode>().map_err(|_| VfsError::Invalid)?;
+        root.real().get_super_block()?.stat_fs()
+    }
+
+    fn super_type(&self) -> SuperType {
+        SuperType::Independent
+    }
+
+    fn fs_type(&self) -> Arc<dyn VfsFsType> {
+        self.fs_type.clone()
+    }
+
+    fn root_inode(&self) -> VfsResult<Arc<dyn VfsInode>> {
+        let root = self.root.lock().upgrade().ok_or(VfsError::Invalid)?;
+        Ok(root as Arc<dyn VfsInode>)
+    }
+}
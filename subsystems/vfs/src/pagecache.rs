@@ -0,0 +1,579 @@
+//! 普通文件的页缓存
+//!
+//! 每个打开的普通文件有一个页缓存 (按 inode 区分, 同一个文件的不同打开共享),
+//! `read`/`write` 和共享内存映射 (MAP_SHARED) 使用同一组页, 因此通过映射的修改
+//! 立即对 `read` 可见, `write` 的内容也立即出现在映射中。
+//!
+//! + 读: 缺页时从文件读入; 顺序读时预读后面的页, 预读窗口随连续的顺序读加倍
+//! + 写: 文件大小之内的写入只修改缓存页并标记为脏; 超出文件末尾的部分直接写入文件,
+//!   文件大小总是由文件系统记录
+//! + 写回: `fsync`、`msync`、最后一次关闭 (和最后一个映射解除) 时, 以及回收脏页时,
+//!   通过 [`write_back_mapping`](crate::write_back_mapping) 写回
+//! + 回收: 缓存的页数超过 [`PAGE_CACHE_MAX_PAGES`] 或分配物理页失败时, 回收最久未访问的页
+//!   (先写回脏页), 被映射的页不会被回收
+//!
+//! procfs、sysfs、devfs 和 pipefs 的文件没有页缓存: 它们的内容在读写时由内核生成或处理
+//! (如写入时立即生效的控制文件、只能追加的变更流), 读写直接访问文件, 也不能共享映射。
+//!
+//! DBFS 的文件是例外: 写入需要在调用者的事务中完成, 事务回滚也会在缓存之外改变文件,
+//! 因此 `read`/`write` 直接访问文件, 缓存中只有被映射的页, 读写时与这些页保持一致。
+//!
+//! 可写的共享映射可以直接修改页而不经过内核, 这样的页在映射期间每次写回时都被写入
+//! (DBFS 上只写入真正改变了的页)。
+use alloc::{
+    collections::BTreeMap,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    cmp::min,
+    ops::Range,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use config::{FRAME_SIZE, PAGE_CACHE_MAX_PAGES};
+use constants::{AlienResult, LinuxErrno};
+use ksync::Mutex;
+use log::{info, warn};
+use mem::{try_alloc_frame_trackers, FrameTracker};
+use vfscore::{inode::VfsInode, utils::VfsNodeType};
+
+/// 预读窗口的初始页数
+const READAHEAD_MIN: usize = 4;
+/// 预读窗口的最大页数
+const READAHEAD_MAX: usize = 32;
+/// 一次回收的页数
+const RECLAIM_BATCH: usize = 64;
+
+/// 不缓存的文件系统 (按注册的名字)
+const UNCACHED_FS: [&str; 4] = ["procfs", "sysfs", "devfs", "pipefs"];
+
+/// inode 的地址
+///
+/// 不使用 inode 号: procfs 的文件的 inode 号都是 0, overlay 的文件在复制到上层前后
+/// inode 号不同。页缓存持有 inode, 因此地址在页缓存存在期间不会被重用。
+type Key = usize;
+
+/// 所有打开的文件的页缓存
+static CACHES: Mutex<BTreeMap<Key, Arc<PageCache>>> = Mutex::new(BTreeMap::new());
+/// 所有页缓存中的页数
+static CACHED_PAGES: AtomicUsize = AtomicUsize::new(0);
+/// 访问时间戳, 每次访问页时递增
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+fn key(inode: &Arc<dyn VfsInode>) -> Key {
+    Arc::as_ptr(inode) as *const u8 as usize
+}
+
+/// `inode` 所在的文件系统的内容是否可以缓存, 见 [`UNCACHED_FS`]
+fn cacheable(inode: &Arc<dyn VfsInode>) -> bool {
+    let Ok(sb) = inode.get_super_block() else {
+        return false;
+    };
+    let fs_type = Arc::as_ptr(&sb.fs_type()) as *const u8;
+    !crate::FS
+        .lock()
+        .iter()
+        .filter(|(name, _)| UNCACHED_FS.contains(&name.as_str()))
+        .any(|(_, fs)| Arc::as_ptr(fs) as *const u8 == fs_type)
+}
+
+fn tick() -> u64 {
+    CLOCK.fetch_add(1, Ordering::Relaxed)
+}
+
+struct Page {
+    frame: FrameTracker,
+    dirty: bool,
+    /// 映射这一页的共享映射数, 被映射的页不会被回收
+    maps: usize,
+    /// 其中可写的映射数
+    writable_maps: usize,
+    /// 最近一次访问的时间戳
+    accessed: u64,
+}
+
+impl Page {
+    /// 写回时是否需要写入
+    fn needs_write_back(&self) -> bool {
+        self.dirty || self.writable_maps > 0
+    }
+}
+
+/// 顺序读的预读状态
+#[derive(Default)]
+struct Readahead {
+    /// 顺序读时下一次读取的第一页
+    next: usize,
+    /// 当前的预读页数, 0 表示不预读
+    window: usize,
+}
+
+impl Readahead {
+    /// 读取 `pages` 时更新状态, 返回这次需要预读的页数
+    fn on_read(&mut self, pages: Range<usize>) -> usize {
+        self.window = if pages.start == self.next {
+            (self.window * 2).clamp(READAHEAD_MIN, READAHEAD_MAX)
+        } else {
+            0
+        };
+        self.next = pages.end;
+        self.window
+    }
+}
+
+struct PageCacheInner {
+    /// 文件页号 -> 页
+    pages: BTreeMap<usize, Page>,
+    /// 使用者数: 打开的文件和共享映射
+    users: usize,
+    readahead: Readahead,
+}
+
+pub struct PageCache {
+    key: Key,
+    inode: Arc<dyn VfsInode>,
+    /// 读写是否经过缓存, DBFS 的文件只缓存被映射的页
+    cached: bool,
+    inner: Mutex<PageCacheInner>,
+}
+
+/// 打开 `inode` 的页缓存, `inode` 不是普通文件或所在的文件系统不缓存时为 None
+///
+/// 每次打开对应一次 [`PageCache::release`]。
+pub fn open(inode: &Arc<dyn VfsInode>) -> Option<Arc<PageCache>> {
+    if inode.inode_type() != VfsNodeType::File || !cacheable(inode) {
+        return None;
+    }
+    let key = key(inode);
+    let mut caches = CACHES.lock();
+    let cache = caches.entry(key).or_insert_with(|| {
+        let cached = inode
+            .get_super_block()
+            .ok()
+            .and_then(|sb| dbfs::stats::mount_point_of(&sb))
+            .is_none();
+        Arc::new(PageCache {
+            key,
+            inode: inode.clone(),
+            cached,
+            inner: Mutex::new(PageCacheInner {
+                pages: BTreeMap::new(),
+                users: 0,
+                readahead: Readahead::default(),
+            }),
+        })
+    });
+    cache.inner.lock().users += 1;
+    Some(cache.clone())
+}
+
+/// `inode` 已经打开的页缓存
+pub fn find(inode: &Arc<dyn VfsInode>) -> Option<Arc<PageCache>> {
+    CACHES.lock().get(&key(inode)).cloned()
+}
+
+/// 从 `inode` 读取, 文件打开时经过它的页缓存
+///
+/// 供不通过打开的文件访问 inode 的地方使用 (如 exec 读取程序、loop 设备)。
+pub fn read_at(inode: &Arc<dyn VfsInode>, offset: u64, buf: &mut [u8]) -> AlienResult<usize> {
+    match find(inode) {
+        Some(cache) => cache.read_at(offset, buf),
+        None => Ok(inode.read_at(offset, buf)?),
+    }
+}
+
+/// 写入 `inode`, 文件打开时经过它的页缓存
+pub fn write_at(inode: &Arc<dyn VfsInode>, offset: u64, buf: &[u8]) -> AlienResult<usize> {
+    match find(inode) {
+        Some(cache) => cache.write_at(offset, buf),
+        None => Ok(inode.write_at(offset, buf)?),
+    }
+}
+
+/// 文件被截断到 `len` 后调用
+pub fn truncated(inode: &Arc<dyn VfsInode>, len: u64) {
+    if let Some(cache) = find(inode) {
+        cache.truncate(len);
+    }
+}
+
+/// 写回所有页缓存中的脏页, 返回写入的字节数
+pub fn sync_all() -> AlienResult<usize> {
+    write_back_caches(|_| true)
+}
+
+/// 后台写回: 写回读写经过缓存的页缓存中的脏页, 返回写入的字节数
+///
+/// DBFS 的文件被可写映射的页不在这里写回: 每次写回都是一次事务, 只在 `fsync`、`msync`、
+/// `sync` 和解除映射时写回。
+pub fn write_back_all() -> AlienResult<usize> {
+    write_back_caches(|cache| cache.cached)
+}
+
+fn write_back_caches(select: impl Fn(&PageCache) -> bool) -> AlienResult<usize> {
+    let caches = CACHES
+        .lock()
+        .values()
+        .filter(|cache| select(cache))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut written = 0;
+    let mut result = Ok(());
+    for cache in caches {
+        match cache.sync(0, usize::MAX) {
+            Ok(bytes) => written += bytes,
+            Err(e) => result = Err(e),
+        }
+    }
+    result.map(|_| written)
+}
+
+/// 回收最久未访问的最多 `count` 个页, 脏页先写回, 返回回收的页数
+///
+/// 被映射的页和写回失败的页不会被回收, DBFS 的文件的页 (只在映射期间缓存) 也不回收。
+pub fn reclaim(count: usize) -> usize {
+    let caches = CACHES
+        .lock()
+        .values()
+        .filter(|cache| cache.cached)
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut candidates = Vec::new();
+    for (i, cache) in caches.iter().enumerate() {
+        let inner = cache.inner.lock();
+        candidates.extend(
+            inner
+                .pages
+                .iter()
+                .filter(|(_, page)| page.maps == 0)
+                .map(|(index, page)| (page.accessed, i, *index)),
+        );
+    }
+    candidates.sort_unstable();
+    candidates.truncate(count);
+    let mut victims = vec![Vec::new(); caches.len()];
+    for (_, i, index) in candidates {
+        victims[i].push(index);
+    }
+    let reclaimed: usize = caches
+        .iter()
+        .zip(victims)
+        .filter(|(_, indices)| !indices.is_empty())
+        .map(|(cache, indices)| cache.evict(&indices))
+        .sum();
+    if reclaimed > 0 {
+        info!("page cache: reclaimed {} pages", reclaimed);
+    }
+    reclaimed
+}
+
+/// 分配一个页, 内存不足时先回收页缓存
+fn alloc_page() -> AlienResult<FrameTracker> {
+    if CACHED_PAGES.load(Ordering::Relaxed) >= PAGE_CACHE_MAX_PAGES {
+        reclaim(RECLAIM_BATCH);
+    }
+    let frame = match try_alloc_frame_trackers(1) {
+        Some(frame) => frame,
+        None => {
+            reclaim(RECLAIM_BATCH);
+            try_alloc_frame_trackers(1).ok_or(LinuxErrno::ENOMEM)?
+        }
+    };
+    Ok(frame)
+}
+
+impl PageCache {
+    /// 字节范围 `[start, start + len)` 覆盖的页号
+    fn page_range(start: u64, len: usize) -> Range<usize> {
+        let first = start as usize / FRAME_SIZE;
+        let last = (start as usize + len).div_ceil(FRAME_SIZE);
+        first..last
+    }
+
+    /// 确保 `pages` 中的页都在缓存中, 缺少的页从文件读入
+    ///
+    /// 读文件时不持有锁: 文件系统可能再次访问页缓存 (如 loop 设备上的文件系统)。
+    /// 返回时页可能已经被回收, 调用者需要在持有锁时重新检查。
+    fn load(&self, pages: Range<usize>, size: u64) -> AlienResult<()> {
+        let missing = {
+            let inner = self.inner.lock();
+            pages
+                .filter(|index| !inner.pages.contains_key(index))
+                .collect::<Vec<_>>()
+        };
+        let mut loaded = Vec::with_capacity(missing.len());
+        for index in missing {
+            let mut frame = alloc_page()?;
+            frame.fill(0);
+            let offset = (index * FRAME_SIZE) as u64;
+            if offset < size {
+                let len = min(FRAME_SIZE as u64, size - offset) as usize;
+                self.inode.read_at(offset, &mut frame[..len])?;
+            }
+            loaded.push((index, frame));
+        }
+        let mut inner = self.inner.lock();
+        for (index, frame) in loaded {
+            if inner.pages.contains_key(&index) {
+                continue;
+            }
+            inner.pages.insert(
+                index,
+                Page {
+                    frame,
+                    dirty: false,
+                    maps: 0,
+                    writable_maps: 0,
+                    accessed: tick(),
+                },
+            );
+            CACHED_PAGES.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// 把 `[offset, offset + buf.len())` 中已经缓存的页的内容复制到 `buf`
+    fn copy_from_pages(inner: &PageCacheInner, offset: u64, buf: &mut [u8]) {
+        for (index, page) in inner.pages.range(Self::page_range(offset, buf.len())) {
+            let page_offset = (index * FRAME_SIZE) as u64;
+            let start = page_offset.max(offset);
+            let end = min(page_offset + FRAME_SIZE as u64, offset + buf.len() as u64);
+            buf[(start - offset) as usize..(end - offset) as usize].copy_from_slice(
+                &page.frame[(start - page_offset) as usize..(end - page_offset) as usize],
+            );
+        }
+    }
+
+    /// 把 `buf` 写入 `[offset, offset + buf.len())` 中已经缓存的页, 返回写入的页号
+    fn copy_to_pages(inner: &mut PageCacheInner, offset: u64, buf: &[u8]) -> Vec<usize> {
+        let now = tick();
+        let mut written = Vec::new();
+        for (index, page) in inner.pages.range_mut(Self::page_range(offset, buf.len())) {
+            let page_offset = (index * FRAME_SIZE) as u64;
+            let start = page_offset.max(offset);
+            let end = min(page_offset + FRAME_SIZE as u64, offset + buf.len() as u64);
+            page.frame[(start - page_offset) as usize..(end - page_offset) as usize]
+                .copy_from_slice(&buf[(start - offset) as usize..(end - offset) as usize]);
+            page.accessed = now;
+            written.push(*index);
+        }
+        written
+    }
+
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> AlienResult<usize> {
+        if !self.cached {
+            let read = self.inode.read_at(offset, buf)?;
+            let inner = self.inner.lock();
+            Self::copy_from_pages(&inner, offset, &mut buf[..read]);
+            return Ok(read);
+        }
+        let size = self.inode.get_attr()?.st_size;
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = min(buf.len() as u64, size - offset) as usize;
+        let pages = Self::page_range(offset, len);
+        let window = self.inner.lock().readahead.on_read(pages.clone());
+        let last = min(pages.end + window, (size as usize).div_ceil(FRAME_SIZE));
+        loop {
+            self.load(pages.start..last, size)?;
+            let mut inner = self.inner.lock();
+            if pages.clone().all(|index| inner.pages.contains_key(&index)) {
+                let now = tick();
+                for (_, page) in inner.pages.range_mut(pages.clone()) {
+                    page.accessed = now;
+                }
+                Self::copy_from_pages(&inner, offset, &mut buf[..len]);
+                return Ok(len);
+            }
+        }
+    }
+
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> AlienResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.cached {
+            let written = self.inode.write_at(offset, buf)?;
+            Self::copy_to_pages(&mut self.inner.lock(), offset, &buf[..written]);
+            return Ok(written);
+        }
+        let size = self.inode.get_attr()?.st_size;
+        // 文件大小之内的部分写入缓存页
+        let inside = if offset < size {
+            min(buf.len() as u64, size - offset) as usize
+        } else {
+            0
+        };
+        if inside > 0 {
+            let pages = Self::page_range(offset, inside);
+            loop {
+                // 被整页覆盖的页也先读入, 不单独处理
+                self.load(pages.clone(), size)?;
+                let mut inner = self.inner.lock();
+                if pages.clone().all(|index| inner.pages.contains_key(&index)) {
+                    for index in Self::copy_to_pages(&mut inner, offset, &buf[..inside]) {
+                        inner.pages.get_mut(&index).unwrap().dirty = true;
+                    }
+                    break;
+                }
+            }
+        }
+        if inside == buf.len() {
+            return Ok(inside);
+        }
+        // 超出文件末尾的部分直接写入文件, 并更新已经缓存的页 (原来的最后一页)
+        let rest = &buf[inside..];
+        let rest_offset = offset + inside as u64;
+        let written = self.inode.write_at(rest_offset, rest)?;
+        Self::copy_to_pages(&mut self.inner.lock(), rest_offset, &rest[..written]);
+        Ok(inside + written)
+    }
+
+    /// 写回 `[offset, offset + len)` 中的脏页和被可写映射的页, 返回写入的字节数
+    pub fn sync(&self, offset: usize, len: usize) -> AlienResult<usize> {
+        let end = offset.saturating_add(len);
+        let first = offset / FRAME_SIZE;
+        let last = end / FRAME_SIZE + usize::from(end % FRAME_SIZE != 0);
+        self.write_back(|index| (first..last).contains(&index))
+    }
+
+    /// 写回 `select` 选中的需要写回的页
+    ///
+    /// 页的内容先复制出来并标记为干净, 写文件时不持有锁; 写回失败时重新标记为脏。
+    fn write_back(&self, select: impl Fn(usize) -> bool) -> AlienResult<usize> {
+        let pages = {
+            let mut inner = self.inner.lock();
+            inner
+                .pages
+                .iter_mut()
+                .filter(|(index, page)| select(**index) && page.needs_write_back())
+                .map(|(index, page)| {
+                    page.dirty = false;
+                    (*index, page.frame.to_vec())
+                })
+                .collect::<Vec<_>>()
+        };
+        if pages.is_empty() {
+            return Ok(0);
+        }
+        let slices = pages
+            .iter()
+            .map(|(index, data)| ((index * FRAME_SIZE) as u64, data.as_slice()))
+            .collect::<Vec<_>>();
+        let result = crate::write_back_mapping(&self.inode, &slices);
+        if result.is_err() {
+            let mut inner = self.inner.lock();
+            for (index, _) in &pages {
+                if let Some(page) = inner.pages.get_mut(index) {
+                    page.dirty = true;
+                }
+            }
+        }
+        result
+    }
+
+    /// 回收 `indices` 中仍然可以回收的页, 返回回收的页数
+    fn evict(&self, indices: &[usize]) -> usize {
+        if let Err(e) = self.write_back(|index| indices.contains(&index)) {
+            warn!("page cache: write back before reclaim failed: {:?}", e);
+        }
+        let mut inner = self.inner.lock();
+        let mut evicted = 0;
+        for index in indices {
+            let removable = inner
+                .pages
+                .get(index)
+                .is_some_and(|page| page.maps == 0 && !page.needs_write_back());
+            if removable {
+                inner.pages.remove(index);
+                evicted += 1;
+            }
+        }
+        CACHED_PAGES.fetch_sub(evicted, Ordering::Relaxed);
+        evicted
+    }
+
+    /// 文件被截断到 `len`: 丢弃之后的页, 最后一页中文件末尾之后的部分清零
+    ///
+    /// 仍被映射的页保留 (清零), 直到映射解除。
+    pub fn truncate(&self, len: u64) {
+        let mut inner = self.inner.lock();
+        let first = (len as usize).div_ceil(FRAME_SIZE);
+        let dropped = inner
+            .pages
+            .range(first..)
+            .filter(|(_, page)| page.maps == 0)
+            .map(|(index, _)| *index)
+            .collect::<Vec<_>>();
+        for index in &dropped {
+            inner.pages.remove(index);
+        }
+        CACHED_PAGES.fetch_sub(dropped.len(), Ordering::Relaxed);
+        for (index, page) in inner.pages.range_mut(len as usize / FRAME_SIZE..) {
+            let start = (len as usize).saturating_sub(index * FRAME_SIZE);
+            page.frame[start..].fill(0);
+        }
+    }
+
+    /// 把第 `index` 页用于一个共享映射, 返回页的物理地址
+    ///
+    /// 页在映射解除 ([`PageCache::unmap_page`]) 之前不会被回收。
+    pub fn map_page(&self, index: usize, writable: bool) -> AlienResult<usize> {
+        let size = self.inode.get_attr()?.st_size;
+        loop {
+            self.load(index..index + 1, size)?;
+            let mut inner = self.inner.lock();
+            if let Some(page) = inner.pages.get_mut(&index) {
+                page.maps += 1;
+                if writable {
+                    page.writable_maps += 1;
+                }
+                page.accessed = tick();
+                return Ok(page.frame.start());
+            }
+        }
+    }
+
+    /// 解除第 `index` 页的一个共享映射, 调用前应先写回
+    pub fn unmap_page(&self, index: usize, writable: bool) {
+        let mut inner = self.inner.lock();
+        if let Some(page) = inner.pages.get_mut(&index) {
+            page.maps = page.maps.saturating_sub(1);
+            if writable {
+                page.writable_maps = page.writable_maps.saturating_sub(1);
+            }
+        }
+        // DBFS 的文件只缓存被映射的页
+        if !self.cached && inner.pages.get(&index).is_some_and(|page| page.maps == 0) {
+            inner.pages.remove(&index);
+            CACHED_PAGES.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// 一个使用者 (打开的文件或共享映射) 不再使用页缓存
+    ///
+    /// 最后一个使用者离开时写回所有脏页并释放页缓存; 写回失败时页缓存仍然被释放,
+    /// 错误返回给调用者。
+    pub fn release(&self) -> AlienResult<()> {
+        {
+            let mut inner = self.inner.lock();
+            inner.users = inner.users.saturating_sub(1);
+            if inner.users > 0 {
+                return Ok(());
+            }
+        }
+        let result = self.sync(0, usize::MAX).map(|_| ());
+        let mut caches = CACHES.lock();
+        let mut inner = self.inner.lock();
+        // 写回期间文件可能再次被打开
+        if inner.users == 0 {
+            CACHED_PAGES.fetch_sub(inner.pages.len(), Ordering::Relaxed);
+            inner.pages.clear();
+            caches.remove(&self.key);
+        }
+        result
+    }
+}
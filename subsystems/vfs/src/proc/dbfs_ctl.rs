@@ -0,0 +1,234 @@
+use alloc::{string::String, sync::Arc};
+use core::cmp::min;
+
+use vfscore::{
+    error::VfsError,
+    file::VfsFile,
+    inode::{InodeAttr, VfsInode},
+    superblock::VfsSuperBlock,
+    utils::{VfsFileStat, VfsNodePerm, VfsNodeType, VfsPollEvents},
+    VfsResult,
+};
+
+/// `/proc/fs/dbfs/failpoints`
+///
+/// Reading lists every DBFS failpoint and its state, writing arms or
+/// disarms them, one command per line (see `dbfs::failpoint`).
+pub struct DbfsFailpoints;
+
+impl VfsFile for DbfsFailpoints {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let info = dbfs::failpoint::describe();
+        let info = info.as_bytes();
+        if offset as usize >= info.len() {
+            return Ok(0);
+        }
+        let min_len = min(buf.len(), info.len() - offset as usize);
+        buf[..min_len].copy_from_slice(&info[offset as usize..offset as usize + min_len]);
+        Ok(min_len)
+    }
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let cmds = core::str::from_utf8(buf).map_err(|_| VfsError::Invalid)?;
+        for cmd in cmds.lines() {
+            dbfs::failpoint::apply_command(cmd).map_err(|_| VfsError::Invalid)?;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl VfsInode for DbfsFailpoints {
+    fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
+        Err(VfsError::NoSys)
+    }
+    fn node_perm(&self) -> VfsNodePerm {
+        VfsNodePerm::from_bits_truncate(0o600)
+    }
+    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        Ok(VfsFileStat {
+            st_size: dbfs::failpoint::describe().as_bytes().len() as u64,
+            ..Default::default()
+        })
+    }
+
+    fn inode_type(&self) -> VfsNodeType {
+        VfsNodeType::File
+    }
+}
+
+/// `/proc/fs/dbfs/quotas`
+///
+/// Reading lists every DBFS directory quota with its current usage, writing
+/// sets or clears quotas, one command per line (see `dbfs::quota`).
+pub struct DbfsQuotas;
+
+impl VfsFile for DbfsQuotas {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let info = dbfs::quota::describe();
+        let info = info.as_bytes();
+        if offset as usize >= info.len() {
+            return Ok(0);
+        }
+        let min_len = min(buf.len(), info.len() - offset as usize);
+        buf[..min_len].copy_from_slice(&info[offset as usize..offset as usize + min_len]);
+        Ok(min_len)
+    }
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let cmds = core::str::from_utf8(buf).map_err(|_| VfsError::Invalid)?;
+        for cmd in cmds.lines() {
+            dbfs::quota::apply_command(cmd).map_err(|_| VfsError::Invalid)?;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl VfsInode for DbfsQuotas {
+    fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
+        Err(VfsError::NoSys)
+    }
+    fn node_perm(&self) -> VfsNodePerm {
+        VfsNodePerm::from_bits_truncate(0o600)
+    }
+    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        Ok(VfsFileStat {
+            st_size: dbfs::quota::describe().as_bytes().len() as u64,
+            ..Default::default()
+        })
+    }
+
+    fn inode_type(&self) -> VfsNodeType {
+        VfsNodeType::File
+    }
+}
+
+/// `/proc/fs/dbfs/<mount>/{stats,transactions}`
+///
+/// Read-only view of one DBFS mount, rendered on every read by `describe`
+/// (see `dbfs::stats`). Reading after the mount is gone returns ENOENT.
+pub struct DbfsMountInfo {
+    mount: String,
+    describe: fn(&str) -> Option<String>,
+}
+
+impl DbfsMountInfo {
+    pub fn new(mount: String, describe: fn(&str) -> Option<String>) -> Self {
+        Self { mount, describe }
+    }
+
+    fn info(&self) -> VfsResult<String> {
+        (self.describe)(&self.mount).ok_or(VfsError::NoEntry)
+    }
+}
+
+impl VfsFile for DbfsMountInfo {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let info = self.info()?;
+        let info = info.as_bytes();
+        if offset as usize >= info.len() {
+            return Ok(0);
+        }
+        let min_len = min(buf.len(), info.len() - offset as usize);
+        buf[..min_len].copy_from_slice(&info[offset as usize..offset as usize + min_len]);
+        Ok(min_len)
+    }
+}
+
+impl VfsInode for DbfsMountInfo {
+    fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
+        Err(VfsError::NoSys)
+    }
+    fn node_perm(&self) -> VfsNodePerm {
+        VfsNodePerm::from_bits_truncate(0o444)
+    }
+    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        Ok(VfsFileStat {
+            st_size: self.info()?.as_bytes().len() as u64,
+            ..Default::default()
+        })
+    }
+
+    fn inode_type(&self) -> VfsNodeType {
+        VfsNodeType::File
+    }
+}
+
+/// `/proc/fs/dbfs/<mount>/changes`
+///
+/// Change stream of one DBFS mount, one line per committed transaction
+/// (see `dbfs::cdc`). The file offset is the position in the stream, so a
+/// reader resumes by seeking to `ioctl(CDC_IOC_OFFSET_AFTER, lsn)`.
+///
+/// `poll` on an open file reports `IN` when the stream extends past that
+/// file's offset (see [`DbfsChanges::poll_at`]).
+pub struct DbfsChanges {
+    mount: String,
+}
+
+impl DbfsChanges {
+    pub fn new(mount: String) -> Self {
+        Self { mount }
+    }
+
+    /// Readiness for a reader whose cursor is at `pos`
+    ///
+    /// Called by `KernelFile::poll` with the file's offset, so every reader
+    /// has its own cursor.
+    pub(crate) fn poll_at(&self, pos: u64, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
+        let mut res = VfsPollEvents::empty();
+        if event.contains(VfsPollEvents::IN) && dbfs::changes::end(&self.mount)? > pos {
+            res |= VfsPollEvents::IN;
+        }
+        Ok(res)
+    }
+}
+
+impl VfsFile for DbfsChanges {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        dbfs::changes::read(&self.mount, offset, buf)
+    }
+    fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
+        self.poll_at(0, event)
+    }
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            dbfs::changes::CDC_IOC_OFFSET_AFTER => {
+                dbfs::changes::offset_after(&self.mount, arg as u64).map(|offset| offset as usize)
+            }
+            _ => Err(VfsError::Invalid),
+        }
+    }
+}
+
+impl VfsInode for DbfsChanges {
+    fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
+        Err(VfsError::NoSys)
+    }
+    fn node_perm(&self) -> VfsNodePerm {
+        VfsNodePerm::from_bits_truncate(0o444)
+    }
+    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        Ok(VfsFileStat {
+            st_size: dbfs::changes::end(&self.mount)?,
+            ..Default::default()
+        })
+    }
+
+    fn inode_type(&self) -> VfsNodeType {
+        VfsNodeType::File
+    }
+}
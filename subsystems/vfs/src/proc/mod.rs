@@ -1,21 +1,36 @@
+pub(crate) mod dbfs_ctl;
 mod filesystem;
 mod interrupt;
 mod mem;
 mod mounts;
+mod process;
 
-use alloc::sync::Arc;
-use core::ops::Index;
-
+use alloc::{string::ToString, sync::Arc};
+use dbfs_ctl::{DbfsChanges, DbfsFailpoints, DbfsMountInfo, DbfsQuotas};
 use dynfs::DynFsDirInode;
 use filesystem::SystemSupportFS;
 use interrupt::InterruptRecord;
+use log::warn;
 use mem::MemInfo;
 use mounts::MountInfo;
-use vfscore::{dentry::VfsDentry, error::VfsError, fstype::VfsFsType, path::VfsPath};
+use process::ProcessNode;
+pub(crate) use process::current_fs;
+pub use process::{register_process_info, ProcessFile, ProcessInfo, ProcessLink};
+use spin::Once;
+use vfscore::{
+    dentry::VfsDentry, error::VfsError, fstype::VfsFsType, inode::VfsInode, path::VfsPath,
+    VfsResult,
+};
 
-use crate::{CommonFsProviderImpl, FS};
+use crate::CommonFsProviderImpl;
 pub type ProcFsDirInodeImpl = DynFsDirInode<CommonFsProviderImpl, spin::Mutex<()>>;
 
+/// `/proc`, per-process directories are added and removed at runtime
+static PROC_ROOT_DIR: Once<(Arc<dyn VfsDentry>, Arc<ProcFsDirInodeImpl>)> = Once::new();
+
+/// `/proc/fs/dbfs`, per-mount directories are added and removed at runtime
+static DBFS_PROC_DIR: Once<(Arc<dyn VfsDentry>, Arc<ProcFsDirInodeImpl>)> = Once::new();
+
 ///
 /// ```bash
 /// |
@@ -23,6 +38,16 @@ pub type ProcFsDirInodeImpl = DynFsDirInode<CommonFsProviderImpl, spin::Mutex<()
 /// |-- interrupts
 /// |-- mounts
 /// |-- filesystems
+/// |-- self -> <pid of the caller>
+/// |-- <pid>           (see process.rs)
+/// |-- fs
+///    |-- dbfs
+///       |-- failpoints
+///       |-- quotas
+///       |-- <mount>
+///          |-- stats
+///          |-- transactions
+///          |-- changes
 /// ```
 // todo!(use ramfs instead of dynfs)
 pub fn init_procfs(procfs: Arc<dyn VfsFsType>) -> Arc<dyn VfsDentry> {
@@ -46,21 +71,95 @@ pub fn init_procfs(procfs: Arc<dyn VfsFsType>) -> Arc<dyn VfsDentry> {
         .add_file_manually("filesystems", Arc::new(support_fs), "r--r--r--".into())
         .unwrap();
 
+    let fs_dir = root_inode
+        .add_dir_manually("fs", "r-xr-xr-x".into())
+        .unwrap()
+        .downcast_arc::<ProcFsDirInodeImpl>()
+        .map_err(|_| VfsError::Invalid)
+        .unwrap();
+    let dbfs_dir = fs_dir
+        .add_dir_manually("dbfs", "r-xr-xr-x".into())
+        .unwrap()
+        .downcast_arc::<ProcFsDirInodeImpl>()
+        .map_err(|_| VfsError::Invalid)
+        .unwrap();
+    dbfs_dir
+        .add_file_manually("failpoints", Arc::new(DbfsFailpoints), "rw-------".into())
+        .unwrap();
+    dbfs_dir
+        .add_file_manually("quotas", Arc::new(DbfsQuotas), "rw-------".into())
+        .unwrap();
+
     root_inode
-        .add_dir_manually("self", "r-xr-xr-x".into())
+        .add_file_manually("self", Arc::new(ProcessNode::self_link()), "rwxrwxrwx".into())
         .unwrap();
 
     let path = VfsPath::new(root_dt.clone(), root_dt.clone());
-    let ramfs = FS.lock().index("ramfs").clone();
-    let fake_ramfs = ramfs.i_mount(0, "/proc/self", None, &[]).unwrap();
-    path.join("self").unwrap().mount(fake_ramfs, 0).unwrap();
-
-    path.join("self/exe")
-        .unwrap()
-        .symlink("/bin/busybox")
-        .unwrap();
+    let dbfs_dt = path.join("fs/dbfs").unwrap().open(None).unwrap();
+    DBFS_PROC_DIR.call_once(|| (dbfs_dt, dbfs_dir));
+    PROC_ROOT_DIR.call_once(|| (root_dt.clone(), root_inode));
 
     println!("procfs init success");
 
     root_dt
 }
+
+/// Add `/proc/<pid>/` for a new process
+pub fn register_process(pid: usize) -> VfsResult<()> {
+    let (_, root_dir) = PROC_ROOT_DIR.get().ok_or(VfsError::NoSys)?;
+    let dir = Arc::new(ProcessNode::process(pid));
+    root_dir.add_file_manually(&pid.to_string(), dir, "r-xr-xr-x".into())?;
+    Ok(())
+}
+
+/// Remove `/proc/<pid>/` after the process is reaped
+pub fn unregister_process(pid: usize) {
+    let (root_dt, root_dir) = match PROC_ROOT_DIR.get() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let name = pid.to_string();
+    root_dt.remove(&name);
+    if let Err(e) = root_dir.remove_manually(&name) {
+        warn!("remove /proc/{} failed: {:?}", name, e);
+    }
+}
+
+/// Add `/proc/fs/dbfs/<mount>/` for a new DBFS mount
+///
+/// `<mount>` is named by `dbfs::stats::proc_name`.
+pub fn register_dbfs_mount(mount_point: &str) -> VfsResult<()> {
+    let (_, dbfs_dir) = DBFS_PROC_DIR.get().ok_or(VfsError::NoSys)?;
+    let name = dbfs::stats::proc_name(mount_point);
+    let dir = dbfs_dir
+        .add_dir_manually(&name, "r-xr-xr-x".into())?
+        .downcast_arc::<ProcFsDirInodeImpl>()
+        .map_err(|_| VfsError::Invalid)?;
+    let stats = DbfsMountInfo::new(name.clone(), dbfs::stats::describe_stats);
+    dir.add_file_manually("stats", Arc::new(stats), "r--r--r--".into())?;
+    let transactions = DbfsMountInfo::new(name.clone(), dbfs::stats::describe_transactions);
+    dir.add_file_manually("transactions", Arc::new(transactions), "r--r--r--".into())?;
+    let changes = DbfsChanges::new(name);
+    dir.add_file_manually("changes", Arc::new(changes), "r--r--r--".into())?;
+    Ok(())
+}
+
+/// Remove `/proc/fs/dbfs/<mount>/` after a DBFS mount is gone
+pub fn unregister_dbfs_mount(mount_point: &str) {
+    let (dbfs_dt, dbfs_dir) = match DBFS_PROC_DIR.get() {
+        Some(dir) => dir,
+        None => return,
+    };
+    let name = dbfs::stats::proc_name(mount_point);
+    dbfs_dt.remove(&name);
+    if let Ok(dir) = dbfs_dir.lookup(&name) {
+        if let Ok(dir) = dir.downcast_arc::<ProcFsDirInodeImpl>() {
+            let _ = dir.remove_manually("stats");
+            let _ = dir.remove_manually("transactions");
+            let _ = dir.remove_manually("changes");
+        }
+    }
+    if let Err(e) = dbfs_dir.remove_manually(&name) {
+        warn!("remove /proc/fs/dbfs/{} failed: {:?}", name, e);
+    }
+}
@@ -1,4 +1,4 @@
-use alloc::sync::Arc;
+use alloc::{format, string::String, sync::Arc};
 use core::cmp::min;
 
 use vfscore::{
@@ -10,18 +10,39 @@ use vfscore::{
     VfsResult,
 };
 
-// todo!(dynamic mount info)
-const MOUNT_INFO: &str = r"
- rootfs / rootfs rw 0 0
- devfs /dev devfs rw 0 0
- fat32 / fat rw 0 0
-";
+use crate::mount::{caller_fs, relative, Mount};
+
+/// `/proc/mounts`, 每次读取时由调用者所在命名空间的挂载表生成
+///
+/// 只显示调用者根目录 (`chroot`) 之下的挂载, 挂载点为相对于根目录的路径。
 pub struct MountInfo;
 
+fn mount_info() -> String {
+    let (mnt_ns, root) = caller_fs();
+    let root = root.path();
+    mnt_ns
+        .mounts()
+        .into_iter()
+        .filter_map(|mount| {
+            let target = relative(&mount.target, &root)?;
+            let mount = Mount {
+                target: format!("/{}", target),
+                ..mount
+            };
+            Some(mount.describe())
+        })
+        .collect()
+}
+
 impl VfsFile for MountInfo {
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
-        let min_len = min(buf.len(), MOUNT_INFO.as_bytes().len() - offset as usize);
-        buf[..min_len].copy_from_slice(&MOUNT_INFO.as_bytes()[..min_len]);
+        let info = mount_info();
+        let info = info.as_bytes();
+        if offset as usize >= info.len() {
+            return Ok(0);
+        }
+        let min_len = min(buf.len(), info.len() - offset as usize);
+        buf[..min_len].copy_from_slice(&info[offset as usize..offset as usize + min_len]);
         Ok(min_len)
     }
 }
@@ -39,7 +60,7 @@ impl VfsInode for MountInfo {
 
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
         Ok(VfsFileStat {
-            st_size: MOUNT_INFO.as_bytes().len() as u64,
+            st_size: mount_info().len() as u64,
             ..Default::default()
         })
     }
@@ -0,0 +1,317 @@
+//! `/proc/<pid>` and `/proc/self`
+//!
+//! A directory is registered for each process when it is created and removed
+//! when it is reaped. Everything below it is generated on every lookup, readdir
+//! and read from the [`ProcessInfo`] the kernel registers at boot, so the
+//! entries always match the task's current state.
+//!
+//! ```bash
+//! /proc/<pid>
+//! |-- stat
+//! |-- status
+//! |-- cmdline
+//! |-- environ
+//! |-- maps
+//! |-- cwd -> <dir>
+//! |-- exe -> <file>
+//! |-- fd
+//! |  |-- <fd> -> <file>
+//! |-- task
+//!    |-- <tid>   (same entries, without task/)
+//! ```
+use alloc::{
+    boxed::Box,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::cmp::min;
+
+use spin::Once;
+use vfscore::{
+    dentry::VfsDentry,
+    error::VfsError,
+    file::VfsFile,
+    inode::{InodeAttr, VfsInode},
+    superblock::VfsSuperBlock,
+    utils::{VfsDirEntry, VfsFileStat, VfsNodePerm, VfsNodeType},
+    VfsResult,
+};
+
+use crate::mount::MountNamespace;
+
+/// Generated files of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessFile {
+    Stat,
+    Status,
+    Cmdline,
+    Environ,
+    Maps,
+}
+
+/// Symlinks of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessLink {
+    Cwd,
+    Exe,
+    Fd(usize),
+}
+
+/// Task information for procfs, implemented by the kernel
+pub trait ProcessInfo: Send + Sync {
+    /// Process id of the caller (`/proc/self`)
+    fn current_pid(&self) -> Option<usize>;
+    /// Whether task `tid` still exists (zombies included)
+    fn is_alive(&self, tid: usize) -> bool;
+    /// Thread ids of process `pid`
+    fn threads(&self, pid: usize) -> Vec<usize>;
+    /// Open file descriptors of task `tid`
+    fn fds(&self, tid: usize) -> Vec<usize>;
+    /// Content of `file` for task `tid`, `None` once the task is gone
+    fn read(&self, tid: usize, file: ProcessFile) -> Option<Vec<u8>>;
+    /// Target of `link` for task `tid`, `None` once the task or fd is gone
+    fn link(&self, tid: usize, link: ProcessLink) -> Option<String>;
+    /// Mount namespace and root directory (after `chroot`) of the caller
+    fn current_fs(&self) -> Option<(Arc<MountNamespace>, Arc<dyn VfsDentry>)>;
+}
+
+static PROCESS_INFO: Once<Box<dyn ProcessInfo>> = Once::new();
+
+/// Register the source of `/proc/<pid>` contents, called once by the kernel
+pub fn register_process_info(info: Box<dyn ProcessInfo>) {
+    PROCESS_INFO.call_once(|| info);
+}
+
+fn info() -> VfsResult<&'static dyn ProcessInfo> {
+    PROCESS_INFO
+        .get()
+        .map(|info| info.as_ref())
+        .ok_or(VfsError::NoSys)
+}
+
+/// Mount namespace and root directory of the caller, `None` without a calling task
+pub(crate) fn current_fs() -> Option<(Arc<MountNamespace>, Arc<dyn VfsDentry>)> {
+    info().ok()?.current_fs()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    /// `/proc/<pid>` or `/proc/<pid>/task/<tid>`
+    Task { thread: bool },
+    /// `/proc/<pid>/task`
+    Threads,
+    /// `/proc/<pid>/fd`
+    Fds,
+    File(ProcessFile),
+    Link(ProcessLink),
+    /// `/proc/self`
+    SelfLink,
+}
+
+/// Inode numbers of task nodes: this bit, the tid from bit 24 and the
+/// node kind (see [`NodeKind::code`]) below it
+const TASK_INO_BASE: u64 = 1 << 48;
+
+impl NodeKind {
+    /// Number of the kind within one task, fds start at 0x100
+    fn code(&self) -> u64 {
+        match self {
+            NodeKind::Task { thread: false } => 1,
+            NodeKind::Task { thread: true } => 2,
+            NodeKind::Threads => 3,
+            NodeKind::Fds => 4,
+            NodeKind::File(file) => 0x10 + *file as u64,
+            NodeKind::Link(ProcessLink::Cwd) => 0x20,
+            NodeKind::Link(ProcessLink::Exe) => 0x21,
+            NodeKind::Link(ProcessLink::Fd(fd)) => 0x100 + *fd as u64,
+            NodeKind::SelfLink => 0,
+        }
+    }
+
+    /// Inode number of this kind of node for task `tid`, unique per (tid, kind)
+    fn ino(&self, tid: usize) -> u64 {
+        TASK_INO_BASE | (tid as u64) << 24 | (self.code() & 0xff_ffff)
+    }
+
+    fn node_type(&self) -> VfsNodeType {
+        match self {
+            NodeKind::Task { .. } | NodeKind::Threads | NodeKind::Fds => VfsNodeType::Dir,
+            NodeKind::File(_) => VfsNodeType::File,
+            NodeKind::Link(_) | NodeKind::SelfLink => VfsNodeType::SymLink,
+        }
+    }
+}
+
+const TASK_FILES: [(&str, NodeKind); 9] = [
+    ("stat", NodeKind::File(ProcessFile::Stat)),
+    ("status", NodeKind::File(ProcessFile::Status)),
+    ("cmdline", NodeKind::File(ProcessFile::Cmdline)),
+    ("environ", NodeKind::File(ProcessFile::Environ)),
+    ("maps", NodeKind::File(ProcessFile::Maps)),
+    ("cwd", NodeKind::Link(ProcessLink::Cwd)),
+    ("exe", NodeKind::Link(ProcessLink::Exe)),
+    ("fd", NodeKind::Fds),
+    ("task", NodeKind::Threads),
+];
+
+/// A node below `/proc/<pid>`, rendered for task `tid` on demand
+pub struct ProcessNode {
+    tid: usize,
+    kind: NodeKind,
+}
+
+impl ProcessNode {
+    /// `/proc/<pid>`
+    pub fn process(pid: usize) -> Self {
+        Self::new(pid, NodeKind::Task { thread: false })
+    }
+
+    /// `/proc/self`
+    pub fn self_link() -> Self {
+        Self::new(0, NodeKind::SelfLink)
+    }
+
+    fn new(tid: usize, kind: NodeKind) -> Self {
+        Self { tid, kind }
+    }
+
+    /// Entries of a directory node
+    fn entries(&self) -> VfsResult<Vec<(String, NodeKind)>> {
+        let info = info()?;
+        let entries = match self.kind {
+            NodeKind::Task { thread } => {
+                if !info.is_alive(self.tid) {
+                    return Err(VfsError::NoEntry);
+                }
+                TASK_FILES
+                    .iter()
+                    .filter(|(name, _)| !(thread && *name == "task"))
+                    .map(|(name, kind)| (name.to_string(), *kind))
+                    .collect()
+            }
+            NodeKind::Threads => info
+                .threads(self.tid)
+                .into_iter()
+                .map(|tid| (tid.to_string(), NodeKind::Task { thread: true }))
+                .collect(),
+            NodeKind::Fds => info
+                .fds(self.tid)
+                .into_iter()
+                .map(|fd| (fd.to_string(), NodeKind::Link(ProcessLink::Fd(fd))))
+                .collect(),
+            _ => return Err(VfsError::NotDir),
+        };
+        Ok(entries)
+    }
+
+    /// Task a child entry belongs to: the thread for `task/<tid>`, else this one
+    fn child_tid(&self, name: &str, kind: NodeKind) -> usize {
+        match kind {
+            NodeKind::Task { thread: true } => name.parse().unwrap_or(self.tid),
+            _ => self.tid,
+        }
+    }
+
+    fn content(&self) -> VfsResult<Vec<u8>> {
+        match self.kind {
+            NodeKind::File(file) => info()?.read(self.tid, file).ok_or(VfsError::NoEntry),
+            NodeKind::Link(link) => info()?
+                .link(self.tid, link)
+                .map(String::into_bytes)
+                .ok_or(VfsError::NoEntry),
+            NodeKind::SelfLink => info()?
+                .current_pid()
+                .map(|pid| pid.to_string().into_bytes())
+                .ok_or(VfsError::NoEntry),
+            _ => Err(VfsError::IsDir),
+        }
+    }
+}
+
+impl VfsFile for ProcessNode {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        if !matches!(self.kind, NodeKind::File(_)) {
+            return Err(VfsError::Invalid);
+        }
+        let content = self.content()?;
+        if offset as usize >= content.len() {
+            return Ok(0);
+        }
+        let min_len = min(buf.len(), content.len() - offset as usize);
+        buf[..min_len].copy_from_slice(&content[offset as usize..offset as usize + min_len]);
+        Ok(min_len)
+    }
+
+    fn readdir(&self, start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
+        let entry = self.entries()?.into_iter().nth(start_index);
+        Ok(entry.map(|(name, kind)| VfsDirEntry {
+            ino: kind.ino(self.child_tid(&name, kind)),
+            ty: kind.node_type(),
+            name,
+        }))
+    }
+}
+
+impl VfsInode for ProcessNode {
+    fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
+        Err(VfsError::NoSys)
+    }
+
+    fn node_perm(&self) -> VfsNodePerm {
+        match self.kind {
+            NodeKind::File(ProcessFile::Environ) => VfsNodePerm::from_bits_truncate(0o400),
+            NodeKind::File(_) => VfsNodePerm::from_bits_truncate(0o444),
+            NodeKind::Link(_) | NodeKind::SelfLink => VfsNodePerm::from_bits_truncate(0o777),
+            _ => VfsNodePerm::from_bits_truncate(0o555),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> VfsResult<Arc<dyn VfsInode>> {
+        let (name, kind) = self
+            .entries()?
+            .into_iter()
+            .find(|(entry, _)| entry == name)
+            .ok_or(VfsError::NoEntry)?;
+        Ok(Arc::new(ProcessNode::new(self.child_tid(&name, kind), kind)))
+    }
+
+    fn readlink(&self, buf: &mut [u8]) -> VfsResult<usize> {
+        if !matches!(self.kind, NodeKind::Link(_) | NodeKind::SelfLink) {
+            return Err(VfsError::Invalid);
+        }
+        let target = self.content()?;
+        let len = min(buf.len(), target.len());
+        buf[..len].copy_from_slice(&target[..len]);
+        Ok(len)
+    }
+
+    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+        Ok(())
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        let format = match self.kind.node_type() {
+            VfsNodeType::Dir => 0o040000,
+            VfsNodeType::SymLink => 0o120000,
+            _ => 0o100000,
+        };
+        // like Linux, generated files report size 0 and are read until EOF
+        let size = match self.kind {
+            NodeKind::Link(_) | NodeKind::SelfLink => self.content()?.len() as u64,
+            _ => 0,
+        };
+        Ok(VfsFileStat {
+            st_ino: self.kind.ino(self.tid),
+            st_mode: format | self.node_perm().bits() as u32,
+            st_nlink: 1,
+            st_size: size,
+            ..Default::default()
+        })
+    }
+
+    fn inode_type(&self) -> VfsNodeType {
+        self.kind.node_type()
+    }
+}
+